@@ -1187,6 +1187,11 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
 }
 
 impl Aggregation {
+    /// Whether the aggregation can be computed during a plain scan of a stored relation,
+    /// see [SessionTx::simple_aggr_scan](crate::runtime::transact::SessionTx::simple_aggr_scan).
+    pub(crate) fn is_simple_scan_aggr(&self) -> bool {
+        [AGGR_COUNT.name, AGGR_SUM.name, AGGR_MIN.name, AGGR_MAX.name].contains(&self.name)
+    }
     pub(crate) fn meet_init(&mut self, _args: &[DataValue]) -> Result<()> {
         self.meet_op.replace(match self.name {
             name if name == AGGR_AND.name => Box::new(MeetAggrAnd),
@@ -10,11 +10,12 @@ use std::cmp::{max, min};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::mem;
+use std::sync::{Arc, RwLock};
 
 use itertools::Itertools;
 use miette::{bail, miette, Diagnostic, Result};
 use serde::de::{Error, Visitor};
-use serde::{Deserializer, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
@@ -38,6 +39,13 @@ pub enum Bytecode {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// push 1
+    Param {
+        name: SmartString<LazyCompact>,
+        slot: ParamSlot,
+        #[serde(skip)]
+        span: SourceSpan,
+    },
     /// pop n, push 1
     Apply {
         op: &'static Op,
@@ -64,6 +72,60 @@ pub enum Bytecode {
 #[diagnostic(code(eval::unbound))]
 struct UnboundVariableError(String, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("The value of parameter '${0}' is not available here")]
+#[diagnostic(code(eval::param_not_available))]
+#[diagnostic(help(
+    "Parameters of prepared queries are only supplied on execution, \
+    so they cannot be used where a constant is required"
+))]
+struct ParamNotAvailableError(String, #[label] SourceSpan);
+
+/// Holds the value of a parameter of a prepared query,
+/// which is filled in anew each time the query is executed.
+/// See [Db::prepare](crate::Db::prepare).
+#[derive(Clone, Default)]
+pub struct ParamSlot(Arc<RwLock<Option<DataValue>>>);
+
+impl ParamSlot {
+    pub(crate) fn set(&self, val: Option<DataValue>) {
+        *self.0.write().unwrap() = val;
+    }
+    fn get(&self, name: &str, span: SourceSpan) -> Result<DataValue> {
+        match &*self.0.read().unwrap() {
+            Some(val) => Ok(val.clone()),
+            None => bail!(ParamNotAvailableError(name.to_string(), span)),
+        }
+    }
+}
+
+impl PartialEq for ParamSlot {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ParamSlot {}
+
+impl Debug for ParamSlot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0.read().unwrap())
+    }
+}
+
+impl Serialize for ParamSlot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.read().unwrap().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ParamSlot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let val = Option::<DataValue>::deserialize(deserializer)?;
+        Ok(Self(Arc::new(RwLock::new(val))))
+    }
+}
+
 #[derive(Error, Diagnostic, Debug)]
 #[error("The tuple bound by variable '{0}' is too short: index is {1}, length is {2}")]
 #[diagnostic(help("This is definitely a bug. Please report it."))]
@@ -126,6 +188,10 @@ pub fn eval_bytecode(
                 stack.push(val.clone());
                 pointer += 1;
             }
+            Bytecode::Param { name, slot, span } => {
+                stack.push(slot.get(name, *span)?);
+                pointer += 1;
+            }
             Bytecode::Apply { op, arity, span } => {
                 let frame_start = stack.len() - *arity;
                 let args_frame = &stack[frame_start..];
@@ -172,6 +238,16 @@ pub enum Expr {
         #[serde(skip)]
         span: SourceSpan,
     },
+    /// Parameter of a prepared query, whose value is only known when the query is executed
+    Param {
+        /// The name of the parameter, without the leading `$`
+        name: SmartString<LazyCompact>,
+        /// Holds the value of the parameter during execution
+        slot: ParamSlot,
+        /// Source span
+        #[serde(skip)]
+        span: SourceSpan,
+    },
     /// Function application
     Apply {
         /// Op representing the function to apply
@@ -208,6 +284,41 @@ impl Debug for Expr {
     }
 }
 
+impl Expr {
+    /// Writes the expression as CozoScript that parses back to an equivalent expression,
+    /// unlike [Display], which is meant for reading
+    pub(crate) fn fmt_script(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fn write_call(f: &mut Formatter<'_>, name: &str, args: &[Expr]) -> std::fmt::Result {
+            write!(f, "{name}(")?;
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                arg.fmt_script(f)?;
+            }
+            f.write_str(")")
+        }
+        match self {
+            Expr::Binding { var, .. } => write!(f, "{}", var.name),
+            Expr::Const { val, .. } => val.fmt_script(f),
+            Expr::Param { name, .. } => write!(f, "${name}"),
+            Expr::Apply { op, args, .. } => write_call(
+                f,
+                &op.name.strip_prefix("OP_").unwrap().to_lowercase(),
+                args,
+            ),
+            Expr::UnboundApply { op, args, .. } => write_call(f, op, args),
+            Expr::Cond { clauses, .. } => {
+                let args = clauses
+                    .iter()
+                    .flat_map(|(cond, val)| [cond.clone(), val.clone()])
+                    .collect_vec();
+                write_call(f, "cond", &args)
+            }
+        }
+    }
+}
+
 impl Display for Expr {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -217,6 +328,17 @@ impl Display for Expr {
             Expr::Const { val, .. } => {
                 write!(f, "{val}")
             }
+            Expr::Param { name, .. } => {
+                write!(f, "${name}")
+            }
+            // `debug_tuple` would drop the parentheses of calls without arguments
+            Expr::Apply { op, args, .. } if args.is_empty() => {
+                write!(
+                    f,
+                    "{}()",
+                    op.name.strip_prefix("OP_").unwrap().to_lowercase()
+                )
+            }
             Expr::Apply { op, args, .. } => {
                 let mut writer =
                     f.debug_tuple(op.name.strip_prefix("OP_").unwrap().to_lowercase().as_str());
@@ -225,6 +347,7 @@ impl Display for Expr {
                 }
                 writer.finish()
             }
+            Expr::UnboundApply { op, args, .. } if args.is_empty() => write!(f, "{op}()"),
             Expr::UnboundApply { op, args, .. } => {
                 let mut writer = f.debug_tuple(op);
                 for arg in args.iter() {
@@ -274,7 +397,10 @@ impl Expr {
     pub(crate) fn span(&self) -> SourceSpan {
         match self {
             Expr::Binding { var, .. } => var.span,
-            Expr::Const { span, .. } | Expr::Apply { span, .. } | Expr::Cond { span, .. } => *span,
+            Expr::Const { span, .. }
+            | Expr::Param { span, .. }
+            | Expr::Apply { span, .. }
+            | Expr::Cond { span, .. } => *span,
             Expr::UnboundApply { span, .. } => *span,
         }
     }
@@ -342,7 +468,7 @@ impl Expr {
                     .ok_or_else(|| BadBindingError(var.to_string(), var.span))?;
                 *tuple_pos = Some(found_idx)
             }
-            Expr::Const { .. } => {}
+            Expr::Const { .. } | Expr::Param { .. } => {}
             Expr::Apply { args, .. } => {
                 for arg in args.iter_mut() {
                     arg.fill_binding_indices(binding_map)?;
@@ -374,7 +500,7 @@ impl Expr {
                     coll.insert(*idx);
                 }
             }
-            Expr::Const { .. } => {}
+            Expr::Const { .. } | Expr::Param { .. } => {}
             Expr::Apply { args, .. } => {
                 for arg in args.iter() {
                     arg.do_binding_indices(coll)?;
@@ -405,6 +531,7 @@ impl Expr {
         self.partial_eval()?;
         match self {
             Expr::Const { val, .. } => Ok(val),
+            Expr::Param { name, slot, span } => slot.get(&name, span),
             _ => bail!(NotConstError),
         }
     }
@@ -454,7 +581,7 @@ impl Expr {
             Expr::Binding { var, .. } => {
                 coll.insert(var.clone());
             }
-            Expr::Const { .. } => {}
+            Expr::Const { .. } | Expr::Param { .. } => {}
             Expr::Apply { args, .. } => {
                 for arg in args.iter() {
                     arg.collect_bindings(coll)?;
@@ -492,6 +619,7 @@ impl Expr {
                     .clone()),
             },
             Expr::Const { val, .. } => Ok(val.clone()),
+            Expr::Param { name, slot, span } => slot.get(name, *span),
             Expr::Apply { op, args, .. } => {
                 let args: Box<[DataValue]> = args
                     .iter()
@@ -520,7 +648,9 @@ impl Expr {
     }
     pub(crate) fn extract_bound(&self, target: &Symbol) -> Result<ValueRange> {
         Ok(match self {
-            Expr::Binding { .. } | Expr::Const { .. } | Expr::Cond { .. } => ValueRange::default(),
+            Expr::Binding { .. } | Expr::Const { .. } | Expr::Param { .. } | Expr::Cond { .. } => {
+                ValueRange::default()
+            }
             Expr::Apply { op, args, .. } => match op.name {
                 n if n == OP_GE.name || n == OP_GT.name => {
                     if let Some(symb) = args[0].get_binding() {
@@ -606,6 +736,19 @@ impl Expr {
             }
         })
     }
+    /// Whether the expression refers to parameters of a prepared query
+    pub(crate) fn has_params(&self) -> bool {
+        match self {
+            Expr::Param { .. } => true,
+            Expr::Binding { .. } | Expr::Const { .. } => false,
+            Expr::Apply { args, .. } | Expr::UnboundApply { args, .. } => {
+                args.iter().any(|arg| arg.has_params())
+            }
+            Expr::Cond { clauses, .. } => clauses
+                .iter()
+                .any(|(cond, val)| cond.has_params() || val.has_params()),
+        }
+    }
     pub(crate) fn get_variables(&self) -> Result<BTreeSet<String>> {
         let mut ret = BTreeSet::new();
         self.do_get_variables(&mut ret)?;
@@ -616,7 +759,7 @@ impl Expr {
             Expr::Binding { var, .. } => {
                 coll.insert(var.to_string());
             }
-            Expr::Const { .. } => {}
+            Expr::Const { .. } | Expr::Param { .. } => {}
             Expr::Apply { args, .. } => {
                 for arg in args.iter() {
                     arg.do_get_variables(coll)?;
@@ -800,6 +943,7 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "parse_json" => &OP_PARSE_JSON,
         "dump_json" => &OP_DUMP_JSON,
         "json_object" => &OP_JSON_OBJECT,
+        "json_merge_patch" => &OP_JSON_MERGE_PATCH,
         "is_json" => &OP_IS_JSON,
         "json_to_scalar" => &OP_JSON_TO_SCALAR,
         "add" => &OP_ADD,
@@ -182,6 +182,35 @@ pub(crate) fn op_remove_json_path(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Json(JsonData(result)))
 }
 
+define_op!(OP_JSON_MERGE_PATCH, 2, false);
+pub(crate) fn op_json_merge_patch(args: &[DataValue]) -> Result<DataValue> {
+    let target = to_json(&args[0]);
+    let patch = to_json(&args[1]);
+    Ok(DataValue::Json(JsonData(json_merge_patch(target, patch))))
+}
+
+/// Apply `patch` to `target` following RFC 7396
+fn json_merge_patch(target: JsonValue, patch: JsonValue) -> JsonValue {
+    match patch {
+        JsonValue::Object(patch) => {
+            let mut target = match target {
+                JsonValue::Object(obj) => obj,
+                _ => serde_json::Map::new(),
+            };
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    let existing = target.remove(&key).unwrap_or(JsonValue::Null);
+                    target.insert(key, json_merge_patch(existing, value));
+                }
+            }
+            JsonValue::Object(target)
+        }
+        patch => patch,
+    }
+}
+
 define_op!(OP_JSON_OBJECT, 0, true);
 pub(crate) fn op_json_object(args: &[DataValue]) -> Result<DataValue> {
     ensure!(
@@ -368,4 +368,152 @@ impl DataValue {
     }
 }
 
+/// The checked counterparts of the decoders above, returning `None` on malformed input
+/// instead of panicking. They are slower, and only used to verify stored data.
+#[cfg(feature = "storage-sqlite")]
+mod checked {
+    use super::*;
+
+    fn split_at(bs: &[u8], mid: usize) -> Option<(&[u8], &[u8])> {
+        (bs.len() >= mid).then(|| bs.split_at(mid))
+    }
+
+    fn try_decode_bytes(data: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+        let mut key = vec![];
+        let mut remaining = data;
+        loop {
+            let (chunk, next) = split_at(remaining, ENC_GROUP_SIZE + 1)?;
+            remaining = next;
+            let (&marker, bytes) = chunk.split_last().unwrap();
+            let pad_size = (ENC_MARKER - marker) as usize;
+            if pad_size == 0 {
+                key.extend_from_slice(bytes);
+                continue;
+            }
+            if pad_size > ENC_GROUP_SIZE {
+                return None;
+            }
+            let (bytes, padding) = bytes.split_at(ENC_GROUP_SIZE - pad_size);
+            if padding.iter().any(|x| *x != 0) {
+                return None;
+            }
+            key.extend_from_slice(bytes);
+            return Some((key, remaining));
+        }
+    }
+
+    fn try_decode_num(bs: &[u8]) -> Option<(Num, &[u8])> {
+        let (float_part, remaining) = split_at(bs, 8)?;
+        let f = order_decode_f64(BigEndian::read_u64(float_part));
+        let (tag, remaining) = remaining.split_first()?;
+        match *tag {
+            IS_FLOAT => Some((Num::Float(f), remaining)),
+            IS_EXACT_INT => Some((Num::Int(f as i64), remaining)),
+            IS_APPROX_INT => {
+                let (int_part, remaining) = split_at(remaining, 8)?;
+                let i = order_decode_i64(BigEndian::read_u64(int_part));
+                Some((Num::Int(i), remaining))
+            }
+            _ => None,
+        }
+    }
+
+    fn try_decode_vec(bs: &[u8]) -> Option<(Vector, &[u8])> {
+        let (t_tag, remaining) = bs.split_first()?;
+        let (len_bytes, rest) = split_at(remaining, 8)?;
+        let len = BigEndian::read_u64(len_bytes) as usize;
+        let width = match *t_tag {
+            VEC_F32 => 4,
+            VEC_F64 => 8,
+            _ => return None,
+        };
+        let (data, rest) = split_at(rest, len.checked_mul(width)?)?;
+        let vec = if width == 4 {
+            Vector::F32(data.chunks(4).map(BigEndian::read_f32).collect())
+        } else {
+            Vector::F64(data.chunks(8).map(BigEndian::read_f64).collect())
+        };
+        Some((vec, rest))
+    }
+
+    impl DataValue {
+        pub(crate) fn try_decode_from_key(bs: &[u8]) -> Option<(Self, &[u8])> {
+            let (tag, remaining) = bs.split_first()?;
+            Some(match *tag {
+                NULL_TAG => (DataValue::Null, remaining),
+                FALSE_TAG => (DataValue::from(false), remaining),
+                TRUE_TAG => (DataValue::from(true), remaining),
+                NUM_TAG => {
+                    let (n, remaining) = try_decode_num(remaining)?;
+                    (DataValue::Num(n), remaining)
+                }
+                STR_TAG => {
+                    let (bytes, remaining) = try_decode_bytes(remaining)?;
+                    let s = String::from_utf8(bytes).ok()?;
+                    (DataValue::Str(s.into()), remaining)
+                }
+                JSON_TAG => {
+                    let (bytes, remaining) = try_decode_bytes(remaining)?;
+                    let json = serde_json::from_slice(&bytes).ok()?;
+                    (DataValue::Json(JsonData(json)), remaining)
+                }
+                BYTES_TAG => {
+                    let (bytes, remaining) = try_decode_bytes(remaining)?;
+                    (DataValue::Bytes(bytes), remaining)
+                }
+                UUID_TAG => {
+                    let (uuid_data, remaining) = split_at(remaining, 16)?;
+                    let mut s_rest = [0u8; 8];
+                    s_rest.copy_from_slice(&uuid_data[8..]);
+                    let uuid = uuid::Uuid::from_fields(
+                        BigEndian::read_u32(&uuid_data[4..8]),
+                        BigEndian::read_u16(&uuid_data[2..4]),
+                        BigEndian::read_u16(&uuid_data[0..2]),
+                        &s_rest,
+                    );
+                    (DataValue::Uuid(UuidWrapper(uuid)), remaining)
+                }
+                REGEX_TAG => {
+                    let (bytes, remaining) = try_decode_bytes(remaining)?;
+                    let rx = Regex::new(std::str::from_utf8(&bytes).ok()?).ok()?;
+                    (DataValue::Regex(RegexWrapper(rx)), remaining)
+                }
+                LIST_TAG | SET_TAG => {
+                    let mut collected = vec![];
+                    let mut remaining = remaining;
+                    while *remaining.first()? != INIT_TAG {
+                        let (val, next_chunk) = DataValue::try_decode_from_key(remaining)?;
+                        remaining = next_chunk;
+                        collected.push(val);
+                    }
+                    let val = if *tag == LIST_TAG {
+                        DataValue::List(collected)
+                    } else {
+                        DataValue::Set(collected.into_iter().collect())
+                    };
+                    (val, &remaining[1..])
+                }
+                VLD_TAG => {
+                    let (ts_flipped_bytes, rest) = split_at(remaining, 8)?;
+                    let ts = order_decode_i64(!BigEndian::read_u64(ts_flipped_bytes));
+                    let (is_assert_byte, rest) = rest.split_first()?;
+                    (
+                        DataValue::Validity(Validity {
+                            timestamp: ValidityTs(Reverse(ts)),
+                            is_assert: Reverse(*is_assert_byte == 0),
+                        }),
+                        rest,
+                    )
+                }
+                BOT_TAG => (DataValue::Bot, remaining),
+                VEC_TAG => {
+                    let (vec, remaining) = try_decode_vec(remaining)?;
+                    (DataValue::Vec(vec), remaining)
+                }
+                _ => return None,
+            })
+        }
+    }
+}
+
 impl<T: Write> MemCmpEncoder for T {}
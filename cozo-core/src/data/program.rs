@@ -6,11 +6,13 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::borrow::Cow;
 use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::Arc;
 
+use itertools::Itertools;
 use miette::{bail, ensure, miette, Diagnostic, Result};
 use smallvec::SmallVec;
 use smartstring::{LazyCompact, SmartString};
@@ -20,9 +22,10 @@ use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
 use crate::data::relation::StoredRelationMetadata;
 use crate::data::symb::{Symbol, PROG_ENTRY};
-use crate::data::value::{DataValue, ValidityTs};
+use crate::data::value::{DataValue, Num, ValidityTs};
 use crate::fixed_rule::{FixedRule, FixedRuleHandle};
 use crate::fts::FtsIndexManifest;
+use crate::parse::query::eval_count_option;
 use crate::parse::SourceSpan;
 use crate::query::compile::ContainedRuleMultiplicity;
 use crate::query::logical::{Disjunction, NamedFieldNotFound};
@@ -33,11 +36,15 @@ use crate::runtime::relation::{
 };
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum QueryAssertion {
     AssertNone(SourceSpan),
     AssertSome(SourceSpan),
+    /// The query returns exactly this many rows, counted before `:limit` and `:offset`
+    AssertCount(usize, SourceSpan),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -46,17 +53,90 @@ pub(crate) enum ReturnMutation {
     Returning,
 }
 
+/// How floats in the result of a query are written, set by the `:float_fmt` option
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum FloatFormat {
+    /// As strings holding the shortest decimal representation that parses back
+    /// to the same float, so that no consumer of the result can change their values
+    String,
+    /// Rounded to this many decimal places, at most [MAX_FLOAT_FMT_PLACES].
+    /// They remain floats, and are written to JSON with no more places than this.
+    Fixed(usize),
+}
+
+/// Floats have no more than 15 significant decimal digits that survive a round trip
+pub(crate) const MAX_FLOAT_FMT_PLACES: usize = 15;
+
+impl Display for FloatFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FloatFormat::String => write!(f, "'string'"),
+            FloatFormat::Fixed(places) => write!(f, "{places}"),
+        }
+    }
+}
+
+impl FloatFormat {
+    /// Format the floats of the rows, including those in lists
+    pub(crate) fn apply(self, rows: &mut NamedRows) {
+        for row in rows.rows.iter_mut() {
+            for val in row.iter_mut() {
+                self.apply_to_value(val);
+            }
+        }
+    }
+    fn apply_to_value(self, val: &mut DataValue) {
+        match val {
+            DataValue::Num(Num::Float(n)) => {
+                *val = match self {
+                    FloatFormat::String => DataValue::from(n.to_string()),
+                    // the float nearest to the rounded decimal prints as that decimal
+                    FloatFormat::Fixed(places) => {
+                        DataValue::from(format!("{n:.places$}").parse::<f64>().unwrap())
+                    }
+                };
+            }
+            DataValue::List(l) => {
+                for v in l.iter_mut() {
+                    self.apply_to_value(v);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Default)]
 pub(crate) struct QueryOutOptions {
     pub(crate) limit: Option<usize>,
     pub(crate) offset: Option<usize>,
+    /// `:limit` and `:offset` given by parameters of a prepared query, see [Self::resolve_params]
+    pub(crate) paging_params: Option<Box<PagingParams>>,
     pub(crate) timeout: Option<f64>,
     pub(crate) sleep: Option<f64>,
+    pub(crate) max_fanout: Option<f64>,
+    pub(crate) max_join_matches: Option<usize>,
+    pub(crate) max_eval_threads: Option<usize>,
+    pub(crate) mem_limit: Option<usize>,
+    pub(crate) float_fmt: Option<FloatFormat>,
+    /// Set by `:profile`: report the time taken by compilation and by each stratum
+    pub(crate) profile: bool,
+    /// Set by `:count`: return only the number of rows, not the rows themselves
+    pub(crate) count: bool,
+    /// Set by `:with_types`: return the types of the columns with the rows
+    pub(crate) with_types: bool,
     pub(crate) sorters: Vec<(Symbol, SortDir)>,
     pub(crate) store_relation: Option<(InputRelationHandle, RelationOp, ReturnMutation)>,
     pub(crate) assertion: Option<QueryAssertion>,
 }
 
+/// `:limit` and `:offset` options whose values are only known once the parameters are bound
+#[derive(Clone, PartialEq, Default)]
+pub(crate) struct PagingParams {
+    pub(crate) limit: Option<Expr>,
+    pub(crate) offset: Option<Expr>,
+}
+
 impl Debug for QueryOutOptions {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{self}")
@@ -71,9 +151,41 @@ impl Display for QueryOutOptions {
         if let Some(l) = self.offset {
             writeln!(f, ":offset {l};")?;
         }
+        if let Some(params) = &self.paging_params {
+            if let Some(l) = &params.limit {
+                writeln!(f, ":limit {l};")?;
+            }
+            if let Some(l) = &params.offset {
+                writeln!(f, ":offset {l};")?;
+            }
+        }
         if let Some(l) = self.timeout {
             writeln!(f, ":timeout {l};")?;
         }
+        if let Some(l) = self.max_fanout {
+            writeln!(f, ":max_fanout {l};")?;
+        }
+        if let Some(l) = self.max_join_matches {
+            writeln!(f, ":max_join_matches {l};")?;
+        }
+        if let Some(l) = self.max_eval_threads {
+            writeln!(f, ":max_eval_threads {l};")?;
+        }
+        if let Some(l) = self.mem_limit {
+            writeln!(f, ":mem_limit {l};")?;
+        }
+        if let Some(l) = self.float_fmt {
+            writeln!(f, ":float_fmt {l};")?;
+        }
+        if self.profile {
+            writeln!(f, ":profile;")?;
+        }
+        if self.count {
+            writeln!(f, ":count;")?;
+        }
+        if self.with_types {
+            writeln!(f, ":with_types;")?;
+        }
         for (symb, dir) in &self.sorters {
             write!(f, ":order ")?;
             if *dir == SortDir::Dsc {
@@ -96,35 +208,7 @@ impl Display for QueryOutOptions {
             if *return_mutation == ReturnMutation::Returning {
                 writeln!(f, ":returning")?;
             }
-            match op {
-                RelationOp::Create => {
-                    write!(f, ":create ")?;
-                }
-                RelationOp::Replace => {
-                    write!(f, ":replace ")?;
-                }
-                RelationOp::Insert => {
-                    write!(f, ":insert ")?;
-                }
-                RelationOp::Put => {
-                    write!(f, ":put ")?;
-                }
-                RelationOp::Update => {
-                    write!(f, ":update ")?;
-                }
-                RelationOp::Rm => {
-                    write!(f, ":rm ")?;
-                }
-                RelationOp::Delete => {
-                    write!(f, ":delete ")?;
-                }
-                RelationOp::Ensure => {
-                    write!(f, ":ensure ")?;
-                }
-                RelationOp::EnsureNot => {
-                    write!(f, ":ensure_not ")?;
-                }
-            }
+            write!(f, "{} ", op.command())?;
             write!(f, "{name} {{")?;
             let mut is_first = true;
             for (col, bind) in keys.iter().zip(key_bindings) {
@@ -166,6 +250,9 @@ impl Display for QueryOutOptions {
                 QueryAssertion::AssertSome(_) => {
                     writeln!(f, ":assert some;")?;
                 }
+                QueryAssertion::AssertCount(n, _) => {
+                    writeln!(f, ":assert count = {n};")?;
+                }
             }
         }
 
@@ -174,7 +261,27 @@ impl Display for QueryOutOptions {
 }
 
 impl QueryOutOptions {
+    /// The options with `:limit` and `:offset` given by parameters of a prepared query
+    /// replaced by their values, which must have been set in the parameter slots.
+    pub(crate) fn resolve_params(&self) -> Result<Cow<'_, Self>> {
+        let mut ret = match &self.paging_params {
+            None => return Ok(Cow::Borrowed(self)),
+            Some(_) => self.clone(),
+        };
+        let params = ret.paging_params.take().unwrap();
+        if let Some(expr) = params.limit {
+            ret.limit = Some(eval_count_option("limit", expr)?);
+        }
+        if let Some(expr) = params.offset {
+            ret.offset = Some(eval_count_option("offset", expr)?);
+        }
+        Ok(Cow::Owned(ret))
+    }
     pub(crate) fn num_to_take(&self) -> Option<usize> {
+        // all rows must be derived to be counted
+        if let Some(QueryAssertion::AssertCount(..)) = self.assertion {
+            return None;
+        }
         match (self.limit, self.offset) {
             (None, _) => None,
             (Some(i), None) => Some(i),
@@ -193,6 +300,9 @@ pub(crate) enum SortDir {
 pub(crate) enum RelationOp {
     Create,
     Replace,
+    /// Create the relation if absent, otherwise replace its rows but keep the relation
+    /// itself (triggers, access level, etc.)
+    CreateOrReplace,
     Put,
     Insert,
     Update,
@@ -202,6 +312,24 @@ pub(crate) enum RelationOp {
     EnsureNot,
 }
 
+impl RelationOp {
+    /// The query option making the write, e.g. `:put`
+    pub(crate) fn command(&self) -> &'static str {
+        match self {
+            RelationOp::Create => ":create",
+            RelationOp::Replace => ":replace",
+            RelationOp::CreateOrReplace => ":create_or_replace",
+            RelationOp::Put => ":put",
+            RelationOp::Insert => ":insert",
+            RelationOp::Update => ":update",
+            RelationOp::Rm => ":rm",
+            RelationOp::Delete => ":delete",
+            RelationOp::Ensure => ":ensure",
+            RelationOp::EnsureNot => ":ensure_not",
+        }
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct TempSymbGen {
     last_id: u32,
@@ -465,6 +593,8 @@ pub(crate) struct InputProgram {
     pub(crate) prog: BTreeMap<Symbol, InputInlineRulesOrFixed>,
     pub(crate) out_opts: QueryOutOptions,
     pub(crate) disable_magic_rewrite: bool,
+    /// From the first rule or option of the query to the last one
+    pub(crate) span: SourceSpan,
 }
 
 impl Display for InputProgram {
@@ -567,6 +697,102 @@ impl InputProgram {
         }
     }
 
+    /// Describes the write this program makes to a stored relation, if any.
+    /// Temp relations live only as long as the script and do not count.
+    pub(crate) fn find_mutation(&self) -> Option<String> {
+        self.out_opts
+            .store_relation
+            .as_ref()
+            .filter(|(h, _, _)| !h.name.is_temp_store_name())
+            .map(|(h, _, _)| format!("writes to relation '{}'", h.name))
+    }
+
+    /// Names of all stored relations read by the program
+    pub(crate) fn stored_relations(&self) -> BTreeSet<SmartString<LazyCompact>> {
+        let mut coll = BTreeSet::new();
+        for rules_or_fixed in self.prog.values() {
+            match rules_or_fixed {
+                InputInlineRulesOrFixed::Rules { rules } => {
+                    for rule in rules {
+                        for atom in &rule.body {
+                            atom.collect_stored_relations(&mut coll);
+                        }
+                    }
+                }
+                InputInlineRulesOrFixed::Fixed { fixed } => {
+                    for arg in &fixed.rule_args {
+                        match arg {
+                            FixedRuleArg::InMem { .. } => {}
+                            FixedRuleArg::Stored { name, .. }
+                            | FixedRuleArg::NamedStored { name, .. } => {
+                                coll.insert(name.name.clone());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        coll
+    }
+
+    /// Rules that cannot be reached from the entry rule, in source order.
+    /// These rules are never evaluated.
+    pub(crate) fn unused_rules(&self) -> Vec<&Symbol> {
+        let mut reached: BTreeSet<&Symbol> = BTreeSet::new();
+        let mut stack = vec![];
+        if let Some((entry, _)) = self
+            .prog
+            .get_key_value(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0)))
+        {
+            reached.insert(entry);
+            stack.push(entry);
+        }
+        while let Some(name) = stack.pop() {
+            let mut used = BTreeSet::new();
+            match self.prog.get(name) {
+                Some(InputInlineRulesOrFixed::Rules { rules }) => {
+                    for rule in rules {
+                        for atom in &rule.body {
+                            atom.collect_rules(&mut used);
+                        }
+                    }
+                }
+                Some(InputInlineRulesOrFixed::Fixed { fixed }) => {
+                    for arg in &fixed.rule_args {
+                        if let FixedRuleArg::InMem { name, .. } = arg {
+                            used.insert(name);
+                        }
+                    }
+                }
+                None => {}
+            }
+            for used_name in used {
+                if let Some((k, _)) = self.prog.get_key_value(used_name) {
+                    if reached.insert(k) {
+                        stack.push(k);
+                    }
+                }
+            }
+        }
+        self.prog
+            .keys()
+            .filter(|k| !reached.contains(k))
+            .sorted_by_key(|k| k.span.0)
+            .collect_vec()
+    }
+
+    pub(crate) fn unused_rule_warnings(&self) -> Vec<String> {
+        self.unused_rules()
+            .into_iter()
+            .map(|rule| {
+                format!(
+                    "Rule '{}' at {} is never used by the query",
+                    rule, rule.span
+                )
+            })
+            .collect_vec()
+    }
+
     pub(crate) fn get_entry_arity(&self) -> Result<usize> {
         if let Some(entry) = self.prog.get(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0))) {
             return match entry {
@@ -956,6 +1182,50 @@ pub(crate) enum InputAtom {
     },
 }
 
+impl InputAtom {
+    fn collect_rules<'a>(&'a self, coll: &mut BTreeSet<&'a Symbol>) {
+        match self {
+            InputAtom::Rule { inner } => {
+                coll.insert(&inner.name);
+            }
+            InputAtom::Negation { inner, .. } => inner.collect_rules(coll),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for atom in inner {
+                    atom.collect_rules(coll);
+                }
+            }
+            InputAtom::NamedFieldRelation { .. }
+            | InputAtom::Relation { .. }
+            | InputAtom::Search { .. }
+            | InputAtom::Predicate { .. }
+            | InputAtom::Unification { .. } => {}
+        }
+    }
+
+    fn collect_stored_relations(&self, coll: &mut BTreeSet<SmartString<LazyCompact>>) {
+        match self {
+            InputAtom::NamedFieldRelation { inner } => {
+                coll.insert(inner.name.name.clone());
+            }
+            InputAtom::Relation { inner } => {
+                coll.insert(inner.name.name.clone());
+            }
+            InputAtom::Search { inner } => {
+                coll.insert(inner.relation.name.clone());
+            }
+            InputAtom::Negation { inner, .. } => inner.collect_stored_relations(coll),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for atom in inner {
+                    atom.collect_stored_relations(coll);
+                }
+            }
+            InputAtom::Rule { .. }
+            | InputAtom::Predicate { .. }
+            | InputAtom::Unification { .. } => {}
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct SearchInput {
     pub(crate) relation: Symbol,
@@ -123,6 +123,29 @@ pub(crate) struct StoredRelationMetadata {
     pub(crate) non_keys: Vec<ColumnDef>,
 }
 
+impl Display for ColumnDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.typing)?;
+        if let Some(gen) = &self.default_gen {
+            f.write_str(" default ")?;
+            gen.fmt_script(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats as a table schema, e.g. `{k: Int => v: String default ""}`,
+/// which is accepted back by `:create` and by the `schema` of imported data.
+impl Display for StoredRelationMetadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{{}", self.keys.iter().join(", "))?;
+        if !self.non_keys.is_empty() {
+            write!(f, " => {}", self.non_keys.iter().join(", "))?;
+        }
+        write!(f, "}}")
+    }
+}
+
 impl StoredRelationMetadata {
     pub(crate) fn satisfied_by_required_col(&self, col: &ColumnDef) -> Result<()> {
         for target in self.keys.iter().chain(self.non_keys.iter()) {
@@ -30,3 +30,47 @@ fn expression_eval() {
         .unwrap();
     assert_eq!(res.rows[0][0].get_bool().unwrap(), true);
 }
+
+#[test]
+fn case_expression() {
+    let db = DbInstance::default();
+
+    let res = db
+        .run_default(
+            r#"
+    ?[x, bucket] := x in [1, 50, 500],
+                    bucket = case when x < 10 then 'small'
+                                  when x < 100 then 'medium'
+                                  else 'large' end
+    "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![DataValue::from(1), DataValue::from("small")],
+            vec![DataValue::from(50), DataValue::from("medium")],
+            vec![DataValue::from(500), DataValue::from("large")],
+        ]
+    );
+
+    let res = db
+        .run_default(
+            r#"
+    ?[x] := x in [1, 2, 3], case when x == 2 then false else true end
+    "#,
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from(1)], vec![DataValue::from(3)]]
+    );
+
+    let res = db
+        .run_default("?[a] := a = case when 1 > 2 then 'yes' end")
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::Null);
+
+    let res = db.run_default("?[case] := case = 1 + 1").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(2));
+}
@@ -12,7 +12,7 @@ use regex::Regex;
 use serde_json::json;
 
 use crate::data::functions::*;
-use crate::data::value::{DataValue, RegexWrapper};
+use crate::data::value::{DataValue, JsonData, RegexWrapper};
 use crate::DbInstance;
 
 #[test]
@@ -1487,3 +1487,42 @@ fn test_range() {
         .into_json();
     assert_eq!(res["rows"][0][0], json!([15, 13, 11, 9, 7, 5]));
 }
+
+#[test]
+fn test_json_merge_patch() {
+    let j = |v| DataValue::Json(JsonData(v));
+    let patched = op_json_merge_patch(&[
+        j(json!({"title": "Goodbye!", "author": {"givenName": "John", "familyName": "Doe"}, "tags": ["example", "sample"], "content": "This will be unchanged"})),
+        j(json!({"title": "Hello!", "phoneNumber": "+01-123-456-7890", "author": {"familyName": null}, "tags": ["example"]})),
+    ])
+    .unwrap();
+    assert_eq!(
+        patched,
+        j(
+            json!({"title": "Hello!", "author": {"givenName": "John"}, "tags": ["example"], "content": "This will be unchanged", "phoneNumber": "+01-123-456-7890"})
+        )
+    );
+    assert_eq!(
+        op_json_merge_patch(&[DataValue::Null, j(json!({"a": {"b": null}}))]).unwrap(),
+        j(json!({"a": {}}))
+    );
+    assert_eq!(
+        op_json_merge_patch(&[j(json!({"a": 1})), j(json!([1]))]).unwrap(),
+        j(json!([1]))
+    );
+
+    let db = DbInstance::default();
+    db.run_default(
+        r#"?[id, doc] <- [[1, json({"a": 1, "b": {"c": 2}})]] :create docs {id => doc}"#,
+    )
+    .unwrap();
+    db.run_default(
+        r#"
+        ?[id, doc] := *docs{id, doc: old}, id = 1, doc = json_merge_patch(old, json({"b": {"c": null, "d": 3}}))
+        :put docs {id => doc}
+        "#,
+    )
+    .unwrap();
+    let res = db.run_default("?[doc] := *docs{doc}").unwrap().into_json();
+    assert_eq!(res["rows"][0][0], json!({"a": 1, "b": {"d": 3}}));
+}
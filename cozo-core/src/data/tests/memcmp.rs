@@ -135,3 +135,30 @@ fn encode_decode_datavalues() {
     assert!(remaining.is_empty());
     assert_eq!(decoded, v);
 }
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn checked_decode_datavalues() {
+    use crate::data::value::Vector;
+
+    let v = DataValue::List(vec![
+        DataValue::from(i64::MIN),
+        DataValue::from(-0.5),
+        DataValue::from("MSS"),
+        DataValue::Bytes(vec![1, 2, 3, 4, 5, 6, 7, 8, 9]),
+        DataValue::Uuid(UuidWrapper(Uuid::from_u128(0x1234_5678_9abc_def0))),
+        DataValue::Vec(Vector::F32(ndarray::Array1::from(vec![1.0, 2.5]))),
+        DataValue::Validity((10, true).into()),
+        DataValue::Bot,
+    ]);
+    let mut encoded = vec![];
+    encoded.encode_datavalue(&v);
+    let (decoded, remaining) = DataValue::try_decode_from_key(&encoded).unwrap();
+    assert!(remaining.is_empty());
+    assert_eq!(decoded, v);
+
+    for len in 0..encoded.len() {
+        assert!(DataValue::try_decode_from_key(&encoded[..len]).is_none());
+    }
+    assert!(DataValue::try_decode_from_key(&[0x42]).is_none());
+}
@@ -19,6 +19,11 @@ pub type Tuple = Vec<DataValue>;
 pub(crate) type TupleIter<'a> = Box<dyn Iterator<Item = Result<Tuple>> + 'a>;
 
 pub(crate) trait TupleT {
+    /// Encode the tuple as a full, memcmp-ordered storage key.
+    ///
+    /// Keys are always stored in full: every key must be seekable and comparable on its own
+    /// for point lookups and range scans, so sharing prefixes between consecutive keys
+    /// (front-coding) is left to the storage engine, e.g. the delta-encoded blocks of RocksDB.
     fn encode_as_key(&self, prefix: RelationId) -> Vec<u8>;
 }
 
@@ -49,6 +54,19 @@ pub fn decode_tuple_from_key(key: &[u8], size_hint: usize) -> Tuple {
     ret
 }
 
+/// Like [decode_tuple_from_key], but returns `None` if the key is malformed
+#[cfg(feature = "storage-sqlite")]
+pub(crate) fn try_decode_tuple_from_key(key: &[u8]) -> Option<Tuple> {
+    let mut remaining = key.get(ENCODED_KEY_MIN_LEN..)?;
+    let mut ret = vec![];
+    while !remaining.is_empty() {
+        let (val, next) = DataValue::try_decode_from_key(remaining)?;
+        ret.push(val);
+        remaining = next;
+    }
+    Some(ret)
+}
+
 const DEFAULT_SIZE_HINT: usize = 16;
 
 /// Check if the tuple key passed in should be a valid return for a validity query.
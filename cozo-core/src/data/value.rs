@@ -627,7 +627,108 @@ impl Display for DataValue {
     }
 }
 
+/// Writes `s` as a single-quoted CozoScript string literal. Double-quoted literals
+/// cannot be used, since those without escapes are taken as raw strings.
+fn write_script_str(f: &mut Formatter<'_>, s: &str) -> std::fmt::Result {
+    f.write_str("'")?;
+    for c in s.chars() {
+        match c {
+            '\'' => f.write_str("\\'")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("'")
+}
+
+/// Writes `x` as a CozoScript float literal, which needs a `.` or an exponent
+fn write_script_float(f: &mut Formatter<'_>, x: f64) -> std::fmt::Result {
+    if x.is_nan() {
+        f.write_str("to_float('NAN')")
+    } else if x == f64::INFINITY {
+        f.write_str("to_float('INF')")
+    } else if x == f64::NEG_INFINITY {
+        f.write_str("to_float('NEG_INF')")
+    } else {
+        write!(f, "{x:?}")
+    }
+}
+
 impl DataValue {
+    /// Writes the value as a CozoScript expression that evaluates back to it,
+    /// unlike [Display], which is meant for reading
+    pub(crate) fn fmt_script(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let write_list = |f: &mut Formatter<'_>, vals: &mut dyn Iterator<Item = &DataValue>| {
+            f.write_str("[")?;
+            for (i, val) in vals.enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                val.fmt_script(f)?;
+            }
+            f.write_str("]")
+        };
+        match self {
+            DataValue::Null | DataValue::Bot => f.write_str("null"),
+            DataValue::Bool(b) => write!(f, "{b}"),
+            // the literal would overflow before being negated
+            DataValue::Num(Num::Int(i64::MIN)) => f.write_str("(-9223372036854775807 - 1)"),
+            DataValue::Num(Num::Int(i)) => write!(f, "{i}"),
+            DataValue::Num(Num::Float(x)) => write_script_float(f, *x),
+            DataValue::Str(s) => write_script_str(f, s),
+            DataValue::Bytes(b) => {
+                f.write_str("decode_base64(")?;
+                write_script_str(f, &STANDARD.encode(b))?;
+                f.write_str(")")
+            }
+            DataValue::Uuid(u) => write!(f, "to_uuid('{}')", u.0),
+            DataValue::Regex(rx) => {
+                f.write_str("regex(")?;
+                write_script_str(f, rx.0.as_str())?;
+                f.write_str(")")
+            }
+            DataValue::List(l) => write_list(f, &mut l.iter()),
+            DataValue::Set(s) => write_list(f, &mut s.iter()),
+            DataValue::Validity(v) => write!(f, "[{}, {}]", v.timestamp.0 .0, v.is_assert.0),
+            DataValue::Vec(v) => {
+                f.write_str("vec([")?;
+                let (xs, suffix): (Vec<f64>, _) = match v {
+                    Vector::F32(a) => (a.iter().map(|x| *x as f64).collect(), ""),
+                    Vector::F64(a) => (a.to_vec(), ", 'F64'"),
+                };
+                for (i, x) in xs.into_iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write_script_float(f, x)?;
+                }
+                write!(f, "]{suffix})")
+            }
+            DataValue::Json(j) => {
+                f.write_str("parse_json(")?;
+                write_script_str(f, &j.0.to_string())?;
+                f.write_str(")")
+            }
+        }
+    }
+    /// Approximate number of bytes the value occupies in memory, including its heap allocations
+    pub(crate) fn estimated_size(&self) -> usize {
+        let heap = match self {
+            DataValue::Str(s) if !s.is_inline() => s.len(),
+            DataValue::Bytes(b) => b.len(),
+            DataValue::List(l) => l.iter().map(|v| v.estimated_size()).sum(),
+            DataValue::Set(s) => s.iter().map(|v| v.estimated_size()).sum(),
+            DataValue::Vec(Vector::F32(a)) => a.len() * std::mem::size_of::<f32>(),
+            DataValue::Vec(Vector::F64(a)) => a.len() * std::mem::size_of::<f64>(),
+            DataValue::Json(JsonData(j)) => j.to_string().len(),
+            _ => 0,
+        };
+        std::mem::size_of::<DataValue>() + heap
+    }
     /// Returns a slice of bytes if this one is a Bytes
     pub fn get_bytes(&self) -> Option<&[u8]> {
         match self {
@@ -32,12 +32,14 @@
 #![allow(clippy::too_many_arguments)]
 
 use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
 use std::path::Path;
 use std::thread;
 #[allow(unused_imports)]
 use std::time::Instant;
+use std::time::Duration;
 
-use crossbeam::channel::{bounded, Receiver, Sender};
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, Sender};
 use lazy_static::lazy_static;
 pub use miette::Error;
 use miette::Report;
@@ -52,6 +54,7 @@ pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, Valid
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
+pub use runtime::db::QueryReport;
 pub use runtime::relation::decode_tuple_from_kv;
 pub use runtime::temp_store::RegularTempStore;
 pub use storage::mem::{new_cozo_mem, MemStorage};
@@ -63,20 +66,35 @@ pub use storage::sled::{new_cozo_sled, SledStorage};
 pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
 #[cfg(feature = "storage-tikv")]
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
-pub use storage::{Storage, StoreTx};
+pub use storage::{CompactOptions, Durability, ScanHint, Storage, StorageInfo, StoreTx};
 
 pub use crate::data::expr::Expr;
 use crate::data::json::JsonValue;
 pub use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
 pub use crate::data::value::{JsonData, Vector};
 pub use crate::fixed_rule::SimpleFixedRule;
 pub use crate::parse::SourceSpan;
-pub use crate::runtime::callback::CallbackOp;
+pub use crate::runtime::callback::{CallbackOp, ChangeEvent};
+pub use crate::runtime::cursor::ResultCursor;
 pub use crate::runtime::db::evaluate_expressions;
 pub use crate::runtime::db::get_variables;
+pub use crate::runtime::db::AggrProgress;
+pub use crate::runtime::db::BACKUP_PROGRESS_INTERVAL;
+pub use crate::runtime::db::CozoError;
+pub use crate::runtime::db::EvalProgress;
+pub use crate::runtime::db::InvalidImportRow;
+pub use crate::runtime::db::NDJSON_IMPORT_BATCH_ROWS;
 pub use crate::runtime::db::Poison;
 pub use crate::runtime::db::ScriptMutability;
 pub use crate::runtime::db::TransactionPayload;
+pub use crate::runtime::explain::{ExplainOp, ExplainPlan, ExplainStats, ExplainStep, JoinKind};
+pub use crate::runtime::export::{ColumnOrder, ExportFormat, MAX_SORTED_EXPORT_ROWS};
+pub use crate::runtime::outline::{ParsedProgram, ParsedScript, ScriptKind};
+pub use crate::runtime::prepared::PreparedQuery;
+pub use crate::runtime::relation::{AccessLevel, ColumnSchema, RelationSchema};
+pub use crate::runtime::transaction::CozoTransaction;
+pub use crate::runtime::verify::{BackupStats, CorruptEntry};
 
 pub(crate) mod data;
 pub(crate) mod fixed_rule;
@@ -189,6 +207,372 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.run_script(payload, params, mutability),
         }
     }
+    /// Dispatcher method. See [crate::Db::run_script_with_report].
+    pub fn run_script_with_report(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<(NamedRows, QueryReport)> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_with_report(payload, params, mutability),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_with_report(payload, params, mutability),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_with_report(payload, params, mutability),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_with_report(payload, params, mutability),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_with_report(payload, params, mutability),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_cursor].
+    pub fn run_script_cursor(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<ResultCursor> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_cursor(payload, params, mutability),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_cursor(payload, params, mutability),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_cursor(payload, params, mutability),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_cursor(payload, params, mutability),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_cursor(payload, params, mutability),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_arrow].
+    #[cfg(feature = "arrow")]
+    pub fn run_script_arrow(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<Vec<u8>> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_arrow(payload, params, mutability),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_arrow(payload, params, mutability),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_arrow(payload, params, mutability),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_arrow(payload, params, mutability),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_arrow(payload, params, mutability),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::prepare].
+    pub fn prepare(&self, payload: &str) -> Result<PreparedQuery> {
+        match self {
+            DbInstance::Mem(db) => db.prepare(payload),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.prepare(payload),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.prepare(payload),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.prepare(payload),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.prepare(payload),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::execute].
+    pub fn execute(
+        &self,
+        prepared: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.execute(prepared, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.execute(prepared, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.execute(prepared, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.execute(prepared, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.execute(prepared, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::execute_with_report].
+    pub fn execute_with_report(
+        &self,
+        prepared: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<(NamedRows, QueryReport)> {
+        match self {
+            DbInstance::Mem(db) => db.execute_with_report(prepared, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.execute_with_report(prepared, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.execute_with_report(prepared, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.execute_with_report(prepared, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.execute_with_report(prepared, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_with_progress].
+    pub fn run_script_with_progress(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        on_progress: impl Fn(AggrProgress) + Send + Sync + 'static,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => {
+                db.run_script_with_progress(payload, params, mutability, on_progress)
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_script_with_progress(payload, params, mutability, on_progress)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_script_with_progress(payload, params, mutability, on_progress)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.run_script_with_progress(payload, params, mutability, on_progress)
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.run_script_with_progress(payload, params, mutability, on_progress)
+            }
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_with_cancel].
+    pub fn run_script_with_cancel(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        cancel: Poison,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_with_cancel(payload, params, mutability, cancel),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_script_with_cancel(payload, params, mutability, cancel)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_script_with_cancel(payload, params, mutability, cancel)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_with_cancel(payload, params, mutability, cancel),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_with_cancel(payload, params, mutability, cancel),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_default_mem_limit].
+    pub fn set_default_mem_limit(&self, bytes: Option<usize>) {
+        match self {
+            DbInstance::Mem(db) => db.set_default_mem_limit(bytes),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_default_mem_limit(bytes),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_default_mem_limit(bytes),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_default_mem_limit(bytes),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_default_mem_limit(bytes),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_max_concurrent_queries].
+    pub fn set_max_concurrent_queries(&self, n: Option<usize>) {
+        match self {
+            DbInstance::Mem(db) => db.set_max_concurrent_queries(n),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_max_concurrent_queries(n),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_max_concurrent_queries(n),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_max_concurrent_queries(n),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_max_concurrent_queries(n),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_write_lock_timeout].
+    pub fn set_write_lock_timeout(&self, secs: f64) {
+        match self {
+            DbInstance::Mem(db) => db.set_write_lock_timeout(secs),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_write_lock_timeout(secs),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_write_lock_timeout(secs),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_write_lock_timeout(secs),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_write_lock_timeout(secs),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_scan_readahead].
+    pub fn set_scan_readahead(&self, bytes: usize) {
+        match self {
+            DbInstance::Mem(db) => db.set_scan_readahead(bytes),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_scan_readahead(bytes),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_scan_readahead(bytes),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_scan_readahead(bytes),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_scan_readahead(bytes),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_durability].
+    pub fn set_durability(&self, durability: Durability) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.set_durability(durability),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_durability(durability),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_durability(durability),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_durability(durability),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_durability(durability),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::flush].
+    pub fn flush(&self) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.flush(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.flush(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.flush(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.flush(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.flush(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_default_timeout].
+    pub fn set_default_timeout(&self, secs: f64) {
+        match self {
+            DbInstance::Mem(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_default_timeout(secs),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_default_timeout(secs),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::describe_relation].
+    pub fn describe_relation(&self, name: &str) -> Result<RelationSchema> {
+        match self {
+            DbInstance::Mem(db) => db.describe_relation(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.describe_relation(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.describe_relation(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.describe_relation(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.describe_relation(name),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_default_access_level].
+    pub fn set_default_access_level(&self, level: AccessLevel) {
+        match self {
+            DbInstance::Mem(db) => db.set_default_access_level(level),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_default_access_level(level),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_default_access_level(level),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_default_access_level(level),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_default_access_level(level),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_with_query_id].
+    pub fn run_script_with_query_id(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        on_start: impl FnOnce(u64) + Send + Sync + 'static,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => {
+                db.run_script_with_query_id(payload, params, mutability, on_start)
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_script_with_query_id(payload, params, mutability, on_start)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_script_with_query_id(payload, params, mutability, on_start)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.run_script_with_query_id(payload, params, mutability, on_start)
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.run_script_with_query_id(payload, params, mutability, on_start)
+            }
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_read_only].
+    pub fn run_script_read_only(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_read_only(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_read_only(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_read_only(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_read_only(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_read_only(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_script_with_eval_progress].
+    pub fn run_script_with_eval_progress(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        on_progress: impl Fn(EvalProgress) -> bool + Send + Sync + 'static,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => {
+                db.run_script_with_eval_progress(payload, params, mutability, on_progress)
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.run_script_with_eval_progress(payload, params, mutability, on_progress)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.run_script_with_eval_progress(payload, params, mutability, on_progress)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.run_script_with_eval_progress(payload, params, mutability, on_progress)
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.run_script_with_eval_progress(payload, params, mutability, on_progress)
+            }
+        }
+    }
     /// `run_script` with mutable script and no parameters
     pub fn run_default(&self, payload: &str) -> Result<NamedRows> {
         self.run_script(payload, BTreeMap::new(), ScriptMutability::Mutable)
@@ -205,9 +589,10 @@ impl DbInstance {
         #[cfg(not(target_arch = "wasm32"))]
             let start = Instant::now();
 
-        match self.run_script(payload, params, mutability) {
-            Ok(named_rows) => {
+        match self.run_script_with_report(payload, params, mutability) {
+            Ok((named_rows, report)) => {
                 let mut j_val = named_rows.into_json();
+                report.extend_json(&mut j_val);
                 #[cfg(not(target_arch = "wasm32"))]
                     let took = start.elapsed().as_secs_f64();
                 let map = j_val.as_object_mut().unwrap();
@@ -220,7 +605,37 @@ impl DbInstance {
             Err(err) => format_error_as_json(err, Some(payload)),
         }
     }
+    /// Run the CozoScript passed in, like [DbInstance::run_script], but report errors
+    /// as a [CozoError], whose code, message and span can be relied upon by clients.
+    pub fn run_script_diag(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> std::result::Result<NamedRows, CozoError> {
+        self.run_script(payload, params, mutability)
+            .map_err(CozoError::from)
+    }
+    /// Run several independent scripts in one call, returning one result per script in the
+    /// order given, each in the format of [DbInstance::run_script_fold_err].
+    ///
+    /// Every script runs in its own transaction with the same `params`, and a failing
+    /// script does not prevent the following ones from running.
+    pub fn run_scripts(
+        &self,
+        payloads: &[&str],
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Vec<JsonValue> {
+        payloads
+            .iter()
+            .map(|payload| self.run_script_fold_err(payload, params.clone(), mutability))
+            .collect()
+    }
     /// Run the CozoScript passed in. The `params` argument is a map of parameters formatted as JSON.
+    /// JSON numbers become integers if they are written without a fraction or exponent
+    /// and fit in a signed 64-bit integer, and floats otherwise, so write `1.0` to pass a float.
+    /// Use [DbInstance::run_script] to pass exactly typed values.
     /// See [crate::Db::run_script].
     pub fn run_script_str(&self, payload: &str, params: &str, immutable: bool) -> String {
         let params_json = if params.is_empty() {
@@ -248,62 +663,556 @@ impl DbInstance {
         )
             .to_string()
     }
-    /// Dispatcher method. See [crate::Db::export_relations].
-    pub fn export_relations<I, T>(&self, relations: I) -> Result<BTreeMap<String, NamedRows>>
-        where
-            T: AsRef<str>,
-            I: Iterator<Item=T>,
-    {
+    /// Dispatcher method. See [crate::Db::export_relations_ordered].
+    pub fn export_relations_ordered<I, T>(
+        &self,
+        relations: I,
+        order: ColumnOrder,
+    ) -> Result<BTreeMap<String, NamedRows>>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        match self {
+            DbInstance::Mem(db) => db.export_relations_ordered(relations, order),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relations_ordered(relations, order),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_relations_ordered(relations, order),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relations_ordered(relations, order),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relations_ordered(relations, order),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_relations_projected].
+    pub fn export_relations_projected(
+        &self,
+        relations: &[(&str, Vec<&str>)],
+    ) -> Result<BTreeMap<String, NamedRows>> {
+        match self {
+            DbInstance::Mem(db) => db.export_relations_projected(relations),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relations_projected(relations),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_relations_projected(relations),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relations_projected(relations),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relations_projected(relations),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_relation_iter].
+    pub fn export_relation_iter(
+        &self,
+        relation: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<Vec<DataValue>>> + '_>> {
+        Ok(match self {
+            DbInstance::Mem(db) => Box::new(db.export_relation_iter(relation)?),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => Box::new(db.export_relation_iter(relation)?),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => Box::new(db.export_relation_iter(relation)?),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => Box::new(db.export_relation_iter(relation)?),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => Box::new(db.export_relation_iter(relation)?),
+        })
+    }
+    /// Dispatcher method. See [crate::Db::export_relations_csv].
+    pub fn export_relations_csv<I, T>(&self, relations: I, writer: impl Write) -> Result<()>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        match self {
+            DbInstance::Mem(db) => db.export_relations_csv(relations, writer),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relations_csv(relations, writer),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_relations_csv(relations, writer),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relations_csv(relations, writer),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relations_csv(relations, writer),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_relations_parquet].
+    #[cfg(feature = "export-parquet")]
+    pub fn export_relations_parquet(&self, relation: &str, path: &Path) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.export_relations_parquet(relation, path),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relations_parquet(relation, path),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_relations_parquet(relation, path),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relations_parquet(relation, path),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relations_parquet(relation, path),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::explain].
+    pub fn explain(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<ExplainPlan> {
+        match self {
+            DbInstance::Mem(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.explain(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.explain(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::plan].
+    pub fn plan(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<ExplainPlan>> {
+        match self {
+            DbInstance::Mem(db) => db.plan(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.plan(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.plan(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.plan(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.plan(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::parse_script].
+    pub fn parse_script(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<ParsedScript> {
+        match self {
+            DbInstance::Mem(db) => db.parse_script(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.parse_script(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.parse_script(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.parse_script(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.parse_script(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::script_is_write].
+    pub fn script_is_write(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.script_is_write(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.script_is_write(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.script_is_write(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.script_is_write(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.script_is_write(payload, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::storage_info].
+    pub fn storage_info(&self) -> StorageInfo {
+        match self {
+            DbInstance::Mem(db) => db.storage_info(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.storage_info(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.storage_info(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.storage_info(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.storage_info(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_relations].
+    pub fn export_relations<I, T>(&self, relations: I) -> Result<BTreeMap<String, NamedRows>>
+        where
+            T: AsRef<str>,
+            I: Iterator<Item=T>,
+    {
+        match self {
+            DbInstance::Mem(db) => db.export_relations(relations),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relations(relations),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_relations(relations),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relations(relations),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relations(relations),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_relations_with_schema].
+    #[allow(clippy::type_complexity)]
+    pub fn export_relations_with_schema<I, T>(
+        &self,
+        relations: I,
+    ) -> Result<(BTreeMap<String, NamedRows>, BTreeMap<String, String>)>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        match self {
+            DbInstance::Mem(db) => db.export_relations_with_schema(relations),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relations_with_schema(relations),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_relations_with_schema(relations),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relations_with_schema(relations),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relations_with_schema(relations),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_schema].
+    pub fn export_schema<I, T>(&self, relations: I) -> Result<JsonValue>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        match self {
+            DbInstance::Mem(db) => db.export_schema(relations),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_schema(relations),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_schema(relations),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_schema(relations),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_schema(relations),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_schema].
+    pub fn import_schema(&self, schema: &JsonValue) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_schema(schema),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_schema(schema),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_schema(schema),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_schema(schema),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_schema(schema),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_relation_range].
+    pub fn export_relation_range(
+        &self,
+        relation: &str,
+        lower: Vec<DataValue>,
+        upper: Vec<DataValue>,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.export_relation_range(relation, lower, upper),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relation_range(relation, lower, upper),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_relation_range(relation, lower, upper),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relation_range(relation, lower, upper),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relation_range(relation, lower, upper),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_relation_where].
+    pub fn export_relation_where(
+        &self,
+        relation: &str,
+        predicate: &str,
+        writer: impl Write,
+        format: ExportFormat,
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.export_relation_where(relation, predicate, writer, format),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relation_where(relation, predicate, writer, format),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.export_relation_where(relation, predicate, writer, format)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relation_where(relation, predicate, writer, format),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relation_where(relation, predicate, writer, format),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_relation_sorted].
+    pub fn export_relation_sorted(
+        &self,
+        relation: &str,
+        sort_cols: &[&str],
+        writer: impl Write,
+        format: ExportFormat,
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.export_relation_sorted(relation, sort_cols, writer, format),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.export_relation_sorted(relation, sort_cols, writer, format)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.export_relation_sorted(relation, sort_cols, writer, format)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relation_sorted(relation, sort_cols, writer, format),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relation_sorted(relation, sort_cols, writer, format),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::export_relation_by_key].
+    pub fn export_relation_by_key(
+        &self,
+        relation: &str,
+        writer: impl Write,
+        format: ExportFormat,
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.export_relation_by_key(relation, writer, format),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_relation_by_key(relation, writer, format),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_relation_by_key(relation, writer, format),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_relation_by_key(relation, writer, format),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_relation_by_key(relation, writer, format),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::distinct_values].
+    pub fn distinct_values(
+        &self,
+        relation: &str,
+        column: &str,
+        limit: usize,
+    ) -> Result<Vec<DataValue>> {
+        match self {
+            DbInstance::Mem(db) => db.distinct_values(relation, column, limit),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.distinct_values(relation, column, limit),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.distinct_values(relation, column, limit),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.distinct_values(relation, column, limit),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.distinct_values(relation, column, limit),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::inspect_relation].
+    pub fn inspect_relation(&self, name: &str, sample_size: usize) -> Result<JsonValue> {
+        match self {
+            DbInstance::Mem(db) => db.inspect_relation(name, sample_size),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.inspect_relation(name, sample_size),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.inspect_relation(name, sample_size),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.inspect_relation(name, sample_size),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.inspect_relation(name, sample_size),
+        }
+    }
+    /// Export relations to JSON-encoded string.
+    /// See [crate::Db::export_relations]
+    ///
+    /// When the payload has `"with_schema": true`, the export of each relation also carries
+    /// its schema under the key `schema`, see [crate::Db::export_relations_with_schema].
+    pub fn export_relations_str(&self, data: &str) -> String {
+        match self.export_relations_str_inner(data) {
+            Ok(s) => {
+                let ret = json!({"ok": true, "data": s});
+                format!("{ret}")
+            }
+            Err(err) => {
+                let ret = json!({"ok": false, "message": err.to_string()});
+                format!("{ret}")
+            }
+        }
+    }
+    fn export_relations_str_inner(&self, data: &str) -> Result<JsonValue> {
+        #[derive(serde_derive::Deserialize)]
+        struct Payload {
+            relations: Vec<String>,
+            #[serde(default)]
+            with_schema: bool,
+        }
+        let j_val: Payload = serde_json::from_str(data).into_diagnostic()?;
+        let relations = j_val.relations.iter().map(|s| s as &str);
+        let (results, mut schemas) = if j_val.with_schema {
+            self.export_relations_with_schema(relations)?
+        } else {
+            (self.export_relations(relations)?, BTreeMap::new())
+        };
+        Ok(results
+            .into_iter()
+            .map(|(k, v)| {
+                let mut v = v.into_json();
+                if let Some(schema) = schemas.remove(&k) {
+                    v["schema"] = json!(schema);
+                }
+                (k, v)
+            })
+            .collect())
+    }
+    /// Dispatcher method. See [crate::Db::import_relations].
+    pub fn import_relations(&self, data: BTreeMap<String, NamedRows>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_relations(data),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_relations(data),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_relations(data),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_relations(data),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_relations(data),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_relations_with_schema].
+    pub fn import_relations_with_schema(
+        &self,
+        data: BTreeMap<String, NamedRows>,
+        schemas: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_relations_with_schema(data, schemas),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_relations_with_schema(data, schemas),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_relations_with_schema(data, schemas),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_relations_with_schema(data, schemas),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_relations_with_schema(data, schemas),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_relations_chunked].
+    pub fn import_relations_chunked(
+        &self,
+        data: BTreeMap<String, NamedRows>,
+        batch_size: usize,
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_relations_chunked(data, batch_size),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_relations_chunked(data, batch_size),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_relations_chunked(data, batch_size),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_relations_chunked(data, batch_size),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_relations_chunked(data, batch_size),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_relations_resumable].
+    pub fn import_relations_resumable(
+        &self,
+        data: BTreeMap<String, NamedRows>,
+        batch_size: usize,
+        checkpoint_rel: &str,
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_relations_resumable(data, batch_size, checkpoint_rel),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.import_relations_resumable(data, batch_size, checkpoint_rel)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.import_relations_resumable(data, batch_size, checkpoint_rel)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_relations_resumable(data, batch_size, checkpoint_rel),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_relations_resumable(data, batch_size, checkpoint_rel),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_ndjson].
+    pub fn import_ndjson(&self, relation: &str, reader: impl BufRead) -> Result<usize> {
         match self {
-            DbInstance::Mem(db) => db.export_relations(relations),
+            DbInstance::Mem(db) => db.import_ndjson(relation, reader),
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.export_relations(relations),
+            DbInstance::Sqlite(db) => db.import_ndjson(relation, reader),
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.export_relations(relations),
+            DbInstance::RocksDb(db) => db.import_ndjson(relation, reader),
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.export_relations(relations),
+            DbInstance::Sled(db) => db.import_ndjson(relation, reader),
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.export_relations(relations),
+            DbInstance::TiKv(db) => db.import_ndjson(relation, reader),
         }
     }
-    /// Export relations to JSON-encoded string.
-    /// See [crate::Db::export_relations]
-    pub fn export_relations_str(&self, data: &str) -> String {
-        match self.export_relations_str_inner(data) {
-            Ok(s) => {
-                let ret = json!({"ok": true, "data": s});
-                format!("{ret}")
-            }
-            Err(err) => {
-                let ret = json!({"ok": false, "message": err.to_string()});
-                format!("{ret}")
-            }
+    /// Dispatcher method. See [crate::Db::relation_get].
+    pub fn relation_get(&self, name: &str, key: Tuple) -> Result<Option<Tuple>> {
+        match self {
+            DbInstance::Mem(db) => db.relation_get(name, key),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.relation_get(name, key),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.relation_get(name, key),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.relation_get(name, key),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.relation_get(name, key),
         }
     }
-    fn export_relations_str_inner(&self, data: &str) -> Result<JsonValue> {
-        #[derive(serde_derive::Deserialize)]
-        struct Payload {
-            relations: Vec<String>,
+    /// Dispatcher method. See [crate::Db::relation_put].
+    pub fn relation_put(&self, name: &str, row: Tuple) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.relation_put(name, row),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.relation_put(name, row),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.relation_put(name, row),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.relation_put(name, row),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.relation_put(name, row),
         }
-        let j_val: Payload = serde_json::from_str(data).into_diagnostic()?;
-        let results = self.export_relations(j_val.relations.iter().map(|s| s as &str))?;
-        Ok(results
-            .into_iter()
-            .map(|(k, v)| (k, v.into_json()))
-            .collect())
     }
-    /// Dispatcher method. See [crate::Db::import_relations].
-    pub fn import_relations(&self, data: BTreeMap<String, NamedRows>) -> Result<()> {
+    /// Dispatcher method. See [crate::Db::relation_del].
+    pub fn relation_del(&self, name: &str, key: Tuple) -> Result<()> {
         match self {
-            DbInstance::Mem(db) => db.import_relations(data),
+            DbInstance::Mem(db) => db.relation_del(name, key),
             #[cfg(feature = "storage-sqlite")]
-            DbInstance::Sqlite(db) => db.import_relations(data),
+            DbInstance::Sqlite(db) => db.relation_del(name, key),
             #[cfg(feature = "storage-rocksdb")]
-            DbInstance::RocksDb(db) => db.import_relations(data),
+            DbInstance::RocksDb(db) => db.relation_del(name, key),
             #[cfg(feature = "storage-sled")]
-            DbInstance::Sled(db) => db.import_relations(data),
+            DbInstance::Sled(db) => db.relation_del(name, key),
             #[cfg(feature = "storage-tikv")]
-            DbInstance::TiKv(db) => db.import_relations(data),
+            DbInstance::TiKv(db) => db.relation_del(name, key),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::validate_import].
+    pub fn validate_import(
+        &self,
+        data: &BTreeMap<String, NamedRows>,
+    ) -> Result<BTreeMap<String, Vec<InvalidImportRow>>> {
+        match self {
+            DbInstance::Mem(db) => db.validate_import(data),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.validate_import(data),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.validate_import(data),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.validate_import(data),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.validate_import(data),
         }
     }
     /// Import a relation, the data is given as a JSON string, and the returned result is converted into a string.
@@ -320,18 +1229,34 @@ impl DbInstance {
     }
     /// Import a relation, the data is given as a JSON string.
     /// See [crate::Db::import_relations].
+    ///
+    /// The data of a relation may carry its schema under the key `schema`, as exported by
+    /// [Self::export_relations_str] with `"with_schema": true`, to create the relation
+    /// if it does not exist. See [crate::Db::import_relations_with_schema].
     pub fn import_relations_str_with_err(&self, data: &str) -> Result<()> {
         let json_data: JsonValue = serde_json::from_str(data).into_diagnostic()?;
         let json_object = json_data
             .as_object()
             .ok_or_else(|| miette!("A JSON object is requried"))?;
+        let mut schemas = BTreeMap::new();
+        for (k, v) in json_object {
+            match v.get("schema") {
+                None | Some(JsonValue::Null) => {}
+                Some(schema) => {
+                    let schema = schema
+                        .as_str()
+                        .ok_or_else(|| miette!("'schema' field must be a string"))?;
+                    schemas.insert(k.to_string(), schema.to_string());
+                }
+            }
+        }
         let mapping = json_object
             .iter()
             .map(|(k, v)| -> Result<(String, NamedRows)> {
                 Ok((k.to_string(), NamedRows::from_json(v)?))
             })
             .collect::<Result<_>>()?;
-        self.import_relations(mapping)
+        self.import_relations_with_schema(mapping, &schemas)
     }
     /// Dispatcher method. See [crate::Db::backup_db].
     pub fn backup_db(&self, out_file: impl AsRef<Path>) -> Result<()> {
@@ -347,6 +1272,53 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.backup_db(out_file),
         }
     }
+    /// Dispatcher method. See [crate::Db::backup_db_with_progress].
+    pub fn backup_db_with_progress(
+        &self,
+        out_file: impl AsRef<Path>,
+        progress: impl FnMut(u64),
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.backup_db_with_progress(out_file, progress),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.backup_db_with_progress(out_file, progress),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.backup_db_with_progress(out_file, progress),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.backup_db_with_progress(out_file, progress),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.backup_db_with_progress(out_file, progress),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::backup_db_with_key_transform].
+    pub fn backup_db_with_key_transform(
+        &self,
+        out_file: impl AsRef<Path>,
+        key_transform: impl Fn(&[u8]) -> Vec<u8>,
+        progress: impl FnMut(u64),
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => {
+                db.backup_db_with_key_transform(out_file, key_transform, progress)
+            }
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => {
+                db.backup_db_with_key_transform(out_file, key_transform, progress)
+            }
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => {
+                db.backup_db_with_key_transform(out_file, key_transform, progress)
+            }
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => {
+                db.backup_db_with_key_transform(out_file, key_transform, progress)
+            }
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => {
+                db.backup_db_with_key_transform(out_file, key_transform, progress)
+            }
+        }
+    }
     /// Backup the running database into an Sqlite file, with JSON string return value.
     /// See [crate::Db::backup_db].
     pub fn backup_db_str(&self, out_file: impl AsRef<Path>) -> String {
@@ -355,6 +1327,34 @@ impl DbInstance {
             Err(err) => json!({"ok": false, "message": err.to_string()}).to_string(),
         }
     }
+    /// Dispatcher method. See [crate::Db::export_all_to_bytes].
+    pub fn export_all_to_bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            DbInstance::Mem(db) => db.export_all_to_bytes(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.export_all_to_bytes(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.export_all_to_bytes(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.export_all_to_bytes(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.export_all_to_bytes(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_all_from_bytes].
+    pub fn import_all_from_bytes(&self, data: &[u8]) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_all_from_bytes(data),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_all_from_bytes(data),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_all_from_bytes(data),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_all_from_bytes(data),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_all_from_bytes(data),
+        }
+    }
     /// Dispatcher method. See [crate::Db::restore_backup].
     pub fn restore_backup(&self, in_file: impl AsRef<Path>) -> Result<()> {
         match self {
@@ -369,6 +1369,24 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.restore_backup(in_file),
         }
     }
+    /// Dispatcher method. See [crate::Db::restore_backup_with_progress].
+    pub fn restore_backup_with_progress(
+        &self,
+        in_file: impl AsRef<Path>,
+        progress: impl FnMut(u64),
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.restore_backup_with_progress(in_file, progress),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.restore_backup_with_progress(in_file, progress),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.restore_backup_with_progress(in_file, progress),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.restore_backup_with_progress(in_file, progress),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.restore_backup_with_progress(in_file, progress),
+        }
+    }
     /// Restore from an Sqlite backup, with JSON string return value.
     /// See [crate::Db::restore_backup].
     pub fn restore_backup_str(&self, in_file: impl AsRef<Path>) -> String {
@@ -377,6 +1395,66 @@ impl DbInstance {
             Err(err) => json!({"ok": false, "message": err.to_string()}).to_string(),
         }
     }
+    /// Dispatcher method. See [crate::Db::backup_db_incremental].
+    pub fn backup_db_incremental(
+        &self,
+        out_file: impl AsRef<Path>,
+        since: Option<u64>,
+    ) -> Result<u64> {
+        match self {
+            DbInstance::Mem(db) => db.backup_db_incremental(out_file, since),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.backup_db_incremental(out_file, since),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.backup_db_incremental(out_file, since),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.backup_db_incremental(out_file, since),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.backup_db_incremental(out_file, since),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::restore_backup_incremental].
+    pub fn restore_backup_incremental(&self, in_file: impl AsRef<Path>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.restore_backup_incremental(in_file),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.restore_backup_incremental(in_file),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.restore_backup_incremental(in_file),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.restore_backup_incremental(in_file),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.restore_backup_incremental(in_file),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::restore_backup_prefixed].
+    pub fn restore_backup_prefixed(&self, in_file: impl AsRef<Path>, prefix: &str) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.restore_backup_prefixed(in_file, prefix),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.restore_backup_prefixed(in_file, prefix),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.restore_backup_prefixed(in_file, prefix),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.restore_backup_prefixed(in_file, prefix),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.restore_backup_prefixed(in_file, prefix),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::verify_backup].
+    pub fn verify_backup(&self, in_file: impl AsRef<Path>) -> Result<BackupStats> {
+        match self {
+            DbInstance::Mem(db) => db.verify_backup(in_file),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.verify_backup(in_file),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.verify_backup(in_file),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.verify_backup(in_file),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.verify_backup(in_file),
+        }
+    }
     /// Dispatcher method. See [crate::Db::import_from_backup].
     pub fn import_from_backup(
         &self,
@@ -395,8 +1473,27 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.import_from_backup(in_file, relations),
         }
     }
+    /// Dispatcher method. See [crate::Db::import_from_backup_renamed].
+    pub fn import_from_backup_renamed(
+        &self,
+        in_file: impl AsRef<Path>,
+        relations: &[(String, String)],
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_from_backup_renamed(in_file, relations),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_from_backup_renamed(in_file, relations),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_from_backup_renamed(in_file, relations),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_from_backup_renamed(in_file, relations),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_from_backup_renamed(in_file, relations),
+        }
+    }
     /// Import relations from an Sqlite backup, with JSON string return value.
-    /// See [crate::Db::import_from_backup].
+    /// Each of the `relations` of the payload is either a name, or a pair of the name in
+    /// the backup and the name in the database, see [crate::Db::import_from_backup_renamed].
     pub fn import_from_backup_str(&self, payload: &str) -> String {
         match self.import_from_backup_str_inner(payload) {
             Ok(_) => json!({"ok": true}).to_string(),
@@ -404,14 +1501,28 @@ impl DbInstance {
         }
     }
     fn import_from_backup_str_inner(&self, payload: &str) -> Result<()> {
+        #[derive(serde_derive::Deserialize)]
+        #[serde(untagged)]
+        enum RelationSpec {
+            Same(String),
+            Renamed(String, String),
+        }
         #[derive(serde_derive::Deserialize)]
         struct Payload {
             path: String,
-            relations: Vec<String>,
+            relations: Vec<RelationSpec>,
         }
         let json_payload: Payload = serde_json::from_str(payload).into_diagnostic()?;
+        let relations = json_payload
+            .relations
+            .into_iter()
+            .map(|spec| match spec {
+                RelationSpec::Same(name) => (name.clone(), name),
+                RelationSpec::Renamed(src, dst) => (src, dst),
+            })
+            .collect::<Vec<_>>();
 
-        self.import_from_backup(&json_payload.path, &json_payload.relations)
+        self.import_from_backup_renamed(&json_payload.path, &relations)
     }
 
     /// Dispatcher method. See [crate::Db::register_callback].
@@ -434,6 +1545,22 @@ impl DbInstance {
         }
     }
 
+    /// Dispatcher method. See [crate::Db::subscribe].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn subscribe(&self, relation: &str) -> Receiver<ChangeEvent> {
+        match self {
+            DbInstance::Mem(db) => db.subscribe(relation),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.subscribe(relation),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.subscribe(relation),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.subscribe(relation),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.subscribe(relation),
+        }
+    }
+
     /// Dispatcher method. See [crate::Db::unregister_callback].
     #[cfg(not(target_arch = "wasm32"))]
     pub fn unregister_callback(&self, id: u32) -> bool {
@@ -480,6 +1607,20 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.unregister_fixed_rule(name),
         }
     }
+    /// Dispatcher method. See [crate::Db::capabilities].
+    pub fn capabilities(&self) -> JsonValue {
+        match self {
+            DbInstance::Mem(db) => db.capabilities(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.capabilities(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.capabilities(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.capabilities(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.capabilities(),
+        }
+    }
 
     /// Dispatcher method. See [crate::Db::run_multi_transaction]
     pub fn run_multi_transaction(
@@ -500,6 +1641,81 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.run_multi_transaction(write, payloads, results),
         }
     }
+    /// Dispatcher method. See [crate::Db::begin_transaction].
+    pub fn begin_transaction(&self, write: bool) -> Result<DbInstanceTransaction<'_>> {
+        Ok(match self {
+            DbInstance::Mem(db) => DbInstanceTransaction::Mem(db.begin_transaction(write)?),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => DbInstanceTransaction::Sqlite(db.begin_transaction(write)?),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => DbInstanceTransaction::RocksDb(db.begin_transaction(write)?),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => DbInstanceTransaction::Sled(db.begin_transaction(write)?),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => DbInstanceTransaction::TiKv(db.begin_transaction(write)?),
+        })
+    }
+    /// Dispatcher method. See [crate::Db::expire_relations].
+    pub fn expire_relations(&self) -> Result<BTreeMap<String, usize>> {
+        match self {
+            DbInstance::Mem(db) => db.expire_relations(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.expire_relations(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.expire_relations(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.expire_relations(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.expire_relations(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::compact_relation_named].
+    pub fn compact_relation_named(&self, name: &str, options: CompactOptions) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.compact_relation_named(name, options),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.compact_relation_named(name, options),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.compact_relation_named(name, options),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.compact_relation_named(name, options),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.compact_relation_named(name, options),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::compact_relation_id].
+    pub fn compact_relation_id(&self, id: u64, options: CompactOptions) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.compact_relation_id(id, options),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.compact_relation_id(id, options),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.compact_relation_id(id, options),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.compact_relation_id(id, options),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.compact_relation_id(id, options),
+        }
+    }
+    /// Delete expired rows of relations with a time to live in the background,
+    /// by calling [crate::Db::expire_relations] on a dedicated thread every `interval`.
+    /// Errors are logged and do not stop the sweeper.
+    /// The sweeper runs until the returned handle is stopped or dropped.
+    pub fn spawn_ttl_sweeper(&self, interval: Duration) -> TtlSweeper {
+        let (stop_send, stop_recv) = bounded::<()>(0);
+        let db = self.clone();
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_recv.recv_timeout(interval) {
+                if let Err(err) = db.expire_relations() {
+                    log::error!("expiring rows failed: {err:?}");
+                }
+            }
+        });
+        TtlSweeper {
+            stop: stop_send,
+            handle,
+        }
+    }
     /// A higher-level, blocking wrapper for [crate::Db::run_multi_transaction]. Runs the transaction on a dedicated thread.
     /// Write transactions _may_ block other reads, but we guarantee that this does not happen for the RocksDB backend.
     pub fn multi_transaction(&self, write: bool) -> MultiTransaction {
@@ -514,6 +1730,21 @@ impl DbInstance {
     }
 }
 
+/// Handle of the background sweeper started by [DbInstance::spawn_ttl_sweeper].
+/// Dropping it stops the sweeper without waiting for it.
+pub struct TtlSweeper {
+    stop: Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl TtlSweeper {
+    /// Stop the sweeper, waiting for a sweep in progress to finish
+    pub fn stop(self) {
+        drop(self.stop);
+        let _ = self.handle.join();
+    }
+}
+
 /// A multi-transaction handle.
 /// You should use either the fields directly, or the associated functions.
 pub struct MultiTransaction {
@@ -563,6 +1794,105 @@ impl MultiTransaction {
     }
 }
 
+/// A transaction of a [DbInstance], created by [DbInstance::begin_transaction].
+/// See [CozoTransaction] for how it behaves.
+pub enum DbInstanceTransaction<'a> {
+    /// Transaction of in memory storage
+    Mem(CozoTransaction<'a, MemStorage>),
+    #[cfg(feature = "storage-sqlite")]
+    /// Transaction of Sqlite storage
+    Sqlite(CozoTransaction<'a, SqliteStorage>),
+    #[cfg(feature = "storage-rocksdb")]
+    /// Transaction of RocksDB storage
+    RocksDb(CozoTransaction<'a, RocksDbStorage>),
+    #[cfg(feature = "storage-sled")]
+    /// Transaction of Sled storage
+    Sled(CozoTransaction<'a, SledStorage>),
+    #[cfg(feature = "storage-tikv")]
+    /// Transaction of TiKV storage
+    TiKv(CozoTransaction<'a, TiKvStorage>),
+}
+
+impl DbInstanceTransaction<'_> {
+    /// Dispatcher method. See [CozoTransaction::run_script].
+    pub fn run_script(
+        &mut self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstanceTransaction::Mem(tx) => tx.run_script(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstanceTransaction::Sqlite(tx) => tx.run_script(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstanceTransaction::RocksDb(tx) => tx.run_script(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstanceTransaction::Sled(tx) => tx.run_script(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstanceTransaction::TiKv(tx) => tx.run_script(payload, params),
+        }
+    }
+    /// Dispatcher method. See [CozoTransaction::savepoint].
+    pub fn savepoint(&mut self, name: &str) {
+        match self {
+            DbInstanceTransaction::Mem(tx) => tx.savepoint(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstanceTransaction::Sqlite(tx) => tx.savepoint(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstanceTransaction::RocksDb(tx) => tx.savepoint(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstanceTransaction::Sled(tx) => tx.savepoint(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstanceTransaction::TiKv(tx) => tx.savepoint(name),
+        }
+    }
+    /// Dispatcher method. See [CozoTransaction::rollback_to].
+    pub fn rollback_to(&mut self, name: &str) -> Result<()> {
+        match self {
+            DbInstanceTransaction::Mem(tx) => tx.rollback_to(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstanceTransaction::Sqlite(tx) => tx.rollback_to(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstanceTransaction::RocksDb(tx) => tx.rollback_to(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstanceTransaction::Sled(tx) => tx.rollback_to(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstanceTransaction::TiKv(tx) => tx.rollback_to(name),
+        }
+    }
+    /// Dispatcher method. See [CozoTransaction::release_savepoint].
+    pub fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        match self {
+            DbInstanceTransaction::Mem(tx) => tx.release_savepoint(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstanceTransaction::Sqlite(tx) => tx.release_savepoint(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstanceTransaction::RocksDb(tx) => tx.release_savepoint(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstanceTransaction::Sled(tx) => tx.release_savepoint(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstanceTransaction::TiKv(tx) => tx.release_savepoint(name),
+        }
+    }
+    /// Dispatcher method. See [CozoTransaction::commit].
+    pub fn commit(self) -> Result<()> {
+        match self {
+            DbInstanceTransaction::Mem(tx) => tx.commit(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstanceTransaction::Sqlite(tx) => tx.commit(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstanceTransaction::RocksDb(tx) => tx.commit(),
+            #[cfg(feature = "storage-sled")]
+            DbInstanceTransaction::Sled(tx) => tx.commit(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstanceTransaction::TiKv(tx) => tx.commit(),
+        }
+    }
+    /// Roll back the transaction, discarding all its writes.
+    /// This is the same as dropping it.
+    pub fn rollback(self) {}
+}
+
 /// Convert error raised by the database into friendly JSON format
 pub fn format_error_as_json(mut err: Report, source: Option<&str>) -> JsonValue {
     if err.source_code().is_none() {
@@ -583,6 +1913,10 @@ pub fn format_error_as_json(mut err: Report, source: Option<&str>) -> JsonValue
     let map = json.as_object_mut().unwrap();
     map.insert("ok".to_string(), json!(false));
     map.insert("display".to_string(), json!(text_err));
+    // the first label marks the offending part of the script
+    if let Some(span) = CozoError::from(err).span {
+        map.insert("span".to_string(), json!({"start": span.0, "len": span.1}));
+    }
     json
 }
 
@@ -6,8 +6,6 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeMap;
-
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use miette::{bail, ensure, Diagnostic, Result};
@@ -23,7 +21,7 @@ use crate::data::functions::{
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
-use crate::parse::{ExtractSpan, Pair, Rule, SourceSpan};
+use crate::parse::{ExtractSpan, Pair, ParamPool, Rule, SourceSpan};
 
 lazy_static! {
     static ref PRATT_PARSER: PrattParser<Rule> = {
@@ -65,6 +63,11 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) -> Resul
             val: val.clone(),
             span: *span,
         }),
+        Expr::Param { name, slot, span } => collector.push(Bytecode::Param {
+            name: name.clone(),
+            slot: slot.clone(),
+            span: *span,
+        }),
         Expr::Apply { op, args, span } => {
             let arity = args.len();
             for arg in args.iter() {
@@ -114,7 +117,7 @@ pub(crate) fn expr2bytecode(expr: &Expr, collector: &mut Vec<Bytecode>) -> Resul
     Ok(())
 }
 
-pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
+pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &ParamPool<'_>) -> Result<Expr> {
     ensure!(
         pair.as_rule() == Rule::expr,
         InvalidExpression(pair.extract_span())
@@ -175,7 +178,7 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
     })
 }
 
-fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Expr> {
+fn build_term(pair: Pair<'_>, param_pool: &ParamPool<'_>) -> Result<Expr> {
     let span = pair.extract_span();
     let op = pair.as_rule();
     Ok(match op {
@@ -190,12 +193,23 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
             struct ParamNotFoundError(String, #[label] SourceSpan);
 
             let param_str = pair.as_str().strip_prefix('$').unwrap();
-            Expr::Const {
-                val: param_pool
-                    .get(param_str)
-                    .ok_or_else(|| ParamNotFoundError(param_str.to_string(), span))?
-                    .clone(),
-                span,
+            match param_pool {
+                ParamPool::Values(vals) => Expr::Const {
+                    val: vals
+                        .get(param_str)
+                        .ok_or_else(|| ParamNotFoundError(param_str.to_string(), span))?
+                        .clone(),
+                    span,
+                },
+                ParamPool::Slots(slots) => Expr::Param {
+                    name: SmartString::from(param_str),
+                    slot: slots
+                        .borrow_mut()
+                        .entry(param_str.to_string())
+                        .or_default()
+                        .clone(),
+                    span,
+                },
             }
         }
         Rule::pos_int => {
@@ -294,6 +308,46 @@ fn build_term(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Resul
                 span,
             }
         }
+        Rule::case_expr => {
+            let mut clauses = vec![];
+            let mut has_else = false;
+            for p in pair.into_inner() {
+                match p.as_rule() {
+                    Rule::case_when => {
+                        let mut p = p.into_inner().filter(|p| p.as_rule() == Rule::expr);
+                        let cond = build_expr(p.next().unwrap(), param_pool)?;
+                        let then = build_expr(p.next().unwrap(), param_pool)?;
+                        clauses.push((cond, then));
+                    }
+                    Rule::case_else => {
+                        let p = p.into_inner().find(|p| p.as_rule() == Rule::expr).unwrap();
+                        let val = build_expr(p, param_pool)?;
+                        clauses.push((
+                            Expr::Const {
+                                val: DataValue::from(true),
+                                span,
+                            },
+                            val,
+                        ));
+                        has_else = true;
+                    }
+                    _ => {}
+                }
+            }
+            if !has_else {
+                clauses.push((
+                    Expr::Const {
+                        val: DataValue::from(true),
+                        span,
+                    },
+                    Expr::Const {
+                        val: DataValue::Null,
+                        span,
+                    },
+                ));
+            }
+            Expr::Cond { clauses, span }
+        }
         Rule::apply => {
             let mut p = pair.into_inner();
             let ident_p = p.next().unwrap();
@@ -20,13 +20,13 @@ use crate::parse::query::parse_query;
 use crate::parse::sys::parse_sys;
 use crate::parse::{
     ExtractSpan, ImperativeProgram, ImperativeStmt, ImperativeStmtClause, ImperativeSysop, Pair,
-    Rule, SourceSpan,
+    ParamPool, Rule, SourceSpan,
 };
-use crate::{DataValue, FixedRule, ValidityTs};
+use crate::{FixedRule, ValidityTs};
 
 pub(crate) fn parse_imperative_block(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<ImperativeProgram> {
@@ -59,7 +59,7 @@ struct DuplicateMarker(#[label] SourceSpan);
 
 fn parse_imperative_stmt(
     pair: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<ImperativeStmt> {
@@ -6,25 +6,27 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cell::RefCell;
 use std::cmp::{max, min};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Display, Formatter};
 use std::sync::Arc;
 
-use either::{Either, Left};
+use either::{Either, Left, Right};
 use miette::{bail, Diagnostic, IntoDiagnostic, Result};
 use pest::error::InputLocation;
 use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::expr::ParamSlot;
 use crate::data::program::InputProgram;
-use crate::data::relation::NullableColType;
+use crate::data::relation::{NullableColType, StoredRelationMetadata};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::expr::build_expr;
 use crate::parse::imperative::parse_imperative_block;
 use crate::parse::query::parse_query;
-use crate::parse::schema::parse_nullable_type;
+use crate::parse::schema::{parse_nullable_type, parse_schema};
 use crate::parse::sys::{parse_sys, SysOp};
 use crate::{Expr, FixedRule};
 
@@ -43,7 +45,7 @@ pub(crate) type Pair<'a> = pest::iterators::Pair<'a, Rule>;
 pub(crate) type Pairs<'a> = pest::iterators::Pairs<'a, Rule>;
 
 pub(crate) enum CozoScript {
-    Single(InputProgram),
+    Single(Box<InputProgram>),
     Imperative(ImperativeProgram),
     Sys(SysOp),
 }
@@ -180,11 +182,90 @@ impl ImperativeStmt {
                     SysOp::RemoveIndex(rel, idx) => {
                         collector.insert(SmartString::from(format!("{}:{}", rel.name, idx.name)));
                     }
+                    SysOp::ExpireRelation(rel) => {
+                        collector.insert(rel.name.clone());
+                    }
                     _ => {}
                 }
             }
         }
     }
+
+    /// Collects the queries of the statement, in the order they appear
+    pub(crate) fn into_programs(self, collector: &mut Vec<InputProgram>) {
+        match self {
+            ImperativeStmt::Program { prog } | ImperativeStmt::IgnoreErrorProgram { prog } => {
+                collector.push(prog.prog)
+            }
+            ImperativeStmt::Return { returns } => collector.extend(
+                returns
+                    .into_iter()
+                    .filter_map(|ret| ret.left().map(|p| p.prog)),
+            ),
+            ImperativeStmt::SysOp { sysop } => sysop.sysop.into_programs(collector),
+            ImperativeStmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                if let Right(prog) = condition {
+                    collector.push(prog.prog);
+                }
+                for stmt in then_branch.into_iter().chain(else_branch) {
+                    stmt.into_programs(collector);
+                }
+            }
+            ImperativeStmt::Loop { body, .. } => {
+                for stmt in body {
+                    stmt.into_programs(collector);
+                }
+            }
+            ImperativeStmt::TempDebug { .. }
+            | ImperativeStmt::Break { .. }
+            | ImperativeStmt::Continue { .. }
+            | ImperativeStmt::TempSwap { .. } => {}
+        }
+    }
+
+    /// Describes the first statement that would change the database, if any
+    pub(crate) fn find_mutation(&self) -> Option<String> {
+        match self {
+            ImperativeStmt::Program { prog } | ImperativeStmt::IgnoreErrorProgram { prog } => {
+                prog.prog.find_mutation()
+            }
+            ImperativeStmt::Return { returns } => returns.iter().find_map(|ret| match ret {
+                Left(prog) => prog.prog.find_mutation(),
+                Right(_) => None,
+            }),
+            ImperativeStmt::SysOp { sysop } => sysop
+                .sysop
+                .mutation_command()
+                .map(|cmd| format!("runs {cmd}")),
+            ImperativeStmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let in_condition = match condition {
+                    Left(_) => None,
+                    Right(prog) => prog.prog.find_mutation(),
+                };
+                in_condition.or_else(|| {
+                    then_branch
+                        .iter()
+                        .chain(else_branch.iter())
+                        .find_map(|stmt| stmt.find_mutation())
+                })
+            }
+            ImperativeStmt::Loop { body, .. } => body.iter().find_map(|stmt| stmt.find_mutation()),
+            ImperativeStmt::TempDebug { .. }
+            | ImperativeStmt::Break { .. }
+            | ImperativeStmt::Continue { .. }
+            | ImperativeStmt::TempSwap { .. } => None,
+        }
+    }
 }
 
 impl CozoScript {
@@ -194,12 +275,35 @@ impl CozoScript {
         #[diagnostic(code(parser::expect_singleton))]
         struct ExpectSingleProgram;
         match self {
-            CozoScript::Single(s) => Ok(s),
+            CozoScript::Single(s) => Ok(*s),
             CozoScript::Imperative(_) | CozoScript::Sys(_) => {
                 bail!(ExpectSingleProgram)
             }
         }
     }
+    /// The queries of the script, in the order they appear
+    pub(crate) fn into_programs(self) -> Vec<InputProgram> {
+        let mut collector = vec![];
+        match self {
+            CozoScript::Single(prog) => collector.push(*prog),
+            CozoScript::Imperative(stmts) => {
+                for stmt in stmts {
+                    stmt.into_programs(&mut collector);
+                }
+            }
+            CozoScript::Sys(op) => op.into_programs(&mut collector),
+        }
+        collector
+    }
+    /// Describes the first part of the script that would change the database, if any.
+    /// Writes to temporary relations count as well.
+    pub(crate) fn find_mutation(&self) -> Option<String> {
+        match self {
+            CozoScript::Single(prog) => prog.find_mutation(),
+            CozoScript::Imperative(stmts) => stmts.iter().find_map(|stmt| stmt.find_mutation()),
+            CozoScript::Sys(op) => op.mutation_command().map(|cmd| format!("runs {cmd}")),
+        }
+    }
 }
 
 /// Span of the element in the source script, with starting and ending positions.
@@ -246,6 +350,21 @@ pub(crate) struct ParseError {
     pub(crate) span: SourceSpan,
 }
 
+/// Where the values of the parameters referenced in a script come from
+pub(crate) enum ParamPool<'a> {
+    /// The values are known when parsing, and are substituted as constants
+    Values(&'a BTreeMap<String, DataValue>),
+    /// The values are only supplied on execution: every parameter gets a slot
+    Slots(RefCell<BTreeMap<String, ParamSlot>>),
+}
+
+impl Default for ParamPool<'_> {
+    fn default() -> Self {
+        static NO_PARAMS: BTreeMap<String, DataValue> = BTreeMap::new();
+        ParamPool::Values(&NO_PARAMS)
+    }
+}
+
 pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
     let parsed = CozoScriptParser::parse(Rule::col_type_with_term, src)
         .into_diagnostic()?
@@ -254,6 +373,22 @@ pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
     parse_nullable_type(parsed.into_inner().next().unwrap())
 }
 
+/// Parse a table schema such as `{k: Int => v: String}`, as used by `:create`.
+pub(crate) fn parse_table_schema(src: &str) -> Result<StoredRelationMetadata> {
+    let parsed = CozoScriptParser::parse(Rule::table_schema_with_term, src)
+        .map_err(|err| {
+            let span = match err.location {
+                InputLocation::Pos(p) => SourceSpan(p, 0),
+                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+            };
+            ParseError { span }
+        })?
+        .next()
+        .unwrap();
+    let (metadata, _, _) = parse_schema(parsed.into_inner().next().unwrap())?;
+    Ok(metadata)
+}
+
 pub(crate) fn parse_expressions(
     src: &str,
     param_pool: &BTreeMap<String, DataValue>,
@@ -269,7 +404,10 @@ pub(crate) fn parse_expressions(
         .next()
         .unwrap();
 
-    build_expr(parsed.into_inner().next().unwrap(), param_pool)
+    build_expr(
+        parsed.into_inner().next().unwrap(),
+        &ParamPool::Values(param_pool),
+    )
 }
 
 pub(crate) fn parse_script(
@@ -288,10 +426,11 @@ pub(crate) fn parse_script(
         })?
         .next()
         .unwrap();
+    let param_pool = &ParamPool::Values(param_pool);
     Ok(match parsed.as_rule() {
         Rule::query_script => {
             let q = parse_query(parsed.into_inner(), param_pool, fixed_rules, cur_vld)?;
-            CozoScript::Single(q)
+            CozoScript::Single(Box::new(q))
         }
         Rule::imperative_script => {
             let p = parse_imperative_block(parsed, param_pool, fixed_rules, cur_vld)?;
@@ -308,6 +447,40 @@ pub(crate) fn parse_script(
     })
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Only a single query can be prepared")]
+#[diagnostic(code(parser::prepare_non_query))]
+#[diagnostic(help("Imperative scripts and system ops must be run directly"))]
+struct PrepareNonQueryError;
+
+/// Parse a script consisting of a single query, leaving its parameters
+/// to be filled in later through the returned slots.
+pub(crate) fn parse_prepared_query(
+    src: &str,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+) -> Result<(InputProgram, BTreeMap<String, ParamSlot>)> {
+    let parsed = CozoScriptParser::parse(Rule::script, src)
+        .map_err(|err| {
+            let span = match err.location {
+                InputLocation::Pos(p) => SourceSpan(p, 0),
+                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+            };
+            ParseError { span }
+        })?
+        .next()
+        .unwrap();
+    if parsed.as_rule() != Rule::query_script {
+        bail!(PrepareNonQueryError)
+    }
+    let param_pool = ParamPool::Slots(Default::default());
+    let q = parse_query(parsed.into_inner(), &param_pool, fixed_rules, cur_vld)?;
+    let ParamPool::Slots(slots) = param_pool else {
+        unreachable!()
+    };
+    Ok((q, slots.into_inner()))
+}
+
 trait ExtractSpan {
     fn extract_span(&self) -> SourceSpan;
 }
@@ -24,9 +24,10 @@ use crate::data::aggr::{parse_aggr, Aggregation};
 use crate::data::expr::Expr;
 use crate::data::functions::{str2vld, MAX_VALIDITY_TS};
 use crate::data::program::{
-    FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
+    FixedRuleApply, FixedRuleArg, FloatFormat, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
     InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
     QueryAssertion, QueryOutOptions, RelationOp, ReturnMutation, SearchInput, SortDir, Unification,
+    MAX_FLOAT_FMT_PLACES,
 };
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::{Symbol, PROG_ENTRY};
@@ -35,8 +36,9 @@ use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::{FixedRuleHandle, FixedRuleNotFoundError};
 use crate::parse::expr::build_expr;
 use crate::parse::schema::parse_schema;
-use crate::parse::{CozoScriptParser, ExtractSpan, Pair, Pairs, Rule, SourceSpan};
-use crate::runtime::relation::InputRelationHandle;
+use crate::parse::sys::parse_ttl_seconds;
+use crate::parse::{CozoScriptParser, ExtractSpan, Pair, Pairs, ParamPool, Rule, SourceSpan};
+use crate::runtime::relation::{ttl_column, InputRelationHandle};
 use crate::FixedRule;
 
 #[derive(Error, Diagnostic, Debug)]
@@ -54,6 +56,17 @@ struct OptionNotNonNegIntError(&'static str, #[label] SourceSpan);
 #[diagnostic(code(parser::option_not_pos))]
 struct OptionNotPosIntError(&'static str, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Query option {0} requires a positive number")]
+#[diagnostic(code(parser::option_not_pos_num))]
+struct OptionNotPosNumError(&'static str, #[label] SourceSpan);
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Query option float_fmt requires 'string' or a number of decimal places")]
+#[diagnostic(code(parser::bad_float_fmt))]
+#[diagnostic(help("The number of decimal places can be at most {MAX_FLOAT_FMT_PLACES}"))]
+struct BadFloatFormatError(#[label] SourceSpan);
+
 #[derive(Error, Diagnostic, Debug)]
 #[error("Query option {0} requires a boolean")]
 #[diagnostic(code(parser::option_not_bool))]
@@ -103,9 +116,20 @@ fn merge_spans(symbs: &[Symbol]) -> SourceSpan {
     fst
 }
 
+/// Evaluate the value of the `:limit` or `:offset` option
+pub(crate) fn eval_count_option(name: &'static str, expr: Expr) -> Result<usize> {
+    let span = expr.span();
+    let val = expr
+        .eval(vec![])
+        .map_err(|err| OptionNotConstantError(name, span, [err]))?
+        .get_non_neg_int()
+        .ok_or(OptionNotNonNegIntError(name, span))?;
+    Ok(val as usize)
+}
+
 pub(crate) fn parse_query(
     src: Pairs<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<InputProgram> {
@@ -115,6 +139,19 @@ pub(crate) fn parse_query(
 
     let mut stored_relation = None;
     let mut returning_mutation = ReturnMutation::NotReturning;
+    let mut count_span = None;
+    let mut ttl = None;
+    let span = {
+        let mut pairs = src.clone().filter(|p| p.as_rule() != Rule::EOI);
+        match (pairs.next(), pairs.last()) {
+            (Some(first), last) => {
+                let start = first.as_span().start();
+                let end = last.unwrap_or(first).as_span().end();
+                SourceSpan(start, end - start)
+            }
+            (None, _) => SourceSpan::default(),
+        }
+    };
 
     for pair in src {
         match pair.as_rule() {
@@ -288,25 +325,92 @@ pub(crate) fn parse_query(
                     out_opts.sleep = Some(sleep);
                 }
             }
-            Rule::limit_option => {
+            Rule::max_fanout_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
-                let limit = build_expr(pair, param_pool)?
+                let max_fanout = build_expr(pair, param_pool)?
                     .eval_to_const()
-                    .map_err(|err| OptionNotConstantError("limit", span, [err]))?
-                    .get_non_neg_int()
-                    .ok_or(OptionNotNonNegIntError("limit", span))?;
-                out_opts.limit = Some(limit as usize);
+                    .map_err(|err| OptionNotConstantError("max_fanout", span, [err]))?
+                    .get_float()
+                    .ok_or(OptionNotPosNumError("max_fanout", span))?;
+                ensure!(max_fanout > 0., OptionNotPosNumError("max_fanout", span));
+                out_opts.max_fanout = Some(max_fanout);
             }
-            Rule::offset_option => {
+            Rule::max_join_matches_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
-                let offset = build_expr(pair, param_pool)?
+                let max_matches = build_expr(pair, param_pool)?
                     .eval_to_const()
-                    .map_err(|err| OptionNotConstantError("offset", span, [err]))?
+                    .map_err(|err| OptionNotConstantError("max_join_matches", span, [err]))?
                     .get_non_neg_int()
-                    .ok_or(OptionNotNonNegIntError("offset", span))?;
-                out_opts.offset = Some(offset as usize);
+                    .ok_or(OptionNotNonNegIntError("max_join_matches", span))?;
+                out_opts.max_join_matches = Some(max_matches as usize);
+            }
+            Rule::max_eval_threads_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let max_threads = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("max_eval_threads", span, [err]))?
+                    .get_int()
+                    .ok_or(OptionNotPosIntError("max_eval_threads", span))?;
+                ensure!(
+                    max_threads > 0,
+                    OptionNotPosIntError("max_eval_threads", span)
+                );
+                out_opts.max_eval_threads = Some(max_threads as usize);
+            }
+            Rule::mem_limit_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let mem_limit = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("mem_limit", span, [err]))?
+                    .get_int()
+                    .ok_or(OptionNotPosIntError("mem_limit", span))?;
+                ensure!(mem_limit > 0, OptionNotPosIntError("mem_limit", span));
+                out_opts.mem_limit = Some(mem_limit as usize);
+            }
+            Rule::float_fmt_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let val = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("float_fmt", span, [err]))?;
+                let fmt = match val {
+                    DataValue::Str(s) if s == "string" => FloatFormat::String,
+                    val => match val.get_non_neg_int() {
+                        Some(places) if places as usize <= MAX_FLOAT_FMT_PLACES => {
+                            FloatFormat::Fixed(places as usize)
+                        }
+                        _ => bail!(BadFloatFormatError(span)),
+                    },
+                };
+                out_opts.float_fmt = Some(fmt);
+            }
+            Rule::limit_option => {
+                let expr = build_expr(pair.into_inner().next().unwrap(), param_pool)?;
+                // parameters of a prepared query are only known when it is executed
+                if expr.has_params() {
+                    out_opts
+                        .paging_params
+                        .get_or_insert_with(Default::default)
+                        .limit = Some(expr);
+                } else {
+                    out_opts.limit = Some(eval_count_option("limit", expr)?);
+                }
+            }
+            Rule::offset_option => {
+                let expr = build_expr(pair.into_inner().next().unwrap(), param_pool)?;
+                // parameters of a prepared query are only known when it is executed
+                if expr.has_params() {
+                    out_opts
+                        .paging_params
+                        .get_or_insert_with(Default::default)
+                        .offset = Some(expr);
+                } else {
+                    out_opts.offset = Some(eval_count_option("offset", expr)?);
+                }
             }
             Rule::sort_option => {
                 for part in pair.into_inner() {
@@ -330,12 +434,28 @@ pub(crate) fn parse_query(
             Rule::returning_option => {
                 returning_mutation = ReturnMutation::Returning;
             }
+            Rule::profile_option => {
+                out_opts.profile = true;
+            }
+            Rule::with_types_option => {
+                out_opts.with_types = true;
+            }
+            Rule::ttl_option => {
+                let span = pair.extract_span();
+                let seconds = parse_ttl_seconds(pair.into_inner().next().unwrap(), param_pool)?;
+                ttl = Some((seconds, span));
+            }
+            Rule::count_option => {
+                out_opts.count = true;
+                count_span = Some(pair.extract_span());
+            }
             Rule::relation_option => {
                 let span = pair.extract_span();
                 let mut args = pair.into_inner();
                 let op = match args.next().unwrap().as_rule() {
                     Rule::relation_create => RelationOp::Create,
                     Rule::relation_replace => RelationOp::Replace,
+                    Rule::relation_create_or_replace => RelationOp::CreateOrReplace,
                     Rule::relation_put => RelationOp::Put,
                     Rule::relation_insert => RelationOp::Insert,
                     Rule::relation_update => RelationOp::Update,
@@ -351,24 +471,21 @@ pub(crate) fn parse_query(
                 match args.next() {
                     None => stored_relation = Some(Left((name, span, op))),
                     Some(schema_p) => {
-                        let (mut metadata, mut key_bindings, mut dep_bindings) =
-                            parse_schema(schema_p)?;
-                        if !matches!(op, RelationOp::Create | RelationOp::Replace) {
-                            key_bindings.extend(dep_bindings);
-                            dep_bindings = vec![];
-                            metadata.keys.extend(metadata.non_keys);
-                            metadata.non_keys = vec![];
-                        }
-                        stored_relation = Some(Right((
-                            InputRelationHandle {
-                                name,
-                                metadata,
-                                key_bindings,
-                                dep_bindings,
-                                span,
-                            },
+                        let (metadata, key_bindings, dep_bindings) = parse_schema(schema_p)?;
+                        let mut handle = InputRelationHandle {
+                            name,
+                            metadata,
+                            key_bindings,
+                            dep_bindings,
+                            span,
+                        };
+                        if !matches!(
                             op,
-                        )))
+                            RelationOp::Create | RelationOp::Replace | RelationOp::CreateOrReplace
+                        ) {
+                            handle = handle.with_flattened_keys();
+                        }
+                        stored_relation = Some(Right((handle, op)))
                     }
                 }
             }
@@ -386,6 +503,13 @@ pub(crate) fn parse_query(
                 );
                 out_opts.assertion = Some(QueryAssertion::AssertSome(pair.extract_span()))
             }
+            Rule::assert_count_option => {
+                let span = pair.extract_span();
+                ensure!(out_opts.assertion.is_none(), DuplicateQueryAssertion(span));
+                let expr = build_expr(pair.into_inner().next().unwrap(), param_pool)?;
+                let n = eval_count_option("assert count", expr)?;
+                out_opts.assertion = Some(QueryAssertion::AssertCount(n, span))
+            }
             Rule::disable_magic_rewrite_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
@@ -405,6 +529,7 @@ pub(crate) fn parse_query(
         prog: progs,
         out_opts,
         disable_magic_rewrite,
+        span,
     };
 
     if prog.prog.is_empty() {
@@ -414,7 +539,7 @@ pub(crate) fn parse_query(
                 dep_bindings,
                 ..
             },
-            RelationOp::Create,
+            RelationOp::Create | RelationOp::CreateOrReplace,
             _,
         )) = &prog.out_opts.store_relation
         {
@@ -461,14 +586,42 @@ pub(crate) fn parse_query(
         Some(Right((h, o))) => prog.out_opts.store_relation = Some((h, o, returning_mutation)),
     }
 
+    if let Some((seconds, span)) = ttl {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The option ':ttl' can only be used when creating a stored relation")]
+        #[diagnostic(code(parser::ttl_without_creation))]
+        #[diagnostic(help("Use `::ttl` to change the time to live of an existing relation"))]
+        struct TtlWithoutCreation(#[label] SourceSpan);
+
+        match &mut prog.out_opts.store_relation {
+            Some((
+                handle,
+                RelationOp::Create | RelationOp::Replace | RelationOp::CreateOrReplace,
+                _,
+            )) => handle.metadata.non_keys.push(ttl_column(seconds)),
+            _ => bail!(TtlWithoutCreation(span)),
+        }
+    }
+
     if prog.prog.is_empty() {
-        if let Some((handle, RelationOp::Create, _)) = &prog.out_opts.store_relation {
+        if let Some((handle, RelationOp::Create | RelationOp::CreateOrReplace, _)) =
+            &prog.out_opts.store_relation
+        {
             let mut bindings = handle.dep_bindings.clone();
             bindings.extend_from_slice(&handle.key_bindings);
             make_empty_const_rule(&mut prog, &bindings);
         }
     }
 
+    if let (Some(span), Some(_)) = (count_span, &prog.out_opts.store_relation) {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The option ':count' cannot be used together with a mutation of a stored relation")]
+        #[diagnostic(code(parser::count_with_mutation))]
+        struct CountWithMutation(#[label] SourceSpan);
+
+        bail!(CountWithMutation(span))
+    }
+
     if !prog.out_opts.sorters.is_empty() {
         #[derive(Debug, Error, Diagnostic)]
         #[error("Sort key '{0}' not found")]
@@ -533,7 +686,7 @@ pub(crate) fn parse_query(
 
 fn parse_rule(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
     cur_vld: ValidityTs,
 ) -> Result<(Symbol, InputInlineRule)> {
     let span = src.extract_span();
@@ -573,7 +726,7 @@ fn parse_rule(
 
 fn parse_disjunction(
     pair: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
     cur_vld: ValidityTs,
     ignored_counter: &mut u32,
 ) -> Result<InputAtom> {
@@ -594,7 +747,7 @@ fn parse_disjunction(
 
 fn parse_atom(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
     cur_vld: ValidityTs,
     ignored_counter: &mut u32,
 ) -> Result<InputAtom> {
@@ -778,7 +931,7 @@ fn parse_atom(
 
 fn extract_named_apply_arg(
     pair: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
 ) -> Result<(SmartString<LazyCompact>, Expr)> {
     let mut inner = pair.into_inner();
     let name_p = inner.next().unwrap();
@@ -795,7 +948,7 @@ fn extract_named_apply_arg(
 
 fn parse_rule_head(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
 ) -> Result<(
     Symbol,
     Vec<Symbol>,
@@ -820,7 +973,7 @@ struct AggrNotFound(String, #[label] SourceSpan);
 
 fn parse_rule_head_arg(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
 ) -> Result<(Symbol, Option<(Aggregation, Vec<DataValue>)>)> {
     let src = src.into_inner().next().unwrap();
     Ok(match src.as_rule() {
@@ -854,7 +1007,7 @@ struct BadValiditySpecification(#[label] SourceSpan);
 
 fn parse_fixed_rule(
     src: Pair<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
     fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<(Symbol, FixedRuleApply)> {
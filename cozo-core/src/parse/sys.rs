@@ -18,28 +18,48 @@ use thiserror::Error;
 use crate::data::program::InputProgram;
 use crate::data::relation::VecElementType;
 use crate::data::symb::Symbol;
-use crate::data::value::{DataValue, ValidityTs};
+use crate::data::value::ValidityTs;
 use crate::fts::TokenizerConfig;
 use crate::parse::expr::{build_expr, parse_string};
 use crate::parse::query::parse_query;
-use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
+use crate::parse::{ExtractSpan, Pair, Pairs, ParamPool, Rule, SourceSpan};
 use crate::runtime::relation::AccessLevel;
+use crate::storage::CompactOptions;
 use crate::{Expr, FixedRule};
 
 #[derive(Debug)]
 pub(crate) enum SysOp {
-    Compact,
+    Compact(Option<CompactTarget>, CompactOptions),
     ListColumns(Symbol),
+    RelationStats(Symbol),
+    SetTtl(Symbol, f64),
+    ExpireRelation(Symbol),
     ListIndices(Symbol),
     ListRelations,
     ListRunning,
+    CommitStats,
+    ListRelationIds,
+    ReclaimRelationIds,
+    /// Orphan cleanup followed by a storage-level vacuum, see `Db::vacuum`
+    Vacuum,
+    MigrateEncoding(Symbol),
+    SetOp(RelationSetOp, Symbol, Symbol, Symbol),
     ListFixedRules,
     KillRunning(u64),
+    RunningPlan(u64),
+    KillMatching(String),
     Explain(Box<InputProgram>),
+    ExplainAnalyze(Box<InputProgram>),
     RemoveRelation(Vec<Symbol>),
     RenameRelation(Vec<(Symbol, Symbol)>),
     ShowTrigger(Symbol),
-    SetTriggers(Symbol, Vec<String>, Vec<String>, Vec<String>),
+    ListAllTriggers,
+    SetTriggers(
+        Symbol,
+        Vec<GuardedTrigger>,
+        Vec<GuardedTrigger>,
+        Vec<String>,
+    ),
     SetAccessLevel(Vec<Symbol>, AccessLevel),
     CreateIndex(Symbol, Symbol, Vec<Symbol>),
     CreateVectorIndex(HnswIndexConfig),
@@ -49,6 +69,89 @@ pub(crate) enum SysOp {
     DescribeRelation(Symbol, SmartString<LazyCompact>)
 }
 
+/// The key range compacted by `::compact :rel`
+#[derive(Debug)]
+pub(crate) enum CompactTarget {
+    /// A stored relation or index, or a relation removed since the database was opened
+    Named(Symbol),
+    /// The relation with the id, as listed by `::relation_ids`, whether it still exists or not
+    Id(u64),
+}
+
+impl SysOp {
+    /// The command of this op if it changes the database, `None` if it only reads
+    pub(crate) fn mutation_command(&self) -> Option<&'static str> {
+        match self {
+            SysOp::Compact(..) => Some("::compact"),
+            SysOp::ReclaimRelationIds => Some("::reclaim_ids"),
+            SysOp::Vacuum => Some("::vacuum"),
+            SysOp::MigrateEncoding(_) => Some("::migrate_encoding"),
+            SysOp::SetOp(op, _, _, _) => Some(match op {
+                RelationSetOp::Union => "::union",
+                RelationSetOp::Intersect => "::intersect",
+                RelationSetOp::Except => "::except",
+            }),
+            SysOp::RemoveRelation(_) => Some("::remove"),
+            SysOp::RenameRelation(_) => Some("::rename"),
+            SysOp::SetTriggers(_, _, _, _) => Some("::set_triggers"),
+            SysOp::SetAccessLevel(_, _) => Some("::access_level"),
+            SysOp::SetTtl(_, _) => Some("::ttl"),
+            SysOp::ExpireRelation(_) => Some("::expire"),
+            SysOp::DescribeRelation(_, _) => Some("::describe"),
+            SysOp::CreateIndex(_, _, _) => Some("::index create"),
+            SysOp::CreateVectorIndex(_) => Some("::hnsw create"),
+            SysOp::CreateFtsIndex(_) => Some("::fts create"),
+            SysOp::CreateMinHashLshIndex(_) => Some("::lsh create"),
+            SysOp::RemoveIndex(_, _) => Some("::index drop"),
+            SysOp::ListColumns(_)
+            | SysOp::RelationStats(_)
+            | SysOp::ListIndices(_)
+            | SysOp::ListRelations
+            | SysOp::ListRunning
+            | SysOp::CommitStats
+            | SysOp::ListRelationIds
+            | SysOp::ListFixedRules
+            | SysOp::KillRunning(_)
+            | SysOp::RunningPlan(_)
+            | SysOp::KillMatching(_)
+            | SysOp::Explain(_)
+            | SysOp::ExplainAnalyze(_)
+            | SysOp::ShowTrigger(_)
+            | SysOp::ListAllTriggers => None,
+        }
+    }
+    /// Collects the queries explained by the op, the only ops that contain queries
+    pub(crate) fn into_programs(self, collector: &mut Vec<InputProgram>) {
+        if let SysOp::Explain(prog) | SysOp::ExplainAnalyze(prog) = self {
+            collector.push(*prog)
+        }
+    }
+}
+
+/// The script of a put or rm trigger, with the guard expression selecting the affected rows
+/// the script sees in `_new` and `_old`. The script does not run if no row is selected.
+pub(crate) type GuardedTrigger = (String, Option<String>);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Replace triggers cannot have a guard")]
+#[diagnostic(code(parser::replace_trigger_guard))]
+#[diagnostic(help(
+    "Replace triggers run before the relation is replaced, without any rows to guard on"
+))]
+struct ReplaceTriggerGuard(#[label] SourceSpan);
+
+/// Set operations between two stored relations with the same schema.
+/// Rows are compared as whole tuples.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum RelationSetOp {
+    /// Rows in either relation
+    Union,
+    /// Rows in both relations
+    Intersect,
+    /// Rows in the first relation but not in the second
+    Except,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct FtsIndexConfig {
     pub(crate) base_relation: SmartString<LazyCompact>,
@@ -101,16 +204,99 @@ pub(crate) enum HnswDistance {
 #[diagnostic(code(parser::not_proc_id))]
 struct ProcessIdError(String, #[label] SourceSpan);
 
+#[derive(Debug, Diagnostic, Error)]
+#[error("The time to live must be a positive number of seconds")]
+#[diagnostic(code(parser::bad_ttl))]
+struct BadTtlError(#[label] SourceSpan);
+
+/// The seconds of a time to live, given to `::ttl` or to the `:ttl` query option
+pub(crate) fn parse_ttl_seconds(pair: Pair<'_>, param_pool: &ParamPool<'_>) -> Result<f64> {
+    let span = pair.extract_span();
+    Ok(build_expr(pair, param_pool)?
+        .eval_to_const()?
+        .get_float()
+        .filter(|secs| secs.is_finite() && *secs > 0.)
+        .ok_or(BadTtlError(span))?)
+}
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("The pattern to kill queries by must not be empty")]
+#[diagnostic(code(parser::empty_kill_pattern))]
+#[diagnostic(help("An empty pattern would kill every running query, use `::kill` instead"))]
+struct EmptyKillPattern(#[label] SourceSpan);
+
 pub(crate) fn parse_sys(
     mut src: Pairs<'_>,
-    param_pool: &BTreeMap<String, DataValue>,
+    param_pool: &ParamPool<'_>,
     algorithms: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
     cur_vld: ValidityTs,
 ) -> Result<SysOp> {
     let inner = src.next().unwrap();
     Ok(match inner.as_rule() {
-        Rule::compact_op => SysOp::Compact,
+        Rule::compact_op => {
+            let mut rel = None;
+            let mut options = CompactOptions::default();
+            for pair in inner.into_inner() {
+                match pair.as_rule() {
+                    Rule::index_opt_field => {}
+                    Rule::compact_relation_id => {
+                        let id = pair
+                            .as_str()
+                            .parse::<u64>()
+                            .map_err(|_| miette!("Invalid relation id {}", pair.as_str()))?;
+                        rel = Some(CompactTarget::Id(id));
+                        continue;
+                    }
+                    _ => {
+                        rel = Some(CompactTarget::Named(Symbol::new(
+                            pair.as_str(),
+                            pair.extract_span(),
+                        )));
+                        continue;
+                    }
+                }
+                let mut opt_inner = pair.into_inner();
+                let opt_name = opt_inner.next().unwrap();
+                let mut expr = build_expr(opt_inner.next().unwrap(), param_pool)?;
+                expr.partial_eval()?;
+                let v = expr.eval_to_const()?.get_bool();
+                match opt_name.as_str() {
+                    "bottommost" => {
+                        options.bottommost =
+                            v.ok_or_else(|| miette!("bottommost must be a boolean"))?
+                    }
+                    "exclusive" => {
+                        options.exclusive =
+                            v.ok_or_else(|| miette!("exclusive must be a boolean"))?
+                    }
+                    _ => bail!("Unknown option {} for compaction", opt_name.as_str()),
+                }
+            }
+            SysOp::Compact(rel, options)
+        }
         Rule::running_op => SysOp::ListRunning,
+        Rule::commit_stats_op => SysOp::CommitStats,
+        Rule::relation_ids_op => SysOp::ListRelationIds,
+        Rule::reclaim_ids_op => SysOp::ReclaimRelationIds,
+        Rule::vacuum_op => SysOp::Vacuum,
+        Rule::set_op => {
+            let mut src = inner.into_inner();
+            let op = match src.next().unwrap().as_rule() {
+                Rule::set_union => RelationSetOp::Union,
+                Rule::set_intersect => RelationSetOp::Intersect,
+                Rule::set_except => RelationSetOp::Except,
+                _ => unreachable!(),
+            };
+            let mut rels = src.map(|p| Symbol::new(p.as_str(), p.extract_span()));
+            let left = rels.next().unwrap();
+            let right = rels.next().unwrap();
+            let out = rels.next().unwrap();
+            SysOp::SetOp(op, left, right, out)
+        }
+        Rule::migrate_encoding_op => {
+            let rel_p = inner.into_inner().next().unwrap();
+            SysOp::MigrateEncoding(Symbol::new(rel_p.as_str(), rel_p.extract_span()))
+        }
         Rule::kill_op => {
             let i_expr = inner.into_inner().next().unwrap();
             let i_val = build_expr(i_expr, param_pool)?;
@@ -120,6 +306,26 @@ pub(crate) fn parse_sys(
                 .ok_or_else(|| miette!("Process ID must be an integer"))?;
             SysOp::KillRunning(i_val as u64)
         }
+        Rule::running_plan_op => {
+            let i_expr = inner.into_inner().next().unwrap();
+            let i_val = build_expr(i_expr, param_pool)?;
+            let i_val = i_val.eval_to_const()?;
+            let i_val = i_val
+                .get_int()
+                .ok_or_else(|| miette!("Process ID must be an integer"))?;
+            SysOp::RunningPlan(i_val as u64)
+        }
+        Rule::kill_matching_op => {
+            let pattern_p = inner.into_inner().next().unwrap();
+            let span = pattern_p.extract_span();
+            let pattern = build_expr(pattern_p, param_pool)?;
+            let pattern = pattern.eval_to_const()?;
+            let pattern = pattern
+                .get_str()
+                .ok_or_else(|| miette!("The pattern to kill queries by must be a string"))?;
+            ensure!(!pattern.trim().is_empty(), EmptyKillPattern(span));
+            SysOp::KillMatching(pattern.to_string())
+        }
         Rule::explain_op => {
             let prog = parse_query(
                 inner.into_inner().next().unwrap().into_inner(),
@@ -129,6 +335,15 @@ pub(crate) fn parse_sys(
             )?;
             SysOp::Explain(Box::new(prog))
         }
+        Rule::explain_analyze_op => {
+            let prog = parse_query(
+                inner.into_inner().next().unwrap().into_inner(),
+                param_pool,
+                algorithms,
+                cur_vld,
+            )?;
+            SysOp::ExplainAnalyze(Box::new(prog))
+        }
         Rule::describe_relation_op => {
             let mut inner = inner.into_inner();
             let rels_p = inner.next().unwrap();
@@ -140,6 +355,7 @@ pub(crate) fn parse_sys(
             SysOp::DescribeRelation(rel, description)
         }
         Rule::list_relations_op => SysOp::ListRelations,
+        Rule::list_triggers_op => SysOp::ListAllTriggers,
         Rule::remove_relations_op => {
             let rel = inner
                 .into_inner()
@@ -153,6 +369,24 @@ pub(crate) fn parse_sys(
             let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
             SysOp::ListColumns(rel)
         }
+        Rule::relation_stats_op => {
+            let rels_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            SysOp::RelationStats(rel)
+        }
+        Rule::ttl_op => {
+            let mut src = inner.into_inner();
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let secs_p = src.next().unwrap();
+            let seconds = parse_ttl_seconds(secs_p, param_pool)?;
+            SysOp::SetTtl(rel, seconds)
+        }
+        Rule::expire_op => {
+            let rels_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            SysOp::ExpireRelation(rel)
+        }
         Rule::list_indices_op => {
             let rels_p = inner.into_inner().next().unwrap();
             let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
@@ -203,7 +437,15 @@ pub(crate) fn parse_sys(
             for clause in src {
                 let mut clause_inner = clause.into_inner();
                 let op = clause_inner.next().unwrap();
-                let script = clause_inner.next().unwrap();
+                let mut script = clause_inner.next().unwrap();
+                let guard = if script.as_rule() == Rule::trigger_guard {
+                    let span = script.extract_span();
+                    let source = script.into_inner().next().unwrap().as_str();
+                    script = clause_inner.next().unwrap();
+                    Some((source.to_string(), span))
+                } else {
+                    None
+                };
                 let script_str = script.as_str();
                 parse_query(
                     script.into_inner(),
@@ -212,9 +454,14 @@ pub(crate) fn parse_sys(
                     cur_vld,
                 )?;
                 match op.as_rule() {
-                    Rule::trigger_put => puts.push(script_str.to_string()),
-                    Rule::trigger_rm => rms.push(script_str.to_string()),
-                    Rule::trigger_replace => replaces.push(script_str.to_string()),
+                    Rule::trigger_put => puts.push((script_str.to_string(), guard.map(|(g, _)| g))),
+                    Rule::trigger_rm => rms.push((script_str.to_string(), guard.map(|(g, _)| g))),
+                    Rule::trigger_replace => {
+                        if let Some((_, span)) = guard {
+                            bail!(ReplaceTriggerGuard(span))
+                        }
+                        replaces.push(script_str.to_string())
+                    }
                     r => unreachable!("{:?}", r),
                 }
             }
@@ -225,12 +225,13 @@ impl<'a> SessionTx<'a> {
                             store.access_level
                         ));
                     }
+                    let args = store.positional_args(&rel_app.args, || gen_symb(rel_app.span));
                     ensure!(
-                        store.arity() == rel_app.args.len(),
+                        store.arity() == args.len(),
                         ArityMismatch(
                             rel_app.name.to_string(),
                             store.arity(),
-                            rel_app.args.len(),
+                            args.len(),
                             rel_app.span
                         )
                     );
@@ -245,7 +246,7 @@ impl<'a> SessionTx<'a> {
                     // used for choosing indices
                     let mut join_indices = vec![];
 
-                    for (i, var) in rel_app.args.iter().enumerate() {
+                    for (i, var) in args.iter().enumerate() {
                         if seen_variables.contains(var) {
                             prev_joiner_vars.push(var.clone());
                             let rk = gen_symb(var.span);
@@ -393,12 +394,13 @@ impl<'a> SessionTx<'a> {
                 }
                 MagicAtom::NegatedRelation(rel_app) => {
                     let store = self.get_relation(&rel_app.name, false)?;
+                    let args = store.positional_args(&rel_app.args, || gen_symb(rel_app.span));
                     ensure!(
-                        store.arity() == rel_app.args.len(),
+                        store.arity() == args.len(),
                         ArityMismatch(
                             rel_app.name.to_string(),
                             store.arity(),
-                            rel_app.args.len(),
+                            args.len(),
                             rel_app.span
                         )
                     );
@@ -414,7 +416,7 @@ impl<'a> SessionTx<'a> {
                     // used for choosing indices
                     let mut join_indices = vec![];
 
-                    for (i, var) in rel_app.args.iter().enumerate() {
+                    for (i, var) in args.iter().enumerate() {
                         if seen_variables.contains(var) {
                             prev_joiner_vars.push(var.clone());
                             let rk = gen_symb(var.span);
@@ -9,12 +9,15 @@
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 
 use itertools::Itertools;
 use log::{debug, trace};
-use miette::Result;
+use miette::{bail, Diagnostic, Result};
 #[cfg(not(target_arch = "wasm32"))]
 use rayon::prelude::*;
+use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
 use crate::data::program::{MagicSymbol, NoEntryError};
@@ -26,7 +29,7 @@ use crate::parse::SourceSpan;
 use crate::query::compile::{
     AggrKind, CompiledProgram, CompiledRule, CompiledRuleSet, ContainedRuleMultiplicity,
 };
-use crate::runtime::db::Poison;
+use crate::runtime::db::{AggrProgress, EvalProgress, Poison};
 use crate::runtime::temp_store::{EpochStore, MeetAggrStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 
@@ -61,17 +64,46 @@ impl QueryLimiter {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Query used an estimated {used} bytes for derived tuples, exceeding the memory limit of {limit} bytes")]
+#[diagnostic(code(eval::mem_limit))]
+#[diagnostic(help(
+    "Raise the limit with the `:mem_limit` option, or make the query derive fewer tuples"
+))]
+pub(crate) struct MemLimitExceeded {
+    pub(crate) used: usize,
+    pub(crate) limit: usize,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Semi-naive iteration {iteration} of stratum {stratum} produced {cur} new tuples from {prev}, exceeding the maximum fan-out of {max_fanout}")]
+#[diagnostic(code(eval::fanout_exceeded))]
+#[diagnostic(help("A recursive rule is probably multiplying its results at each step"))]
+pub(crate) struct FanoutExceeded {
+    pub(crate) stratum: usize,
+    pub(crate) iteration: u32,
+    pub(crate) prev: usize,
+    pub(crate) cur: usize,
+    pub(crate) max_fanout: f64,
+}
+
 impl<'a> SessionTx<'a> {
+    /// Evaluate the strata in order. If `stratum_times` is given, the seconds
+    /// taken by each stratum are pushed onto it.
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn stratified_magic_evaluate(
         &self,
         strata: &[CompiledProgram],
         store_lifetimes: BTreeMap<MagicSymbol, usize>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
+        max_fanout: Option<f64>,
         poison: Poison,
+        mut stratum_times: Option<&mut Vec<f64>>,
     ) -> Result<(EpochStore, bool)> {
         let mut stores: BTreeMap<MagicSymbol, EpochStore> = BTreeMap::new();
         let mut early_return = false;
+        let mut mem_used = 0;
         for (stratum, cur_prog) in strata.iter().enumerate() {
             if stratum > 0 {
                 // remove stores that have outlived their usefulness!
@@ -95,13 +127,22 @@ impl<'a> SessionTx<'a> {
                 stores.insert(rule_name.clone(), store);
             }
             debug!("stratum {}", stratum);
+            #[cfg(not(target_arch = "wasm32"))]
+            let start = Instant::now();
             early_return = self.semi_naive_magic_evaluate(
+                stratum,
                 cur_prog,
                 &mut stores,
                 total_num_to_take,
                 num_to_skip,
+                max_fanout,
+                &mut mem_used,
                 poison.clone(),
             )?;
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(times) = stratum_times.as_deref_mut() {
+                times.push(start.elapsed().as_secs_f64());
+            }
         }
         let entry_symbol = MagicSymbol::Muggle {
             inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
@@ -112,10 +153,13 @@ impl<'a> SessionTx<'a> {
     /// returns true if early return is activated
     fn semi_naive_magic_evaluate(
         &self,
+        stratum: usize,
         prog: &CompiledProgram,
         stores: &mut BTreeMap<MagicSymbol, EpochStore>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
+        max_fanout: Option<f64>,
+        mem_used: &mut usize,
         poison: Poison,
     ) -> Result<bool> {
         let limiter = QueryLimiter {
@@ -125,6 +169,8 @@ impl<'a> SessionTx<'a> {
         };
 
         let used_limiter: AtomicBool = false.into();
+        let mut prev_delta_size = 0;
+        let mut stratum_tuples = 0;
 
         for epoch in 0u32.. {
             debug!("epoch {}", epoch);
@@ -289,15 +335,72 @@ impl<'a> SessionTx<'a> {
                 }
             }
             let mut changed = false;
+            let mut delta_size = 0;
             for (k, new_store) in to_merge {
                 let old_store = stores.get_mut(k).unwrap();
                 old_store.merge_in(new_store)?;
                 trace!("delta for {}: {}", k, old_store.has_delta());
                 changed |= old_store.has_delta();
+                delta_size += old_store.delta_len();
+                if self.mem_limit.is_some() {
+                    *mem_used += old_store
+                        .delta_all_iter()
+                        .map(|t| t.into_iter().map(|v| v.estimated_size()).sum::<usize>())
+                        .sum::<usize>();
+                }
+            }
+            if let Some(limit) = self.mem_limit {
+                if *mem_used > limit {
+                    poison.kill();
+                    bail!(MemLimitExceeded {
+                        used: *mem_used,
+                        limit,
+                    })
+                }
+            }
+            if let Some(on_progress) = &self.aggr_progress {
+                for (k, compiled_ruleset) in prog {
+                    if compiled_ruleset.aggr_kind() != AggrKind::Meet {
+                        continue;
+                    }
+                    let store = stores.get(k).unwrap();
+                    if store.has_delta() {
+                        on_progress(AggrProgress {
+                            rule: k.symbol().name.to_string(),
+                            stratum,
+                            iteration: epoch,
+                            rows: store.delta_all_iter().map(|t| t.into_tuple()).collect_vec(),
+                        });
+                    }
+                }
+            }
+            stratum_tuples += delta_size;
+            if let Some(on_progress) = &self.eval_progress {
+                let go_on = on_progress(EvalProgress {
+                    stratum,
+                    iteration: epoch,
+                    tuples: stratum_tuples,
+                });
+                if !go_on {
+                    poison.kill();
+                    poison.check()?;
+                }
             }
             if !changed {
                 break;
             }
+            if let Some(max_fanout) = max_fanout {
+                if prev_delta_size > 0 && delta_size as f64 > prev_delta_size as f64 * max_fanout {
+                    bail!(FanoutExceeded {
+                        stratum,
+                        iteration: epoch,
+                        prev: prev_delta_size,
+                        cur: delta_size,
+                        max_fanout,
+                    })
+                }
+            }
+            prev_delta_size = delta_size;
         }
         Ok(used_limiter.load(Ordering::Acquire))
     }
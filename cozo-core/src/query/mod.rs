@@ -13,6 +13,7 @@ pub(crate) mod logical;
 pub(crate) mod magic;
 pub(crate) mod ra;
 pub(crate) mod reorder;
+pub(crate) mod simple_aggr;
 pub(crate) mod sort;
 pub(crate) mod stored;
 pub(crate) mod stratify;
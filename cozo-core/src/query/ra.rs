@@ -6,9 +6,14 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::cell::{Cell, RefCell};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter, Write};
 use std::iter;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+#[allow(unused_imports)]
+use std::time::{Duration, Instant};
 
 use either::{Left, Right};
 use itertools::Itertools;
@@ -24,6 +29,7 @@ use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
+use crate::runtime::explain::JoinKind;
 use crate::runtime::minhash_lsh::LshSearch;
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::temp_store::EpochStore;
@@ -761,13 +767,13 @@ impl InlineFixedRA {
 }
 
 impl InlineFixedRA {
-    pub(crate) fn join_type(&self) -> &str {
+    pub(crate) fn join_type(&self) -> JoinKind {
         if self.data.is_empty() {
-            "null_join"
+            JoinKind::Null
         } else if self.data.len() == 1 {
-            "singleton_join"
+            JoinKind::Singleton
         } else {
-            "fixed_join"
+            JoinKind::Fixed
         }
     }
     pub(crate) fn join<'a>(
@@ -1784,11 +1790,12 @@ impl Debug for Joiner {
 }
 
 impl Joiner {
-    pub(crate) fn as_map(&self) -> BTreeMap<&str, &str> {
+    /// The joined bindings, left then right
+    pub(crate) fn pairs(&self) -> Vec<(String, String)> {
         self.left_keys
             .iter()
             .zip(self.right_keys.iter())
-            .map(|(l, r)| (&l.name as &str, &r.name as &str))
+            .map(|(l, r)| (l.name.to_string(), r.name.to_string()))
             .collect()
     }
     pub(crate) fn join_indices(
@@ -1901,6 +1908,34 @@ impl RelAlgebra {
         tx: &'a SessionTx<'_>,
         delta_rule: Option<&MagicSymbol>,
         stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+    ) -> Result<TupleIter<'a>> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(collector) = &tx.ra_stats {
+            let start = Instant::now();
+            let inner = self.iter_unmeasured(tx, delta_rule, stores)?;
+            let stats = RaNodeStats {
+                rows: 0,
+                calls: 1,
+                time: start.elapsed(),
+            };
+            return Ok(Box::new(MeasuredIter {
+                inner,
+                node: self.stats_key(),
+                stats,
+                collector,
+            }));
+        }
+        self.iter_unmeasured(tx, delta_rule, stores)
+    }
+    /// Identifies the node in the statistics collected by `::explain_analyze`
+    pub(crate) fn stats_key(&self) -> usize {
+        self as *const RelAlgebra as usize
+    }
+    fn iter_unmeasured<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
     ) -> Result<TupleIter<'a>> {
         match self {
             RelAlgebra::Fixed(f) => Ok(Box::new(f.data.iter().map(|t| Ok(t.clone())))),
@@ -1919,6 +1954,54 @@ impl RelAlgebra {
     }
 }
 
+/// Rows produced by a relational algebra node and the time spent producing them,
+/// collected during `::explain_analyze`
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct RaNodeStats {
+    pub(crate) rows: u64,
+    /// Number of times the node was iterated
+    pub(crate) calls: u64,
+    /// Includes the time spent in the children of the node
+    pub(crate) time: Duration,
+}
+
+pub(crate) type RaStatsCollector = Arc<Mutex<BTreeMap<usize, RaNodeStats>>>;
+
+#[cfg(not(target_arch = "wasm32"))]
+struct MeasuredIter<'a> {
+    inner: TupleIter<'a>,
+    node: usize,
+    stats: RaNodeStats,
+    collector: &'a RaStatsCollector,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Iterator for MeasuredIter<'_> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let ret = self.inner.next();
+        self.stats.time += start.elapsed();
+        if let Some(Ok(_)) = ret {
+            self.stats.rows += 1;
+        }
+        ret
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for MeasuredIter<'_> {
+    // counts are kept locally and only merged once, when the node is done
+    fn drop(&mut self) {
+        let mut collected = self.collector.lock().unwrap();
+        let entry = collected.entry(self.node).or_default();
+        entry.rows += self.stats.rows;
+        entry.calls += self.stats.calls;
+        entry.time += self.stats.time;
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct NegJoin {
     pub(crate) left: RelAlgebra,
@@ -1942,7 +2025,7 @@ impl NegJoin {
         Ok(())
     }
 
-    pub(crate) fn join_type(&self) -> &str {
+    pub(crate) fn join_type(&self) -> JoinKind {
         match &self.right {
             RelAlgebra::TempStore(_) => {
                 let join_indices = self
@@ -1953,9 +2036,9 @@ impl NegJoin {
                     )
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
-                    "mem_neg_prefix_join"
+                    JoinKind::MemNegPrefix
                 } else {
-                    "mem_neg_mat_join"
+                    JoinKind::MemNegMat
                 }
             }
             RelAlgebra::Stored(_) => {
@@ -1967,9 +2050,9 @@ impl NegJoin {
                     )
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
-                    "stored_neg_prefix_join"
+                    JoinKind::StoredNegPrefix
                 } else {
-                    "stored_neg_mat_join"
+                    JoinKind::StoredNegMat
                 }
             }
             _ => {
@@ -2063,7 +2146,7 @@ impl InnerJoin {
         debug_assert_eq!(ret.len(), ret.iter().collect::<BTreeSet<_>>().len());
         ret
     }
-    pub(crate) fn join_type(&self) -> &str {
+    pub(crate) fn join_type(&self) -> JoinKind {
         match &self.right {
             RelAlgebra::Fixed(f) => f.join_type(),
             RelAlgebra::TempStore(_) => {
@@ -2075,9 +2158,9 @@ impl InnerJoin {
                     )
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
-                    "mem_prefix_join"
+                    JoinKind::MemPrefix
                 } else {
-                    "mem_mat_join"
+                    JoinKind::MemMat
                 }
             }
             RelAlgebra::Stored(_) => {
@@ -2089,14 +2172,14 @@ impl InnerJoin {
                     )
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
-                    "stored_prefix_join"
+                    JoinKind::StoredPrefix
                 } else {
-                    "stored_mat_join"
+                    JoinKind::StoredMat
                 }
             }
-            RelAlgebra::HnswSearch(_) => "hnsw_search_join",
-            RelAlgebra::FtsSearch(_) => "fts_search_join",
-            RelAlgebra::LshSearch(_) => "lsh_search_join",
+            RelAlgebra::HnswSearch(_) => JoinKind::HnswSearch,
+            RelAlgebra::FtsSearch(_) => JoinKind::FtsSearch,
+            RelAlgebra::LshSearch(_) => JoinKind::LshSearch,
             RelAlgebra::StoredWithValidity(_) => {
                 let join_indices = self
                     .joiner
@@ -2106,13 +2189,13 @@ impl InnerJoin {
                     )
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
-                    "stored_prefix_join"
+                    JoinKind::StoredPrefix
                 } else {
-                    "stored_mat_join"
+                    JoinKind::StoredMat
                 }
             }
             RelAlgebra::Join(_) | RelAlgebra::Filter(_) | RelAlgebra::Unification(_) => {
-                "generic_mat_join"
+                JoinKind::GenericMat
             }
             RelAlgebra::Reorder(_) => {
                 panic!("joining on reordered")
@@ -2127,6 +2210,61 @@ impl InnerJoin {
         tx: &'a SessionTx<'_>,
         delta_rule: Option<&MagicSymbol>,
         stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+    ) -> Result<TupleIter<'a>> {
+        let counter = tx.max_join_matches.map(|max| {
+            let (left_join_indices, _) = self
+                .joiner
+                .join_indices(
+                    &self.left.bindings_after_eliminate(),
+                    &self.right.bindings_after_eliminate(),
+                )
+                .unwrap();
+            Rc::new(JoinMatchCounter {
+                max,
+                left_join_indices,
+                join_type: self.join_type().to_string(),
+                span: self.span,
+                current_key: Default::default(),
+                matches: Default::default(),
+            })
+        });
+        let it = self.iter_unchecked(tx, delta_rule, stores, &counter)?;
+        Ok(match counter {
+            None => it,
+            Some(counter) => Box::new(it.map(move |tuple| {
+                let tuple = tuple?;
+                counter.record_match()?;
+                Ok(tuple)
+            })),
+        })
+    }
+    /// The left side of the join, letting `counter` know whenever a new left tuple is probed.
+    fn left_iter<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        counter: &Option<Rc<JoinMatchCounter>>,
+    ) -> Result<TupleIter<'a>> {
+        let it = self.left.iter(tx, delta_rule, stores)?;
+        Ok(match counter {
+            None => it,
+            Some(counter) => {
+                let counter = counter.clone();
+                Box::new(it.inspect(move |tuple| {
+                    if let Ok(tuple) = tuple {
+                        counter.start_probe(tuple)
+                    }
+                }))
+            }
+        })
+    }
+    fn iter_unchecked<'a>(
+        &'a self,
+        tx: &'a SessionTx<'_>,
+        delta_rule: Option<&MagicSymbol>,
+        stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        counter: &Option<Rc<JoinMatchCounter>>,
     ) -> Result<TupleIter<'a>> {
         let bindings = self.bindings();
         let eliminate_indices = get_eliminate_indices(&bindings, &self.to_eliminate);
@@ -2140,7 +2278,7 @@ impl InnerJoin {
                     )
                     .unwrap();
                 f.join(
-                    self.left.iter(tx, delta_rule, stores)?,
+                    self.left_iter(tx, delta_rule, stores, counter)?,
                     join_indices,
                     eliminate_indices,
                 )
@@ -2155,14 +2293,14 @@ impl InnerJoin {
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
                     r.prefix_join(
-                        self.left.iter(tx, delta_rule, stores)?,
+                        self.left_iter(tx, delta_rule, stores, counter)?,
                         join_indices,
                         eliminate_indices,
                         delta_rule,
                         stores,
                     )
                 } else {
-                    self.materialized_join(tx, eliminate_indices, delta_rule, stores)
+                    self.materialized_join(tx, eliminate_indices, delta_rule, stores, counter)
                 }
             }
             RelAlgebra::Stored(r) => {
@@ -2176,12 +2314,12 @@ impl InnerJoin {
                 if join_is_prefix(&join_indices.1) {
                     r.prefix_join(
                         tx,
-                        self.left.iter(tx, delta_rule, stores)?,
+                        self.left_iter(tx, delta_rule, stores, counter)?,
                         join_indices,
                         eliminate_indices,
                     )
                 } else {
-                    self.materialized_join(tx, eliminate_indices, delta_rule, stores)
+                    self.materialized_join(tx, eliminate_indices, delta_rule, stores, counter)
                 }
             }
             RelAlgebra::StoredWithValidity(r) => {
@@ -2195,12 +2333,12 @@ impl InnerJoin {
                 if join_is_prefix(&join_indices.1) {
                     r.prefix_join(
                         tx,
-                        self.left.iter(tx, delta_rule, stores)?,
+                        self.left_iter(tx, delta_rule, stores, counter)?,
                         join_indices,
                         eliminate_indices,
                     )
                 } else {
-                    self.materialized_join(tx, eliminate_indices, delta_rule, stores)
+                    self.materialized_join(tx, eliminate_indices, delta_rule, stores, counter)
                 }
             }
             RelAlgebra::Join(_)
@@ -2209,7 +2347,7 @@ impl InnerJoin {
             | RelAlgebra::HnswSearch(_)
             | RelAlgebra::FtsSearch(_)
             | RelAlgebra::LshSearch(_) => {
-                self.materialized_join(tx, eliminate_indices, delta_rule, stores)
+                self.materialized_join(tx, eliminate_indices, delta_rule, stores, counter)
             }
             RelAlgebra::Reorder(_) => {
                 panic!("joining on reordered")
@@ -2225,6 +2363,7 @@ impl InnerJoin {
         eliminate_indices: BTreeSet<usize>,
         delta_rule: Option<&MagicSymbol>,
         stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        counter: &Option<Rc<JoinMatchCounter>>,
     ) -> Result<TupleIter<'a>> {
         debug!("using materialized join");
         let right_bindings = self.right.bindings_after_eliminate();
@@ -2233,7 +2372,7 @@ impl InnerJoin {
             .join_indices(&self.left.bindings_after_eliminate(), &right_bindings)
             .unwrap();
 
-        let mut left_iter = self.left.iter(tx, delta_rule, stores)?;
+        let mut left_iter = self.left_iter(tx, delta_rule, stores, counter)?;
         let left_cache = match left_iter.next() {
             None => return Ok(Box::new(iter::empty())),
             Some(Err(err)) => return Err(err),
@@ -2288,6 +2427,56 @@ impl InnerJoin {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The key {key:?} matched more than {max} rows in {join_type}")]
+#[diagnostic(code(eval::join_matches_exceeded))]
+#[diagnostic(help(
+    "The data is probably skewed on this key, the limit is set by the `:max_join_matches` option"
+))]
+pub(crate) struct JoinMatchesExceeded {
+    key: Tuple,
+    max: usize,
+    join_type: String,
+    #[label]
+    span: SourceSpan,
+}
+
+/// Counts how many rows the right side of a join produced for the left tuple
+/// currently being probed, enforcing the `:max_join_matches` query option.
+/// This relies on the joins consuming the left side one tuple at a time.
+struct JoinMatchCounter {
+    max: usize,
+    left_join_indices: Vec<usize>,
+    join_type: String,
+    span: SourceSpan,
+    current_key: RefCell<Tuple>,
+    matches: Cell<usize>,
+}
+
+impl JoinMatchCounter {
+    fn start_probe(&self, left: &Tuple) {
+        *self.current_key.borrow_mut() = self
+            .left_join_indices
+            .iter()
+            .map(|i| left[*i].clone())
+            .collect_vec();
+        self.matches.set(0);
+    }
+    fn record_match(&self) -> Result<()> {
+        let matches = self.matches.get() + 1;
+        self.matches.set(matches);
+        if matches > self.max {
+            bail!(JoinMatchesExceeded {
+                key: self.current_key.borrow().clone(),
+                max: self.max,
+                join_type: self.join_type.clone(),
+                span: self.span,
+            })
+        }
+        Ok(())
+    }
+}
+
 struct CachedMaterializedIterator<'a> {
     materialized: Vec<Tuple>,
     eliminate_indices: BTreeSet<usize>,
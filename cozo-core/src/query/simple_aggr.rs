@@ -0,0 +1,178 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Fast path for queries that only aggregate the columns of a single stored relation,
+//! such as `?[count(x), max(y)] := *foo[x, y]`. These are answered by scanning the
+//! relation and accumulating directly, without compiling and evaluating the program.
+
+use std::collections::BTreeSet;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::aggr::Aggregation;
+use crate::data::expr::Expr;
+use crate::data::program::{InputAtom, InputInlineRulesOrFixed, InputProgram};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::parse::SourceSpan;
+use crate::runtime::relation::{AccessLevel, RelationHandle};
+use crate::runtime::transact::SessionTx;
+use crate::{NamedRows, Poison};
+
+/// Number of rows scanned between checks of whether the query is killed
+const POISON_CHECK_INTERVAL: usize = 1024;
+
+/// A program eligible for the fast path: the relation to scan, and for each output
+/// column the aggregation and the index of the relation column it is applied to.
+pub(crate) struct SimpleAggrScan {
+    relation: RelationHandle,
+    aggrs: Vec<(Aggregation, usize)>,
+}
+
+impl<'a> SessionTx<'a> {
+    /// Evaluate `prog` by directly scanning the stored relation found by
+    /// [Self::plan_simple_aggr_scan]. The scan stops once `poison` is set.
+    pub(crate) fn simple_aggr_scan(
+        &self,
+        prog: &InputProgram,
+        plan: SimpleAggrScan,
+        poison: &Poison,
+    ) -> Result<NamedRows> {
+        let headers = prog
+            .get_entry_out_head()?
+            .into_iter()
+            .map(|s| s.name.to_string())
+            .collect_vec();
+        let mut aggrs = plan.aggrs;
+        for (aggr, _) in aggrs.iter_mut() {
+            aggr.normal_init(&[])?;
+        }
+        for (i, tuple) in plan.relation.scan_all(self).enumerate() {
+            if i % POISON_CHECK_INTERVAL == 0 {
+                poison.check()?;
+            }
+            let tuple = tuple?;
+            for (aggr, idx) in aggrs.iter_mut() {
+                aggr.normal_op.as_mut().unwrap().set(&tuple[*idx])?;
+            }
+        }
+        let row: Vec<_> = aggrs
+            .iter()
+            .map(|(aggr, _)| aggr.normal_op.as_ref().unwrap().get())
+            .try_collect()?;
+        Ok(NamedRows::new(headers, vec![row]))
+    }
+
+    /// Check whether `prog` consists of a single rule of the form
+    /// `?[count(x), sum(y), min(z), max(w)] := *rel[x, y, z, w]` with no query options,
+    /// and can be evaluated by [Self::simple_aggr_scan]. Returns `None` if the program is
+    /// of any other form.
+    pub(crate) fn plan_simple_aggr_scan(
+        &self,
+        prog: &InputProgram,
+    ) -> Result<Option<SimpleAggrScan>> {
+        let opts = &prog.out_opts;
+        // every option affects either the output or the evaluation, which the scan ignores
+        if opts.limit.is_some()
+            || opts.offset.is_some()
+            || opts.paging_params.is_some()
+            || opts.timeout.is_some()
+            || opts.sleep.is_some()
+            || opts.max_fanout.is_some()
+            || opts.max_join_matches.is_some()
+            || opts.max_eval_threads.is_some()
+            || opts.mem_limit.is_some()
+            || opts.count
+            || opts.with_types
+            || opts.profile
+            || opts.float_fmt.is_some()
+            || !opts.sorters.is_empty()
+            || opts.store_relation.is_some()
+            || opts.assertion.is_some()
+            || prog.prog.len() != 1
+        {
+            return Ok(None);
+        }
+        let rule = match prog.prog.get(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0))) {
+            Some(InputInlineRulesOrFixed::Rules { rules }) if rules.len() == 1 => &rules[0],
+            _ => return Ok(None),
+        };
+        if rule.body.len() != 1 {
+            return Ok(None);
+        }
+
+        // the relation and the variables bound to each of its columns
+        let (name, bindings) = match &rule.body[0] {
+            InputAtom::Relation { inner } if inner.valid_at.is_none() => (
+                &inner.name,
+                inner.args.iter().map(|a| (None, a)).collect_vec(),
+            ),
+            InputAtom::NamedFieldRelation { inner } if inner.valid_at.is_none() => (
+                &inner.name,
+                inner.args.iter().map(|(k, a)| (Some(k), a)).collect_vec(),
+            ),
+            _ => return Ok(None),
+        };
+        if !self.relation_exists(name)? {
+            return Ok(None);
+        }
+        let relation = self.get_relation(name, false)?;
+        if relation.access_level < AccessLevel::ReadOnly {
+            return Ok(None);
+        }
+        let columns = relation
+            .metadata
+            .keys
+            .iter()
+            .chain(relation.metadata.non_keys.iter())
+            .map(|col| &col.name)
+            .collect_vec();
+        if bindings.iter().all(|(k, _)| k.is_none()) && bindings.len() != columns.len() {
+            return Ok(None);
+        }
+        let mut seen = BTreeSet::new();
+        let mut var_cols = vec![];
+        for (i, (col_name, arg)) in bindings.into_iter().enumerate() {
+            let var = match arg {
+                Expr::Binding { var, .. } => var,
+                _ => return Ok(None),
+            };
+            if var.is_ignored_symbol() {
+                continue;
+            }
+            if !seen.insert(&var.name) {
+                return Ok(None);
+            }
+            let idx = match col_name {
+                None => i,
+                Some(col_name) => match columns.iter().position(|c| *c == col_name) {
+                    None => return Ok(None),
+                    Some(idx) => idx,
+                },
+            };
+            var_cols.push((var, idx));
+        }
+
+        let mut aggrs = vec![];
+        for (head, aggr) in rule.head.iter().zip(rule.aggr.iter()) {
+            let aggr = match aggr {
+                Some((aggr, args)) if args.is_empty() && aggr.is_simple_scan_aggr() => aggr,
+                _ => return Ok(None),
+            };
+            let idx = match var_cols.iter().find(|(var, _)| *var == head) {
+                None => return Ok(None),
+                Some((_, idx)) => *idx,
+            };
+            aggrs.push((aggr.clone(), idx));
+        }
+        if aggrs.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(SimpleAggrScan { relation, aggrs }))
+    }
+}
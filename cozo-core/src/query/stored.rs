@@ -15,11 +15,11 @@ use pest::Parser;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::expr::{Bytecode, Expr};
+use crate::data::expr::{eval_bytecode_pred, Bytecode, Expr};
 use crate::data::program::{FixedRuleApply, InputInlineRulesOrFixed, InputProgram, RelationOp};
 use crate::data::relation::{ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
-use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
+use crate::data::tuple::{Tuple, TupleT, ENCODED_KEY_MIN_LEN};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::FixedRuleHandle;
@@ -30,6 +30,7 @@ use crate::runtime::callback::{CallbackCollector, CallbackOp};
 use crate::runtime::minhash_lsh::HashPermutations;
 use crate::runtime::relation::{
     extend_tuple_from_v, AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
+    TTL_COLUMN,
 };
 use crate::runtime::transact::SessionTx;
 use crate::storage::Storage;
@@ -41,6 +42,10 @@ use crate::{Db, NamedRows, SourceSpan, StoreTx};
 struct RelationArityMismatch(String, usize, usize);
 
 impl<'a> SessionTx<'a> {
+    /// Write the tuples of `res_iter` into the stored relation. Returns the key ranges to clear
+    /// when committing, and the number of tuples written or removed by the op itself:
+    /// changes made by triggers are not counted, and neither are the tuples of `:ensure`
+    /// and `:ensure_not`, which write nothing.
     pub(crate) fn execute_relation<'s, S: Storage<'s>>(
         &mut self,
         db: &Db<S>,
@@ -53,10 +58,12 @@ impl<'a> SessionTx<'a> {
         callback_collector: &mut CallbackCollector,
         propagate_triggers: bool,
         force_collect: &str,
-    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    ) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, usize)> {
         let mut to_clear = vec![];
         let mut replaced_old_triggers = None;
-        if op == RelationOp::Replace {
+        let mut is_new = op == RelationOp::Create;
+        let mut kept_store = None;
+        if matches!(op, RelationOp::Replace | RelationOp::CreateOrReplace) {
             if !propagate_triggers {
                 #[derive(Debug, Error, Diagnostic)]
                 #[error("replace op in trigger is not allowed: {0}")]
@@ -65,22 +72,45 @@ impl<'a> SessionTx<'a> {
                 bail!(ReplaceInTrigger(meta.name.to_string()))
             }
             if let Ok(old_handle) = self.get_relation(&meta.name, true) {
-                if !old_handle.indices.is_empty() {
+                // `:create_or_replace` rewrites the rows in place, which no index but
+                // the regular ones would follow
+                let has_indices = if op == RelationOp::Replace {
+                    !old_handle.indices.is_empty()
+                } else {
+                    !old_handle.has_no_index()
+                };
+                if has_indices {
                     #[derive(Debug, Error, Diagnostic)]
                     #[error("cannot replace relation {0} since it has indices")]
                     #[diagnostic(code(eval::replace_rel_with_indices))]
                     struct ReplaceRelationWithIndices(String);
                     bail!(ReplaceRelationWithIndices(old_handle.name.to_string()))
                 }
-                if old_handle.access_level < AccessLevel::Normal {
+                // `:create_or_replace` keeps the relation and only rewrites its rows,
+                // so ordinary write access suffices
+                let required_level = if op == RelationOp::Replace {
+                    AccessLevel::Normal
+                } else {
+                    AccessLevel::Protected
+                };
+                if old_handle.access_level < required_level {
                     bail!(InsufficientAccessLevel(
                         old_handle.name.to_string(),
                         "relation replacement".to_string(),
                         old_handle.access_level
                     ));
                 }
-                if old_handle.has_triggers() {
-                    replaced_old_triggers = Some((old_handle.put_triggers, old_handle.rm_triggers))
+                if op == RelationOp::Replace && old_handle.has_triggers() {
+                    replaced_old_triggers = Some((
+                        (
+                            old_handle.put_triggers.clone(),
+                            old_handle.put_trigger_guards.clone(),
+                        ),
+                        (
+                            old_handle.rm_triggers.clone(),
+                            old_handle.rm_trigger_guards.clone(),
+                        ),
+                    ))
                 }
                 for trigger in &old_handle.replace_triggers {
                     let program = parse_script(
@@ -109,20 +139,41 @@ impl<'a> SessionTx<'a> {
                         })?;
                     to_clear.extend(cleanups);
                 }
-                let destroy_res = self.destroy_relation(&meta.name)?;
-                if !meta.name.is_temp_store_name() {
-                    to_clear.extend(destroy_res);
+                if op == RelationOp::Replace {
+                    let destroy_res = self.destroy_relation(&meta.name)?;
+                    if !meta.name.is_temp_store_name() {
+                        to_clear.extend(destroy_res);
+                    }
+                } else {
+                    self.clear_relation_rows(&old_handle)?;
+                    kept_store = Some(old_handle);
                 }
+            } else {
+                is_new = true;
             }
         }
-        let mut relation_store = if op == RelationOp::Replace || op == RelationOp::Create {
-            self.create_relation(meta.clone())?
-        } else {
-            self.get_relation(&meta.name, false)?
+        let flattened_meta;
+        let (mut relation_store, meta) = match kept_store {
+            Some(store) => {
+                flattened_meta = meta.clone().with_flattened_keys();
+                (store, &flattened_meta)
+            }
+            None if matches!(
+                op,
+                RelationOp::Create | RelationOp::Replace | RelationOp::CreateOrReplace
+            ) =>
+            {
+                (self.create_relation(meta.clone())?, meta)
+            }
+            None => (self.get_relation(&meta.name, false)?, meta),
         };
         if let Some((old_put, old_retract)) = replaced_old_triggers {
-            relation_store.put_triggers = old_put;
-            relation_store.rm_triggers = old_retract;
+            (
+                relation_store.put_triggers,
+                relation_store.put_trigger_guards,
+            ) = old_put;
+            (relation_store.rm_triggers, relation_store.rm_trigger_guards) = old_retract;
+            relation_store.check_trigger_guards()?;
         }
         let InputRelationHandle {
             metadata,
@@ -132,6 +183,8 @@ impl<'a> SessionTx<'a> {
             ..
         } = meta;
 
+        let mut n_affected = 0;
+        let res_iter = res_iter.inspect(|_| n_affected += 1);
         match op {
             RelationOp::Rm | RelationOp::Delete => self.remove_from_relation(
                 db,
@@ -182,27 +235,36 @@ impl<'a> SessionTx<'a> {
                 force_collect,
                 *span,
             )?,
-            RelationOp::Create | RelationOp::Replace | RelationOp::Put | RelationOp::Insert => self
-                .put_into_relation(
-                    db,
-                    res_iter,
-                    headers,
-                    cur_vld,
-                    callback_targets,
-                    callback_collector,
-                    propagate_triggers,
-                    &mut to_clear,
-                    &relation_store,
-                    metadata,
-                    key_bindings,
-                    dep_bindings,
-                    op == RelationOp::Insert,
-                    force_collect,
-                    *span,
-                )?,
+            RelationOp::Create
+            | RelationOp::Replace
+            | RelationOp::CreateOrReplace
+            | RelationOp::Put
+            | RelationOp::Insert => self.put_into_relation(
+                db,
+                res_iter,
+                headers,
+                cur_vld,
+                callback_targets,
+                callback_collector,
+                propagate_triggers,
+                &mut to_clear,
+                &relation_store,
+                metadata,
+                key_bindings,
+                dep_bindings,
+                op == RelationOp::Insert,
+                force_collect,
+                *span,
+            )?,
         };
+        if is_new {
+            self.init_access_level(&meta.name, *db.default_access_level.read().unwrap())?;
+        }
+        if matches!(op, RelationOp::Ensure | RelationOp::EnsureNot) {
+            n_affected = 0;
+        }
 
-        Ok(to_clear)
+        Ok((to_clear, n_affected))
     }
 
     fn put_into_relation<'s, S: Storage<'s>>(
@@ -694,7 +756,13 @@ impl<'a> SessionTx<'a> {
 
         let kv_bindings = bindings;
         if propagate_triggers {
-            for trigger in &relation_store.put_triggers {
+            for (i, trigger) in relation_store.put_triggers.iter().enumerate() {
+                let guard = relation_store.put_trigger_guards.get(i);
+                let (new_tuples, old_tuples) =
+                    rows_passing_guard(relation_store, guard, &new_tuples, &old_tuples)?;
+                if new_tuples.is_empty() {
+                    continue;
+                }
                 let mut program = parse_script(
                     trigger,
                     &Default::default(),
@@ -703,18 +771,8 @@ impl<'a> SessionTx<'a> {
                 )?
                 .get_single_program()?;
 
-                make_const_rule(
-                    &mut program,
-                    "_new",
-                    kv_bindings.clone(),
-                    new_tuples.to_vec(),
-                );
-                make_const_rule(
-                    &mut program,
-                    "_old",
-                    kv_bindings.clone(),
-                    old_tuples.to_vec(),
-                );
+                make_const_rule(&mut program, "_new", kv_bindings.clone(), new_tuples);
+                make_const_rule(&mut program, "_old", kv_bindings.clone(), old_tuples);
 
                 let (_, cleanups) = db
                     .run_query(
@@ -740,11 +798,12 @@ impl<'a> SessionTx<'a> {
             let target_collector = callback_collector
                 .entry(relation_store.name.clone())
                 .or_default();
+            target_collector.n_keys = relation_store.metadata.keys.len();
             let headers = kv_bindings
                 .into_iter()
                 .map(|k| k.name.to_string())
                 .collect_vec();
-            target_collector.push((
+            target_collector.batches.push((
                 CallbackOp::Put,
                 NamedRows::new(
                     headers.clone(),
@@ -1031,7 +1090,13 @@ impl<'a> SessionTx<'a> {
             let kv_bindings = kv_bindings;
 
             if propagate_triggers {
-                for trigger in &relation_store.rm_triggers {
+                for (i, trigger) in relation_store.rm_triggers.iter().enumerate() {
+                    let guard = relation_store.rm_trigger_guards.get(i);
+                    let (old_tuples, new_tuples) =
+                        rows_passing_guard(relation_store, guard, &old_tuples, &new_tuples)?;
+                    if old_tuples.is_empty() {
+                        continue;
+                    }
                     let mut program = parse_script(
                         trigger,
                         &Default::default(),
@@ -1040,14 +1105,9 @@ impl<'a> SessionTx<'a> {
                     )?
                     .get_single_program()?;
 
-                    make_const_rule(&mut program, "_new", k_bindings.clone(), new_tuples.clone());
+                    make_const_rule(&mut program, "_new", k_bindings.clone(), new_tuples);
 
-                    make_const_rule(
-                        &mut program,
-                        "_old",
-                        kv_bindings.clone(),
-                        old_tuples.clone(),
-                    );
+                    make_const_rule(&mut program, "_old", kv_bindings.clone(), old_tuples);
 
                     let (_, cleanups) = db
                         .run_query(
@@ -1073,7 +1133,8 @@ impl<'a> SessionTx<'a> {
                 let target_collector = callback_collector
                     .entry(relation_store.name.clone())
                     .or_default();
-                target_collector.push((
+                target_collector.n_keys = relation_store.metadata.keys.len();
+                target_collector.batches.push((
                     CallbackOp::Rm,
                     NamedRows::new(
                         k_bindings
@@ -1172,7 +1233,8 @@ fn make_extractor(
     tuple_headers: &[Symbol],
 ) -> Result<DataExtractor> {
     for (inp_col, inp_binding) in input.iter().zip(bindings.iter()) {
-        if inp_col.name == stored.name {
+        // the expiry of rows cannot be put, it always comes from the time to live
+        if inp_col.name == stored.name && stored.name != TTL_COLUMN {
             for (idx, tuple_head) in tuple_headers.iter().enumerate() {
                 if tuple_head == inp_binding {
                     return Ok(DataExtractor::IndexExtractor(idx, stored.typing.clone()));
@@ -1227,3 +1289,49 @@ fn make_const_rule(
         },
     );
 }
+
+/// The rows of `guarded` for which the guard of a trigger holds, each holding the values of
+/// all columns of the relation, with the rows of `others` having the same keys.
+/// All rows are kept without a guard.
+fn rows_passing_guard(
+    relation_store: &RelationHandle,
+    guard: Option<&Option<String>>,
+    guarded: &[DataValue],
+    others: &[DataValue],
+) -> Result<(Vec<DataValue>, Vec<DataValue>)> {
+    let guard = match guard {
+        Some(Some(guard)) => guard,
+        _ => return Ok((guarded.to_vec(), others.to_vec())),
+    };
+    let context = || {
+        format!(
+            "when evaluating the trigger guard {} of relation '{}'",
+            guard, relation_store.name
+        )
+    };
+    let bytecode = relation_store
+        .compile_trigger_guard(guard)
+        .wrap_err_with(context)?;
+    let n_keys = relation_store.metadata.keys.len();
+    let key_of =
+        |row: &[DataValue]| (&row[..n_keys.min(row.len())]).encode_as_key(relation_store.id);
+    let mut stack = vec![];
+    let mut passed = vec![];
+    let mut keys = BTreeSet::new();
+    for row in guarded {
+        if let DataValue::List(tuple) = row {
+            if eval_bytecode_pred(&bytecode, tuple, &mut stack, Default::default())
+                .wrap_err_with(context)?
+            {
+                keys.insert(key_of(tuple));
+                passed.push(row.clone());
+            }
+        }
+    }
+    let others = others
+        .iter()
+        .filter(|row| matches!(row, DataValue::List(tuple) if keys.contains(&key_of(tuple))))
+        .cloned()
+        .collect_vec();
+    Ok((passed, others))
+}
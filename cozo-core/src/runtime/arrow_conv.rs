@@ -0,0 +1,110 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    ArrayBuilder, BinaryBuilder, BooleanBuilder, Float32Builder, Float64Builder, Int64Builder,
+    ListBuilder, StringBuilder,
+};
+use arrow_array::RecordBatch;
+use arrow_schema::{DataType, Field, SchemaRef};
+use itertools::Itertools;
+use miette::{IntoDiagnostic, Result};
+
+use crate::data::json::JsonValue;
+use crate::data::relation::{ColType, NullableColType, VecElementType};
+use crate::data::value::{DataValue, Vector};
+
+/// The Arrow type a column is written as.
+/// Types without an Arrow counterpart (`Any`, `Json`, tuples and validities)
+/// are written as strings holding the JSON representation of the values.
+pub(crate) fn arrow_type(typ: &ColType) -> DataType {
+    match typ {
+        ColType::Bool => DataType::Boolean,
+        ColType::Int => DataType::Int64,
+        ColType::Float => DataType::Float64,
+        ColType::Bytes => DataType::Binary,
+        ColType::String | ColType::Uuid => DataType::Utf8,
+        ColType::List { eltype, .. } => DataType::List(Arc::new(arrow_field("item", eltype))),
+        ColType::Vec { eltype, .. } => {
+            let eltype = match eltype {
+                VecElementType::F32 => DataType::Float32,
+                VecElementType::F64 => DataType::Float64,
+            };
+            DataType::List(Arc::new(Field::new("item", eltype, false)))
+        }
+        ColType::Any | ColType::Tuple(_) | ColType::Validity | ColType::Json => DataType::Utf8,
+    }
+}
+
+pub(crate) fn arrow_field(name: &str, typ: &NullableColType) -> Field {
+    Field::new(name, arrow_type(&typ.coltype), typ.nullable)
+}
+
+pub(crate) fn downcast<T: ArrayBuilder>(builder: &mut dyn ArrayBuilder) -> &mut T {
+    // builders are always created by `make_builder` from `arrow_type` of the same column type
+    builder.as_any_mut().downcast_mut::<T>().unwrap()
+}
+
+/// Append a value to a builder created for `typ`. Nulls, and values that do not
+/// fit the type, are appended as nulls.
+pub(crate) fn append_value(builder: &mut dyn ArrayBuilder, typ: &ColType, val: &DataValue) {
+    match typ {
+        ColType::Bool => downcast::<BooleanBuilder>(builder).append_option(val.get_bool()),
+        ColType::Int => downcast::<Int64Builder>(builder).append_option(val.get_int()),
+        ColType::Float => downcast::<Float64Builder>(builder).append_option(val.get_float()),
+        ColType::Bytes => downcast::<BinaryBuilder>(builder).append_option(val.get_bytes()),
+        ColType::String => downcast::<StringBuilder>(builder).append_option(val.get_str()),
+        ColType::Uuid => downcast::<StringBuilder>(builder)
+            .append_option(val.get_uuid().map(|uuid| uuid.to_string())),
+        ColType::List { eltype, .. } => {
+            let builder = downcast::<ListBuilder<Box<dyn ArrayBuilder>>>(builder);
+            match val.get_slice() {
+                Some(items) => {
+                    for item in items {
+                        append_value(builder.values().as_mut(), &eltype.coltype, item);
+                    }
+                    builder.append(true)
+                }
+                None => builder.append(false),
+            }
+        }
+        ColType::Vec { .. } => {
+            let builder = downcast::<ListBuilder<Box<dyn ArrayBuilder>>>(builder);
+            match val {
+                DataValue::Vec(Vector::F32(v)) => {
+                    let values = downcast::<Float32Builder>(builder.values().as_mut());
+                    v.iter().for_each(|x| values.append_value(*x));
+                    builder.append(true)
+                }
+                DataValue::Vec(Vector::F64(v)) => {
+                    let values = downcast::<Float64Builder>(builder.values().as_mut());
+                    v.iter().for_each(|x| values.append_value(*x));
+                    builder.append(true)
+                }
+                _ => builder.append(false),
+            }
+        }
+        ColType::Any | ColType::Tuple(_) | ColType::Validity | ColType::Json => {
+            let builder = downcast::<StringBuilder>(builder);
+            match val {
+                DataValue::Null => builder.append_null(),
+                val => builder.append_value(JsonValue::from(val.clone()).to_string()),
+            }
+        }
+    }
+}
+
+pub(crate) fn finish_batch(
+    schema: &SchemaRef,
+    builders: &mut [Box<dyn ArrayBuilder>],
+) -> Result<RecordBatch> {
+    let columns = builders.iter_mut().map(|b| b.finish()).collect_vec();
+    RecordBatch::try_new(schema.clone(), columns).into_diagnostic()
+}
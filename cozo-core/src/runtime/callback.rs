@@ -12,6 +12,7 @@ use std::fmt::{Display, Formatter};
 use crossbeam::channel::Sender;
 use smartstring::{LazyCompact, SmartString};
 
+use crate::data::tuple::Tuple;
 use crate::{Db, NamedRows, Storage};
 
 /// Represents the kind of operation that triggered the callback
@@ -42,14 +43,44 @@ impl CallbackOp {
     }
 }
 
+/// A change to a single row of a stored relation, received from [Db::subscribe]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangeEvent {
+    /// Whether the row was put or removed
+    pub op: CallbackOp,
+    /// The key columns of the row
+    pub key: Tuple,
+    /// The non-key columns of the row, `None` for removals
+    pub value: Option<Tuple>,
+}
+
 #[allow(dead_code)]
 pub struct CallbackDeclaration {
     pub(crate) dependent: SmartString<LazyCompact>,
-    pub(crate) sender: Sender<(CallbackOp, NamedRows, NamedRows)>,
+    pub(crate) sender: CallbackSender,
+}
+
+impl CallbackDeclaration {
+    fn is_subscription(&self) -> bool {
+        matches!(self.sender, CallbackSender::Events(_))
+    }
 }
 
-pub(crate) type CallbackCollector =
-    BTreeMap<SmartString<LazyCompact>, Vec<(CallbackOp, NamedRows, NamedRows)>>;
+/// Registered by [Db::register_callback] or by [Db::subscribe] respectively
+pub(crate) enum CallbackSender {
+    Rows(Sender<(CallbackOp, NamedRows, NamedRows)>),
+    Events(Sender<ChangeEvent>),
+}
+
+/// Mutations of a relation collected during a transaction
+#[derive(Default)]
+pub(crate) struct CollectedMutations {
+    /// Number of key columns of the relation, which come first in the rows of puts
+    pub(crate) n_keys: usize,
+    pub(crate) batches: Vec<(CallbackOp, NamedRows, NamedRows)>,
+}
+
+pub(crate) type CallbackCollector = BTreeMap<SmartString<LazyCompact>, CollectedMutations>;
 
 #[allow(dead_code)]
 pub(crate) type EventCallbackRegistry = (
@@ -75,34 +106,89 @@ impl<'s, S: Storage<'s>> Db<S> {
             Default::default()
         }
     }
+    /// Relations with subscribers registered by [Db::subscribe]
+    pub(crate) fn current_subscription_targets(&self) -> BTreeSet<SmartString<LazyCompact>> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (cbs, cb_dir) = &*self.event_callbacks.read().unwrap();
+            cb_dir
+                .iter()
+                .filter(|(_, ids)| {
+                    ids.iter()
+                        .any(|id| cbs.get(id).is_some_and(|cb| cb.is_subscription()))
+                })
+                .map(|(name, _)| name.clone())
+                .collect()
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            Default::default()
+        }
+    }
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) fn send_callbacks(&'s self, collector: CallbackCollector) {
         let mut to_remove = vec![];
 
-        for (table, vals) in collector {
-            for (op, new, old) in vals {
-                let (cbs, cb_dir) = &*self.event_callbacks.read().unwrap();
-                if let Some(cb_ids) = cb_dir.get(&table) {
-                    let mut it = cb_ids.iter();
-                    if let Some(fst) = it.next() {
-                        for cb_id in it {
-                            if let Some(cb) = cbs.get(cb_id) {
-                                if cb.sender.send((op, new.clone(), old.clone())).is_err() {
-                                    to_remove.push(*cb_id)
-                                }
-                            }
-                        }
-
-                        if let Some(cb) = cbs.get(fst) {
-                            if cb.sender.send((op, new, old)).is_err() {
-                                to_remove.push(*fst)
-                            }
+        for (table, collected) in collector {
+            let (cbs, cb_dir) = &*self.event_callbacks.read().unwrap();
+            let Some(cb_ids) = cb_dir.get(&table) else {
+                continue;
+            };
+            let has_subscribers = cb_ids
+                .iter()
+                .any(|id| cbs.get(id).is_some_and(|cb| cb.is_subscription()));
+            let mut events = vec![];
+            for (op, new, old) in collected.batches {
+                for cb_id in cb_ids {
+                    if let Some(CallbackDeclaration {
+                        sender: CallbackSender::Rows(sender),
+                        ..
+                    }) = cbs.get(cb_id)
+                    {
+                        if sender.send((op, new.clone(), old.clone())).is_err() {
+                            to_remove.push(*cb_id)
                         }
                     }
                 }
+                if !has_subscribers {
+                    continue;
+                }
+                for mut row in new.rows {
+                    let value = match op {
+                        CallbackOp::Put => Some(row.split_off(collected.n_keys)),
+                        CallbackOp::Rm => None,
+                    };
+                    events.push(ChangeEvent {
+                        op,
+                        key: row,
+                        value,
+                    });
+                }
             }
+            dispatch_change_events(cbs, cb_ids, events, &mut to_remove);
         }
 
+        self.remove_callbacks(to_remove);
+    }
+    /// Send the changes made by imports, which only go to subscribers registered by
+    /// [Db::subscribe], not to callbacks registered by [Db::register_callback].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn send_change_events(
+        &'s self,
+        changes: BTreeMap<SmartString<LazyCompact>, Vec<ChangeEvent>>,
+    ) {
+        let mut to_remove = vec![];
+        for (table, events) in changes {
+            let (cbs, cb_dir) = &*self.event_callbacks.read().unwrap();
+            if let Some(cb_ids) = cb_dir.get(&table) {
+                dispatch_change_events(cbs, cb_ids, events, &mut to_remove);
+            }
+        }
+        self.remove_callbacks(to_remove);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn remove_callbacks(&'s self, to_remove: Vec<u32>) {
         if !to_remove.is_empty() {
             let (cbs, cb_dir) = &mut *self.event_callbacks.write().unwrap();
             for removing_id in &to_remove {
@@ -115,3 +201,28 @@ impl<'s, S: Storage<'s>> Db<S> {
         }
     }
 }
+
+/// Send each event to every subscriber among `cb_ids`, recording the subscribers
+/// that have gone away in `to_remove`
+#[cfg(not(target_arch = "wasm32"))]
+fn dispatch_change_events(
+    cbs: &BTreeMap<u32, CallbackDeclaration>,
+    cb_ids: &BTreeSet<u32>,
+    events: Vec<ChangeEvent>,
+    to_remove: &mut Vec<u32>,
+) {
+    for cb_id in cb_ids {
+        if let Some(CallbackDeclaration {
+            sender: CallbackSender::Events(sender),
+            ..
+        }) = cbs.get(cb_id)
+        {
+            for event in &events {
+                if sender.send(event.clone()).is_err() {
+                    to_remove.push(*cb_id);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::vec;
+
+use itertools::Itertools;
+use miette::{ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::{DataValue, Db, NamedRows, ScriptMutability, Storage};
+
+/// The result of a script, handed out a page at a time. Created by [Db::run_script_cursor].
+///
+/// The script is evaluated once, when the cursor is created; pages are then taken from
+/// the evaluated rows, so later pages cost nothing to compute and no page is converted
+/// to JSON before it is asked for. The cursor holds no transaction.
+///
+/// The whole result is kept in memory by the cursor until its rows are returned:
+/// paging bounds the size of each response, not the memory used by the query.
+#[derive(Debug)]
+pub struct ResultCursor {
+    headers: Vec<String>,
+    warnings: Vec<String>,
+    total: usize,
+    rows: vec::IntoIter<Tuple>,
+}
+
+impl ResultCursor {
+    /// The headers of the result
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+    /// Warnings about the script, e.g. rules that are never used
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+    /// Total number of rows in the result
+    pub fn total(&self) -> usize {
+        self.total
+    }
+    /// Number of rows not yet returned in a page
+    pub fn remaining(&self) -> usize {
+        self.rows.len()
+    }
+    /// The next `n` rows of the result, or fewer for the last page.
+    /// Returns `None` when all rows have been returned, and an error if `n` is 0.
+    pub fn next_page(&mut self, n: usize) -> Result<Option<NamedRows>> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The size of a page must be at least 1")]
+        #[diagnostic(code(cursor::empty_page))]
+        struct EmptyPage;
+
+        ensure!(n > 0, EmptyPage);
+        if self.rows.len() == 0 {
+            return Ok(None);
+        }
+        let rows = self.rows.by_ref().take(n).collect_vec();
+        Ok(Some(NamedRows::new(self.headers.clone(), rows)))
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Run the script like [Db::run_script], but return its result through a [ResultCursor]
+    /// so that it can be retrieved in pages.
+    ///
+    /// Unlike `:limit` and `:offset`, which evaluate the query again for every page,
+    /// the script is evaluated only once. For imperative scripts that return
+    /// several results, only the first one is available from the cursor.
+    pub fn run_script_cursor(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<ResultCursor> {
+        let (res, report) = self.run_script_with_report(payload, params, mutability)?;
+        Ok(ResultCursor {
+            headers: res.headers,
+            warnings: report.warnings,
+            total: res.rows.len(),
+            rows: res.rows.into_iter(),
+        })
+    }
+}
@@ -9,11 +9,12 @@
 use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
 use std::default::Default;
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
+use std::io::BufRead;
 use std::iter;
 use std::path::Path;
 #[allow(unused_imports)]
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 #[allow(unused_imports)]
 use std::thread;
@@ -23,7 +24,7 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 #[allow(unused_imports)]
 use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
 use crossbeam::sync::ShardedLock;
-use either::{Left, Right};
+use either::{Either, Left, Right};
 use itertools::Itertools;
 use miette::Report;
 #[allow(unused_imports)]
@@ -32,36 +33,93 @@ use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::data::functions::current_validity;
+use crate::data::expr::Expr;
+use crate::data::functions::{current_validity, op_now};
 use crate::data::json::JsonValue;
-use crate::data::program::{InputProgram, QueryAssertion, RelationOp, ReturnMutation};
-use crate::data::relation::ColumnDef;
-use crate::data::tuple::{Tuple, TupleT};
-use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
+use crate::data::program::{
+    InputProgram, MagicSymbol, QueryAssertion, QueryOutOptions, RelationOp, ReturnMutation,
+};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, VecElementType};
+use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN};
+use crate::data::value::{DataValue, Num, ValidityTs, Vector, LARGEST_UTF_CHAR};
 use crate::fixed_rule::DEFAULT_FIXED_RULES;
 use crate::fts::TokenizerCache;
-use crate::parse::sys::SysOp;
-use crate::parse::{parse_expressions, parse_script, CozoScript, SourceSpan};
+use crate::parse::sys::{CompactTarget, SysOp};
+use crate::parse::{parse_expressions, parse_script, parse_table_schema, CozoScript, SourceSpan};
 use crate::query::compile::{CompiledProgram, CompiledRule, CompiledRuleSet};
 use crate::query::ra::{
-    FilteredRA, FtsSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, NegJoin, RelAlgebra, ReorderRA,
-    StoredRA, StoredWithValidityRA, TempStoreRA, UnificationRA,
+    FilteredRA, FtsSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, NegJoin, RaNodeStats,
+    RaStatsCollector, RelAlgebra, ReorderRA, StoredRA, StoredWithValidityRA, TempStoreRA,
+    UnificationRA,
 };
 #[allow(unused_imports)]
 use crate::runtime::callback::{
-    CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
+    CallbackCollector, CallbackDeclaration, CallbackOp, CallbackSender, ChangeEvent,
+    EventCallbackRegistry,
+};
+use crate::runtime::explain::{
+    ExplainOp, ExplainPlan, ExplainStats, ExplainStep, RowCountCache, RowEstimator,
 };
+use crate::runtime::incremental::{load_change_marker, ChangeMarker, ChangeTrackingTx};
 use crate::runtime::relation::{
-    extend_tuple_from_v, AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId,
+    extend_tuple_from_v, AccessLevel, ColumnSchema, InputRelationHandle, InsufficientAccessLevel,
+    RelationHandle, RelationId, RelationSchema, TtlNotSet, LATEST_ENCODING_VERSION, TTL_COLUMN,
 };
-use crate::runtime::transact::SessionTx;
+use crate::runtime::transact::{CommitLatencies, SessionTx};
 use crate::storage::temp::TempStorage;
-use crate::storage::Storage;
-use crate::{decode_tuple_from_kv, FixedRule, Symbol};
+use crate::storage::{CompactOptions, Durability, ScanHint, Storage, StorageInfo};
+use crate::{decode_tuple_from_kv, ColumnOrder, FixedRule, Symbol};
 
 pub(crate) struct RunningQueryHandle {
     pub(crate) started_at: f64,
     pub(crate) poison: Poison,
+    pub(crate) script: Option<Arc<RunningScript>>,
+    /// The compiled query, from which `::running_plan` builds its plan when asked.
+    /// `None` for imperative scripts, whose queries are registered on their own,
+    /// and for queries answered without being compiled.
+    pub(crate) program: Option<Arc<[CompiledProgram]>>,
+}
+
+/// The script a running query belongs to, as shown by `::running`
+pub(crate) struct RunningScript {
+    /// The source of the script as written, including any literal values in it
+    pub(crate) source: String,
+    /// The parameters passed with the script, each given as its name and the type of its value.
+    /// The values themselves are left out to keep the listing short.
+    pub(crate) params: Vec<String>,
+}
+
+impl RunningScript {
+    pub(crate) fn new(source: &str, params: &BTreeMap<String, DataValue>) -> Arc<Self> {
+        let params = params
+            .iter()
+            .map(|(name, val)| format!("${name}: {}", value_kind(val)))
+            .collect_vec();
+        Arc::new(Self {
+            source: source.to_string(),
+            params,
+        })
+    }
+}
+
+/// Names the type of a value, to describe it without showing it
+fn value_kind(val: &DataValue) -> &'static str {
+    match val {
+        DataValue::Null => "Null",
+        DataValue::Bool(_) => "Bool",
+        DataValue::Num(Num::Int(_)) => "Int",
+        DataValue::Num(Num::Float(_)) => "Float",
+        DataValue::Str(_) => "String",
+        DataValue::Bytes(_) => "Bytes",
+        DataValue::Uuid(_) => "Uuid",
+        DataValue::Regex(_) => "Regex",
+        DataValue::List(_) => "List",
+        DataValue::Set(_) => "Set",
+        DataValue::Vec(_) => "Vec",
+        DataValue::Json(_) => "Json",
+        DataValue::Validity(_) => "Validity",
+        DataValue::Bot => "Bot",
+    }
 }
 
 pub(crate) struct RunningQueryCleanup {
@@ -78,6 +136,57 @@ impl Drop for RunningQueryCleanup {
     }
 }
 
+/// Counts a script against the limit set by [Db::set_max_concurrent_queries] while it runs
+pub(crate) struct AdmittedScript {
+    admitted: Arc<AtomicUsize>,
+}
+
+impl Drop for AdmittedScript {
+    fn drop(&mut self) {
+        self.admitted.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Split the pairs of `::rename` into renames done in the given order and cycles of renames,
+/// such as `a -> b, b -> a`, that must be done at once. A cycle takes the place of its first pair.
+fn split_rename_cycles(
+    pairs: &[(Symbol, Symbol)],
+) -> Vec<Either<&(Symbol, Symbol), Vec<&(Symbol, Symbol)>>> {
+    let mut in_cycle = vec![false; pairs.len()];
+    let mut ret = vec![];
+    for (i, pair) in pairs.iter().enumerate() {
+        if in_cycle[i] {
+            continue;
+        }
+        let mut chain = vec![i];
+        let mut next = &pair.1.name;
+        while next != &pair.0.name {
+            match (0..pairs.len())
+                .find(|j| !in_cycle[*j] && !chain.contains(j) && pairs[*j].0.name == *next)
+            {
+                Some(j) => {
+                    chain.push(j);
+                    next = &pairs[j].1.name;
+                }
+                None => break,
+            }
+        }
+        if next == &pair.0.name {
+            for j in &chain {
+                in_cycle[*j] = true;
+            }
+            ret.push(Right(chain.into_iter().map(|j| &pairs[j]).collect_vec()));
+        } else {
+            ret.push(Left(pair));
+        }
+    }
+    ret
+}
+
+/// Number of key-value pairs copied between two calls of the progress callback
+/// of [Db::backup_db_with_progress] and [Db::restore_backup_with_progress]
+pub const BACKUP_PROGRESS_INTERVAL: u64 = 4096;
+
 #[derive(serde_derive::Serialize, serde_derive::Deserialize)]
 pub struct DbManifest {
     pub storage_version: u64,
@@ -97,7 +206,7 @@ pub enum ScriptMutability {
 pub struct Db<S> {
     pub(crate) db: S,
     temp_db: TempStorage,
-    relation_store_id: Arc<AtomicU64>,
+    pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) queries_count: Arc<AtomicU64>,
     pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
     pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
@@ -107,6 +216,31 @@ pub struct Db<S> {
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) event_callbacks: Arc<ShardedLock<EventCallbackRegistry>>,
     relation_locks: Arc<ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>>>,
+    /// Ids of the stored relations removed since the database was opened, by name,
+    /// so that their key ranges can still be compacted by name
+    removed_relations: Arc<Mutex<BTreeMap<SmartString<LazyCompact>, RelationId>>>,
+    /// Row counts of stored relations reused by the estimates of `::explain`
+    row_counts: RowCountCache,
+    pub(crate) commit_latencies: Arc<CommitLatencies>,
+    /// Memory budget in bytes for queries without a `:mem_limit` option, 0 for none
+    default_mem_limit: Arc<AtomicUsize>,
+    /// Timeout in seconds for queries without a `:timeout` option, 0 for none
+    default_timeout: Arc<ShardedLock<f64>>,
+    write_lock_timeout: Arc<ShardedLock<f64>>,
+    /// Read-ahead in bytes for full scans of relations, 0 for the engine's default
+    scan_readahead: Arc<AtomicUsize>,
+    /// Maximum number of scripts running at once, 0 for no limit
+    max_concurrent_queries: Arc<AtomicUsize>,
+    /// Number of scripts currently running, counted against that limit
+    admitted_scripts: Arc<AtomicUsize>,
+    /// Access level given to stored relations when they are created
+    pub(crate) default_access_level: Arc<ShardedLock<AccessLevel>>,
+    pub(crate) change_marker: ChangeMarker,
+    /// Thread pools of queries with `:max_eval_threads`, by number of threads,
+    /// built on first use and kept for the following queries. There is at most one pool
+    /// for each size up to the available parallelism, see [Db::eval_thread_pool].
+    #[cfg(not(target_arch = "wasm32"))]
+    eval_thread_pools: Arc<Mutex<BTreeMap<usize, Arc<rayon::ThreadPool>>>>,
 }
 
 impl<S> Debug for Db<S> {
@@ -120,11 +254,480 @@ impl<S> Debug for Db<S> {
 #[diagnostic(code(db::init))]
 pub(crate) struct BadDbInit(#[help] pub(crate) String);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Read-only script cannot change the database, but it {0}")]
+#[diagnostic(code(eval::read_only_violation))]
+pub(crate) struct ReadOnlyViolationError(pub(crate) String);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Cannot import data into relation {0} as it is an index")]
 #[diagnostic(code(tx::import_into_index))]
 pub(crate) struct ImportIntoIndex(pub(crate) String);
 
+#[cfg(feature = "storage-sqlite")]
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad prefix '{0}' for restored relation names")]
+#[diagnostic(code(tx::bad_restore_prefix))]
+#[diagnostic(help(
+    "The prefix must start with a letter and contain only letters, digits and underscores"
+))]
+pub(crate) struct BadRestorePrefix(pub(crate) String);
+
+/// Number of rows of NDJSON data written at a time by [Db::import_ndjson]
+pub const NDJSON_IMPORT_BATCH_ROWS: usize = 10000;
+
+/// A problem found by [Db::validate_import] in the data for a relation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidImportRow {
+    /// Index of the offending row, or `None` if the problem is with the relation or the headers
+    pub row: Option<usize>,
+    /// Description of the problem
+    pub error: String,
+}
+
+/// An error in a machine-readable form that does not depend on how `miette` renders reports,
+/// as returned by [crate::DbInstance::run_script_diag].
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct CozoError {
+    /// The diagnostic code, e.g. `eval::killed`, if the error has one
+    pub code: Option<String>,
+    /// The message of the error, without the messages of the errors causing it
+    pub message: String,
+    /// Offset and length in the script of the first labelled span of the error, if any.
+    /// Serialized as `{"start": offset, "len": length}`, as in [crate::format_error_as_json].
+    #[serde(default, with = "error_span")]
+    pub span: Option<SourceSpan>,
+}
+
+impl Display for CozoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{code}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for CozoError {}
+
+/// Serde helpers giving the span of a [CozoError] named fields
+mod error_span {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::parse::SourceSpan;
+
+    #[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+    struct NamedSpan {
+        start: usize,
+        len: usize,
+    }
+
+    pub(super) fn serialize<S: Serializer>(
+        span: &Option<SourceSpan>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        span.map(|SourceSpan(start, len)| NamedSpan { start, len })
+            .serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<SourceSpan>, D::Error> {
+        let span = Option::<NamedSpan>::deserialize(deserializer)?;
+        Ok(span.map(|NamedSpan { start, len }| SourceSpan(start, len)))
+    }
+}
+
+impl From<Report> for CozoError {
+    fn from(err: Report) -> Self {
+        let span = err
+            .labels()
+            .and_then(|mut labels| labels.next())
+            .map(|label| SourceSpan(label.offset(), label.len()));
+        Self {
+            code: err.code().map(|code| code.to_string()),
+            message: err.to_string(),
+            span,
+        }
+    }
+}
+
+/// The relation targeted by an entry of the data for [Db::import_relations],
+/// and whether the rows are to be deleted from it.
+fn import_target(tx: &SessionTx<'_>, relation_op: &str) -> Result<(RelationHandle, bool)> {
+    let (relation, is_delete) = match relation_op.strip_prefix('-') {
+        None => (relation_op, false),
+        Some(s) => (s, true),
+    };
+    if relation.contains(':') {
+        bail!(ImportIntoIndex(relation.to_string()))
+    }
+    let handle = tx.get_relation(relation, false)?;
+    handle.ensure_importable()?;
+    Ok((handle, is_delete))
+}
+
+/// Create the stored relation targeted by `relation_op` with the `schema` carried by the
+/// imported data, unless it already exists. Indices and temp relations are never created.
+fn create_import_target(
+    tx: &mut SessionTx<'_>,
+    relation_op: &str,
+    schema: &str,
+    access_level: AccessLevel,
+) -> Result<()> {
+    let relation = relation_op.strip_prefix('-').unwrap_or(relation_op);
+    if relation.contains(':') || relation.starts_with('_') || tx.relation_exists(relation)? {
+        return Ok(());
+    }
+    let metadata = parse_table_schema(schema)
+        .wrap_err_with(|| format!("bad schema for relation {relation}"))?;
+    let name = Symbol::new(relation, Default::default());
+    tx.create_relation(InputRelationHandle {
+        name: name.clone(),
+        metadata,
+        key_bindings: vec![],
+        dep_bindings: vec![],
+        span: Default::default(),
+    })?;
+    tx.init_access_level(&name, access_level)
+}
+
+/// Schema of the relation recording the progress of [Db::import_relations_resumable]:
+/// the number of rows of each entry of the imported data that are committed.
+const IMPORT_CHECKPOINT_SCHEMA: &str = "{relation: String => rows: Int}";
+
+/// Get the checkpoint relation of a resumable import, creating it if it does not exist.
+fn import_checkpoint(
+    tx: &mut SessionTx<'_>,
+    name: &str,
+    access_level: AccessLevel,
+) -> Result<RelationHandle> {
+    #[derive(Debug, Diagnostic, Error)]
+    #[error("relation '{0}' cannot hold the checkpoints of an import")]
+    #[diagnostic(code(import::bad_checkpoint_relation))]
+    #[diagnostic(help("The relation must have the schema {IMPORT_CHECKPOINT_SCHEMA}"))]
+    struct BadCheckpointRelation(String);
+
+    if name.contains(':') || name.starts_with('_') {
+        bail!(BadCheckpointRelation(name.to_string()))
+    }
+    create_import_target(tx, name, IMPORT_CHECKPOINT_SCHEMA, access_level)?;
+    let handle = tx.get_relation(name, false)?;
+    handle.ensure_importable()?;
+    ensure!(
+        handle.metadata == parse_table_schema(IMPORT_CHECKPOINT_SCHEMA)?,
+        BadCheckpointRelation(name.to_string())
+    );
+    Ok(handle)
+}
+
+/// The number of rows of `relation_op` committed by earlier runs of a resumable import.
+fn checkpointed_rows(
+    tx: &SessionTx<'_>,
+    checkpoint: &RelationHandle,
+    relation_op: &str,
+) -> Result<usize> {
+    let key = vec![DataValue::from(relation_op)];
+    let k_store = checkpoint.encode_key_for_store(&key, Default::default())?;
+    Ok(match tx.store_tx.get(&k_store, false)? {
+        None => 0,
+        Some(v_store) => {
+            let mut row = key;
+            extend_tuple_from_v(&mut row, &v_store);
+            row[1].get_int().unwrap_or(0).max(0) as usize
+        }
+    })
+}
+
+/// Positions in the imported rows of the key columns and of the value columns of the relation.
+/// Value columns are not needed for deletion.
+fn import_columns<'a>(
+    handle: &'a RelationHandle,
+    headers: &[String],
+    is_delete: bool,
+) -> Result<(Vec<(usize, &'a ColumnDef)>, Vec<(usize, &'a ColumnDef)>)> {
+    #[derive(Debug, Diagnostic, Error)]
+    #[error("header '{0}' appears more than once in data for relation '{1}'")]
+    #[diagnostic(code(import::duplicate_header))]
+    struct DuplicateHeader(String, String);
+
+    let mut header2idx: BTreeMap<&str, usize> = BTreeMap::new();
+    for (i, k) in headers.iter().enumerate() {
+        if header2idx.insert(k, i).is_some() {
+            bail!(DuplicateHeader(k.to_string(), handle.name.to_string()))
+        }
+    }
+    let find_columns = |cols: &'a [ColumnDef]| -> Result<Vec<(usize, &'a ColumnDef)>> {
+        cols.iter()
+            .map(|col| {
+                let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
+                    miette!(
+                        "required header {} not found for relation {}",
+                        col.name,
+                        handle.name
+                    )
+                })?;
+                Ok((*idx, col))
+            })
+            .try_collect()
+    };
+    let key_indices = find_columns(&handle.metadata.keys)?;
+    let val_indices = if is_delete {
+        vec![]
+    } else {
+        find_columns(&handle.metadata.non_keys)?
+    };
+    Ok((key_indices, val_indices))
+}
+
+/// Values of `columns` taken from row number `row_idx` (0-based) of the data for `relation`.
+/// Coercion errors name the relation, the row, the column and the offending value.
+fn coerce_import_row(
+    row: &[DataValue],
+    columns: &[(usize, &ColumnDef)],
+    cur_vld: ValidityTs,
+    relation: &str,
+    row_idx: usize,
+) -> Result<Vec<DataValue>> {
+    columns
+        .iter()
+        .map(|(i, col)| {
+            let v = row
+                .get(*i)
+                .ok_or_else(|| miette!("row too short: {:?}", row))?;
+            col.typing.coerce(v.clone(), cur_vld).wrap_err_with(|| {
+                format!(
+                    "when importing row {row_idx} of relation {relation}: \
+                    column {} cannot hold {v}",
+                    col.name
+                )
+            })
+        })
+        .try_collect()
+}
+
+/// Coerce a row given to [Db::relation_get] and friends to the types of `cols`.
+fn coerce_raw_row(
+    row: &[DataValue],
+    cols: &[ColumnDef],
+    cur_vld: ValidityTs,
+    relation: &str,
+) -> Result<Vec<DataValue>> {
+    #[derive(Debug, Diagnostic, Error)]
+    #[error("relation '{0}' expects {1} values, but {2} were given")]
+    #[diagnostic(code(eval::raw_row_arity))]
+    struct RawRowArity(String, usize, usize);
+
+    ensure!(
+        row.len() == cols.len(),
+        RawRowArity(relation.to_string(), cols.len(), row.len())
+    );
+    row.iter()
+        .zip(cols)
+        .map(|(v, col)| {
+            col.typing.coerce(v.clone(), cur_vld).wrap_err_with(|| {
+                format!("column {} of relation {relation} cannot hold {v}", col.name)
+            })
+        })
+        .try_collect()
+}
+
+/// The types of the columns of the result of a query with `:with_types`. Columns named after
+/// those of `relation`, the stored relation returned by `:returning`, have their declared
+/// types. The others are typed after their values: a column holding values of a single type
+/// has that type, nullable if the column also holds nulls, and any other column is `Any`.
+fn column_types(rows: &NamedRows, relation: Option<&RelationHandle>) -> Vec<String> {
+    fn value_type(val: &DataValue) -> Option<ColType> {
+        Some(match val {
+            DataValue::Bool(_) => ColType::Bool,
+            DataValue::Num(Num::Int(_)) => ColType::Int,
+            DataValue::Num(Num::Float(_)) => ColType::Float,
+            DataValue::Str(_) => ColType::String,
+            DataValue::Bytes(_) => ColType::Bytes,
+            DataValue::Uuid(_) => ColType::Uuid,
+            DataValue::List(_) => ColType::List {
+                eltype: Box::new(NullableColType {
+                    coltype: ColType::Any,
+                    nullable: false,
+                }),
+                len: None,
+            },
+            DataValue::Vec(Vector::F32(v)) => ColType::Vec {
+                eltype: VecElementType::F32,
+                len: v.len(),
+            },
+            DataValue::Vec(Vector::F64(v)) => ColType::Vec {
+                eltype: VecElementType::F64,
+                len: v.len(),
+            },
+            DataValue::Json(_) => ColType::Json,
+            DataValue::Validity(_) => ColType::Validity,
+            DataValue::Null | DataValue::Regex(_) | DataValue::Set(_) | DataValue::Bot => {
+                return None
+            }
+        })
+    }
+
+    let declared = relation
+        .map(|rel| {
+            rel.metadata
+                .keys
+                .iter()
+                .chain(rel.metadata.non_keys.iter())
+                .map(|col| (col.name.as_str(), col.typing.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        })
+        .unwrap_or_default();
+    rows.headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            if let Some(typing) = declared.get(header.as_str()) {
+                return typing.clone();
+            }
+            let mut found = None;
+            let mut nullable = false;
+            for val in rows.rows.iter().filter_map(|row| row.get(i)) {
+                if *val == DataValue::Null {
+                    nullable = true;
+                    continue;
+                }
+                match (value_type(val), &found) {
+                    (Some(t), None) => found = Some(t),
+                    (Some(t), Some(f)) if t == *f => {}
+                    _ => return "Any".to_string(),
+                }
+            }
+            match found {
+                Some(coltype) => NullableColType { coltype, nullable }.to_string(),
+                None => "Any".to_string(),
+            }
+        })
+        .collect_vec()
+}
+
+/// Write a row for [Db::import_relations], deleting it instead if `vals` is `None`,
+/// and update the indices of the relation. The change is recorded in `changes` if given.
+fn write_import_row(
+    tx: &mut SessionTx<'_>,
+    handle: &RelationHandle,
+    keys: Vec<DataValue>,
+    vals: Option<Vec<DataValue>>,
+    changes: Option<&mut Vec<ChangeEvent>>,
+) -> Result<()> {
+    if let Some(changes) = changes {
+        changes.push(ChangeEvent {
+            op: if vals.is_some() {
+                CallbackOp::Put
+            } else {
+                CallbackOp::Rm
+            },
+            key: keys.clone(),
+            value: vals.clone(),
+        });
+    }
+    let has_indices = !handle.indices.is_empty();
+    let k_store = handle.encode_key_for_store(&keys, Default::default())?;
+    let mut kv = keys;
+    if has_indices {
+        if let Some(existing) = tx.store_tx.get(&k_store, false)? {
+            let mut old = kv.clone();
+            extend_tuple_from_v(&mut old, &existing);
+            if vals.as_ref().is_none_or(|vals| old[kv.len()..] != vals[..]) {
+                for (idx_rel, extractor) in handle.indices.values() {
+                    let idx_tup = extractor.iter().map(|i| old[*i].clone()).collect_vec();
+                    let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                    tx.store_tx.del(&encoded)?;
+                }
+            }
+        }
+    }
+    match vals {
+        None => tx.store_tx.del(&k_store)?,
+        Some(vals) => {
+            let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
+            tx.store_tx.put(&k_store, &v_store)?;
+            if has_indices {
+                kv.extend(vals);
+                for (idx_rel, extractor) in handle.indices.values() {
+                    let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
+                    let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                    tx.store_tx.put(&encoded, &[])?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The triggers of a relation as rows of `type`, `idx`, `trigger` and `guard`.
+fn trigger_rows(rel: &RelationHandle) -> Vec<Tuple> {
+    let typed = [
+        ("put", &rel.put_triggers, &rel.put_trigger_guards[..]),
+        ("rm", &rel.rm_triggers, &rel.rm_trigger_guards[..]),
+        ("replace", &rel.replace_triggers, &[][..]),
+    ];
+    typed
+        .into_iter()
+        .flat_map(|(typ, triggers, guards)| {
+            triggers.iter().enumerate().map(move |(i, trigger)| {
+                vec![
+                    DataValue::from(typ),
+                    DataValue::from(i as i64),
+                    DataValue::from(trigger.as_str()),
+                    match guards.get(i) {
+                        Some(Some(guard)) => DataValue::from(guard.as_str()),
+                        _ => DataValue::Null,
+                    },
+                ]
+            })
+        })
+        .collect_vec()
+}
+
+/// Values of `columns` taken from a JSON object of NDJSON data, or from their defaults.
+fn coerce_ndjson_row(
+    obj: &serde_json::Map<String, JsonValue>,
+    columns: &[ColumnDef],
+    cur_vld: ValidityTs,
+) -> Result<Vec<DataValue>> {
+    columns
+        .iter()
+        .map(|col| {
+            let val = match (obj.get(col.name.as_str()), &col.default_gen) {
+                (Some(v), _) => DataValue::from(v),
+                (None, Some(gen)) => gen.clone().eval_to_const()?,
+                (None, None) => bail!("required column {} not found", col.name),
+            };
+            col.typing
+                .coerce(val, cur_vld)
+                .wrap_err_with(|| format!("when processing column {}", col.name))
+        })
+        .try_collect()
+}
+
+fn validate_import_rows(
+    tx: &SessionTx<'_>,
+    relation_op: &str,
+    in_data: &NamedRows,
+    cur_vld: ValidityTs,
+    invalid: &mut Vec<InvalidImportRow>,
+) -> Result<()> {
+    let (handle, is_delete) = import_target(tx, relation_op)?;
+    let (key_indices, val_indices) = import_columns(&handle, &in_data.headers, is_delete)?;
+    for (i, row) in in_data.rows.iter().enumerate() {
+        if let Err(err) = coerce_import_row(row, &key_indices, cur_vld, &handle.name, i)
+            .and_then(|_| coerce_import_row(row, &val_indices, cur_vld, &handle.name, i))
+        {
+            invalid.push(InvalidImportRow {
+                row: Some(i),
+                error: err.chain().join(": "),
+            });
+        }
+    }
+    Ok(())
+}
+
 #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, Clone, Default)]
 /// Rows in a relation, together with headers for the fields.
 pub struct NamedRows {
@@ -224,6 +827,46 @@ impl NamedRows {
     }
 }
 
+/// What a query reports about its result besides the rows,
+/// returned by [Db::run_script_with_report] and [Db::execute_with_report].
+#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct QueryReport {
+    /// Warnings about the query, e.g. rules that are never used
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Seconds taken to compile the query. Only set when the query has the `:profile` option.
+    #[serde(default)]
+    pub compile_time: Option<f64>,
+    /// Seconds taken to evaluate each stratum of the query, in the order of evaluation.
+    /// Only set when the query has the `:profile` option.
+    #[serde(default)]
+    pub stratum_times: Option<Vec<f64>>,
+    /// The type of each column, in the syntax of `:create`, e.g. `Int?`. Only set when the
+    /// query has the `:with_types` option.
+    #[serde(default)]
+    pub column_types: Option<Vec<String>>,
+}
+
+impl QueryReport {
+    /// Add the report to `ret`, the JSON object of the result made by [NamedRows::into_json].
+    /// Only what the query reported is added.
+    pub fn extend_json(self, ret: &mut JsonValue) {
+        if !self.warnings.is_empty() {
+            ret["warnings"] = json!(self.warnings);
+        }
+        if let Some(compile_time) = self.compile_time {
+            ret["compile_time"] = json!(compile_time);
+        }
+        if let Some(stratum_times) = self.stratum_times {
+            ret["stratum_times"] = json!(stratum_times);
+        }
+        if let Some(column_types) = self.column_types {
+            ret["column_types"] = json!(column_types);
+        }
+    }
+}
+
 const STATUS_STR: &str = "status";
 const OK_STR: &str = "OK";
 
@@ -257,12 +900,127 @@ impl<'s, S: Storage<'s>> Db<S> {
             #[cfg(not(target_arch = "wasm32"))]
             event_callbacks: Default::default(),
             relation_locks: Default::default(),
+            removed_relations: Default::default(),
+            row_counts: Default::default(),
+            commit_latencies: Default::default(),
+            default_mem_limit: Default::default(),
+            default_timeout: Default::default(),
+            write_lock_timeout: Default::default(),
+            scan_readahead: Default::default(),
+            max_concurrent_queries: Default::default(),
+            admitted_scripts: Default::default(),
+            default_access_level: Default::default(),
+            change_marker: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            eval_thread_pools: Default::default(),
         };
         Ok(ret)
     }
 
+    /// Set the memory budget, in bytes, for queries that do not set their own with `:mem_limit`.
+    /// `None` removes the budget.
+    ///
+    /// The budget applies to the estimated size of the tuples derived during evaluation,
+    /// and is checked after each semi-naive iteration. Queries exceeding it fail with
+    /// an `eval::mem_limit` error.
+    pub fn set_default_mem_limit(&self, bytes: Option<usize>) {
+        self.default_mem_limit
+            .store(bytes.unwrap_or(0), Ordering::Release);
+    }
+
+    /// Set the timeout, in seconds, for queries that do not set their own with `:timeout`.
+    /// A timeout of 0 removes it.
+    ///
+    /// Each query of a script is timed separately, and queries running out of time fail
+    /// with an `eval::killed` error. As with `:timeout`, the deadline is checked during
+    /// evaluation, without starting a thread per query.
+    pub fn set_default_timeout(&self, secs: f64) {
+        *self.default_timeout.write().unwrap() = secs.max(0.);
+    }
+
+    /// Set the time, in seconds, to wait for other writers when starting a write transaction.
+    /// A timeout of 0, the default, waits indefinitely.
+    ///
+    /// Scripts that cannot start their write transaction in time fail with a
+    /// `db::write_timeout` error. Only engines that allow one writer at a time make writers
+    /// wait: these are the in-memory and the SQLite engines.
+    pub fn set_write_lock_timeout(&self, secs: f64) {
+        *self.write_lock_timeout.write().unwrap() = secs.max(0.);
+    }
+
+    /// Set how many bytes to read ahead of the iterator when exports, backups and imports
+    /// from backups scan whole relations. 0, the default, leaves it to the storage engine.
+    ///
+    /// Only the RocksDB engine reads ahead: a few megabytes speed up full scans a lot when
+    /// they are bound by the cost of fetching each key rather than by the disk.
+    pub fn set_scan_readahead(&self, bytes: usize) {
+        self.scan_readahead.store(bytes, Ordering::Release);
+    }
+
+    /// Set the maximum number of queries and imperative scripts running at once.
+    /// `None`, the default, removes the limit.
+    ///
+    /// Scripts and prepared queries started while the limit is reached are not admitted and
+    /// fail at once with a `db::too_busy` error, which clients may retry later. System ops
+    /// such as `::running` and `::kill` are always admitted. A multi-statement transaction
+    /// is admitted when it begins and counts as one running script until it ends.
+    pub fn set_max_concurrent_queries(&self, n: Option<usize>) {
+        self.max_concurrent_queries
+            .store(n.unwrap_or(0), Ordering::Release);
+    }
+
+    pub(crate) fn admit_script(&self) -> Result<AdmittedScript> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The database is too busy: {0} queries are already running")]
+        #[diagnostic(code(db::too_busy))]
+        #[diagnostic(help("Retry the query later"))]
+        struct TooBusy(usize);
+
+        let max = self.max_concurrent_queries.load(Ordering::Acquire);
+        let running = self.admitted_scripts.fetch_add(1, Ordering::AcqRel);
+        // dropped on failure, which gives back the slot just taken
+        let admitted = AdmittedScript {
+            admitted: self.admitted_scripts.clone(),
+        };
+        ensure!(max == 0 || running < max, TooBusy(running));
+        Ok(admitted)
+    }
+
+    pub(crate) fn scan_hint(&self) -> ScanHint {
+        ScanHint {
+            readahead_bytes: self.scan_readahead.load(Ordering::Acquire),
+        }
+    }
+
+    /// Set how durable the commits of write transactions are. The default is
+    /// [Durability::Full].
+    ///
+    /// With [Durability::Relaxed], commits no longer wait for the disk, which speeds up
+    /// bulk loads made of many transactions, at the price of **losing or corrupting data
+    /// if the machine crashes**. Call [Db::flush] periodically and when the load is done.
+    /// Only the SQLite engine distinguishes the two; the other engines keep their defaults.
+    pub fn set_durability(&self, durability: Durability) -> Result<()> {
+        self.db.set_durability(durability)
+    }
+
+    /// Sync all committed writes to disk, including those committed with
+    /// [Durability::Relaxed]. Waits for the running write transaction, if any, to finish.
+    pub fn flush(&'s self) -> Result<()> {
+        self.db.flush()
+    }
+
+    /// Set the access level given to stored relations created from now on,
+    /// by `:create`, by `:replace` of a relation that does not exist yet, or as the output
+    /// of relation set operations. The initial rows of a `:create` are written regardless.
+    /// Existing relations keep their access levels, and temp relations are unaffected.
+    /// The default is [AccessLevel::Normal].
+    pub fn set_default_access_level(&self, level: AccessLevel) {
+        *self.default_access_level.write().unwrap() = level;
+    }
+
     /// Must be called after creation of the database to initialize the runtime state.
     pub fn initialize(&'s self) -> Result<()> {
+        *self.change_marker.write().unwrap() = load_change_marker(&self.db)?;
         self.load_last_ids()?;
         Ok(())
     }
@@ -280,6 +1038,13 @@ impl<'s, S: Storage<'s>> Db<S> {
         payloads: Receiver<TransactionPayload>,
         results: Sender<Result<NamedRows>>,
     ) {
+        let _admitted = match self.admit_script() {
+            Ok(admitted) => admitted,
+            Err(err) => {
+                let _ = results.send(Err(err));
+                return;
+            }
+        };
         let tx = if is_write {
             self.transact_write()
         } else {
@@ -308,11 +1073,12 @@ impl<'s, S: Storage<'s>> Db<S> {
                         }
                     }
 
-                    let _ = results.send(tx.commit_tx().map(|_| NamedRows::default()));
+                    let committed = tx.commit_tx();
                     #[cfg(not(target_arch = "wasm32"))]
-                    if !callback_collector.is_empty() {
+                    if committed.is_ok() && !callback_collector.is_empty() {
                         self.send_callbacks(callback_collector)
                     }
+                    let _ = results.send(committed.map(|_| NamedRows::default()));
 
                     break;
                 }
@@ -357,6 +1123,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         }
                     }
 
+                    tx.script = Some(RunningScript::new(&script, &params));
                     let res = self.execute_single_program(
                         p,
                         &mut tx,
@@ -374,6 +1141,11 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
 
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
+    ///
+    /// Parameter values are used exactly as given: they are not converted through JSON,
+    /// so integers and floats keep their types and integers keep all their digits.
+    /// Likewise the rows of the result hold the [DataValue]s computed by the query:
+    /// nothing is converted to JSON unless [NamedRows::into_json] is called.
     pub fn run_script(
         &'s self,
         payload: &str,
@@ -386,227 +1158,1040 @@ impl<'s, S: Storage<'s>> Db<S> {
             &params,
             cur_vld,
             mutability == ScriptMutability::Immutable,
+            ScriptHooks::default(),
         )
     }
-    /// Run the CozoScript passed in. The `params` argument is a map of parameters.
+    /// Run the CozoScript passed in, like [Db::run_script], also returning what the query
+    /// reports about its result besides the rows, such as warnings.
+    /// For imperative scripts, the report is that of the last query run.
+    pub fn run_script_with_report(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<(NamedRows, QueryReport)> {
+        let cur_vld = current_validity();
+        let report = Arc::new(Mutex::new(QueryReport::default()));
+        let rows = self.do_run_script(
+            payload,
+            &params,
+            cur_vld,
+            mutability == ScriptMutability::Immutable,
+            ScriptHooks {
+                report: Some(report.clone()),
+                ..Default::default()
+            },
+        )?;
+        let report = std::mem::take(&mut *report.lock().unwrap());
+        Ok((rows, report))
+    }
+    /// Run the CozoScript passed in, like [Db::run_script], while reporting the intermediate
+    /// values of meet aggregations (`min`, `max`, `shortest`, etc.) in recursive rules
+    /// to `on_progress` as evaluation proceeds. Useful for showing progress of long computations.
+    pub fn run_script_with_progress(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        on_progress: impl Fn(AggrProgress) + Send + Sync + 'static,
+    ) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        self.do_run_script(
+            payload,
+            &params,
+            cur_vld,
+            mutability == ScriptMutability::Immutable,
+            ScriptHooks {
+                aggr_progress: Some(Arc::new(on_progress)),
+                ..Default::default()
+            },
+        )
+    }
+    /// Run the CozoScript passed in, like [Db::run_script], while reporting the progress
+    /// of evaluation to `on_progress` after each semi-naive iteration of each stratum.
+    ///
+    /// Returning `false` from `on_progress` terminates the running query
+    /// with an `eval::killed` error.
+    pub fn run_script_with_eval_progress(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        on_progress: impl Fn(EvalProgress) -> bool + Send + Sync + 'static,
+    ) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        self.do_run_script(
+            payload,
+            &params,
+            cur_vld,
+            mutability == ScriptMutability::Immutable,
+            ScriptHooks {
+                eval_progress: Some(Arc::new(on_progress)),
+                ..Default::default()
+            },
+        )
+    }
+    /// Run the CozoScript passed in, like [Db::run_script], with a cancellation token.
+    ///
+    /// Calling [Poison::kill] on `cancel` from another thread terminates the running script
+    /// with an `eval::killed` error. The token is also set when a query in the script
+    /// times out or is killed with `::kill`, so use a fresh token for each script.
+    pub fn run_script_with_cancel(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        cancel: Poison,
+    ) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        self.do_run_script(
+            payload,
+            &params,
+            cur_vld,
+            mutability == ScriptMutability::Immutable,
+            ScriptHooks {
+                cancel: Some(cancel),
+                ..Default::default()
+            },
+        )
+    }
+    /// Run the CozoScript passed in, like [Db::run_script], calling `on_start` with the id
+    /// of the query as soon as it is registered as running, before it is evaluated.
+    ///
+    /// The id can be passed to `::kill` (from another thread) to terminate the query.
+    /// For imperative scripts, the id is that of the whole script.
+    /// System ops are not registered as running queries and do not call `on_start`.
+    pub fn run_script_with_query_id(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+        on_start: impl FnOnce(u64) + Send + Sync + 'static,
+    ) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        self.do_run_script(
+            payload,
+            &params,
+            cur_vld,
+            mutability == ScriptMutability::Immutable,
+            ScriptHooks {
+                on_query_start: Some(Box::new(on_start)),
+                ..Default::default()
+            },
+        )
+    }
+    /// Run the CozoScript passed in, guaranteeing that it does not change the database.
+    /// The `params` argument is a map of parameters.
+    ///
+    /// The script is rejected before any transaction is opened if any of its queries
+    /// writes to a stored relation, or if it contains a system op
+    /// that changes the database, such as `::remove`, `::rename`, `::set_triggers`
+    /// or `::access_level`.
     pub fn run_script_read_only(
         &'s self,
         payload: &str,
         params: BTreeMap<String, DataValue>,
     ) -> Result<NamedRows> {
         let cur_vld = current_validity();
-        self.do_run_script(payload, &params, cur_vld, true)
+        let script = parse_script(payload, &params, &self.fixed_rules.read().unwrap(), cur_vld)?;
+        if let Some(mutation) = script.find_mutation() {
+            bail!(ReadOnlyViolationError(mutation))
+        }
+        let hooks = ScriptHooks {
+            script: Some(RunningScript::new(payload, &params)),
+            ..Default::default()
+        };
+        self.execute_script(script, cur_vld, true, hooks)
+    }
+
+    /// Compile a query and describe how it would be evaluated, without running it.
+    ///
+    /// `payload` must be a single query, optionally wrapped in `::explain { ... }`.
+    /// The plan holds the same information as the rows returned by `::explain`,
+    /// which are obtained with [ExplainPlan::into_named_rows].
+    pub fn explain(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<ExplainPlan> {
+        let script = parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+        )?;
+        let prog = match script {
+            CozoScript::Sys(SysOp::Explain(prog)) => *prog,
+            script => script.get_single_program()?,
+        };
+        let mut tx = self.transact()?;
+        let plan = self.plan_program(&mut tx, prog)?;
+        tx.commit_tx()?;
+        Ok(plan)
+    }
+
+    /// Compile every query of a script and describe how each would be evaluated,
+    /// without running anything.
+    ///
+    /// The plans are returned in the order the queries appear in the script, including
+    /// queries in all branches of imperative scripts and those wrapped in `::explain`.
+    /// Other system ops are skipped. Since nothing is run, a query reading a relation created
+    /// by an earlier statement of the script cannot be compiled, and an error is returned.
+    pub fn plan(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<ExplainPlan>> {
+        let script = parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+        )?;
+        let mut tx = self.transact()?;
+        let mut plans = vec![];
+        for (i, prog) in script.into_programs().into_iter().enumerate() {
+            let plan = self
+                .plan_program(&mut tx, prog)
+                .wrap_err_with(|| format!("when planning query {i} of the script"))?;
+            plans.push(plan);
+        }
+        tx.commit_tx()?;
+        Ok(plans)
+    }
+
+    /// The kind and version of the storage engine of the database
+    pub fn storage_info(&self) -> StorageInfo {
+        StorageInfo {
+            engine: self.db.storage_kind(),
+            version: self.db.storage_version(),
+        }
+    }
+    /// Whether running the script could change the database, determined by parsing it
+    /// without running anything. Scripts for which this returns `true` are exactly those
+    /// rejected when run with [ScriptMutability::Immutable].
+    ///
+    /// Mutating system ops count but writes to temp relations do not, and all branches of
+    /// imperative scripts are considered, whether or not they would be taken.
+    pub fn script_is_write(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<bool> {
+        let script = parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+        )?;
+        Ok(script.find_mutation().is_some())
+    }
+
+    fn plan_program(&'s self, tx: &mut SessionTx<'_>, prog: InputProgram) -> Result<ExplainPlan> {
+        let (normalized_program, _) = prog.into_normalized_program(tx)?;
+        let (stratified_program, _) = normalized_program.into_stratified_program()?;
+        let program = stratified_program.magic_sets_rewrite(tx)?;
+        let compiled = tx.stratified_magic_compile(program)?;
+        self.explain_compiled(Some(tx), &compiled, None)
     }
 
     /// Export relations to JSON data.
     ///
     /// `relations` contains names of the stored relations to export.
+    /// Columns are in [ColumnOrder::Stored] order.
+    ///
+    /// All relations are read in a single read transaction, so the export is a consistent
+    /// snapshot of the database: writes committed while it runs are either fully visible
+    /// or not at all. The Sled backend does not provide snapshots and is the exception.
     pub fn export_relations<I, T>(&'s self, relations: I) -> Result<BTreeMap<String, NamedRows>>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        self.export_relations_ordered(relations, ColumnOrder::Stored)
+    }
+    /// Export relations to JSON data, with the columns in the requested order.
+    ///
+    /// `relations` contains names of the stored relations to export.
+    pub fn export_relations_ordered<I, T>(
+        &'s self,
+        relations: I,
+        order: ColumnOrder,
+    ) -> Result<BTreeMap<String, NamedRows>>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        Ok(self.export_relations_in(relations, order)?.0)
+    }
+    /// Export relations like [Self::export_relations], together with the schema of each
+    /// relation in the syntax of `:create`, e.g. `{k: Int => v: String}`.
+    ///
+    /// Passing both to [Self::import_relations_with_schema] recreates the relations
+    /// in a database where they do not exist yet.
+    #[allow(clippy::type_complexity)]
+    pub fn export_relations_with_schema<I, T>(
+        &'s self,
+        relations: I,
+    ) -> Result<(BTreeMap<String, NamedRows>, BTreeMap<String, String>)>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        self.export_relations_in(relations, ColumnOrder::Stored)
+    }
+    #[allow(clippy::type_complexity)]
+    fn export_relations_in<I, T>(
+        &'s self,
+        relations: I,
+        order: ColumnOrder,
+    ) -> Result<(BTreeMap<String, NamedRows>, BTreeMap<String, String>)>
     where
         T: AsRef<str>,
         I: Iterator<Item = T>,
     {
         let tx = self.transact()?;
         let mut ret: BTreeMap<String, NamedRows> = BTreeMap::new();
+        let mut schemas = BTreeMap::new();
         for rel in relations {
             let handle = tx.get_relation(rel.as_ref(), false)?;
             let size_hint = handle.metadata.keys.len() + handle.metadata.non_keys.len();
 
-            if handle.access_level < AccessLevel::ReadOnly {
-                bail!(InsufficientAccessLevel(
-                    handle.name.to_string(),
-                    "data export".to_string(),
-                    handle.access_level
-                ));
-            }
+            handle.ensure_exportable()?;
 
-            let mut cols = handle
-                .metadata
-                .keys
-                .iter()
-                .map(|col| col.name.clone())
-                .collect_vec();
-            cols.extend(
-                handle
-                    .metadata
-                    .non_keys
-                    .iter()
-                    .map(|col| col.name.clone())
-                    .collect_vec(),
-            );
+            let mut headers = handle.column_names();
+            let permutation = match order {
+                ColumnOrder::Stored => None,
+                ColumnOrder::Declared => tx.declared_column_order(&handle)?,
+            };
 
             let start = Tuple::default().encode_as_key(handle.id);
             let end = Tuple::default().encode_as_key(handle.id.next());
 
             let mut rows = vec![];
-            for data in tx.store_tx.range_scan(&start, &end) {
+            for data in tx
+                .store_tx
+                .range_scan_with_hint(&start, &end, self.scan_hint())
+            {
                 let (k, v) = data?;
                 let tuple = decode_tuple_from_kv(&k, &v, Some(size_hint));
                 rows.push(tuple);
             }
-            let headers = cols.iter().map(|col| col.to_string()).collect_vec();
+            if let Some(permutation) = permutation {
+                headers = permutation
+                    .iter()
+                    .map(|i| headers[*i].clone())
+                    .collect_vec();
+                for row in rows.iter_mut() {
+                    *row = permutation.iter().map(|i| row[*i].clone()).collect_vec();
+                }
+            }
+            schemas.insert(rel.as_ref().to_string(), handle.metadata.to_string());
             ret.insert(rel.as_ref().to_string(), NamedRows::new(headers, rows));
         }
+        Ok((ret, schemas))
+    }
+    /// Export the definitions of stored relations as CozoScript, to be replayed by
+    /// [Self::import_schema] before importing the data returned by [Self::export_relations].
+    ///
+    /// Returns a JSON object mapping each relation name to an array of statements:
+    /// the `:create` statement of the relation, followed by `::index create` statements for
+    /// its standard indices. Vector, FTS and LSH indices, triggers and access levels are
+    /// not included.
+    pub fn export_schema<I, T>(&'s self, relations: I) -> Result<JsonValue>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot export the schema of {0} as it is an index")]
+        #[diagnostic(code(export::schema_of_index))]
+        #[diagnostic(help("Indices are exported together with their relation"))]
+        struct ExportSchemaOfIndex(String);
+
+        let tx = self.transact()?;
+        let mut ret = serde_json::Map::new();
+        for rel in relations {
+            let rel = rel.as_ref();
+            ensure!(!rel.contains(':'), ExportSchemaOfIndex(rel.to_string()));
+            let handle = tx.get_relation(rel, false)?;
+            handle.ensure_exportable()?;
+            let mut statements = vec![json!(format!(
+                ":create {} {}",
+                handle.name, handle.metadata
+            ))];
+            for (idx_name, (idx_handle, _)) in &handle.indices {
+                let cols = idx_handle
+                    .metadata
+                    .keys
+                    .iter()
+                    .map(|col| &col.name)
+                    .join(", ");
+                statements.push(json!(format!(
+                    "::index create {}:{idx_name} {{{cols}}}",
+                    handle.name
+                )));
+            }
+            ret.insert(handle.name.to_string(), JsonValue::Array(statements));
+        }
+        Ok(JsonValue::Object(ret))
+    }
+    /// Create the relations and indices described by the output of [Self::export_schema].
+    ///
+    /// The statements are run one by one, each in its own transaction,
+    /// so if one of them fails the relations created before it are kept.
+    pub fn import_schema(&'s self, schema: &JsonValue) -> Result<()> {
+        let schema = schema
+            .as_object()
+            .ok_or_else(|| miette!("schema must be a JSON object"))?;
+        for (name, statements) in schema {
+            let statements = statements
+                .as_array()
+                .ok_or_else(|| miette!("schema of relation {name} must be an array"))?;
+            for statement in statements {
+                let statement = statement.as_str().ok_or_else(|| {
+                    miette!("schema of relation {name} must be an array of strings")
+                })?;
+                self.run_script(statement, Default::default(), ScriptMutability::Mutable)
+                    .wrap_err_with(|| format!("when creating relation {name}"))?;
+            }
+        }
+        Ok(())
+    }
+    /// Export only some of the columns of stored relations to JSON data.
+    ///
+    /// Each entry of `relations` is the name of a stored relation and the columns to export,
+    /// which are returned in the order given. If only key columns are requested,
+    /// the non-key part of the rows is not decoded at all.
+    pub fn export_relations_projected(
+        &'s self,
+        relations: &[(&str, Vec<&str>)],
+    ) -> Result<BTreeMap<String, NamedRows>> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot export column '{1}' of relation '{0}': no such column")]
+        #[diagnostic(code(export::column_not_found))]
+        struct ExportColumnNotFound(String, String);
+
+        let tx = self.transact()?;
+        let mut ret: BTreeMap<String, NamedRows> = BTreeMap::new();
+        for (rel, columns) in relations {
+            let handle = tx.get_relation(rel, false)?;
+            handle.ensure_exportable()?;
+
+            let all_headers = handle.column_names();
+            let projection: Vec<usize> = columns
+                .iter()
+                .map(|col| {
+                    all_headers
+                        .iter()
+                        .position(|h| h == col)
+                        .ok_or_else(|| ExportColumnNotFound(rel.to_string(), col.to_string()))
+                })
+                .try_collect()?;
+            let n_keys = handle.metadata.keys.len();
+            let keys_only = projection.iter().all(|i| *i < n_keys);
+            let size_hint = if keys_only { n_keys } else { handle.arity() };
+
+            let start = Tuple::default().encode_as_key(handle.id);
+            let end = Tuple::default().encode_as_key(handle.id.next());
+
+            let mut rows = vec![];
+            for data in tx
+                .store_tx
+                .range_scan_with_hint(&start, &end, self.scan_hint())
+            {
+                let (k, v) = data?;
+                let tuple = if keys_only {
+                    decode_tuple_from_key(&k, size_hint)
+                } else {
+                    decode_tuple_from_kv(&k, &v, Some(size_hint))
+                };
+                rows.push(projection.iter().map(|i| tuple[*i].clone()).collect_vec());
+            }
+            let headers = columns.iter().map(|col| col.to_string()).collect_vec();
+            ret.insert(rel.to_string(), NamedRows::new(headers, rows));
+        }
         Ok(ret)
     }
-    /// Import relations. The argument `data` accepts data in the shape of
-    /// what was returned by [Self::export_relations].
-    /// The target stored relations must already exist in the database.
-    /// Any associated indices will be updated.
+    /// Collect up to `limit` distinct values of `column` in a stored relation.
+    ///
+    /// If `column` is the first key column, or the first column of an index on the relation,
+    /// the values are read off an ordered scan and returned in ascending order.
+    /// Otherwise the relation is scanned in key order and values are returned
+    /// in the order they are first encountered.
+    pub fn distinct_values(
+        &'s self,
+        relation: &str,
+        column: &str,
+        limit: usize,
+    ) -> Result<Vec<DataValue>> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        handle.ensure_exportable()?;
+        let col_idx = handle
+            .column_names()
+            .iter()
+            .position(|col| col == column)
+            .ok_or_else(|| miette!("column {} not found in relation {}", column, handle.name))?;
+        let mut ret = vec![];
+        if limit == 0 {
+            return Ok(ret);
+        }
+
+        let ordered_source = if col_idx == 0 {
+            Some(&handle)
+        } else {
+            handle
+                .indices
+                .values()
+                .find(|(_, extractor)| extractor.first() == Some(&col_idx))
+                .map(|(idx_handle, _)| idx_handle)
+        };
+
+        match ordered_source {
+            Some(source) => {
+                // the values come out sorted, so duplicates are always adjacent
+                let size_hint = source.metadata.keys.len() + source.metadata.non_keys.len();
+                let start = Tuple::default().encode_as_key(source.id);
+                let end = Tuple::default().encode_as_key(source.id.next());
+                for data in tx.store_tx.range_scan(&start, &end) {
+                    let (k, v) = data?;
+                    let val = decode_tuple_from_kv(&k, &v, Some(size_hint)).swap_remove(0);
+                    if ret.last() != Some(&val) {
+                        ret.push(val);
+                        if ret.len() >= limit {
+                            break;
+                        }
+                    }
+                }
+            }
+            None => {
+                let size_hint = handle.metadata.keys.len() + handle.metadata.non_keys.len();
+                let start = Tuple::default().encode_as_key(handle.id);
+                let end = Tuple::default().encode_as_key(handle.id.next());
+                // values are never mutated once in the set, whatever interior mutability
+                // `DataValue` allows
+                #[allow(clippy::mutable_key_type)]
+                let mut seen = BTreeSet::new();
+                for data in tx.store_tx.range_scan(&start, &end) {
+                    let (k, v) = data?;
+                    let val = decode_tuple_from_kv(&k, &v, Some(size_hint)).swap_remove(col_idx);
+                    if seen.insert(val.clone()) {
+                        ret.push(val);
+                        if ret.len() >= limit {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+    /// Describe a stored relation in a single call, for tools that display relations.
+    ///
+    /// Returns a JSON object with the `name` of the relation, its `columns`
+    /// (as returned by `::columns`), the number of rows as `row_count`,
+    /// and up to `sample_size` rows as `sample`, taken from the start of the relation in key order.
+    pub fn inspect_relation(&'s self, name: &str, sample_size: usize) -> Result<JsonValue> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(name, false)?;
+        handle.ensure_exportable()?;
+        let columns = self.list_columns(&tx, name)?;
+
+        let start = Tuple::default().encode_as_key(handle.id);
+        let end = Tuple::default().encode_as_key(handle.id.next());
+        let row_count = tx.store_tx.range_count(&start, &end)?;
+        let sample: Vec<_> = handle.scan_all(&tx).take(sample_size).try_collect()?;
+
+        Ok(json!({
+            "name": handle.name,
+            "columns": columns.into_json(),
+            "row_count": row_count,
+            "sample": NamedRows::new(handle.column_names(), sample).into_json(),
+        }))
+    }
+    /// Import relations. The argument `data` accepts data in the shape of
+    /// what was returned by [Self::export_relations].
+    /// Any associated indices will be updated.
+    ///
+    /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
+    /// Relations that triggers derive from the imported ones are therefore not updated,
+    /// and the caller is responsible for rebuilding them afterwards.
+    /// If you need to activate triggers or callbacks, use queries with parameters.
+    /// Subscribers registered by [Db::subscribe] do receive the imported changes.
+    pub fn import_relations(&'s self, data: BTreeMap<String, NamedRows>) -> Result<()> {
+        self.import_relations_with_schema(data, &BTreeMap::new())
+    }
+    /// Import relations like [Self::import_relations], first creating each target stored
+    /// relation that does not exist from its schema in `schemas`, in the shape of what was
+    /// returned by [Self::export_relations_with_schema]. Indices and temp relations are
+    /// never created, and relations without a schema must already exist.
+    pub fn import_relations_with_schema(
+        &'s self,
+        data: BTreeMap<String, NamedRows>,
+        schemas: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        #[derive(Debug, Diagnostic, Error)]
+        #[error("cannot import data for relation '{0}': {1}")]
+        #[diagnostic(code(import::bad_data))]
+        struct BadDataForRelation(String, JsonValue);
+
+        let rel_names = data.keys().map(SmartString::from).collect_vec();
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+
+        let cur_vld = current_validity();
+        let access_level = *self.default_access_level.read().unwrap();
+
+        let subscribed = self.current_subscription_targets();
+        let mut changes = BTreeMap::new();
+
+        let mut tx = self.transact_write()?;
+
+        for (relation_op, in_data) in data {
+            if let Some(schema) = schemas.get(&relation_op) {
+                create_import_target(&mut tx, &relation_op, schema, access_level)?;
+            }
+            let (handle, is_delete) = import_target(&tx, &relation_op)?;
+            let (key_indices, val_indices) = import_columns(&handle, &in_data.headers, is_delete)?;
+            if subscribed.contains(&handle.name) {
+                changes.entry(handle.name.clone()).or_default();
+            }
+
+            for (i, row) in in_data.rows.into_iter().enumerate() {
+                let keys = coerce_import_row(&row, &key_indices, cur_vld, &handle.name, i)?;
+                let vals = if is_delete {
+                    None
+                } else {
+                    Some(coerce_import_row(
+                        &row,
+                        &val_indices,
+                        cur_vld,
+                        &handle.name,
+                        i,
+                    )?)
+                };
+                write_import_row(&mut tx, &handle, keys, vals, changes.get_mut(&handle.name))?;
+            }
+        }
+        tx.commit_tx()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        self.send_change_events(changes);
+        Ok(())
+    }
+    /// Import relations like [Db::import_relations], but commit every `batch_size` rows
+    /// instead of once at the end, so that the storage engine never has to hold more than
+    /// `batch_size` rows of pending writes.
+    ///
+    /// The import is therefore **not** atomic: if it fails, the batches committed before
+    /// the failure stay in the database, and other transactions can see a partial import
+    /// while it runs. The error of a failed import states how many rows were committed.
+    pub fn import_relations_chunked(
+        &'s self,
+        data: BTreeMap<String, NamedRows>,
+        batch_size: usize,
+    ) -> Result<()> {
+        self.import_relations_batched(data, batch_size, None)
+    }
+    /// Import relations like [Db::import_relations_chunked], recording with each batch the
+    /// number of rows of each entry of `data` that are committed in the stored relation
+    /// `checkpoint_rel`, which is created if it does not exist.
+    ///
+    /// When the import is run again with the same data after a failure or a crash, the rows
+    /// already committed are skipped and the import resumes where it stopped. The checkpoints
+    /// are kept once the import is complete, so running it again imports nothing: remove the
+    /// checkpoint relation before starting a different import with it.
+    pub fn import_relations_resumable(
+        &'s self,
+        data: BTreeMap<String, NamedRows>,
+        batch_size: usize,
+        checkpoint_rel: &str,
+    ) -> Result<()> {
+        self.import_relations_batched(data, batch_size, Some(checkpoint_rel))
+    }
+    fn import_relations_batched(
+        &'s self,
+        data: BTreeMap<String, NamedRows>,
+        batch_size: usize,
+        checkpoint_rel: Option<&str>,
+    ) -> Result<()> {
+        #[derive(Debug, Diagnostic, Error)]
+        #[error("the batch size of a chunked import must be positive")]
+        #[diagnostic(code(import::bad_batch_size))]
+        struct BadBatchSize;
+
+        ensure!(batch_size > 0, BadBatchSize);
+
+        let mut rel_names = data.keys().map(SmartString::from).collect_vec();
+        rel_names.extend(checkpoint_rel.map(SmartString::from));
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+
+        let cur_vld = current_validity();
+        let access_level = *self.default_access_level.read().unwrap();
+
+        let subscribed = self.current_subscription_targets();
+        let mut changes = BTreeMap::new();
+
+        let mut tx = self.transact_write()?;
+        let mut n_committed = 0;
+        let mut n_pending = 0;
+        let import = || -> Result<()> {
+            let checkpoint = match checkpoint_rel {
+                None => None,
+                Some(name) => Some(import_checkpoint(&mut tx, name, access_level)?),
+            };
+            for (relation_op, in_data) in data {
+                let (handle, is_delete) = import_target(&tx, &relation_op)?;
+                let (key_indices, val_indices) =
+                    import_columns(&handle, &in_data.headers, is_delete)?;
+                if subscribed.contains(&handle.name) {
+                    changes.entry(handle.name.clone()).or_default();
+                }
+                let n_rows = in_data.rows.len();
+                let n_done = match &checkpoint {
+                    None => 0,
+                    Some(checkpoint) => checkpointed_rows(&tx, checkpoint, &relation_op)?,
+                };
+                // the checkpoint is written with the rows, so that both are committed together
+                let write_checkpoint = |tx: &mut SessionTx<'_>, n: usize| match &checkpoint {
+                    None => Ok(()),
+                    Some(checkpoint) => write_import_row(
+                        tx,
+                        checkpoint,
+                        vec![DataValue::from(relation_op.as_str())],
+                        Some(vec![DataValue::from(n as i64)]),
+                        None,
+                    ),
+                };
+
+                for (i, row) in in_data.rows.into_iter().enumerate().skip(n_done) {
+                    let keys = coerce_import_row(&row, &key_indices, cur_vld, &handle.name, i)?;
+                    let vals = if is_delete {
+                        None
+                    } else {
+                        Some(coerce_import_row(
+                            &row,
+                            &val_indices,
+                            cur_vld,
+                            &handle.name,
+                            i,
+                        )?)
+                    };
+                    write_import_row(&mut tx, &handle, keys, vals, changes.get_mut(&handle.name))?;
+                    n_pending += 1;
+                    if n_pending == batch_size {
+                        write_checkpoint(&mut tx, i + 1)?;
+                        tx.commit_tx()?;
+                        n_committed += n_pending;
+                        n_pending = 0;
+                        #[cfg(not(target_arch = "wasm32"))]
+                        self.send_change_events(
+                            changes
+                                .iter_mut()
+                                .map(|(name, events)| (name.clone(), std::mem::take(events)))
+                                .collect(),
+                        );
+                        // the old transaction must be gone before the next one is started,
+                        // as storage engines may allow only one write transaction at a time
+                        drop(tx);
+                        tx = self.transact_write()?;
+                    }
+                }
+                if n_rows > n_done {
+                    write_checkpoint(&mut tx, n_rows)?;
+                }
+            }
+            tx.commit_tx()?;
+            #[cfg(not(target_arch = "wasm32"))]
+            self.send_change_events(std::mem::take(&mut changes));
+            Ok(())
+        };
+        import().wrap_err_with(|| format!("{n_committed} rows were committed before the failure"))
+    }
+    /// Import newline-delimited JSON into a stored relation.
     ///
-    /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
-    /// If you need to activate triggers or callbacks, use queries with parameters.
-    pub fn import_relations(&'s self, data: BTreeMap<String, NamedRows>) -> Result<()> {
+    /// Each non-empty line of `reader` must be a JSON object mapping column names to values.
+    /// Columns missing from an object take their default values, and objects for
+    /// `-relation` only need the key columns, as the rows are deleted instead.
+    /// Lines are read and written in batches of [NDJSON_IMPORT_BATCH_ROWS] rows, so the
+    /// data is never held in memory as a whole, but all rows are written in a single
+    /// transaction. If any line cannot be parsed or coerced, reading continues so that all
+    /// offending lines are reported, with their line numbers, and nothing is imported.
+    ///
+    /// Returns the number of rows imported. As with [Db::import_relations], triggers are not run.
+    pub fn import_ndjson(&'s self, relation: &str, reader: impl BufRead) -> Result<usize> {
         #[derive(Debug, Diagnostic, Error)]
-        #[error("cannot import data for relation '{0}': {1}")]
-        #[diagnostic(code(import::bad_data))]
-        struct BadDataForRelation(String, JsonValue);
+        #[error("{} lines of NDJSON data for relation '{1}' could not be imported", .0.len())]
+        #[diagnostic(code(import::bad_ndjson))]
+        struct BadNdjsonLines(Vec<(usize, String)>, String, #[help] String);
 
-        let rel_names = data.keys().map(SmartString::from).collect_vec();
-        let locks = self.obtain_relation_locks(rel_names.iter());
+        let rel_name = SmartString::from(relation.strip_prefix('-').unwrap_or(relation));
+        let locks = self.obtain_relation_locks(iter::once(&rel_name));
         let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
         let cur_vld = current_validity();
-
         let mut tx = self.transact_write()?;
+        let (handle, is_delete) = import_target(&tx, relation)?;
+        let mut changes = self
+            .current_subscription_targets()
+            .contains(&handle.name)
+            .then(Vec::new);
+        let key_cols = &handle.metadata.keys;
+        let val_cols = if is_delete {
+            &[][..]
+        } else {
+            &handle.metadata.non_keys[..]
+        };
 
-        for (relation_op, in_data) in data {
-            let is_delete;
-            let relation: &str = match relation_op.strip_prefix('-') {
-                None => {
-                    is_delete = false;
-                    &relation_op
-                }
-                Some(s) => {
-                    is_delete = true;
-                    s
-                }
-            };
-            if relation.contains(':') {
-                bail!(ImportIntoIndex(relation.to_string()))
+        let mut bad_lines = vec![];
+        let mut batch = Vec::with_capacity(NDJSON_IMPORT_BATCH_ROWS);
+        let mut n_imported = 0;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.into_diagnostic()?;
+            if line.trim().is_empty() {
+                continue;
             }
-            let handle = tx.get_relation(relation, false)?;
-            let has_indices = !handle.indices.is_empty();
-
-            if handle.access_level < AccessLevel::Protected {
-                bail!(InsufficientAccessLevel(
-                    handle.name.to_string(),
-                    "data import".to_string(),
-                    handle.access_level
-                ));
+            let row = serde_json::from_str::<JsonValue>(&line)
+                .into_diagnostic()
+                .and_then(|obj| {
+                    let obj = obj
+                        .as_object()
+                        .ok_or_else(|| miette!("expected a JSON object"))?;
+                    let keys = coerce_ndjson_row(obj, key_cols, cur_vld)?;
+                    let vals = coerce_ndjson_row(obj, val_cols, cur_vld)?;
+                    Ok((keys, vals))
+                });
+            match row {
+                Err(err) => bad_lines.push((i + 1, err.to_string())),
+                Ok(row) if bad_lines.is_empty() => batch.push(row),
+                Ok(_) => {}
             }
-
-            let header2idx: BTreeMap<_, _> = in_data
-                .headers
+            if batch.len() == NDJSON_IMPORT_BATCH_ROWS {
+                n_imported += batch.len();
+                for (keys, vals) in batch.drain(..) {
+                    write_import_row(
+                        &mut tx,
+                        &handle,
+                        keys,
+                        (!is_delete).then_some(vals),
+                        changes.as_mut(),
+                    )?;
+                }
+            }
+        }
+        if !bad_lines.is_empty() {
+            let help = bad_lines
                 .iter()
-                .enumerate()
-                .map(|(i, k)| -> Result<(&str, usize)> { Ok((k as &str, i)) })
-                .try_collect()?;
+                .map(|(line, err)| format!("line {line}: {err}"))
+                .join("\n");
+            bail!(BadNdjsonLines(bad_lines, handle.name.to_string(), help))
+        }
+        n_imported += batch.len();
+        for (keys, vals) in batch {
+            write_import_row(
+                &mut tx,
+                &handle,
+                keys,
+                (!is_delete).then_some(vals),
+                changes.as_mut(),
+            )?;
+        }
+        tx.commit_tx()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(events) = changes {
+            self.send_change_events([(handle.name.clone(), events)].into());
+        }
+        Ok(n_imported)
+    }
+    /// Check data for [Db::import_relations] without importing it.
+    ///
+    /// All the checks of the import are performed, and all the problems found are reported,
+    /// instead of only the first one. The returned map contains, for each entry of `data`
+    /// with problems, the offending rows. Problems with the relation itself or with the headers
+    /// are reported without a row index, in which case the rows are not checked.
+    /// An empty map means that the import would succeed, unless the relations are changed
+    /// in the meantime.
+    pub fn validate_import(
+        &'s self,
+        data: &BTreeMap<String, NamedRows>,
+    ) -> Result<BTreeMap<String, Vec<InvalidImportRow>>> {
+        let cur_vld = current_validity();
+        let mut tx = self.transact()?;
+        let mut ret = BTreeMap::new();
+        for (relation_op, in_data) in data {
+            let mut invalid = vec![];
+            if let Err(err) = validate_import_rows(&tx, relation_op, in_data, cur_vld, &mut invalid)
+            {
+                invalid.push(InvalidImportRow {
+                    row: None,
+                    error: err.to_string(),
+                });
+            }
+            if !invalid.is_empty() {
+                ret.insert(relation_op.clone(), invalid);
+            }
+        }
+        tx.commit_tx()?;
+        Ok(ret)
+    }
+    /// Get the row of a stored relation with the given key, bypassing the query engine.
+    /// The row is returned whole, its key columns followed by its value columns.
+    ///
+    /// The key must have a value for each key column, which is coerced to the type of the
+    /// column. As with exports, the relation must have the access level read_only or above.
+    pub fn relation_get(&'s self, name: &str, key: Tuple) -> Result<Option<Tuple>> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(name, false)?;
+        handle.ensure_exportable()?;
+        let key = coerce_raw_row(&key, &handle.metadata.keys, current_validity(), name)?;
+        let k_store = handle.encode_key_for_store(&key, Default::default())?;
+        Ok(tx.store_tx.get(&k_store, false)?.map(|v_store| {
+            let mut row = key;
+            extend_tuple_from_v(&mut row, &v_store);
+            row
+        }))
+    }
+    /// Put a row, its key columns followed by its value columns, into a stored relation
+    /// in its own transaction, bypassing the query engine. An existing row with the same key
+    /// is replaced.
+    ///
+    /// As with [Db::import_relations], the values are coerced to the types of the columns,
+    /// the indices of the relation are updated, and triggers are not run.
+    pub fn relation_put(&'s self, name: &str, row: Tuple) -> Result<()> {
+        self.write_raw_row(name, row, true)
+    }
+    /// Delete the row with the given key from a stored relation in its own transaction,
+    /// bypassing the query engine. Deleting a row that does not exist is not an error.
+    ///
+    /// As with [Db::import_relations], the indices of the relation are updated,
+    /// and triggers are not run.
+    pub fn relation_del(&'s self, name: &str, key: Tuple) -> Result<()> {
+        self.write_raw_row(name, key, false)
+    }
+    fn write_raw_row(&'s self, name: &str, row: Tuple, is_put: bool) -> Result<()> {
+        if name.contains(':') {
+            bail!(ImportIntoIndex(name.to_string()))
+        }
+        let rel_name = SmartString::from(name);
+        let locks = self.obtain_relation_locks(iter::once(&rel_name));
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+        let subscribed = self.current_subscription_targets().contains(&rel_name);
 
-            let key_indices: Vec<_> = handle
+        let mut tx = self.transact_write()?;
+        let handle = tx.get_relation(name, false)?;
+        handle.ensure_importable()?;
+        let cur_vld = current_validity();
+        let (keys, vals) = if is_put {
+            let cols = handle
                 .metadata
                 .keys
                 .iter()
-                .map(|col| -> Result<(usize, &ColumnDef)> {
-                    let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
-                        miette!(
-                            "required header {} not found for relation {}",
-                            col.name,
-                            relation
-                        )
-                    })?;
-                    Ok((*idx, col))
-                })
-                .try_collect()?;
-
-            let val_indices: Vec<_> = if is_delete {
-                vec![]
-            } else {
-                handle
-                    .metadata
-                    .non_keys
-                    .iter()
-                    .map(|col| -> Result<(usize, &ColumnDef)> {
-                        let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
-                            miette!(
-                                "required header {} not found for relation {}",
-                                col.name,
-                                relation
-                            )
-                        })?;
-                        Ok((*idx, col))
-                    })
-                    .try_collect()?
-            };
-
-            for row in in_data.rows {
-                let keys: Vec<_> = key_indices
-                    .iter()
-                    .map(|(i, col)| -> Result<DataValue> {
-                        let v = row
-                            .get(*i)
-                            .ok_or_else(|| miette!("row too short: {:?}", row))?;
-                        col.typing.coerce(v.clone(), cur_vld)
-                    })
-                    .try_collect()?;
-                let k_store = handle.encode_key_for_store(&keys, Default::default())?;
-                if has_indices {
-                    if let Some(existing) = tx.store_tx.get(&k_store, false)? {
-                        let mut old = keys.clone();
-                        extend_tuple_from_v(&mut old, &existing);
-                        if is_delete || old != row {
-                            for (idx_rel, extractor) in handle.indices.values() {
-                                let idx_tup =
-                                    extractor.iter().map(|i| old[*i].clone()).collect_vec();
-                                let encoded =
-                                    idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
-                                tx.store_tx.del(&encoded)?;
-                            }
-                        }
-                    }
-                }
-                if is_delete {
-                    tx.store_tx.del(&k_store)?;
-                } else {
-                    let vals: Vec<_> = val_indices
-                        .iter()
-                        .map(|(i, col)| -> Result<DataValue> {
-                            let v = row
-                                .get(*i)
-                                .ok_or_else(|| miette!("row too short: {:?}", row))?;
-                            col.typing.coerce(v.clone(), cur_vld)
-                        })
-                        .try_collect()?;
-                    let v_store = handle.encode_val_only_for_store(&vals, Default::default())?;
-                    tx.store_tx.put(&k_store, &v_store)?;
-                    if has_indices {
-                        let mut kv = keys;
-                        kv.extend(vals);
-                        for (idx_rel, extractor) in handle.indices.values() {
-                            let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
-                            let encoded =
-                                idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
-                            tx.store_tx.put(&encoded, &[])?;
-                        }
-                    }
-                }
-            }
-        }
+                .chain(handle.metadata.non_keys.iter())
+                .cloned()
+                .collect_vec();
+            let mut keys = coerce_raw_row(&row, &cols, cur_vld, name)?;
+            let vals = keys.split_off(handle.metadata.keys.len());
+            (keys, Some(vals))
+        } else {
+            let keys = coerce_raw_row(&row, &handle.metadata.keys, cur_vld, name)?;
+            (keys, None)
+        };
+        let mut changes = vec![];
+        write_import_row(
+            &mut tx,
+            &handle,
+            keys,
+            vals,
+            subscribed.then_some(&mut changes),
+        )?;
         tx.commit_tx()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if subscribed {
+            self.send_change_events(BTreeMap::from([(rel_name, changes)]));
+        }
         Ok(())
     }
     /// Backup the running database into an Sqlite file
-    #[allow(unused_variables)]
     pub fn backup_db(&'s self, out_file: impl AsRef<Path>) -> Result<()> {
+        self.backup_db_with_progress(out_file, |_| {})
+    }
+    /// Backup the running database into an Sqlite file, reporting progress.
+    /// `progress` is called with the number of key-value pairs copied so far
+    /// every [BACKUP_PROGRESS_INTERVAL] pairs, and once more with the total when done.
+    pub fn backup_db_with_progress(
+        &'s self,
+        out_file: impl AsRef<Path>,
+        progress: impl FnMut(u64),
+    ) -> Result<()> {
+        self.backup_db_keys_transformed(out_file, None, progress)
+    }
+    /// Backup the running database into an Sqlite file like [Db::backup_db_with_progress],
+    /// passing each key through `key_transform` on its way out, e.g. to remap prefixes or add
+    /// a namespace when the data is mirrored into an external key-value store.
+    ///
+    /// Values are copied unchanged. Unless `key_transform` keeps the keys as they are,
+    /// the file is no longer a backup that [Db::restore_backup] can restore.
+    ///
+    /// Keys are written in a single ordered pass, so `key_transform` must preserve their
+    /// order and map distinct keys to distinct keys: if `a < b` then
+    /// `key_transform(a) < key_transform(b)`, as adding a fixed prefix does.
+    /// The backup fails with an error as soon as a transformed key is not greater
+    /// than the one before it.
+    pub fn backup_db_with_key_transform(
+        &'s self,
+        out_file: impl AsRef<Path>,
+        key_transform: impl Fn(&[u8]) -> Vec<u8>,
+        progress: impl FnMut(u64),
+    ) -> Result<()> {
+        self.backup_db_keys_transformed(out_file, Some(&key_transform), progress)
+    }
+    #[allow(unused_variables)]
+    fn backup_db_keys_transformed(
+        &'s self,
+        out_file: impl AsRef<Path>,
+        key_transform: Option<&dyn Fn(&[u8]) -> Vec<u8>>,
+        progress: impl FnMut(u64),
+    ) -> Result<()> {
         #[cfg(feature = "storage-sqlite")]
         {
+            let mut progress = progress;
             let sqlite_db = crate::new_cozo_sqlite(out_file)?;
             if sqlite_db.relation_store_id.load(Ordering::SeqCst) != 0 {
                 bail!("Cannot create backup: data exists in the target database.");
             }
             let mut tx = self.transact()?;
-            let iter = tx.store_tx.range_scan(&[], &[0xFF]);
-            sqlite_db.db.batch_put(iter)?;
+            let mut copied = 0;
+            let iter = tx
+                .store_tx
+                .range_scan_with_hint(&[], &[0xFF], self.scan_hint())
+                .inspect(|_| {
+                    copied += 1;
+                    if copied % BACKUP_PROGRESS_INTERVAL == 0 {
+                        progress(copied)
+                    }
+                })
+                .map({
+                    let mut last_key: Option<Vec<u8>> = None;
+                    move |kv| {
+                        let (k, v) = kv?;
+                        let f = match key_transform {
+                            None => return Ok((k, v)),
+                            Some(f) => f,
+                        };
+                        let k = f(&k);
+                        if matches!(&last_key, Some(last) if *last >= k) {
+                            bail!(
+                                "Cannot create backup: the key transform does not keep \
+                                keys in strictly ascending order"
+                            )
+                        }
+                        last_key = Some(k.clone());
+                        Ok((k, v))
+                    }
+                });
+            sqlite_db.db.batch_put(Box::new(iter))?;
+            progress(copied);
             tx.commit_tx()?;
             Ok(())
         }
@@ -614,10 +2199,19 @@ impl<'s, S: Storage<'s>> Db<S> {
         bail!("backup requires the 'storage-sqlite' feature to be enabled")
     }
     /// Restore from an Sqlite backup
-    #[allow(unused_variables)]
     pub fn restore_backup(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
+        self.restore_backup_with_progress(in_file, |_| {})
+    }
+    /// Restore from an Sqlite backup, reporting progress as [Db::backup_db_with_progress] does.
+    #[allow(unused_variables)]
+    pub fn restore_backup_with_progress(
+        &'s self,
+        in_file: impl AsRef<Path>,
+        progress: impl FnMut(u64),
+    ) -> Result<()> {
         #[cfg(feature = "storage-sqlite")]
         {
+            let mut progress = progress;
             let sqlite_db = crate::new_cozo_sqlite(in_file)?;
             let mut s_tx = sqlite_db.transact()?;
             {
@@ -632,8 +2226,15 @@ impl<'s, S: Storage<'s>> Db<S> {
                 }
                 tx.commit_tx()?;
             }
-            let iter = s_tx.store_tx.total_scan();
-            self.db.batch_put(iter)?;
+            let mut copied = 0;
+            let iter = s_tx.store_tx.total_scan().inspect(|_| {
+                copied += 1;
+                if copied % BACKUP_PROGRESS_INTERVAL == 0 {
+                    progress(copied)
+                }
+            });
+            self.db.batch_put(Box::new(iter))?;
+            progress(copied);
             s_tx.commit_tx()?;
             Ok(())
         }
@@ -643,22 +2244,40 @@ impl<'s, S: Storage<'s>> Db<S> {
     /// Import data from relations in a backup file.
     /// The target stored relations must already exist in the database, and it must not
     /// have any associated indices. If you want to import into relations with indices,
-    /// use [Db::import_relations].
+    /// use [Db::import_relations]. As rows are copied as they are stored, each target must
+    /// have the same columns as in the backup, which is checked before anything is copied.
     ///
     /// Note that triggers and callbacks are _not_ run for the relations, if any exists.
     /// If you need to activate triggers or callbacks, use queries with parameters.
-    #[allow(unused_variables)]
     pub fn import_from_backup(
         &'s self,
         in_file: impl AsRef<Path>,
         relations: &[String],
+    ) -> Result<()> {
+        let relations = relations
+            .iter()
+            .map(|name| (name.clone(), name.clone()))
+            .collect_vec();
+        self.import_from_backup_renamed(in_file, &relations)
+    }
+    /// Import data from relations in a backup file like [Db::import_from_backup], but into
+    /// relations with other names. Each pair of `relations` is the name of a relation in the
+    /// backup, followed by the name of the existing relation in the database to import into.
+    #[allow(unused_variables)]
+    pub fn import_from_backup_renamed(
+        &'s self,
+        in_file: impl AsRef<Path>,
+        relations: &[(String, String)],
     ) -> Result<()> {
         #[cfg(not(feature = "storage-sqlite"))]
         bail!("backup requires the 'storage-sqlite' feature to be enabled");
 
         #[cfg(feature = "storage-sqlite")]
         {
-            let rel_names = relations.iter().map(SmartString::from).collect_vec();
+            let rel_names = relations
+                .iter()
+                .map(|(_, dst)| SmartString::from(dst))
+                .collect_vec();
             let locks = self.obtain_relation_locks(rel_names.iter());
             let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
 
@@ -666,12 +2285,16 @@ impl<'s, S: Storage<'s>> Db<S> {
             let mut src_tx = source_db.transact()?;
             let mut dst_tx = self.transact_write()?;
 
-            for relation in relations {
-                if relation.contains(':') {
-                    bail!(ImportIntoIndex(relation.to_string()))
+            // check every relation before copying anything
+            let mut handles = vec![];
+            for (src, dst) in relations {
+                for relation in [src, dst] {
+                    if relation.contains(':') {
+                        bail!(ImportIntoIndex(relation.to_string()))
+                    }
                 }
-                let src_handle = src_tx.get_relation(relation, false)?;
-                let dst_handle = dst_tx.get_relation(relation, false)?;
+                let src_handle = src_tx.get_relation(src, false)?;
+                let dst_handle = dst_tx.get_relation(dst, false)?;
 
                 if !dst_handle.indices.is_empty() {
                     #[derive(Debug, Error, Diagnostic)]
@@ -683,19 +2306,19 @@ impl<'s, S: Storage<'s>> Db<S> {
                     bail!(RestoreIntoRelWithIndices(dst_handle.name.to_string()))
                 }
 
-                if dst_handle.access_level < AccessLevel::Protected {
-                    bail!(InsufficientAccessLevel(
-                        dst_handle.name.to_string(),
-                        "data import".to_string(),
-                        dst_handle.access_level
-                    ));
-                }
+                dst_handle.ensure_importable()?;
+                dst_handle.ensure_same_layout(&src_handle)?;
+                handles.push((src_handle, dst_handle));
+            }
 
+            for (src_handle, dst_handle) in handles {
                 let src_lower = Tuple::default().encode_as_key(src_handle.id);
                 let src_upper = Tuple::default().encode_as_key(src_handle.id.next());
 
-                let data_it = src_tx.store_tx.range_scan(&src_lower, &src_upper).map(
-                    |src_pair| -> Result<(Vec<u8>, Vec<u8>)> {
+                let data_it = src_tx
+                    .store_tx
+                    .range_scan_with_hint(&src_lower, &src_upper, self.scan_hint())
+                    .map(|src_pair| -> Result<(Vec<u8>, Vec<u8>)> {
                         let (mut src_k, mut src_v) = src_pair?;
                         dst_handle.amend_key_prefix(&mut src_k);
                         dst_handle.amend_key_prefix(&mut src_v);
@@ -712,6 +2335,46 @@ impl<'s, S: Storage<'s>> Db<S> {
             dst_tx.commit_tx()
         }
     }
+    /// Restore all stored relations of a backup file into this database, which need not
+    /// be empty, with `prefix` prepended to the relation names.
+    ///
+    /// The relations are given new ids, and their indices are restored with them.
+    /// Restoring fails without changes if any of the prefixed names is already taken.
+    /// Triggers are not restored, since their scripts refer to the original relation names.
+    #[allow(unused_variables)]
+    pub fn restore_backup_prefixed(
+        &'s self,
+        in_file: impl AsRef<Path>,
+        prefix: &str,
+    ) -> Result<()> {
+        #[cfg(not(feature = "storage-sqlite"))]
+        bail!("backup requires the 'storage-sqlite' feature to be enabled");
+
+        #[cfg(feature = "storage-sqlite")]
+        {
+            let mut chars = prefix.chars();
+            if !chars.next().is_some_and(char::is_alphabetic)
+                || !chars.all(|c| c.is_alphanumeric() || c == '_')
+            {
+                bail!(BadRestorePrefix(prefix.to_string()))
+            }
+
+            let source_db = crate::new_cozo_sqlite(in_file)?;
+            let mut src_tx = source_db.transact()?;
+            let new_names = src_tx
+                .live_relation_ids()?
+                .into_iter()
+                .map(|(_, name)| SmartString::from(format!("{prefix}{name}")))
+                .collect_vec();
+            let locks = self.obtain_relation_locks(new_names.iter());
+            let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+
+            let mut dst_tx = self.transact_write()?;
+            dst_tx.copy_relations_prefixed(&src_tx, prefix)?;
+            src_tx.commit_tx()?;
+            dst_tx.commit_tx()
+        }
+    }
     /// Register a custom fixed rule implementation.
     pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<()>
     where
@@ -739,6 +2402,42 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(self.fixed_rules.write().unwrap().remove(name).is_some())
     }
 
+    /// Describe what this build of the library supports, for clients that need to
+    /// negotiate capabilities at runtime: the storage engines and optional features
+    /// compiled in, and the fixed rules available, including custom registered ones.
+    pub fn capabilities(&self) -> JsonValue {
+        let (builtin_rules, custom_rules): (Vec<_>, Vec<_>) = self
+            .fixed_rules
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .partition(|name| DEFAULT_FIXED_RULES.contains_key(name));
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "encoding_version": LATEST_ENCODING_VERSION,
+            "storage": {
+                "mem": true,
+                "sqlite": cfg!(feature = "storage-sqlite"),
+                "rocksdb": cfg!(feature = "storage-rocksdb"),
+                "sled": cfg!(feature = "storage-sled"),
+                "tikv": cfg!(feature = "storage-tikv"),
+            },
+            "features": {
+                "backup": cfg!(feature = "storage-sqlite"),
+                "graph_algo": cfg!(feature = "graph-algo"),
+                "requests": cfg!(feature = "requests"),
+                "callbacks": cfg!(not(target_arch = "wasm32")),
+                "threads": cfg!(not(target_arch = "wasm32")),
+            },
+            "export_formats": ["json", "jsonl", "csv"],
+            "fixed_rules": {
+                "builtin": builtin_rules,
+                "custom": custom_rules,
+            },
+        })
+    }
+
     /// Register callback channel to receive changes when the requested relation are successfully committed.
     /// The returned ID can be used to unregister the callback channel.
     #[cfg(not(target_arch = "wasm32"))]
@@ -754,7 +2453,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         };
         let cb = CallbackDeclaration {
             dependent: SmartString::from(relation),
-            sender,
+            sender: CallbackSender::Rows(sender),
         };
 
         let mut guard = self.event_callbacks.write().unwrap();
@@ -769,6 +2468,31 @@ impl<'s, S: Storage<'s>> Db<S> {
         (new_id, receiver)
     }
 
+    /// Subscribe to the changes of the stored relation `relation`, one [ChangeEvent] per row.
+    ///
+    /// Unlike the callbacks of [Db::register_callback], the changes made by imports are
+    /// also received. As with them, events are only sent once the transaction making the
+    /// changes has been committed. Dropping the receiver ends the subscription.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn subscribe(&self, relation: &str) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = unbounded();
+        let cb = CallbackDeclaration {
+            dependent: SmartString::from(relation),
+            sender: CallbackSender::Events(sender),
+        };
+
+        let mut guard = self.event_callbacks.write().unwrap();
+        let new_id = self.callback_count.fetch_add(1, Ordering::SeqCst);
+        guard
+            .1
+            .entry(SmartString::from(relation))
+            .or_default()
+            .insert(new_id);
+
+        guard.0.insert(new_id, cb);
+        receiver
+    }
+
     /// Unregister callbacks/channels to run when changes to relations are committed.
     #[cfg(not(target_arch = "wasm32"))]
     pub fn unregister_callback(&self, id: u32) -> bool {
@@ -811,14 +2535,134 @@ impl<'s, S: Storage<'s>> Db<S> {
         collected
     }
 
-    fn compact_relation(&'s self) -> Result<()> {
+    fn compact_relation(&'s self, options: CompactOptions) -> Result<()> {
         let l = Tuple::default().encode_as_key(RelationId(0));
         let u = vec![DataValue::Bot].encode_as_key(RelationId(u64::MAX));
-        self.db.range_compact(&l, &u)?;
+        self.db.range_compact(&l, &u, options)?;
         Ok(())
     }
 
-    fn load_last_ids(&'s self) -> Result<()> {
+    /// The schema of the stored relation or index `name`, as a typed counterpart of
+    /// the rows returned by `::columns`
+    pub fn describe_relation(&'s self, name: &str) -> Result<RelationSchema> {
+        let mut tx = self.transact()?;
+        let handle = tx.get_relation(name, false)?;
+        tx.commit_tx()?;
+        Ok(RelationSchema {
+            keys: handle
+                .metadata
+                .keys
+                .iter()
+                .map(ColumnSchema::from)
+                .collect(),
+            non_keys: handle
+                .metadata
+                .non_keys
+                .iter()
+                .filter(|col| col.name != TTL_COLUMN)
+                .map(ColumnSchema::from)
+                .collect(),
+            access_level: handle.access_level,
+        })
+    }
+
+    /// Compact only the key range of the stored relation or index `name`,
+    /// which is much cheaper than compacting the whole database with `::compact`.
+    /// Useful after many rows of a single relation were removed, or after the relation
+    /// itself was removed: `name` can also be a relation removed since the database was opened.
+    /// Can be a no-op if the storage engine does not have the concept of compaction.
+    pub fn compact_relation_named(&'s self, name: &str, options: CompactOptions) -> Result<()> {
+        let tx = self.transact()?;
+        let id = self.compaction_target(&tx, name)?;
+        drop(tx);
+        self.compact_relation_range(id, options)
+    }
+
+    /// Compact only the key range of the relation with the id `id`, as listed by
+    /// `::relation_ids`. Unlike [Self::compact_relation_named], this also works for
+    /// relations removed before the database was opened.
+    pub fn compact_relation_id(&'s self, id: u64, options: CompactOptions) -> Result<()> {
+        ensure!(id < 2u64.pow(6 * 8), "Invalid relation id {id}");
+        self.compact_relation_range(RelationId::new(id), options)
+    }
+
+    /// The id of the stored relation or index `name`, falling back to the relation
+    /// last removed under that name
+    fn compaction_target(&'s self, tx: &SessionTx<'_>, name: &str) -> Result<RelationId> {
+        match tx.get_relation(name, false) {
+            Ok(handle) => Ok(handle.id),
+            Err(err) => match self.removed_relations.lock().unwrap().get(name) {
+                Some(id) => Ok(*id),
+                None => Err(err),
+            },
+        }
+    }
+
+    /// `::vacuum`: orphan cleanup followed by a storage-level vacuum, without compacting
+    /// everything. Deletes the rows of removed relations that were left behind, compacts only
+    /// their ranges, then lets the storage engine give back free space where that is cheap,
+    /// which for SQLite means a `VACUUM` once enough of the file is free.
+    ///
+    /// Ranges of live relations are not compacted, even after many of their rows were
+    /// removed: use [Self::compact_relation_named] for those.
+    fn vacuum(&'s self) -> Result<NamedRows> {
+        let mut tx = self.transact_write()?;
+        let (ranges, n_rows, n_bytes) = tx.delete_orphaned_rows()?;
+        tx.commit_tx()?;
+        drop(tx);
+        // nothing but deletion markers is left in these ranges
+        let options = CompactOptions {
+            bottommost: true,
+            exclusive: false,
+        };
+        for (lower, upper) in &ranges {
+            self.db.range_compact(lower, upper, options)?;
+        }
+        let reclaimed = self.db.reclaim_space()?;
+        Ok(NamedRows::new(
+            vec![
+                "orphaned_rows".to_string(),
+                "orphaned_bytes".to_string(),
+                "reclaimed_bytes".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(n_rows as i64),
+                DataValue::from(n_bytes as i64),
+                DataValue::from(reclaimed as i64),
+            ]],
+        ))
+    }
+
+    fn reclaim_relation_ids(&'s self) -> Result<NamedRows> {
+        let (old, new) = {
+            let mut tx = self.transact_write()?;
+            let ids = tx.reclaim_relation_ids()?;
+            tx.commit_tx()?;
+            ids
+        };
+        // relations created since the commit keep the ids they were given
+        let _ =
+            self.relation_store_id
+                .compare_exchange(old, new, Ordering::SeqCst, Ordering::SeqCst);
+        Ok(NamedRows::new(
+            vec![
+                "old_high_water_mark".to_string(),
+                "high_water_mark".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(old as i64),
+                DataValue::from(new as i64),
+            ]],
+        ))
+    }
+
+    fn compact_relation_range(&'s self, id: RelationId, options: CompactOptions) -> Result<()> {
+        let l = Tuple::default().encode_as_key(id);
+        let u = Tuple::default().encode_as_key(id.next());
+        self.db.range_compact(&l, &u, options)
+    }
+
+    pub(crate) fn load_last_ids(&'s self) -> Result<()> {
         let mut tx = self.transact_write()?;
         self.relation_store_id
             .store(tx.init_storage()?.0, Ordering::Release);
@@ -832,16 +2676,59 @@ impl<'s, S: Storage<'s>> Db<S> {
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            commit_latencies: None,
+            max_join_matches: None,
+            mem_limit: None,
+            aggr_progress: None,
+            eval_progress: None,
+            cancel: None,
+            script: None,
+            on_query_start: None,
+            ra_stats: None,
+            stratum_times: None,
+            report: None,
+            committed: false,
+            is_write: false,
         };
         Ok(ret)
     }
     pub(crate) fn transact_write(&'s self) -> Result<SessionTx<'_>> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Could not start a write transaction within {0} seconds")]
+        #[diagnostic(code(db::write_timeout))]
+        #[diagnostic(help("Another write transaction is holding the database"))]
+        struct WriteLockTimeout(f64);
+
+        let timeout = *self.write_lock_timeout.read().unwrap();
+        let store_tx = if timeout > 0. {
+            self.db
+                .transact_write_within(Duration::from_secs_f64(timeout))?
+                .ok_or(WriteLockTimeout(timeout))?
+        } else {
+            self.db.transact(true)?
+        };
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(true)?),
+            store_tx: Box::new(ChangeTrackingTx::new(
+                Box::new(store_tx),
+                self.change_marker.clone(),
+            )),
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
             tokenizers: self.tokenizers.clone(),
+            commit_latencies: Some(self.commit_latencies.clone()),
+            max_join_matches: None,
+            mem_limit: None,
+            aggr_progress: None,
+            eval_progress: None,
+            cancel: None,
+            script: None,
+            on_query_start: None,
+            ra_stats: None,
+            stratum_times: None,
+            report: None,
+            committed: false,
+            is_write: true,
         };
         Ok(ret)
     }
@@ -862,7 +2749,17 @@ impl<'s, S: Storage<'s>> Db<S> {
         cleanups.extend(q_cleanups);
         #[cfg(not(target_arch = "wasm32"))]
         if let Some(secs) = sleep_opt {
-            thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
+            // the transaction is still open here: sleeping in a write transaction
+            // would stall every other writer of the database for as long
+            if tx.is_write {
+                let warning = format!("`:sleep {secs}` is ignored within a write transaction");
+                log::warn!("{warning}");
+                if let Some(report) = &tx.report {
+                    report.lock().unwrap().warnings.push(warning);
+                }
+            } else {
+                thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
+            }
         }
         Ok(q_res)
     }
@@ -873,25 +2770,48 @@ impl<'s, S: Storage<'s>> Db<S> {
         param_pool: &BTreeMap<String, DataValue>,
         cur_vld: ValidityTs,
         read_only: bool,
+        mut hooks: ScriptHooks,
     ) -> Result<NamedRows> {
-        match parse_script(
+        let script = parse_script(
             payload,
             param_pool,
             &self.fixed_rules.read().unwrap(),
             cur_vld,
-        )? {
-            CozoScript::Single(p) => self.execute_single(cur_vld, p, read_only),
-            CozoScript::Imperative(ps) => self.execute_imperative(cur_vld, &ps, read_only),
-            CozoScript::Sys(op) => self.run_sys_op(op, read_only),
+        )?;
+        hooks.script = Some(RunningScript::new(payload, param_pool));
+        self.execute_script(script, cur_vld, read_only, hooks)
+    }
+
+    fn execute_script(
+        &'s self,
+        script: CozoScript,
+        cur_vld: ValidityTs,
+        read_only: bool,
+        hooks: ScriptHooks,
+    ) -> Result<NamedRows> {
+        match script {
+            CozoScript::Single(p) => {
+                let _admitted = self.admit_script()?;
+                self.execute_single(cur_vld, *p, read_only, hooks)
+            }
+            CozoScript::Imperative(ps) => {
+                let _admitted = self.admit_script()?;
+                self.execute_imperative(cur_vld, &ps, read_only, hooks)
+            }
+            CozoScript::Sys(op) => self.run_sys_op(op, cur_vld, read_only, hooks),
         }
     }
 
     fn execute_single(
         &'s self,
         cur_vld: ValidityTs,
-        p: InputProgram,
+        mut p: InputProgram,
         read_only: bool,
+        hooks: ScriptHooks,
     ) -> Result<NamedRows, Report> {
+        // a lone query sleeps once its transaction is over, holding nothing up
+        #[allow(unused_variables)]
+        let sleep_opt = p.out_opts.sleep.take();
         let mut callback_collector = BTreeMap::new();
         let write_lock_names = p.needs_write_lock();
         let is_write = write_lock_names.is_some();
@@ -899,7 +2819,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             bail!("write lock required for read-only query");
         }
         let write_lock = self.obtain_relation_locks(write_lock_names.iter());
-        let _write_lock_guards = if is_write {
+        let write_lock_guards = if is_write {
             Some(write_lock[0].read().unwrap())
         } else {
             None
@@ -917,6 +2837,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             } else {
                 self.transact()?
             };
+            hooks.install(&mut tx);
 
             res = self.execute_single_program(
                 p,
@@ -933,107 +2854,120 @@ impl<'s, S: Storage<'s>> Db<S> {
 
             tx.commit_tx()?;
         }
+        drop(write_lock_guards);
         #[cfg(not(target_arch = "wasm32"))]
         if !callback_collector.is_empty() {
             self.send_callbacks(callback_collector)
         }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(secs) = sleep_opt {
+            thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
+        }
 
         Ok(res)
     }
-    fn explain_compiled(&self, strata: &[CompiledProgram]) -> Result<NamedRows> {
-        let mut ret: Vec<JsonValue> = vec![];
-        const STRATUM: &str = "stratum";
-        const ATOM_IDX: &str = "atom_idx";
-        const OP: &str = "op";
-        const RULE_IDX: &str = "rule_idx";
-        const RULE_NAME: &str = "rule";
-        const REF_NAME: &str = "ref";
-        const OUT_BINDINGS: &str = "out_relation";
-        const JOINS_ON: &str = "joins_on";
-        const FILTERS: &str = "filters/expr";
-
-        let headers = vec![
-            STRATUM.to_string(),
-            RULE_IDX.to_string(),
-            RULE_NAME.to_string(),
-            ATOM_IDX.to_string(),
-            OP.to_string(),
-            REF_NAME.to_string(),
-            JOINS_ON.to_string(),
-            FILTERS.to_string(),
-            OUT_BINDINGS.to_string(),
-        ];
+    /// With `stats`, the plan is augmented with the actual rows, calls and time of each node.
+    /// Without `tx`, the number of rows is not estimated.
+    fn explain_compiled(
+        &self,
+        tx: Option<&SessionTx<'_>>,
+        strata: &[CompiledProgram],
+        stats: Option<&BTreeMap<usize, RaNodeStats>>,
+    ) -> Result<ExplainPlan> {
+        let filter_strs = |filters: &[Expr]| filters.iter().map(|f| f.to_string()).collect_vec();
+        let mut steps = vec![];
+        let mut estimator = tx.map(|tx| RowEstimator::new(tx, &self.row_counts));
 
         for (stratum, p) in strata.iter().enumerate() {
-            let mut clause_idx = -1;
+            if let Some(estimator) = &mut estimator {
+                estimator.estimate_stratum(p)?;
+            }
+            let mut clause_idx = 0;
             for (rule_name, v) in p {
                 match v {
                     CompiledRuleSet::Rules(rules) => {
                         for CompiledRule { aggr, relation, .. } in rules.iter() {
-                            clause_idx += 1;
-                            let mut ret_for_relation = vec![];
-                            let mut rel_stack = vec![relation];
+                            let mut steps_for_relation = vec![];
+                            let mut rel_stack = vec![(relation, relation.stats_key())];
                             let mut idx = 0;
-                            let mut atom_type = "out";
+                            let mut atom_type = ExplainOp::Out;
                             for (a, _) in aggr.iter().flatten() {
                                 if a.is_meet {
-                                    if atom_type == "out" {
-                                        atom_type = "meet_aggr_out";
+                                    if atom_type == ExplainOp::Out {
+                                        atom_type = ExplainOp::MeetAggrOut;
                                     }
                                 } else {
-                                    atom_type = "aggr_out";
+                                    atom_type = ExplainOp::AggrOut;
                                 }
                             }
+                            let step = |idx: usize, op: ExplainOp, rel: &RelAlgebra| ExplainStep {
+                                stratum,
+                                rule_idx: clause_idx,
+                                rule: rule_name.to_string(),
+                                atom_idx: idx,
+                                op,
+                                reference: None,
+                                joins_on: vec![],
+                                filters: vec![],
+                                out_relation: Some(
+                                    rel.bindings_after_eliminate()
+                                        .into_iter()
+                                        .map(|v| v.to_string())
+                                        .collect_vec(),
+                                ),
+                                est_rows: None,
+                                stats: None,
+                            };
 
-                            ret_for_relation.push(json!({
-                                STRATUM: stratum,
-                                ATOM_IDX: idx,
-                                OP: atom_type,
-                                RULE_IDX: clause_idx,
-                                RULE_NAME: rule_name.to_string(),
-                                OUT_BINDINGS: relation.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec()
-                            }));
+                            let clause_rows = match &mut estimator {
+                                Some(estimator) => estimator.estimate(relation)?,
+                                None => None,
+                            };
+                            let mut out_step = step(idx, atom_type, relation);
+                            out_step.est_rows = clause_rows.map(|rows| rows.ceil() as u64);
+                            steps_for_relation.push(out_step);
                             idx += 1;
 
-                            while let Some(rel) = rel_stack.pop() {
-                                let (atom_type, ref_name, joins_on, filters) = match rel {
+                            while let Some((rel, stats_key)) = rel_stack.pop() {
+                                let (op, reference, joins_on, filters) = match rel {
                                     r @ RelAlgebra::Fixed(..) => {
                                         if r.is_unit() {
                                             continue;
                                         }
-                                        ("fixed", json!(null), json!(null), json!(null))
+                                        (ExplainOp::Fixed, None, vec![], vec![])
                                     }
                                     RelAlgebra::TempStore(TempStoreRA {
                                         storage_key,
                                         filters,
                                         ..
                                     }) => (
-                                        "load_mem",
-                                        json!(storage_key.to_string()),
-                                        json!(null),
-                                        json!(filters.iter().map(|f| f.to_string()).collect_vec()),
+                                        ExplainOp::LoadMem,
+                                        Some(storage_key.to_string()),
+                                        vec![],
+                                        filter_strs(filters),
                                     ),
                                     RelAlgebra::Stored(StoredRA {
                                         storage, filters, ..
                                     }) => (
-                                        "load_stored",
-                                        json!(format!(":{}", storage.name)),
-                                        json!(null),
-                                        json!(filters.iter().map(|f| f.to_string()).collect_vec()),
+                                        ExplainOp::LoadStored,
+                                        Some(format!(":{}", storage.name)),
+                                        vec![],
+                                        filter_strs(filters),
                                     ),
                                     RelAlgebra::StoredWithValidity(StoredWithValidityRA {
                                         storage,
                                         filters,
                                         ..
                                     }) => (
-                                        "load_stored_with_validity",
-                                        json!(format!(":{}", storage.name)),
-                                        json!(null),
-                                        json!(filters.iter().map(|f| f.to_string()).collect_vec()),
+                                        ExplainOp::LoadStoredWithValidity,
+                                        Some(format!(":{}", storage.name)),
+                                        vec![],
+                                        filter_strs(filters),
                                     ),
                                     RelAlgebra::Join(inner) => {
                                         if inner.left.is_unit() {
-                                            rel_stack.push(&inner.right);
+                                            // the join only feeds the right side, so it stands in for it
+                                            rel_stack.push((&inner.right, stats_key));
                                             continue;
                                         }
                                         let t = inner.join_type();
@@ -1043,9 +2977,9 @@ impl<'s, S: Storage<'s>> Db<S> {
                                             joiner,
                                             ..
                                         } = inner.as_ref();
-                                        rel_stack.push(left);
-                                        rel_stack.push(right);
-                                        (t, json!(null), json!(joiner.as_map()), json!(null))
+                                        rel_stack.push((left, left.stats_key()));
+                                        rel_stack.push((right, right.stats_key()));
+                                        (ExplainOp::Join(t), None, joiner.pairs(), vec![])
                                     }
                                     RelAlgebra::NegJoin(inner) => {
                                         let t = inner.join_type();
@@ -1055,26 +2989,21 @@ impl<'s, S: Storage<'s>> Db<S> {
                                             joiner,
                                             ..
                                         } = inner.as_ref();
-                                        rel_stack.push(left);
-                                        rel_stack.push(right);
-                                        (t, json!(null), json!(joiner.as_map()), json!(null))
+                                        rel_stack.push((left, left.stats_key()));
+                                        rel_stack.push((right, right.stats_key()));
+                                        (ExplainOp::Join(t), None, joiner.pairs(), vec![])
                                     }
                                     RelAlgebra::Reorder(ReorderRA { relation, .. }) => {
-                                        rel_stack.push(relation);
-                                        ("reorder", json!(null), json!(null), json!(null))
+                                        rel_stack.push((relation, relation.stats_key()));
+                                        (ExplainOp::Reorder, None, vec![], vec![])
                                     }
                                     RelAlgebra::Filter(FilteredRA {
                                         parent,
                                         filters: pred,
                                         ..
                                     }) => {
-                                        rel_stack.push(parent);
-                                        (
-                                            "filter",
-                                            json!(null),
-                                            json!(null),
-                                            json!(pred.iter().map(|f| f.to_string()).collect_vec()),
-                                        )
+                                        rel_stack.push((parent, parent.stats_key()));
+                                        (ExplainOp::Filter, None, vec![], filter_strs(pred))
                                     }
                                     RelAlgebra::Unification(UnificationRA {
                                         parent,
@@ -1083,91 +3012,89 @@ impl<'s, S: Storage<'s>> Db<S> {
                                         is_multi,
                                         ..
                                     }) => {
-                                        rel_stack.push(parent);
+                                        rel_stack.push((parent, parent.stats_key()));
                                         (
-                                            if *is_multi { "multi-unify" } else { "unify" },
-                                            json!(binding.name),
-                                            json!(null),
-                                            json!(expr.to_string()),
+                                            if *is_multi {
+                                                ExplainOp::MultiUnify
+                                            } else {
+                                                ExplainOp::Unify
+                                            },
+                                            Some(binding.name.to_string()),
+                                            vec![],
+                                            vec![expr.to_string()],
                                         )
                                     }
                                     RelAlgebra::HnswSearch(HnswSearchRA {
                                         hnsw_search, ..
                                     }) => (
-                                        "hnsw_index",
-                                        json!(format!(":{}", hnsw_search.query.name)),
-                                        json!(hnsw_search.query.name),
-                                        json!(hnsw_search
-                                            .filter
-                                            .iter()
-                                            .map(|f| f.to_string())
-                                            .collect_vec()),
+                                        ExplainOp::HnswIndex,
+                                        Some(format!(":{}", hnsw_search.query.name)),
+                                        vec![],
+                                        filter_strs(hnsw_search.filter.as_slice()),
                                     ),
                                     RelAlgebra::FtsSearch(FtsSearchRA { fts_search, .. }) => (
-                                        "fts_index",
-                                        json!(format!(":{}", fts_search.query.name)),
-                                        json!(fts_search.query.name),
-                                        json!(fts_search
-                                            .filter
-                                            .iter()
-                                            .map(|f| f.to_string())
-                                            .collect_vec()),
+                                        ExplainOp::FtsIndex,
+                                        Some(format!(":{}", fts_search.query.name)),
+                                        vec![],
+                                        filter_strs(fts_search.filter.as_slice()),
                                     ),
                                     RelAlgebra::LshSearch(LshSearchRA { lsh_search, .. }) => (
-                                        "lsh_index",
-                                        json!(format!(":{}", lsh_search.query.name)),
-                                        json!(lsh_search.query.name),
-                                        json!(lsh_search
-                                            .filter
-                                            .iter()
-                                            .map(|f| f.to_string())
-                                            .collect_vec()),
+                                        ExplainOp::LshIndex,
+                                        Some(format!(":{}", lsh_search.query.name)),
+                                        vec![],
+                                        filter_strs(lsh_search.filter.as_slice()),
                                     ),
                                 };
-                                ret_for_relation.push(json!({
-                                    STRATUM: stratum,
-                                    ATOM_IDX: idx,
-                                    OP: atom_type,
-                                    RULE_IDX: clause_idx,
-                                    RULE_NAME: rule_name.to_string(),
-                                    REF_NAME: ref_name,
-                                    OUT_BINDINGS: rel.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec(),
-                                    JOINS_ON: joins_on,
-                                    FILTERS: filters,
-                                }));
+                                let mut step = step(idx, op, rel);
+                                step.reference = reference;
+                                step.joins_on = joins_on;
+                                step.filters = filters;
+                                if let Some(estimator) = &mut estimator {
+                                    step.est_rows =
+                                        estimator.estimate(rel)?.map(|rows| rows.ceil() as u64);
+                                }
+                                // nodes only probed by a join are never iterated and have no stats
+                                step.stats =
+                                    stats.and_then(|s| s.get(&stats_key)).map(|s| ExplainStats {
+                                        rows: s.rows,
+                                        calls: s.calls,
+                                        time_ms: s.time.as_secs_f64() * 1000.,
+                                    });
+                                steps_for_relation.push(step);
                                 idx += 1;
                             }
-                            ret_for_relation.reverse();
-                            ret.extend(ret_for_relation)
+                            steps_for_relation.reverse();
+                            steps.extend(steps_for_relation);
+                            clause_idx += 1;
                         }
                     }
-                    CompiledRuleSet::Fixed(_) => ret.push(json!({
-                        STRATUM: stratum,
-                        ATOM_IDX: 0,
-                        OP: "algo",
-                        RULE_IDX: 0,
-                        RULE_NAME: rule_name.to_string(),
-                    })),
+                    CompiledRuleSet::Fixed(_) => steps.push(ExplainStep {
+                        stratum,
+                        rule_idx: 0,
+                        rule: rule_name.to_string(),
+                        atom_idx: 0,
+                        op: ExplainOp::Algo,
+                        reference: None,
+                        joins_on: vec![],
+                        filters: vec![],
+                        out_relation: None,
+                        est_rows: None,
+                        stats: None,
+                    }),
                 }
             }
         }
 
-        let rows = ret
-            .into_iter()
-            .map(|m| {
-                headers
-                    .iter()
-                    .map(|i| DataValue::from(m.get(i).unwrap_or(&JsonValue::Null)))
-                    .collect_vec()
-            })
-            .collect_vec();
-
-        Ok(NamedRows::new(headers, rows))
+        Ok(ExplainPlan {
+            steps,
+            analyzed: stats.is_some(),
+        })
     }
     pub(crate) fn run_sys_op_with_tx(
         &'s self,
         tx: &mut SessionTx<'_>,
         op: &SysOp,
+        cur_vld: ValidityTs,
         read_only: bool,
         skip_locking: bool,
     ) -> Result<NamedRows> {
@@ -1177,18 +3104,72 @@ impl<'s, S: Storage<'s>> Db<S> {
                 let (stratified_program, _) = normalized_program.into_stratified_program()?;
                 let program = stratified_program.magic_sets_rewrite(tx)?;
                 let compiled = tx.stratified_magic_compile(program)?;
-                self.explain_compiled(&compiled)
+                Ok(self
+                    .explain_compiled(Some(tx), &compiled, None)?
+                    .into_named_rows())
+            }
+            SysOp::ExplainAnalyze(prog) => {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Cannot analyze a query that mutates relations")]
+                #[diagnostic(code(eval::explain_analyze_mutation))]
+                #[diagnostic(help(
+                    "The query is run in full, remove the mutation option to analyze it"
+                ))]
+                struct ExplainAnalyzeMutation;
+
+                ensure!(
+                    prog.out_opts.store_relation.is_none(),
+                    ExplainAnalyzeMutation
+                );
+                let entry_head = prog.get_entry_out_head_or_default()?;
+                let (normalized_program, out_opts) = prog.clone().into_normalized_program(tx)?;
+                let (stratified_program, store_lifetimes) =
+                    normalized_program.into_stratified_program()?;
+                let program = stratified_program.magic_sets_rewrite(tx)?;
+                let compiled: Arc<[CompiledProgram]> = tx.stratified_magic_compile(program)?.into();
+
+                let collector = RaStatsCollector::default();
+                let outer_stats = tx.ra_stats.replace(collector.clone());
+                let res = self.run_compiled_query(
+                    tx,
+                    &compiled,
+                    store_lifetimes,
+                    &entry_head,
+                    &out_opts,
+                    cur_vld,
+                    &Default::default(),
+                    &mut Default::default(),
+                    false,
+                );
+                tx.ra_stats = outer_stats;
+                // the results of the query are discarded
+                res?;
+
+                let stats = collector.lock().unwrap();
+                Ok(self
+                    .explain_compiled(Some(tx), &compiled, Some(&stats))?
+                    .into_named_rows())
             }
-            SysOp::Compact => {
+            SysOp::Compact(rel, options) => {
                 if read_only {
                     bail!("Cannot compact in read-only mode");
                 }
-                self.compact_relation()?;
+                match rel {
+                    None => self.compact_relation(*options)?,
+                    Some(CompactTarget::Named(rel)) => {
+                        let id = self.compaction_target(tx, &rel.name)?;
+                        self.compact_relation_range(id, *options)?
+                    }
+                    Some(CompactTarget::Id(id)) => self.compact_relation_id(*id, *options)?,
+                }
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::Vacuum => {
+                bail!("::vacuum must be run on its own, as it needs to commit before compacting")
+            }
             SysOp::ListRelations => self.list_relations(tx),
             SysOp::ListFixedRules => {
                 let rules = self.fixed_rules.read().unwrap();
@@ -1212,15 +3193,19 @@ impl<'s, S: Storage<'s>> Db<S> {
                 };
                 let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
                 let mut bounds = vec![];
+                let mut removed = vec![];
                 for rs in rel_names {
+                    let id = tx.get_relation(rs, false)?.id;
                     let bound = tx.destroy_relation(rs)?;
                     if !rs.is_temp_store_name() {
                         bounds.extend(bound);
+                        removed.push((rs.name.clone(), id));
                     }
                 }
                 for (lower, upper) in bounds {
                     tx.store_tx.del_range_from_persisted(&lower, &upper)?;
                 }
+                self.removed_relations.lock().unwrap().extend(removed);
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
@@ -1334,6 +3319,30 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::ListColumns(rs) => self.list_columns(tx, rs),
+            SysOp::RelationStats(rs) => self.relation_stats(tx, rs),
+            SysOp::SetTtl(name, ttl) => {
+                if read_only {
+                    bail!("Cannot set time to live in read-only mode");
+                }
+                tx.set_relation_ttl(name, *ttl)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::ExpireRelation(name) => {
+                if read_only {
+                    bail!("Cannot expire rows in read-only mode");
+                }
+                let n_rows = self.expire_relation(tx, name, skip_locking)?;
+                Ok(NamedRows::new(
+                    vec!["relation".to_string(), "expired".to_string()],
+                    vec![vec![
+                        DataValue::from(&name.name as &str),
+                        DataValue::from(n_rows as i64),
+                    ]],
+                ))
+            }
             SysOp::ListIndices(rs) => self.list_indices(tx, rs),
             SysOp::RenameRelation(rename_pairs) => {
                 if read_only {
@@ -1346,8 +3355,11 @@ impl<'s, S: Storage<'s>> Db<S> {
                     self.obtain_relation_locks(rel_names)
                 };
                 let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
-                for (old, new) in rename_pairs {
-                    tx.rename_relation(old, new)?;
+                for renames in split_rename_cycles(rename_pairs) {
+                    match renames {
+                        Left((old, new)) => tx.rename_relation(old, new)?,
+                        Right(cycle) => tx.rename_relation_cycle(&cycle)?,
+                    }
                 }
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
@@ -1355,9 +3367,100 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::ListRunning => self.list_running(),
+            SysOp::CommitStats => self.commit_stats(),
+            SysOp::ListRelationIds => {
+                let live = tx.live_relation_ids()?;
+                Ok(NamedRows::new(
+                    vec![
+                        "high_water_mark".to_string(),
+                        "n_live".to_string(),
+                        "live".to_string(),
+                    ],
+                    vec![vec![
+                        DataValue::from(tx.relation_store_id.load(Ordering::SeqCst) as i64),
+                        DataValue::from(live.len() as i64),
+                        DataValue::List(
+                            live.into_iter()
+                                .map(|(id, name)| {
+                                    DataValue::List(vec![
+                                        DataValue::from(id.0 as i64),
+                                        DataValue::Str(name),
+                                    ])
+                                })
+                                .collect_vec(),
+                        ),
+                    ]],
+                ))
+            }
+            SysOp::ReclaimRelationIds => {
+                bail!("::reclaim_ids must be run on its own, as it needs to commit first")
+            }
+            SysOp::SetOp(op, left, right, out) => {
+                if read_only {
+                    bail!("Cannot create relations in read-only mode");
+                }
+                let n_rows = if skip_locking {
+                    tx.relation_set_op(*op, left, right, out)?
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&out.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.relation_set_op(*op, left, right, out)?
+                };
+                tx.init_access_level(out, *self.default_access_level.read().unwrap())?;
+                Ok(NamedRows::new(
+                    vec!["relation".to_string(), "rows".to_string()],
+                    vec![vec![
+                        DataValue::from(&out.name as &str),
+                        DataValue::from(n_rows as i64),
+                    ]],
+                ))
+            }
+            SysOp::MigrateEncoding(rel_name) => {
+                if read_only {
+                    bail!("Cannot migrate encoding in read-only mode");
+                }
+                let migrated = if skip_locking {
+                    tx.migrate_relation_encoding(rel_name)?
+                } else {
+                    let lock = self
+                        .obtain_relation_locks(iter::once(&rel_name.name))
+                        .pop()
+                        .unwrap();
+                    let _guard = lock.write().unwrap();
+                    tx.migrate_relation_encoding(rel_name)?
+                };
+                Ok(NamedRows::new(
+                    vec![
+                        "relation".to_string(),
+                        "old_version".to_string(),
+                        "version".to_string(),
+                        "rows_rewritten".to_string(),
+                    ],
+                    migrated
+                        .into_iter()
+                        .map(|(name, old_version, n_rows)| {
+                            vec![
+                                DataValue::Str(name),
+                                DataValue::from(old_version as i64),
+                                DataValue::from(LATEST_ENCODING_VERSION as i64),
+                                DataValue::from(n_rows as i64),
+                            ]
+                        })
+                        .collect_vec(),
+                ))
+            }
             SysOp::KillRunning(id) => {
                 let queries = self.running_queries.lock().unwrap();
                 Ok(match queries.get(id) {
+                    // query ids are handed out in increasing order, so an id that was
+                    // given out but is no longer running belongs to a finished query
+                    None if *id < self.queries_count.load(Ordering::Acquire) => NamedRows::new(
+                        vec![STATUS_STR.to_string()],
+                        vec![vec![DataValue::from("ALREADY_FINISHED")]],
+                    ),
                     None => NamedRows::new(
                         vec![STATUS_STR.to_string()],
                         vec![vec![DataValue::from("NOT_FOUND")]],
@@ -1371,27 +3474,58 @@ impl<'s, S: Storage<'s>> Db<S> {
                     }
                 })
             }
+            SysOp::RunningPlan(id) => {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("No query with id {0} is running")]
+                #[diagnostic(code(eval::query_not_running))]
+                #[diagnostic(help("Running queries are listed by `::running`"))]
+                struct QueryNotRunning(u64);
+
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Query {0} has no plan of its own")]
+                #[diagnostic(code(eval::no_running_plan))]
+                #[diagnostic(help(
+                    "Imperative scripts and simple aggregations over a stored relation have no \
+                    plan. The queries of a script are listed by `::running` under their own ids"
+                ))]
+                struct NoRunningPlan(u64);
+
+                let program = {
+                    let queries = self.running_queries.lock().unwrap();
+                    let handle = queries.get(id).ok_or(QueryNotRunning(*id))?;
+                    handle.program.clone().ok_or(NoRunningPlan(*id))?
+                };
+                Ok(self
+                    .explain_compiled(None, &program, None)?
+                    .into_named_rows())
+            }
+            SysOp::KillMatching(pattern) => {
+                let queries = self.running_queries.lock().unwrap();
+                let rows = queries
+                    .iter()
+                    .filter(|(_, handle)| {
+                        matches!(&handle.script, Some(script) if script.source.contains(pattern.as_str()))
+                    })
+                    .map(|(id, handle)| {
+                        handle.poison.0.store(true, Ordering::Relaxed);
+                        vec![DataValue::from(*id as i64)]
+                    })
+                    .collect_vec();
+                Ok(NamedRows::new(vec!["id".to_string()], rows))
+            }
             SysOp::ShowTrigger(name) => {
                 let rel = tx.get_relation(name, false)?;
-                let mut rows: Vec<Vec<JsonValue>> = vec![];
-                for (i, trigger) in rel.put_triggers.iter().enumerate() {
-                    rows.push(vec![json!("put"), json!(i), json!(trigger)])
-                }
-                for (i, trigger) in rel.rm_triggers.iter().enumerate() {
-                    rows.push(vec![json!("rm"), json!(i), json!(trigger)])
-                }
-                for (i, trigger) in rel.replace_triggers.iter().enumerate() {
-                    rows.push(vec![json!("replace"), json!(i), json!(trigger)])
-                }
-                let rows = rows
-                    .into_iter()
-                    .map(|row| row.into_iter().map(DataValue::from).collect_vec())
-                    .collect_vec();
                 Ok(NamedRows::new(
-                    vec!["type".to_string(), "idx".to_string(), "trigger".to_string()],
-                    rows,
+                    vec![
+                        "type".to_string(),
+                        "idx".to_string(),
+                        "trigger".to_string(),
+                        "guard".to_string(),
+                    ],
+                    trigger_rows(&rel),
                 ))
             }
+            SysOp::ListAllTriggers => self.list_all_triggers(tx),
             SysOp::SetTriggers(name, puts, rms, replaces) => {
                 if read_only {
                     bail!("Cannot set triggers in read-only mode");
@@ -1416,13 +3550,33 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         }
     }
-    fn run_sys_op(&'s self, op: SysOp, read_only: bool) -> Result<NamedRows> {
+    fn run_sys_op(
+        &'s self,
+        op: SysOp,
+        cur_vld: ValidityTs,
+        read_only: bool,
+        hooks: ScriptHooks,
+    ) -> Result<NamedRows> {
+        if let SysOp::Vacuum = op {
+            if read_only {
+                bail!("Cannot vacuum in read-only mode");
+            }
+            return self.vacuum();
+        }
+        if let SysOp::ReclaimRelationIds = op {
+            if read_only {
+                bail!("Cannot reclaim relation ids in read-only mode");
+            }
+            return self.reclaim_relation_ids();
+        }
         let mut tx = if read_only {
             self.transact()?
         } else {
             self.transact_write()?
         };
-        let res = self.run_sys_op_with_tx(&mut tx, &op, read_only, false)?;
+        // so that queries run by the op, as with `::explain_analyze`, can be listed and killed
+        hooks.install(&mut tx);
+        let res = self.run_sys_op_with_tx(&mut tx, &op, cur_vld, read_only, false)?;
         tx.commit_tx()?;
         Ok(res)
     }
@@ -1436,9 +3590,6 @@ impl<'s, S: Storage<'s>> Db<S> {
         callback_collector: &mut CallbackCollector,
         top_level: bool,
     ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
-        // cleanups contain stored relations that should be deleted at the end of query
-        let mut clean_ups = vec![];
-
         // Some checks in case the query specifies mutation
         if let Some((meta, op, _)) = &input_program.out_opts.store_relation {
             if *op == RelationOp::Create {
@@ -1451,6 +3602,11 @@ impl<'s, S: Storage<'s>> Db<S> {
                     !tx.relation_exists(&meta.name)?,
                     StoreRelationConflict(meta.name.to_string())
                 )
+            } else if *op == RelationOp::CreateOrReplace {
+                if tx.relation_exists(&meta.name)? {
+                    let existing = tx.get_relation(&meta.name, false)?;
+                    existing.ensure_compatible(&meta.clone().with_flattened_keys(), false)?;
+                }
             } else if *op != RelationOp::Replace {
                 #[derive(Debug, Error, Diagnostic)]
                 #[error("Stored relation {0} not found")]
@@ -1471,56 +3627,220 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         };
 
+        // simple aggregations over a single stored relation skip compilation altogether,
+        // unless the limits set for all queries call for the full evaluation
+        let has_default_limits = *self.default_timeout.read().unwrap() > 0.
+            || self.default_mem_limit.load(Ordering::Acquire) > 0;
+        if !has_default_limits {
+            if let Some(plan) = tx.plan_simple_aggr_scan(&input_program)? {
+                let poison = tx.cancel.clone().unwrap_or_default();
+                let _guard = self.register_running_query(tx, &poison, None)?;
+                let rows = tx.simple_aggr_scan(&input_program, plan, &poison)?;
+                if let (true, Some(report)) = (top_level, &tx.report) {
+                    *report.lock().unwrap() = QueryReport::default();
+                }
+                return Ok((rows, vec![]));
+            }
+        }
+
         // query compilation
+        #[cfg(not(target_arch = "wasm32"))]
+        let compile_start = std::time::Instant::now();
         let entry_head_or_default = input_program.get_entry_out_head_or_default()?;
+        let warnings = input_program.unused_rule_warnings();
         let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
         let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
         let program = stratified_program.magic_sets_rewrite(tx)?;
-        let compiled = tx.stratified_magic_compile(program)?;
+        let compiled: Arc<[CompiledProgram]> = tx.stratified_magic_compile(program)?.into();
+        #[cfg(not(target_arch = "wasm32"))]
+        let compile_time = compile_start.elapsed().as_secs_f64();
 
-        // poison is used to terminate queries early
-        let poison = Poison::default();
-        if let Some(secs) = out_opts.timeout {
-            poison.set_timeout(secs)?;
+        let (rows, clean_ups) = self.run_compiled_query(
+            tx,
+            &compiled,
+            store_lifetimes,
+            &entry_head_or_default,
+            &out_opts,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            top_level,
+        )?;
+        if let (true, Some(report)) = (top_level, &tx.report) {
+            let mut report = report.lock().unwrap();
+            report.warnings = warnings;
+            #[cfg(not(target_arch = "wasm32"))]
+            if out_opts.profile {
+                report.compile_time = Some(compile_time);
+            }
+        }
+        Ok((rows, clean_ups))
+    }
+    /// The thread pool for `:max_eval_threads`, with `n_threads` threads but no more
+    /// than the available parallelism, as more threads could not run at the same time anyway
+    #[cfg(not(target_arch = "wasm32"))]
+    fn eval_thread_pool(&self, n_threads: usize) -> Result<Arc<rayon::ThreadPool>> {
+        let parallelism = thread::available_parallelism().map_or(1, |n| n.get());
+        let n_threads = n_threads.min(parallelism);
+        let mut pools = self.eval_thread_pools.lock().unwrap();
+        if let Some(pool) = pools.get(&n_threads) {
+            return Ok(pool.clone());
         }
-        // give the query an ID and store it so that it can be queried and cancelled
+        let pool = Arc::new(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(n_threads)
+                .build()
+                .into_diagnostic()?,
+        );
+        pools.insert(n_threads, pool.clone());
+        Ok(pool)
+    }
+    /// Give a query an ID and store it so that it can be listed and killed,
+    /// until the returned guard is dropped
+    fn register_running_query(
+        &self,
+        tx: &mut SessionTx<'_>,
+        poison: &Poison,
+        program: Option<Arc<[CompiledProgram]>>,
+    ) -> Result<RunningQueryCleanup> {
         let id = self.queries_count.fetch_add(1, Ordering::AcqRel);
-
-        // time the query
-        let since_the_epoch = seconds_since_the_epoch()?;
-
         let handle = RunningQueryHandle {
-            started_at: since_the_epoch,
+            started_at: seconds_since_the_epoch()?,
             poison: poison.clone(),
+            script: tx.script.clone(),
+            program,
         };
         self.running_queries.lock().unwrap().insert(id, handle);
-
-        // RAII cleanups of running query handle
-        let _guard = RunningQueryCleanup {
+        if let Some(on_start) = tx.on_query_start.take() {
+            on_start(id);
+        }
+        Ok(RunningQueryCleanup {
             id,
             running_queries: self.running_queries.clone(),
-        };
+        })
+    }
+    /// Evaluate a compiled query and deal with its output options. The report of a top-level
+    /// query is sent to [SessionTx::report], for the caller to add what it knows
+    /// about the compilation.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn run_compiled_query(
+        &self,
+        tx: &mut SessionTx<'_>,
+        compiled: &Arc<[CompiledProgram]>,
+        store_lifetimes: BTreeMap<MagicSymbol, usize>,
+        entry_head_or_default: &[Symbol],
+        out_opts: &QueryOutOptions,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        top_level: bool,
+    ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let (rows, clean_ups) = self.eval_compiled_query(
+            tx,
+            compiled,
+            store_lifetimes,
+            entry_head_or_default,
+            out_opts,
+            cur_vld,
+            callback_targets,
+            callback_collector,
+            top_level,
+        )?;
+        let stratum_times = tx.stratum_times.take();
+        if let (true, Some(report)) = (top_level, tx.report.clone()) {
+            let column_types = if out_opts.with_types {
+                let relation = match &out_opts.store_relation {
+                    Some((meta, _, ReturnMutation::Returning)) => {
+                        Some(tx.get_relation(&meta.name, false)?)
+                    }
+                    _ => None,
+                };
+                Some(column_types(&rows, relation.as_ref()))
+            } else {
+                None
+            };
+            *report.lock().unwrap() = QueryReport {
+                stratum_times,
+                column_types,
+                ..Default::default()
+            };
+        }
+        Ok((rows, clean_ups))
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn eval_compiled_query(
+        &self,
+        tx: &mut SessionTx<'_>,
+        compiled: &Arc<[CompiledProgram]>,
+        store_lifetimes: BTreeMap<MagicSymbol, usize>,
+        entry_head_or_default: &[Symbol],
+        out_opts: &QueryOutOptions,
+        cur_vld: ValidityTs,
+        callback_targets: &BTreeSet<SmartString<LazyCompact>>,
+        callback_collector: &mut CallbackCollector,
+        top_level: bool,
+    ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
+        // cleanups contain stored relations that should be deleted at the end of query
+        let mut clean_ups = vec![];
 
-        let total_num_to_take = if out_opts.sorters.is_empty() {
-            out_opts.num_to_take()
-        } else {
-            None
-        };
+        // poison is used to terminate queries early
+        let poison = tx.cancel.clone().unwrap_or_default();
+        let default_timeout = *self.default_timeout.read().unwrap();
+        if let Some(secs) = out_opts
+            .timeout
+            .or((default_timeout > 0.).then_some(default_timeout))
+        {
+            poison.set_timeout(secs)?;
+        }
+        let _guard = self.register_running_query(tx, &poison, Some(compiled.clone()))?;
 
-        let num_to_skip = if out_opts.sorters.is_empty() {
-            out_opts.offset
-        } else {
-            None
-        };
+        // the order of rows does not matter when only their number is returned
+        let sorted = !out_opts.sorters.is_empty() && !out_opts.count;
+
+        let total_num_to_take = if sorted { None } else { out_opts.num_to_take() };
+
+        let num_to_skip = if sorted { None } else { out_opts.offset };
 
         // the real evaluation
-        let (result_store, early_return) = tx.stratified_magic_evaluate(
-            &compiled,
-            store_lifetimes,
-            total_num_to_take,
-            num_to_skip,
-            poison,
-        )?;
+        let outer_max_join_matches =
+            std::mem::replace(&mut tx.max_join_matches, out_opts.max_join_matches);
+        let mem_limit = match self.default_mem_limit.load(Ordering::Acquire) {
+            0 => out_opts.mem_limit,
+            default => Some(out_opts.mem_limit.unwrap_or(default)),
+        };
+        let outer_mem_limit = std::mem::replace(&mut tx.mem_limit, mem_limit);
+        let mut stratum_times = out_opts.profile.then(Vec::new);
+        let evaluated = match out_opts.max_eval_threads {
+            // independent rules of a stratum are evaluated in parallel on the global thread pool,
+            // unless the query asks for a smaller one
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(n_threads) => self.eval_thread_pool(n_threads).and_then(|pool| {
+                pool.install(|| {
+                    tx.stratified_magic_evaluate(
+                        compiled,
+                        store_lifetimes,
+                        total_num_to_take,
+                        num_to_skip,
+                        out_opts.max_fanout,
+                        poison,
+                        stratum_times.as_mut(),
+                    )
+                })
+            }),
+            _ => tx.stratified_magic_evaluate(
+                compiled,
+                store_lifetimes,
+                total_num_to_take,
+                num_to_skip,
+                out_opts.max_fanout,
+                poison,
+                stratum_times.as_mut(),
+            ),
+        };
+        tx.max_join_matches = outer_max_join_matches;
+        tx.stratum_times = stratum_times;
+        tx.mem_limit = outer_mem_limit;
+        let (result_store, early_return) = evaluated?;
 
         // deal with assertions
         if let Some(assertion) = &out_opts.assertion {
@@ -1545,13 +3865,23 @@ impl<'s, S: Storage<'s>> Db<S> {
                         bail!(AssertSomeFailure(*span))
                     }
                 }
+                QueryAssertion::AssertCount(expected, span) => {
+                    let found = result_store.all_iter().count();
+                    if found != *expected {
+                        #[derive(Debug, Error, Diagnostic)]
+                        #[error("The query is asserted to return {0} rows, but returned {1}")]
+                        #[diagnostic(code(eval::assert_count_failure))]
+                        struct AssertCountFailure(usize, usize, #[label] SourceSpan);
+                        bail!(AssertCountFailure(*expected, found, *span))
+                    }
+                }
             }
         }
 
-        if !out_opts.sorters.is_empty() {
+        if sorted {
             // sort outputs if required
             let sorted_result =
-                tx.sort_and_collect(result_store, &out_opts.sorters, &entry_head_or_default)?;
+                tx.sort_and_collect(result_store, &out_opts.sorters, entry_head_or_default)?;
             let sorted_iter = if let Some(offset) = out_opts.offset {
                 Left(sorted_result.into_iter().skip(offset))
             } else {
@@ -1563,13 +3893,13 @@ impl<'s, S: Storage<'s>> Db<S> {
                 Right(sorted_iter)
             };
             if let Some((meta, relation_op, returning)) = &out_opts.store_relation {
-                let to_clear = tx
+                let (to_clear, n_affected) = tx
                     .execute_relation(
                         self,
                         sorted_iter,
                         *relation_op,
                         meta,
-                        &entry_head_or_default,
+                        entry_head_or_default,
                         cur_vld,
                         callback_targets,
                         callback_collector,
@@ -1582,22 +3912,26 @@ impl<'s, S: Storage<'s>> Db<S> {
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
-                let returned_rows =
-                    tx.get_returning_rows(callback_collector, &meta.name, returning)?;
+                let mut returned_rows =
+                    tx.get_returning_rows(callback_collector, &meta.name, returning, n_affected)?;
+                if let Some(fmt) = out_opts.float_fmt {
+                    fmt.apply(&mut returned_rows);
+                }
                 Ok((returned_rows, clean_ups))
             } else {
                 // not sorting outputs
                 let rows: Vec<Tuple> = sorted_iter.collect_vec();
-                Ok((
-                    NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
-                    ),
-                    clean_ups,
-                ))
+                let mut res = NamedRows::new(
+                    entry_head_or_default
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect_vec(),
+                    rows,
+                );
+                if let Some(fmt) = out_opts.float_fmt {
+                    fmt.apply(&mut res);
+                }
+                Ok((res, clean_ups))
             }
         } else {
             let scan = if early_return {
@@ -1619,13 +3953,13 @@ impl<'s, S: Storage<'s>> Db<S> {
             };
 
             if let Some((meta, relation_op, returning)) = &out_opts.store_relation {
-                let to_clear = tx
+                let (to_clear, n_affected) = tx
                     .execute_relation(
                         self,
                         scan,
                         *relation_op,
                         meta,
-                        &entry_head_or_default,
+                        entry_head_or_default,
                         cur_vld,
                         callback_targets,
                         callback_collector,
@@ -1638,44 +3972,101 @@ impl<'s, S: Storage<'s>> Db<S> {
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
-                let returned_rows =
-                    tx.get_returning_rows(callback_collector, &meta.name, returning)?;
+                let mut returned_rows =
+                    tx.get_returning_rows(callback_collector, &meta.name, returning, n_affected)?;
+                if let Some(fmt) = out_opts.float_fmt {
+                    fmt.apply(&mut returned_rows);
+                }
 
                 Ok((returned_rows, clean_ups))
-            } else {
-                let rows: Vec<Tuple> = scan.collect_vec();
-
+            } else if out_opts.count {
+                // the rows are consumed without being collected
+                let n = scan.count();
                 Ok((
                     NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
+                        vec!["count".to_string()],
+                        vec![vec![DataValue::from(n as i64)]],
                     ),
                     clean_ups,
                 ))
+            } else {
+                let rows: Vec<Tuple> = scan.collect_vec();
+                let mut res = NamedRows::new(
+                    entry_head_or_default
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect_vec(),
+                    rows,
+                );
+                if let Some(fmt) = out_opts.float_fmt {
+                    fmt.apply(&mut res);
+                }
+                Ok((res, clean_ups))
             }
         }
     }
     pub(crate) fn list_running(&self) -> Result<NamedRows> {
+        let now = seconds_since_the_epoch()?;
         let rows = self
             .running_queries
             .lock()
             .unwrap()
             .iter()
             .map(|(k, v)| {
+                let (source, params) = match &v.script {
+                    Some(script) => (
+                        DataValue::from(script.source.as_str()),
+                        DataValue::List(
+                            script
+                                .params
+                                .iter()
+                                .map(|p| DataValue::from(p.as_str()))
+                                .collect_vec(),
+                        ),
+                    ),
+                    None => (DataValue::Null, DataValue::Null),
+                };
                 vec![
                     DataValue::from(*k as i64),
-                    DataValue::from(format!("{:?}", v.started_at)),
+                    DataValue::from(v.started_at),
+                    DataValue::from(now - v.started_at),
+                    source,
+                    params,
                 ]
             })
             .collect_vec();
         Ok(NamedRows::new(
-            vec!["id".to_string(), "started_at".to_string()],
+            vec![
+                "id".to_string(),
+                "started_at".to_string(),
+                "elapsed".to_string(),
+                "source".to_string(),
+                "params".to_string(),
+            ],
             rows,
         ))
     }
+    pub(crate) fn commit_stats(&self) -> Result<NamedRows> {
+        let latencies = &self.commit_latencies;
+        Ok(NamedRows::new(
+            vec![
+                "count".to_string(),
+                "mean".to_string(),
+                "p50".to_string(),
+                "p90".to_string(),
+                "p99".to_string(),
+                "max".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(latencies.count() as i64),
+                DataValue::from(latencies.mean()),
+                DataValue::from(latencies.percentile(0.5)),
+                DataValue::from(latencies.percentile(0.9)),
+                DataValue::from(latencies.percentile(0.99)),
+                DataValue::from(latencies.max()),
+            ]],
+        ))
+    }
     fn list_indices(&'s self, tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
         let handle = tx.get_relation(name, false)?;
         let mut rows = vec![];
@@ -1764,7 +4155,12 @@ impl<'s, S: Storage<'s>> Db<S> {
             ]);
             idx += 1;
         }
-        for col in &handle.metadata.non_keys {
+        for col in handle
+            .metadata
+            .non_keys
+            .iter()
+            .filter(|col| col.name != TTL_COLUMN)
+        {
             rows.push(vec![
                 json!(col.name),
                 json!(false),
@@ -1789,6 +4185,159 @@ impl<'s, S: Storage<'s>> Db<S> {
             rows,
         ))
     }
+    /// Delete the rows of a relation created with `:ttl` whose expiry has passed, returning
+    /// how many were deleted. The rows are deleted by a `:rm` query, so indices are kept up to
+    /// date and triggers run, but no event callbacks are sent.
+    fn expire_relation(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        name: &str,
+        skip_locking: bool,
+    ) -> Result<usize> {
+        let handle = tx.get_relation(name, false)?;
+        if !handle.has_ttl() {
+            bail!(TtlNotSet(name.to_string()))
+        }
+        let keys = handle
+            .metadata
+            .keys
+            .iter()
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let fields = keys
+            .iter()
+            .cloned()
+            .chain(iter::once(format!("{TTL_COLUMN}: expiry")))
+            .join(", ");
+        let keys = keys.join(", ");
+        let script = format!(
+            "?[{keys}] := *{name}{{{fields}}}, expiry <= $now
+            :rm {name} {{{keys}}}
+            :returning"
+        );
+        let params = BTreeMap::from([("now".to_string(), op_now(&[])?)]);
+        let cur_vld = current_validity();
+        let prog = parse_script(&script, &params, &self.fixed_rules.read().unwrap(), cur_vld)?
+            .get_single_program()?;
+        let lock = if skip_locking {
+            None
+        } else {
+            self.obtain_relation_locks(iter::once(&handle.name)).pop()
+        };
+        let _guard = lock.as_ref().map(|lock| lock.read().unwrap());
+        let (res, _) = self
+            .run_query(
+                tx,
+                prog,
+                cur_vld,
+                &Default::default(),
+                &mut Default::default(),
+                true,
+            )
+            .wrap_err_with(|| format!("when expiring rows of relation '{name}'"))?;
+        // the returned rows list both the requested and the actually deleted rows
+        let deleted = DataValue::from("deleted");
+        Ok(res.rows.iter().filter(|row| row[0] == deleted).count())
+    }
+    /// Delete the expired rows of all relations created with `:ttl`,
+    /// each relation in its own transaction. Returns the number of rows deleted
+    /// from each relation.
+    pub fn expire_relations(&'s self) -> Result<BTreeMap<String, usize>> {
+        let mut names = vec![];
+        {
+            let mut tx = self.transact()?;
+            for (_, name) in tx.live_relation_ids()? {
+                if tx.get_relation(&name, false)?.has_ttl() {
+                    names.push(name);
+                }
+            }
+            tx.commit_tx()?;
+        }
+        let mut ret = BTreeMap::new();
+        for name in names {
+            let mut tx = self.transact_write()?;
+            let n_rows = self.expire_relation(&mut tx, &name, false)?;
+            tx.commit_tx()?;
+            ret.insert(name.to_string(), n_rows);
+        }
+        Ok(ret)
+    }
+    fn relation_stats(&'s self, tx: &SessionTx<'_>, name: &str) -> Result<NamedRows> {
+        let handle = tx.get_relation(name, false)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "reading statistics".to_string(),
+                handle.access_level
+            ));
+        }
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let mut n_rows = 0;
+        let mut key_bytes = 0;
+        let mut val_bytes = 0;
+        let mut first_key = None;
+        let mut last_key = None;
+        for kv in tx.store_tx.range_scan(&lower, &upper) {
+            let (k, v) = kv?;
+            n_rows += 1;
+            key_bytes += k.len();
+            val_bytes += v.len();
+            if first_key.is_none() {
+                first_key = Some(k.clone());
+            }
+            last_key = Some(k);
+        }
+        let first_col = |key: Option<Vec<u8>>| match key {
+            Some(key) if key.len() > ENCODED_KEY_MIN_LEN => {
+                DataValue::decode_from_key(&key[ENCODED_KEY_MIN_LEN..]).0
+            }
+            _ => DataValue::Null,
+        };
+        Ok(NamedRows::new(
+            vec![
+                "rows".to_string(),
+                "key_bytes".to_string(),
+                "value_bytes".to_string(),
+                "min_key".to_string(),
+                "max_key".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(n_rows as i64),
+                DataValue::from(key_bytes as i64),
+                DataValue::from(val_bytes as i64),
+                first_col(first_key),
+                first_col(last_key),
+            ]],
+        ))
+    }
+    fn list_all_triggers(&'s self, tx: &SessionTx<'_>) -> Result<NamedRows> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut rows = vec![];
+        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+            let (_, v_slice) = kv_res?;
+            let rel = RelationHandle::decode(&v_slice)?;
+            for row in trigger_rows(&rel) {
+                rows.push(
+                    iter::once(DataValue::from(rel.name.as_str()))
+                        .chain(row)
+                        .collect_vec(),
+                );
+            }
+        }
+        Ok(NamedRows::new(
+            vec![
+                "relation".to_string(),
+                "type".to_string(),
+                "idx".to_string(),
+                "trigger".to_string(),
+                "guard".to_string(),
+            ],
+            rows,
+        ))
+    }
     fn list_relations(&'s self, tx: &SessionTx<'_>) -> Result<NamedRows> {
         let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
         let upper =
@@ -1889,9 +4438,69 @@ fn _get_variables(src: &str, params: &BTreeMap<String, DataValue>) -> Result<BTr
     expr.get_variables()
 }
 
+/// Intermediate values of a meet aggregation (such as `min` or `max`), reported to the callback
+/// given to [Db::run_script_with_progress] after each semi-naive iteration that changed them.
+#[derive(Debug, Clone)]
+pub struct AggrProgress {
+    /// Name of the aggregating rule
+    pub rule: String,
+    /// Index of the stratum being evaluated
+    pub stratum: usize,
+    /// Semi-naive iteration within the stratum, starting from 0
+    pub iteration: u32,
+    /// The groups whose aggregated values changed in this iteration,
+    /// with their current values: grouping columns first, then the aggregated values
+    pub rows: Vec<Tuple>,
+}
+
+/// Progress of query evaluation, reported to the callback given to
+/// [Db::run_script_with_eval_progress] after each semi-naive iteration.
+#[derive(Debug, Clone)]
+pub struct EvalProgress {
+    /// Index of the stratum being evaluated
+    pub stratum: usize,
+    /// Semi-naive iteration within the stratum, starting from 0
+    pub iteration: u32,
+    /// Number of new tuples derived in the stratum so far
+    pub tuples: usize,
+}
+
+pub(crate) type AggrProgressCallback = Arc<dyn Fn(AggrProgress) + Send + Sync>;
+pub(crate) type EvalProgressCallback = Arc<dyn Fn(EvalProgress) -> bool + Send + Sync>;
+pub(crate) type QueryStartCallback = Box<dyn FnOnce(u64) + Send + Sync>;
+
+/// Caller-supplied hooks into the evaluation of a script
+#[derive(Default)]
+pub(crate) struct ScriptHooks {
+    pub(crate) aggr_progress: Option<AggrProgressCallback>,
+    pub(crate) eval_progress: Option<EvalProgressCallback>,
+    pub(crate) cancel: Option<Poison>,
+    pub(crate) on_query_start: Option<QueryStartCallback>,
+    /// The script being run, see [SessionTx::script]
+    pub(crate) script: Option<Arc<RunningScript>>,
+    /// See [SessionTx::report]
+    pub(crate) report: Option<Arc<Mutex<QueryReport>>>,
+}
+
+impl ScriptHooks {
+    pub(crate) fn install(self, tx: &mut SessionTx<'_>) {
+        tx.aggr_progress = self.aggr_progress;
+        tx.eval_progress = self.eval_progress;
+        tx.cancel = self.cancel;
+        tx.on_query_start = self.on_query_start;
+        tx.script = self.script;
+        tx.report = self.report;
+    }
+}
+
 /// Used for user-initiated termination of running queries
 #[derive(Clone, Default)]
-pub struct Poison(pub(crate) Arc<AtomicBool>);
+pub struct Poison(
+    pub(crate) Arc<AtomicBool>,
+    /// Deadline of the timeout in seconds since the epoch, as bits of an `f64`, 0 for none.
+    /// Enforced by [Poison::check], so that timeouts need no thread of their own.
+    Arc<AtomicU64>,
+);
 
 impl Poison {
     /// Will return `Err` if user has initiated termination.
@@ -1906,19 +4515,20 @@ impl Poison {
         if self.0.load(Ordering::Relaxed) {
             bail!(ProcessKilled)
         }
+        let deadline = f64::from_bits(self.1.load(Ordering::Relaxed));
+        if deadline > 0. && seconds_since_the_epoch()? >= deadline {
+            self.kill();
+            bail!(ProcessKilled)
+        }
         Ok(())
     }
-    #[cfg(target_arch = "wasm32")]
-    pub(crate) fn set_timeout(&self, _secs: f64) -> Result<()> {
-        bail!("Cannot set timeout when threading is disallowed");
+    /// Terminate the queries watching this poison, see [Db::run_script_with_cancel].
+    pub fn kill(&self) {
+        self.0.store(true, Ordering::Relaxed);
     }
-    #[cfg(not(target_arch = "wasm32"))]
     pub(crate) fn set_timeout(&self, secs: f64) -> Result<()> {
-        let pill = self.clone();
-        thread::spawn(move || {
-            thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
-            pill.0.store(true, Ordering::Relaxed);
-        });
+        let deadline = seconds_since_the_epoch()? + secs;
+        self.1.store(deadline.to_bits(), Ordering::Relaxed);
         Ok(())
     }
 }
@@ -1933,5 +4543,5 @@ pub(crate) fn seconds_since_the_epoch() -> Result<f64> {
         .as_secs_f64());
 
     #[cfg(target_arch = "wasm32")]
-    Ok(js_sys::Date::now())
+    Ok(js_sys::Date::now() / 1000.)
 }
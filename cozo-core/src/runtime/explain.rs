@@ -0,0 +1,493 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use miette::Result;
+use serde_json::json;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::json::JsonValue;
+use crate::data::program::MagicSymbol;
+use crate::data::value::DataValue;
+use crate::query::compile::{CompiledProgram, CompiledRuleSet};
+use crate::query::ra::{
+    FilteredRA, FtsSearchRA, HnswSearchRA, InnerJoin, LshSearchRA, RelAlgebra, ReorderRA, StoredRA,
+    StoredWithValidityRA, TempStoreRA, UnificationRA,
+};
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::NamedRows;
+
+/// Fraction of rows assumed to pass each filter when estimating the number of rows
+const FILTER_SELECTIVITY: f64 = 0.5;
+/// Stored relations are counted up to this many rows when estimating the number of rows,
+/// so that explaining a query stays cheap. Larger relations are estimated to have this many.
+pub(crate) const EXPLAIN_COUNT_LIMIT: usize = 100_000;
+/// How long the row count of a stored relation is reused by later estimates
+const ROW_COUNT_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Row counts of stored relations by id, with the time they were taken.
+/// Kept by [crate::Db] so that explaining queries does not count the same relations every time.
+/// Not used under WASM, which has no clock to age the counts by.
+pub(crate) type RowCountCache = Arc<Mutex<BTreeMap<RelationId, (usize, Instant)>>>;
+
+/// How a query would be evaluated, as returned by [Db::explain](crate::Db::explain).
+///
+/// This is the typed form of the rows returned by `::explain` and `::explain_analyze`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplainPlan {
+    /// The steps of the evaluation. Within a rule, steps are listed from the innermost
+    /// operation to the output.
+    pub steps: Vec<ExplainStep>,
+    /// Whether the query was run and the steps carry [ExplainStats]
+    pub analyzed: bool,
+}
+
+/// A single operation in an [ExplainPlan].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplainStep {
+    /// The stratum the rule is evaluated in
+    pub stratum: usize,
+    /// Index of the rule within the stratum, 0 for fixed rules
+    pub rule_idx: usize,
+    /// Name of the rule
+    pub rule: String,
+    /// Index of the operation within the rule, the output having index 0
+    pub atom_idx: usize,
+    /// The operation
+    pub op: ExplainOp,
+    /// The relation or index read, or the variable bound by a unification
+    pub reference: Option<String>,
+    /// The pairs of variables the left and right sides of a join are joined on
+    pub joins_on: Vec<(String, String)>,
+    /// The filters applied, or the expression of a unification
+    pub filters: Vec<String>,
+    /// The variables produced by the operation, `None` for fixed rules
+    pub out_relation: Option<Vec<String>>,
+    /// Rough estimate of the number of rows produced, `None` if unknown,
+    /// e.g. for the outputs of fixed rules and for recursive rules
+    pub est_rows: Option<u64>,
+    /// Measurements taken while running the query, if it was analyzed and
+    /// the operation was iterated over
+    pub stats: Option<ExplainStats>,
+}
+
+/// Measurements of an [ExplainStep] taken by `::explain_analyze`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExplainStats {
+    /// Number of rows produced
+    pub rows: u64,
+    /// Number of times the operation was iterated over
+    pub calls: u64,
+    /// Total time spent, in milliseconds
+    pub time_ms: f64,
+}
+
+/// The kind of operation of an [ExplainStep].
+/// Displayed as the `op` column of `::explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExplainOp {
+    /// Output of a rule without aggregations
+    Out,
+    /// Output of a rule with normal aggregations
+    AggrOut,
+    /// Output of a rule with only meet aggregations
+    MeetAggrOut,
+    /// Constant rows
+    Fixed,
+    /// Reading a rule or temporary relation held in memory
+    LoadMem,
+    /// Reading a stored relation
+    LoadStored,
+    /// Reading a stored relation at a point in time
+    LoadStoredWithValidity,
+    /// Joining the rows of two operations
+    Join(JoinKind),
+    /// Reordering columns
+    Reorder,
+    /// Filtering rows
+    Filter,
+    /// Binding a variable to an expression
+    Unify,
+    /// Binding a variable to each element of a list
+    MultiUnify,
+    /// Searching a vector index
+    HnswIndex,
+    /// Searching a full-text index
+    FtsIndex,
+    /// Searching a MinHash-LSH index
+    LshIndex,
+    /// Applying a fixed rule
+    Algo,
+}
+
+/// The algorithm used for an [ExplainOp::Join].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JoinKind {
+    /// Joining with empty constant rows
+    Null,
+    /// Joining with a single constant row
+    Singleton,
+    /// Joining with constant rows
+    Fixed,
+    /// Prefix scans of an in-memory relation
+    MemPrefix,
+    /// Materializing an in-memory relation
+    MemMat,
+    /// Prefix scans of a stored relation
+    StoredPrefix,
+    /// Materializing a stored relation
+    StoredMat,
+    /// Searching a vector index for each row
+    HnswSearch,
+    /// Searching a full-text index for each row
+    FtsSearch,
+    /// Searching a MinHash-LSH index for each row
+    LshSearch,
+    /// Materializing the right side
+    GenericMat,
+    /// Negation by prefix scans of an in-memory relation
+    MemNegPrefix,
+    /// Negation by materializing an in-memory relation
+    MemNegMat,
+    /// Negation by prefix scans of a stored relation
+    StoredNegPrefix,
+    /// Negation by materializing a stored relation
+    StoredNegMat,
+}
+
+impl JoinKind {
+    /// Whether rows are kept only if they have no match
+    pub fn is_negated(&self) -> bool {
+        matches!(
+            self,
+            JoinKind::MemNegPrefix
+                | JoinKind::MemNegMat
+                | JoinKind::StoredNegPrefix
+                | JoinKind::StoredNegMat
+        )
+    }
+    fn as_str(&self) -> &'static str {
+        match self {
+            JoinKind::Null => "null_join",
+            JoinKind::Singleton => "singleton_join",
+            JoinKind::Fixed => "fixed_join",
+            JoinKind::MemPrefix => "mem_prefix_join",
+            JoinKind::MemMat => "mem_mat_join",
+            JoinKind::StoredPrefix => "stored_prefix_join",
+            JoinKind::StoredMat => "stored_mat_join",
+            JoinKind::HnswSearch => "hnsw_search_join",
+            JoinKind::FtsSearch => "fts_search_join",
+            JoinKind::LshSearch => "lsh_search_join",
+            JoinKind::GenericMat => "generic_mat_join",
+            JoinKind::MemNegPrefix => "mem_neg_prefix_join",
+            JoinKind::MemNegMat => "mem_neg_mat_join",
+            JoinKind::StoredNegPrefix => "stored_neg_prefix_join",
+            JoinKind::StoredNegMat => "stored_neg_mat_join",
+        }
+    }
+}
+
+impl Display for JoinKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Display for ExplainOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ExplainOp::Out => "out",
+            ExplainOp::AggrOut => "aggr_out",
+            ExplainOp::MeetAggrOut => "meet_aggr_out",
+            ExplainOp::Fixed => "fixed",
+            ExplainOp::LoadMem => "load_mem",
+            ExplainOp::LoadStored => "load_stored",
+            ExplainOp::LoadStoredWithValidity => "load_stored_with_validity",
+            ExplainOp::Join(kind) => kind.as_str(),
+            ExplainOp::Reorder => "reorder",
+            ExplainOp::Filter => "filter",
+            ExplainOp::Unify => "unify",
+            ExplainOp::MultiUnify => "multi-unify",
+            ExplainOp::HnswIndex => "hnsw_index",
+            ExplainOp::FtsIndex => "fts_index",
+            ExplainOp::LshIndex => "lsh_index",
+            ExplainOp::Algo => "algo",
+        })
+    }
+}
+
+const STRATUM: &str = "stratum";
+const ATOM_IDX: &str = "atom_idx";
+const OP: &str = "op";
+const RULE_IDX: &str = "rule_idx";
+const RULE_NAME: &str = "rule";
+const REF_NAME: &str = "ref";
+const OUT_BINDINGS: &str = "out_relation";
+const JOINS_ON: &str = "joins_on";
+const FILTERS: &str = "filters/expr";
+const EST_ROWS: &str = "est_rows";
+const ACTUAL_ROWS: &str = "actual_rows";
+const CALLS: &str = "calls";
+const TIME_MS: &str = "time_ms";
+
+impl ExplainStep {
+    fn to_json(&self) -> JsonValue {
+        let mut row = json!({
+            STRATUM: self.stratum,
+            ATOM_IDX: self.atom_idx,
+            OP: self.op.to_string(),
+            RULE_IDX: self.rule_idx,
+            RULE_NAME: self.rule,
+            REF_NAME: self.reference,
+            OUT_BINDINGS: self.out_relation,
+            EST_ROWS: self.est_rows,
+        });
+        match self.op {
+            ExplainOp::Join(_) => {
+                row[JOINS_ON] = self
+                    .joins_on
+                    .iter()
+                    .map(|(l, r)| (l.clone(), json!(r)))
+                    .collect::<serde_json::Map<_, _>>()
+                    .into()
+            }
+            ExplainOp::LoadMem
+            | ExplainOp::LoadStored
+            | ExplainOp::LoadStoredWithValidity
+            | ExplainOp::Filter => row[FILTERS] = json!(self.filters),
+            ExplainOp::Unify | ExplainOp::MultiUnify => row[FILTERS] = json!(self.filters.first()),
+            ExplainOp::HnswIndex | ExplainOp::FtsIndex | ExplainOp::LshIndex => {
+                // index searches have always shown the bare index name here
+                row[JOINS_ON] = json!(self
+                    .reference
+                    .as_ref()
+                    .map(|r| r.strip_prefix(':').unwrap_or(r)));
+                row[FILTERS] = json!(self.filters);
+            }
+            _ => {}
+        }
+        if let Some(stats) = &self.stats {
+            row[ACTUAL_ROWS] = json!(stats.rows);
+            row[CALLS] = json!(stats.calls);
+            row[TIME_MS] = json!(stats.time_ms);
+        }
+        row
+    }
+}
+
+impl ExplainPlan {
+    /// The plan as the rows returned by `::explain`, or by `::explain_analyze`
+    /// if the plan was analyzed.
+    pub fn into_named_rows(self) -> NamedRows {
+        let mut headers = vec![
+            STRATUM.to_string(),
+            RULE_IDX.to_string(),
+            RULE_NAME.to_string(),
+            ATOM_IDX.to_string(),
+            OP.to_string(),
+            REF_NAME.to_string(),
+            JOINS_ON.to_string(),
+            FILTERS.to_string(),
+            OUT_BINDINGS.to_string(),
+            EST_ROWS.to_string(),
+        ];
+        if self.analyzed {
+            headers.extend([
+                ACTUAL_ROWS.to_string(),
+                CALLS.to_string(),
+                TIME_MS.to_string(),
+            ]);
+        }
+        let rows = self
+            .steps
+            .iter()
+            .map(|step| {
+                let m = step.to_json();
+                headers
+                    .iter()
+                    .map(|i| DataValue::from(m.get(i).unwrap_or(&JsonValue::Null)))
+                    .collect_vec()
+            })
+            .collect_vec();
+        NamedRows::new(headers, rows)
+    }
+}
+
+/// Rough estimates of the number of rows produced by the operations of a query, for `::explain`.
+///
+/// Stored relations are counted, up to [EXPLAIN_COUNT_LIMIT] rows, and the counts are reused
+/// for [ROW_COUNT_MAX_AGE] by later estimates of the same database. Each filter is assumed to
+/// keep [FILTER_SELECTIVITY] of the rows, and joins on keys are assumed to produce as many rows
+/// as their smaller side. Rules held in memory are estimated from the estimates of their
+/// clauses, which must be computed in strata order with [RowEstimator::estimate_stratum].
+/// Recursive rules are not estimated.
+pub(crate) struct RowEstimator<'a, 'b> {
+    tx: &'a SessionTx<'b>,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    row_counts: &'a RowCountCache,
+    relation_rows: BTreeMap<SmartString<LazyCompact>, f64>,
+    rule_rows: BTreeMap<MagicSymbol, Option<f64>>,
+}
+
+impl<'a, 'b> RowEstimator<'a, 'b> {
+    pub(crate) fn new(tx: &'a SessionTx<'b>, row_counts: &'a RowCountCache) -> Self {
+        Self {
+            tx,
+            row_counts,
+            relation_rows: Default::default(),
+            rule_rows: Default::default(),
+        }
+    }
+    /// Estimate the number of rows of each rule of a stratum, the sum of those of its clauses.
+    ///
+    /// Rules within a stratum are not ordered by their dependencies, so this repeats until no
+    /// more estimates can be made.
+    pub(crate) fn estimate_stratum(&mut self, prog: &CompiledProgram) -> Result<()> {
+        for _ in 0..prog.len() {
+            let mut changed = false;
+            for (name, ruleset) in prog {
+                if matches!(self.rule_rows.get(name), Some(Some(_))) {
+                    continue;
+                }
+                let rows = match ruleset {
+                    CompiledRuleSet::Rules(rules) => {
+                        let mut rows = Some(0.);
+                        for rule in rules {
+                            rows = rows.zip(self.estimate(&rule.relation)?).map(|(a, b)| a + b);
+                        }
+                        rows
+                    }
+                    CompiledRuleSet::Fixed(_) => None,
+                };
+                changed |= rows.is_some();
+                self.rule_rows.insert(name.clone(), rows);
+            }
+            if !changed {
+                break;
+            }
+        }
+        Ok(())
+    }
+    /// The rows of a stored relation, up to [EXPLAIN_COUNT_LIMIT]. Temp relations only live
+    /// for a script, so their counts are not kept.
+    fn count_rows(&self, storage: &RelationHandle) -> Result<usize> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if !storage.is_temp {
+            let counts = self.row_counts.lock().unwrap();
+            if let Some((rows, at)) = counts.get(&storage.id) {
+                if at.elapsed() < ROW_COUNT_MAX_AGE {
+                    return Ok(*rows);
+                }
+            }
+        }
+        let mut rows = 0;
+        for tuple in storage.scan_all(self.tx).take(EXPLAIN_COUNT_LIMIT) {
+            tuple?;
+            rows += 1;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if !storage.is_temp {
+            let mut counts = self.row_counts.lock().unwrap();
+            counts.insert(storage.id, (rows, Instant::now()));
+        }
+        Ok(rows)
+    }
+    pub(crate) fn estimate(&mut self, rel: &RelAlgebra) -> Result<Option<f64>> {
+        let filtered =
+            |rows: f64, n_filters: usize| rows * FILTER_SELECTIVITY.powi(n_filters as i32);
+        Ok(match rel {
+            RelAlgebra::Fixed(fixed) => Some(fixed.data.len() as f64),
+            RelAlgebra::TempStore(TempStoreRA {
+                storage_key,
+                filters,
+                ..
+            }) => self
+                .rule_rows
+                .get(storage_key)
+                .copied()
+                .flatten()
+                .map(|rows| filtered(rows, filters.len())),
+            RelAlgebra::Stored(StoredRA {
+                storage, filters, ..
+            })
+            | RelAlgebra::StoredWithValidity(StoredWithValidityRA {
+                storage, filters, ..
+            }) => {
+                let rows = match self.relation_rows.get(&storage.name) {
+                    Some(rows) => *rows,
+                    None => {
+                        let rows = self.count_rows(storage)? as f64;
+                        self.relation_rows.insert(storage.name.clone(), rows);
+                        rows
+                    }
+                };
+                Some(filtered(rows, filters.len()))
+            }
+            RelAlgebra::Join(inner) => {
+                let InnerJoin {
+                    left,
+                    right,
+                    joiner,
+                    ..
+                } = inner.as_ref();
+                let left = self.estimate(left)?;
+                let right = self.estimate(right)?;
+                left.zip(right).map(|(l, r)| {
+                    if joiner.left_keys.is_empty() {
+                        l * r
+                    } else {
+                        l.min(r)
+                    }
+                })
+            }
+            // at most the rows of the left side
+            RelAlgebra::NegJoin(inner) => self.estimate(&inner.left)?,
+            RelAlgebra::Reorder(ReorderRA { relation, .. }) => self.estimate(relation)?,
+            RelAlgebra::Filter(FilteredRA {
+                parent, filters, ..
+            }) => self
+                .estimate(parent)?
+                .map(|rows| filtered(rows, filters.len())),
+            RelAlgebra::Unification(UnificationRA {
+                parent,
+                expr,
+                is_multi,
+                ..
+            }) => {
+                let fan_out = match expr.clone().eval_to_const() {
+                    Ok(DataValue::List(l)) if *is_multi => l.len() as f64,
+                    _ => 1.,
+                };
+                self.estimate(parent)?.map(|rows| rows * fan_out)
+            }
+            RelAlgebra::HnswSearch(HnswSearchRA {
+                parent,
+                hnsw_search,
+                ..
+            }) => self
+                .estimate(parent)?
+                .map(|rows| rows * hnsw_search.k as f64),
+            RelAlgebra::FtsSearch(FtsSearchRA {
+                parent, fts_search, ..
+            }) => self
+                .estimate(parent)?
+                .map(|rows| rows * fts_search.k as f64),
+            RelAlgebra::LshSearch(LshSearchRA {
+                parent, lsh_search, ..
+            }) => self
+                .estimate(parent)?
+                .map(|rows| rows * lsh_search.k.unwrap_or(1) as f64),
+        })
+    }
+}
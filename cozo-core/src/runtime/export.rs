@@ -0,0 +1,417 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::iter;
+
+use indexmap::IndexMap;
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::json::JsonValue;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::transact::SessionTx;
+use crate::{decode_tuple_from_kv, Db, NamedRows, ScanHint, ScriptMutability, Storage};
+
+/// Maximum number of rows [Db::export_relation_sorted] will sort.
+/// Sorting is done in memory, so larger relations are rejected instead of
+/// risking running out of memory.
+pub const MAX_SORTED_EXPORT_ROWS: usize = 1_000_000;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation '{0}' has more than {1} rows and is too large for a sorted export")]
+#[diagnostic(code(export::too_large_for_sort))]
+#[diagnostic(help(
+    "Export the relation unsorted, or filter it first with `export_relation_where`"
+))]
+pub(crate) struct TooLargeForSortedExport(String, usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot sort export of relation '{0}' by '{1}': no such column")]
+#[diagnostic(code(export::sort_column_not_found))]
+pub(crate) struct SortColumnNotFound(String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad bound for the export of a range of relation '{0}': {1}")]
+#[diagnostic(code(export::bad_range_bound))]
+pub(crate) struct BadExportRangeBound(String, String);
+
+/// Formats available when exporting relations into a writer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// A single JSON object with `headers` and `rows`,
+    /// in the same shape as [NamedRows::into_json](crate::NamedRows::into_json).
+    #[default]
+    Json,
+    /// One JSON object per line, keyed by the headers, which are kept in order.
+    JsonLines,
+    /// RFC 4180 CSV with a header row. Null is written as an empty field,
+    /// lists and other composite values as JSON.
+    Csv,
+}
+
+/// Order of the columns when reading whole stored relations.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ColumnOrder {
+    /// Keys first, then non-keys, as the columns are stored.
+    /// For relations created by `:create`, this is the order in which the columns were declared.
+    /// This is the order used by all whole-relation reads unless stated otherwise.
+    #[default]
+    Stored,
+    /// The order in which the columns were declared. This only differs from [ColumnOrder::Stored]
+    /// for index relations (`relation:index`), which store the indexed columns first:
+    /// their columns are returned in the declaration order of the indexed relation instead.
+    Declared,
+}
+
+/// Number of rows read from storage at a time by [Db::export_relation_iter]
+const EXPORT_ITER_CHUNK: usize = 1024;
+
+/// Lazily yields the rows of a stored relation, keeping its transaction open.
+/// Rows are fetched from storage in chunks, each scan resuming after the last key seen.
+struct RelationRowIter<'s> {
+    tx: SessionTx<'s>,
+    lower: Vec<u8>,
+    upper: Vec<u8>,
+    size_hint: usize,
+    scan_hint: ScanHint,
+    buffer: VecDeque<Tuple>,
+    exhausted: bool,
+}
+
+impl Iterator for RelationRowIter<'_> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.exhausted {
+            let mut n_read = 0;
+            for kv in self
+                .tx
+                .store_tx
+                .range_scan_with_hint(&self.lower, &self.upper, self.scan_hint)
+                .take(EXPORT_ITER_CHUNK)
+            {
+                let (k, v) = match kv {
+                    Ok(kv) => kv,
+                    Err(err) => {
+                        self.exhausted = true;
+                        return Some(Err(err));
+                    }
+                };
+                self.buffer
+                    .push_back(decode_tuple_from_kv(&k, &v, Some(self.size_hint)));
+                // the smallest key greater than `k`
+                self.lower = k;
+                self.lower.push(0);
+                n_read += 1;
+            }
+            self.exhausted = n_read < EXPORT_ITER_CHUNK;
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+/// Write rows into `writer` in the requested format. Rows are consumed one at a time,
+/// so the iterator may be backed directly by a storage scan.
+pub(crate) fn write_rows(
+    mut writer: impl Write,
+    headers: &[String],
+    rows: impl Iterator<Item = Result<Tuple>>,
+    format: ExportFormat,
+) -> Result<()> {
+    match format {
+        ExportFormat::Json => {
+            write!(writer, "{{\"headers\":{},\"rows\":[", json!(headers)).into_diagnostic()?;
+            for (i, row) in rows.enumerate() {
+                let row = row?;
+                if i != 0 {
+                    writer.write_all(b",").into_diagnostic()?;
+                }
+                let row = row.into_iter().map(JsonValue::from).collect_vec();
+                serde_json::to_writer(&mut writer, &row).into_diagnostic()?;
+            }
+            writer.write_all(b"]}").into_diagnostic()?;
+        }
+        ExportFormat::JsonLines => {
+            for row in rows {
+                // `serde_json::Map` sorts its keys, so use a map that keeps the column order
+                let obj: IndexMap<&str, JsonValue> = headers
+                    .iter()
+                    .map(|h| h.as_str())
+                    .zip(row?.into_iter().map(JsonValue::from))
+                    .collect();
+                serde_json::to_writer(&mut writer, &obj).into_diagnostic()?;
+                writer.write_all(b"\n").into_diagnostic()?;
+            }
+        }
+        ExportFormat::Csv => {
+            write_csv_record(&mut writer, headers.iter().map(|h| h.to_string()))?;
+            for row in rows {
+                write_csv_record(&mut writer, row?.into_iter().map(csv_field))?;
+            }
+        }
+    }
+    writer.flush().into_diagnostic()
+}
+
+fn csv_field(val: DataValue) -> String {
+    match val {
+        DataValue::Str(s) => s.to_string(),
+        val => match JsonValue::from(val) {
+            JsonValue::Null => String::new(),
+            JsonValue::String(s) => s,
+            j => j.to_string(),
+        },
+    }
+}
+
+fn write_csv_record(writer: &mut impl Write, fields: impl Iterator<Item = String>) -> Result<()> {
+    for (i, field) in fields.enumerate() {
+        if i != 0 {
+            writer.write_all(b",").into_diagnostic()?;
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(writer, "\"{}\"", field.replace('"', "\"\"")).into_diagnostic()?;
+        } else {
+            writer.write_all(field.as_bytes()).into_diagnostic()?;
+        }
+    }
+    writer.write_all(b"\r\n").into_diagnostic()
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Iterate over the rows of a stored relation in key order, without collecting them
+    /// in memory first. Columns are in [ColumnOrder::Stored] order.
+    ///
+    /// The iterator holds a read transaction open for as long as it lives,
+    /// so all rows come from the same snapshot. Storage errors are yielded as `Err` items.
+    pub fn export_relation_iter(
+        &'s self,
+        relation: &str,
+    ) -> Result<impl Iterator<Item = Result<Tuple>> + 's> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        handle.ensure_exportable()?;
+        Ok(RelationRowIter {
+            lower: Tuple::default().encode_as_key(handle.id),
+            upper: Tuple::default().encode_as_key(handle.id.next()),
+            size_hint: handle.arity(),
+            scan_hint: self.scan_hint(),
+            tx,
+            buffer: VecDeque::new(),
+            exhausted: false,
+        })
+    }
+
+    /// Export stored relations into `writer` as CSV, streaming rows from storage.
+    ///
+    /// Each relation is written as a section: a record holding only the relation name,
+    /// a header row, and then the rows in key order, formatted as in [ExportFormat::Csv].
+    /// Sections are separated by an empty line.
+    pub fn export_relations_csv<I, T>(&'s self, relations: I, mut writer: impl Write) -> Result<()>
+    where
+        T: AsRef<str>,
+        I: Iterator<Item = T>,
+    {
+        let tx = self.transact()?;
+        for (i, rel) in relations.enumerate() {
+            let handle = tx.get_relation(rel.as_ref(), false)?;
+            handle.ensure_exportable()?;
+            if i != 0 {
+                writer.write_all(b"\r\n").into_diagnostic()?;
+            }
+            write_csv_record(&mut writer, iter::once(handle.name.to_string()))?;
+            write_rows(
+                &mut writer,
+                &handle.column_names(),
+                handle.scan_all(&tx),
+                ExportFormat::Csv,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Export the rows of a stored relation that satisfy `predicate` into `writer`.
+    ///
+    /// `predicate` is a CozoScript boolean expression over the columns of the relation,
+    /// for example `status != 'cancelled'`. The filtering is done by running
+    /// an immutable query against the relation.
+    pub fn export_relation_where(
+        &'s self,
+        relation: &str,
+        predicate: &str,
+        writer: impl Write,
+        format: ExportFormat,
+    ) -> Result<()> {
+        let headers = self.export_headers(relation, None)?;
+        let bindings = headers.join(", ");
+        let script = format!("?[{bindings}] := *{relation}{{{bindings}}}, {predicate}");
+        let res = self
+            .run_script(&script, Default::default(), ScriptMutability::Immutable)
+            .wrap_err_with(|| format!("when filtering relation '{relation}' for export"))?;
+        write_rows(writer, &res.headers, res.rows.into_iter().map(Ok), format)
+    }
+
+    /// Export a stored relation into `writer` with the rows ordered by `sort_cols`
+    /// instead of by key.
+    ///
+    /// Each entry of `sort_cols` is a column name, optionally prefixed with `-` for
+    /// descending or `+` for ascending order, as in the `:order` query option.
+    /// The rows are sorted in memory by the query engine, so relations with more than
+    /// [MAX_SORTED_EXPORT_ROWS] rows are rejected.
+    pub fn export_relation_sorted(
+        &'s self,
+        relation: &str,
+        sort_cols: &[&str],
+        writer: impl Write,
+        format: ExportFormat,
+    ) -> Result<()> {
+        let headers = self.export_headers(relation, Some(MAX_SORTED_EXPORT_ROWS))?;
+        for col in sort_cols {
+            let name = col.strip_prefix(['+', '-']).unwrap_or(col).trim();
+            if !headers.iter().any(|h| h == name) {
+                bail!(SortColumnNotFound(relation.to_string(), name.to_string()));
+            }
+        }
+        let bindings = headers.join(", ");
+        let mut script = format!("?[{bindings}] := *{relation}{{{bindings}}}");
+        if !sort_cols.is_empty() {
+            script.push_str(&format!("\n:order {}", sort_cols.join(", ")));
+        }
+        let res = self
+            .run_script(&script, Default::default(), ScriptMutability::Immutable)
+            .wrap_err_with(|| format!("when sorting relation '{relation}' for export"))?;
+        write_rows(writer, &res.headers, res.rows.into_iter().map(Ok), format)
+    }
+
+    /// Export the rows of a stored relation whose keys lie between `lower` and `upper`,
+    /// in key order. Columns are in [ColumnOrder::Stored] order.
+    ///
+    /// The bounds are prefixes of the key: a row is exported if the first `lower.len()`
+    /// values of its key are not less than `lower`, and the first `upper.len()` values
+    /// are not greater than `upper`. Empty bounds do not restrict the range.
+    /// Only the range is read from storage.
+    pub fn export_relation_range(
+        &'s self,
+        relation: &str,
+        lower: Tuple,
+        upper: Tuple,
+    ) -> Result<NamedRows> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        handle.ensure_exportable()?;
+        let cur_vld = current_validity();
+        let check_bound = |bound: Tuple| -> Result<Tuple> {
+            if bound.len() > handle.metadata.keys.len() {
+                bail!(BadExportRangeBound(
+                    relation.to_string(),
+                    format!(
+                        "{} values given, but the relation has {} key columns",
+                        bound.len(),
+                        handle.metadata.keys.len()
+                    )
+                ));
+            }
+            bound
+                .into_iter()
+                .zip(&handle.metadata.keys)
+                .map(|(val, col)| {
+                    col.typing.coerce(val, cur_vld).map_err(|err| {
+                        BadExportRangeBound(
+                            relation.to_string(),
+                            format!("key column {}: {err}", col.name),
+                        )
+                        .into()
+                    })
+                })
+                .try_collect()
+        };
+        let lower = check_bound(lower)?;
+        let upper = check_bound(upper)?;
+        let rows: Vec<_> = handle
+            .scan_bounded_prefix(&tx, &[], &lower, &upper)
+            .try_collect()?;
+        Ok(NamedRows::new(handle.column_names(), rows))
+    }
+
+    /// Export a stored relation into `writer` with the rows guaranteed to be in ascending
+    /// order of their encoded keys, which is the order of their key columns.
+    ///
+    /// Other whole-relation exports return rows in the order the storage engine scans them.
+    /// That is key order for all engines shipped with Cozo, but it is not part of the
+    /// [Storage] contract: here the keys are scanned once to check their order, and the rows
+    /// are then streamed from a second scan. Only if they are found out of order are they
+    /// held in memory and sorted before being written.
+    /// Columns are in [ColumnOrder::Declared] order, also for the keys of the objects
+    /// written by [ExportFormat::JsonLines].
+    pub fn export_relation_by_key(
+        &'s self,
+        relation: &str,
+        writer: impl Write,
+        format: ExportFormat,
+    ) -> Result<()> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        handle.ensure_exportable()?;
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let mut in_order = true;
+        let mut last_key: Option<Vec<u8>> = None;
+        for kv in tx.store_tx.range_scan(&lower, &upper) {
+            let (key, _) = kv?;
+            if matches!(&last_key, Some(last) if *last > key) {
+                in_order = false;
+                break;
+            }
+            last_key = Some(key);
+        }
+        let kvs: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>> = if in_order {
+            tx.store_tx.range_scan(&lower, &upper)
+        } else {
+            let mut kvs: Vec<_> = tx.store_tx.range_scan(&lower, &upper).try_collect()?;
+            kvs.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Box::new(kvs.into_iter().map(Ok))
+        };
+        let mut headers = handle.column_names();
+        let permutation = tx.declared_column_order(&handle)?;
+        if let Some(permutation) = &permutation {
+            headers = permutation
+                .iter()
+                .map(|i| headers[*i].clone())
+                .collect_vec();
+        }
+        let rows = kvs.map(|kv| {
+            let (k, v) = kv?;
+            let tuple = decode_tuple_from_kv(&k, &v, Some(handle.arity()));
+            Ok(match &permutation {
+                None => tuple,
+                Some(permutation) => permutation.iter().map(|i| tuple[*i].clone()).collect_vec(),
+            })
+        });
+        write_rows(writer, &headers, rows, format)
+    }
+
+    /// Column names of a relation about to be exported, checking access rights
+    /// and, if `max_rows` is given, that the relation is not larger than that.
+    fn export_headers(&'s self, relation: &str, max_rows: Option<usize>) -> Result<Vec<String>> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        handle.ensure_exportable()?;
+        if let Some(max_rows) = max_rows {
+            if handle.scan_all(&tx).take(max_rows + 1).count() > max_rows {
+                bail!(TooLargeForSortedExport(relation.to_string(), max_rows));
+            }
+        }
+        Ok(handle.column_names())
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::builder::make_builder;
+use arrow_schema::{Schema, SchemaRef};
+use itertools::Itertools;
+use miette::{IntoDiagnostic, Result, WrapErr};
+use parquet::arrow::ArrowWriter;
+
+use crate::runtime::arrow_conv::{append_value, arrow_field, finish_batch};
+use crate::{Db, Storage};
+
+/// Number of rows collected into each record batch written by [Db::export_relations_parquet]
+const PARQUET_BATCH_ROWS: usize = 8192;
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Export a stored relation as a Parquet file at `path`, overwriting any existing file.
+    ///
+    /// Columns are in [ColumnOrder::Stored](crate::ColumnOrder::Stored) order and keep the
+    /// nullability of their declared types. `Int`, `Float`, `Bool`, `Bytes` and `String`
+    /// columns become `Int64`, `Float64`, `Boolean`, `Binary` and `Utf8` columns, UUIDs
+    /// are written as strings, and lists and vectors as nested lists. Columns of any other
+    /// type hold the JSON representation of the values as strings.
+    /// Rows are streamed from storage in batches of 8192.
+    pub fn export_relations_parquet(&'s self, relation: &str, path: &Path) -> Result<()> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        handle.ensure_exportable()?;
+        let columns = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .collect_vec();
+        let schema: SchemaRef = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|col| arrow_field(&col.name, &col.typing))
+                .collect_vec(),
+        ));
+        let mut builders = schema
+            .fields()
+            .iter()
+            .map(|field| make_builder(field.data_type(), PARQUET_BATCH_ROWS))
+            .collect_vec();
+
+        let file = File::create(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("when creating Parquet file {}", path.display()))?;
+        let mut writer = ArrowWriter::try_new(file, schema.clone(), None).into_diagnostic()?;
+        let mut n_buffered = 0;
+        for row in handle.scan_all(&tx) {
+            let row = row?;
+            for ((builder, col), val) in builders.iter_mut().zip(&columns).zip(&row) {
+                append_value(builder.as_mut(), &col.typing.coltype, val);
+            }
+            n_buffered += 1;
+            if n_buffered == PARQUET_BATCH_ROWS {
+                writer
+                    .write(&finish_batch(&schema, &mut builders)?)
+                    .into_diagnostic()?;
+                n_buffered = 0;
+            }
+        }
+        if n_buffered != 0 {
+            writer
+                .write(&finish_batch(&schema, &mut builders)?)
+                .into_diagnostic()?;
+        }
+        writer.close().into_diagnostic()?;
+        Ok(())
+    }
+}
@@ -20,7 +20,9 @@ use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationM
 use crate::data::symb::Symbol;
 use crate::parse::{ImperativeCondition, ImperativeProgram, ImperativeStmt, SourceSpan};
 use crate::runtime::callback::CallbackCollector;
-use crate::runtime::db::{seconds_since_the_epoch, RunningQueryCleanup, RunningQueryHandle};
+use crate::runtime::db::{
+    seconds_since_the_epoch, RunningQueryCleanup, RunningQueryHandle, ScriptHooks,
+};
 use crate::runtime::relation::InputRelationHandle;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, Db, NamedRows, Poison, Storage, ValidityTs};
@@ -120,7 +122,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                     ret = NamedRows::default();
                 }
                 ImperativeStmt::SysOp { sysop, .. } => {
-                    ret = self.run_sys_op_with_tx(tx, &sysop.sysop, readonly, true)?;
+                    ret = self.run_sys_op_with_tx(tx, &sysop.sysop, cur_vld, readonly, true)?;
                     if let Some(store_as) = &sysop.store_as {
                         tx.script_store_as_relation(self, store_as, &ret, cur_vld)?;
                     }
@@ -257,6 +259,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         cur_vld: ValidityTs,
         ps: &ImperativeProgram,
         readonly: bool,
+        hooks: ScriptHooks,
     ) -> Result<NamedRows, Report> {
         let mut callback_collector = BTreeMap::new();
         let mut write_lock_names = BTreeSet::new();
@@ -283,16 +286,22 @@ impl<'s, S: Storage<'s>> Db<S> {
             } else {
                 self.transact()?
             };
+            hooks.install(&mut tx);
 
-            let poison = Poison::default();
+            let poison = tx.cancel.clone().unwrap_or_default();
             let qid = self.queries_count.fetch_add(1, Ordering::AcqRel);
             let since_the_epoch = seconds_since_the_epoch()?;
 
             let q_handle = RunningQueryHandle {
                 started_at: since_the_epoch,
                 poison: poison.clone(),
+                script: tx.script.clone(),
+                program: None,
             };
             self.running_queries.lock().unwrap().insert(qid, q_handle);
+            if let Some(on_start) = tx.on_query_start.take() {
+                on_start(qid);
+            }
             let _guard = RunningQueryCleanup {
                 id: qid,
                 running_queries: self.running_queries.clone(),
@@ -0,0 +1,501 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Change tracking for incremental backups.
+//!
+//! Once enabled, every write transaction that changes stored data is assigned a commit marker,
+//! one greater than the previous one, and records the keys it wrote in a change log
+//! under the marker. The log and the current marker are stored in the same transaction as the
+//! changes, under keys starting with [RESERVED_PREFIX], which no relation can use.
+//! An incremental backup copies the current values of the keys logged after a given marker.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crossbeam::sync::ShardedLock;
+#[allow(unused_imports)]
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::storage::{ScanHint, Storage, StoreTx};
+use crate::Db;
+
+/// First byte of all keys used by change tracking. Stored relations never use it,
+/// so these keys are left out of relation scans and of [Db::backup_db].
+const RESERVED_PREFIX: u8 = 0xFF;
+/// The marker of the last tracked commit, present only when tracking is enabled
+const CHANGE_MARKER_KEY: [u8; 2] = [RESERVED_PREFIX, 0x00];
+/// Keys written by a commit: prefix, then the marker, then the key. Values are empty.
+const POINT_LOG_PREFIX: [u8; 2] = [RESERVED_PREFIX, 0x01];
+/// Ranges deleted by a commit: prefix, then the marker, then the lower bound.
+/// Values are the upper bounds.
+const RANGE_LOG_PREFIX: [u8; 2] = [RESERVED_PREFIX, 0x02];
+/// Log entries up to and including this marker have been removed
+#[cfg(feature = "storage-sqlite")]
+const LOG_FLOOR_KEY: [u8; 2] = [RESERVED_PREFIX, 0x03];
+/// In a backup file, the [BackupMarker] of the backup.
+/// In a restored database, the [BackupMarker] of the last backup applied.
+#[cfg(feature = "storage-sqlite")]
+const BACKUP_MARKER_KEY: [u8; 2] = [RESERVED_PREFIX, 0x04];
+/// In a backup file, keys deleted since the previous backup. Values are empty.
+#[cfg(feature = "storage-sqlite")]
+const BACKUP_TOMBSTONE_PREFIX: [u8; 2] = [RESERVED_PREFIX, 0x05];
+/// In a backup file, ranges deleted since the previous backup. Values are the upper bounds.
+#[cfg(feature = "storage-sqlite")]
+const BACKUP_RANGE_TOMBSTONE_PREFIX: [u8; 2] = [RESERVED_PREFIX, 0x06];
+
+/// Current commit marker of a database, `None` if change tracking is not enabled.
+/// Tracked commits hold the write lock while committing, so that they commit in marker order.
+pub(crate) type ChangeMarker = Arc<ShardedLock<Option<u64>>>;
+
+#[cfg(feature = "storage-sqlite")]
+#[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, PartialEq, Eq)]
+struct BackupMarker {
+    /// Marker of the backup this one builds on, `None` for a full backup
+    since: Option<u64>,
+    marker: u64,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The transaction started before change tracking was enabled and cannot be committed")]
+#[diagnostic(code(tx::change_tracking_started))]
+#[diagnostic(help("Retry the transaction"))]
+struct ChangeTrackingStarted;
+
+#[cfg(feature = "storage-sqlite")]
+#[derive(Debug, Error, Diagnostic)]
+#[error("Change tracking is not enabled for this database")]
+#[diagnostic(code(backup::not_tracking))]
+#[diagnostic(help("Take a full backup with `backup_db_incremental(path, None)` first"))]
+struct ChangeTrackingNotEnabled;
+
+#[cfg(feature = "storage-sqlite")]
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot back up changes since marker {0}: the current marker is {1}")]
+#[diagnostic(code(backup::unknown_marker))]
+struct UnknownMarker(u64, u64);
+
+#[cfg(feature = "storage-sqlite")]
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot back up changes since marker {0}: changes up to marker {1} are no longer kept")]
+#[diagnostic(code(backup::marker_pruned))]
+#[diagnostic(help("Take a new full backup with `backup_db_incremental(path, None)`"))]
+struct MarkerPruned(u64, u64);
+
+#[cfg(feature = "storage-sqlite")]
+#[derive(Debug, Error, Diagnostic)]
+#[error("The file is not a backup made by `backup_db_incremental`")]
+#[diagnostic(code(backup::not_incremental))]
+struct NotIncrementalBackup;
+
+#[cfg(feature = "storage-sqlite")]
+#[derive(Debug, Error, Diagnostic)]
+#[error("Backup out of order: it contains changes since marker {0}, but the database is at {1}")]
+#[diagnostic(code(backup::out_of_order))]
+#[diagnostic(help("Restore the full backup first, then every later backup in order"))]
+struct BackupOutOfOrder(u64, String);
+
+fn log_key(prefix: [u8; 2], marker: u64, key: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(10 + key.len());
+    ret.extend(prefix);
+    ret.extend(marker.to_be_bytes());
+    ret.extend(key);
+    ret
+}
+
+#[cfg(feature = "storage-sqlite")]
+fn prefixed_key(prefix: [u8; 2], key: &[u8]) -> Vec<u8> {
+    let mut ret = prefix.to_vec();
+    ret.extend(key);
+    ret
+}
+
+fn is_reserved(key: &[u8]) -> bool {
+    key.first() == Some(&RESERVED_PREFIX)
+}
+
+fn decode_marker(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes.try_into().unwrap())
+}
+
+fn read_marker(tx: &dyn StoreTx<'_>, key: &[u8]) -> Result<Option<u64>> {
+    Ok(tx.get(key, false)?.map(|v| decode_marker(&v)))
+}
+
+#[cfg(feature = "storage-sqlite")]
+fn read_backup_marker(tx: &dyn StoreTx<'_>) -> Result<Option<BackupMarker>> {
+    tx.get(&BACKUP_MARKER_KEY, false)?
+        .map(|v| rmp_serde::from_slice(&v).into_diagnostic())
+        .transpose()
+}
+
+/// Read the commit marker of a database when it is opened
+pub(crate) fn load_change_marker<'s, S: Storage<'s>>(storage: &'s S) -> Result<Option<u64>> {
+    let tx = storage.transact(false)?;
+    read_marker(&tx, &CHANGE_MARKER_KEY)
+}
+
+/// Wraps the storage transaction of every write transaction, recording the keys it changes
+/// if change tracking is enabled.
+pub(crate) struct ChangeTrackingTx<'s> {
+    inner: Box<dyn StoreTx<'s> + 's>,
+    marker: ChangeMarker,
+    /// Whether change tracking was enabled when the transaction started
+    recording: bool,
+    /// Enable change tracking when committing
+    enable: bool,
+    dirty: AtomicBool,
+    points: Mutex<BTreeSet<Vec<u8>>>,
+    ranges: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl<'s> ChangeTrackingTx<'s> {
+    pub(crate) fn new(inner: Box<dyn StoreTx<'s> + 's>, marker: ChangeMarker) -> Self {
+        let recording = marker.read().unwrap().is_some();
+        Self {
+            inner,
+            marker,
+            recording,
+            enable: false,
+            dirty: AtomicBool::new(false),
+            points: Default::default(),
+            ranges: Default::default(),
+        }
+    }
+    /// Commit the transaction, enabling change tracking with it
+    #[cfg(feature = "storage-sqlite")]
+    fn enable_tracking(mut self) -> Result<()> {
+        self.enable = true;
+        *self.dirty.get_mut() = true;
+        self.commit()
+    }
+    fn record(&self, key: &[u8]) {
+        self.dirty.store(true, Ordering::Relaxed);
+        if self.recording && !is_reserved(key) {
+            self.points.lock().unwrap().insert(key.to_vec());
+        }
+    }
+}
+
+impl<'s> StoreTx<'s> for ChangeTrackingTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record(key);
+        self.inner.put(key, val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record(key);
+        self.inner.par_put(key, val)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.record(key);
+        self.inner.del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.record(key);
+        self.inner.par_del(key)
+    }
+
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.dirty.store(true, Ordering::Relaxed);
+        if self.recording && !is_reserved(lower) {
+            self.ranges.insert(lower.to_vec(), upper.to_vec());
+        }
+        self.inner.del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let points = mem::take(self.points.get_mut().unwrap());
+        let ranges = mem::take(&mut self.ranges);
+        if !*self.dirty.get_mut() {
+            return self.inner.commit();
+        }
+        if points.is_empty() && ranges.is_empty() && !self.enable {
+            // holding the lock keeps tracking from being enabled while committing
+            let marker = self.marker.read().unwrap();
+            if marker.is_some() && !self.recording {
+                bail!(ChangeTrackingStarted)
+            }
+            return self.inner.commit();
+        }
+        let mut marker = self.marker.write().unwrap();
+        let next = match *marker {
+            Some(m) if self.recording => m + 1,
+            Some(_) if !self.enable => bail!(ChangeTrackingStarted),
+            Some(m) => m,
+            None if self.enable => 0,
+            None => return self.inner.commit(),
+        };
+        for key in points {
+            self.inner
+                .put(&log_key(POINT_LOG_PREFIX, next, &key), &[])?;
+        }
+        for (lower, upper) in ranges {
+            self.inner
+                .put(&log_key(RANGE_LOG_PREFIX, next, &lower), &upper)?;
+        }
+        self.inner.put(&CHANGE_MARKER_KEY, &next.to_be_bytes())?;
+        self.inner.commit()?;
+        *marker = Some(next);
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.points
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.ranges.clear();
+        *self.dirty.get_mut() = false;
+        self.inner.rollback()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_scan_with_hint<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        hint: ScanHint,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_with_hint(lower, upper, hint)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.total_scan()
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Backup the running database into an Sqlite file, copying only what changed since
+    /// an earlier backup. Returns the commit marker of the backup.
+    ///
+    /// With `since` set to `None`, a full backup is made, and change tracking is enabled
+    /// if it is not already. From then on, every write transaction records the keys
+    /// it changes, and commits of such transactions are serialized.
+    /// With `since` set to the marker returned by an earlier backup, the backup only contains
+    /// the current values of the keys changed after that backup, and the keys deleted since.
+    /// The changes logged up to `since` are then discarded, so later backups cannot
+    /// start from an earlier marker.
+    ///
+    /// The marker is stored in the database, in the same transaction as the changes it covers.
+    /// Use [Db::restore_backup_incremental] to restore the backups.
+    #[allow(unused_variables)]
+    pub fn backup_db_incremental(
+        &'s self,
+        out_file: impl AsRef<Path>,
+        since: Option<u64>,
+    ) -> Result<u64> {
+        #[cfg(feature = "storage-sqlite")]
+        {
+            let sqlite_db = crate::new_cozo_sqlite(out_file)?;
+            if sqlite_db.relation_store_id.load(Ordering::SeqCst) != 0 {
+                bail!("Cannot create backup: data exists in the target database.");
+            }
+            if since.is_none() && self.change_marker.read().unwrap().is_none() {
+                ChangeTrackingTx::new(
+                    Box::new(self.db.transact(true)?),
+                    self.change_marker.clone(),
+                )
+                .enable_tracking()?;
+            }
+
+            let tx = self.transact()?;
+            let marker =
+                read_marker(&*tx.store_tx, &CHANGE_MARKER_KEY)?.ok_or(ChangeTrackingNotEnabled)?;
+            let backup_marker = rmp_serde::to_vec_named(&BackupMarker { since, marker }).unwrap();
+            match since {
+                None => {
+                    let iter = tx
+                        .store_tx
+                        .range_scan(&[], &[RESERVED_PREFIX])
+                        .chain([Ok((BACKUP_MARKER_KEY.to_vec(), backup_marker))]);
+                    sqlite_db.db.batch_put(Box::new(iter))?;
+                }
+                Some(since) => {
+                    if since > marker {
+                        bail!(UnknownMarker(since, marker))
+                    }
+                    let floor = read_marker(&*tx.store_tx, &LOG_FLOOR_KEY)?.unwrap_or(0);
+                    if since < floor {
+                        bail!(MarkerPruned(since, floor))
+                    }
+                    let mut points = BTreeSet::new();
+                    let lower = log_key(POINT_LOG_PREFIX, since + 1, &[]);
+                    let upper = log_key(POINT_LOG_PREFIX, marker + 1, &[]);
+                    for kv in tx.store_tx.range_scan(&lower, &upper) {
+                        let (k, _) = kv?;
+                        points.insert(k[10..].to_vec());
+                    }
+                    let mut ranges = BTreeMap::new();
+                    let lower = log_key(RANGE_LOG_PREFIX, since + 1, &[]);
+                    let upper = log_key(RANGE_LOG_PREFIX, marker + 1, &[]);
+                    for kv in tx.store_tx.range_scan(&lower, &upper) {
+                        let (k, v) = kv?;
+                        ranges.insert(prefixed_key(BACKUP_RANGE_TOMBSTONE_PREFIX, &k[10..]), v);
+                    }
+
+                    let mut puts = vec![];
+                    let mut tombstones = vec![];
+                    for key in points {
+                        match tx.store_tx.get(&key, false)? {
+                            Some(val) => puts.push((key, val)),
+                            None => tombstones
+                                .push((prefixed_key(BACKUP_TOMBSTONE_PREFIX, &key), vec![])),
+                        }
+                    }
+                    let iter = puts
+                        .into_iter()
+                        .chain([(BACKUP_MARKER_KEY.to_vec(), backup_marker)])
+                        .chain(tombstones)
+                        .chain(ranges)
+                        .map(Ok);
+                    sqlite_db.db.batch_put(Box::new(iter))?;
+                    // some engines block writes while a read transaction is open
+                    drop(tx);
+
+                    let mut tx = self.transact_write()?;
+                    let upper = log_key(POINT_LOG_PREFIX, since + 1, &[]);
+                    tx.store_tx
+                        .del_range_from_persisted(&POINT_LOG_PREFIX, &upper)?;
+                    let upper = log_key(RANGE_LOG_PREFIX, since + 1, &[]);
+                    tx.store_tx
+                        .del_range_from_persisted(&RANGE_LOG_PREFIX, &upper)?;
+                    tx.store_tx.put(&LOG_FLOOR_KEY, &since.to_be_bytes())?;
+                    tx.commit_tx()?;
+                }
+            }
+            Ok(marker)
+        }
+        #[cfg(not(feature = "storage-sqlite"))]
+        bail!("backup requires the 'storage-sqlite' feature to be enabled")
+    }
+
+    /// Restore from a backup made by [Db::backup_db_incremental].
+    ///
+    /// The full backup must be restored first, into an empty database, and then every backup
+    /// made since, in the order they were made. Backups applied out of order are rejected.
+    #[allow(unused_variables)]
+    pub fn restore_backup_incremental(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
+        #[cfg(feature = "storage-sqlite")]
+        {
+            let in_file = in_file.as_ref();
+            let sqlite_db = crate::new_cozo_sqlite(in_file)?;
+            let s_tx = sqlite_db.transact()?;
+            let backup_marker = read_backup_marker(&*s_tx.store_tx)?.ok_or(NotIncrementalBackup)?;
+            let since = match backup_marker.since {
+                None => {
+                    drop(s_tx);
+                    self.restore_backup(in_file)?;
+                    return self.load_last_ids();
+                }
+                Some(since) => since,
+            };
+
+            let mut tx = self.transact_write()?;
+            match read_backup_marker(&*tx.store_tx)? {
+                Some(BackupMarker { marker, .. }) if marker == since => {}
+                Some(BackupMarker { marker, .. }) => {
+                    bail!(BackupOutOfOrder(since, format!("marker {marker}")))
+                }
+                None => bail!(BackupOutOfOrder(since, "no backup".to_string())),
+            }
+            let upper = [RESERVED_PREFIX, BACKUP_RANGE_TOMBSTONE_PREFIX[1] + 1];
+            for kv in s_tx
+                .store_tx
+                .range_scan(&BACKUP_RANGE_TOMBSTONE_PREFIX, &upper)
+            {
+                let (k, v) = kv?;
+                tx.store_tx.del_range_from_persisted(&k[2..], &v)?;
+            }
+            let upper = [RESERVED_PREFIX, BACKUP_TOMBSTONE_PREFIX[1] + 1];
+            for kv in s_tx.store_tx.range_scan(&BACKUP_TOMBSTONE_PREFIX, &upper) {
+                let (k, _) = kv?;
+                tx.store_tx.del(&k[2..])?;
+            }
+            for kv in s_tx.store_tx.range_scan(&[], &[RESERVED_PREFIX]) {
+                let (k, v) = kv?;
+                tx.store_tx.put(&k, &v)?;
+            }
+            tx.store_tx.put(
+                &BACKUP_MARKER_KEY,
+                &rmp_serde::to_vec_named(&backup_marker).unwrap(),
+            )?;
+            tx.commit_tx()?;
+            drop(tx);
+            self.load_last_ids()
+        }
+        #[cfg(not(feature = "storage-sqlite"))]
+        bail!("backup requires the 'storage-sqlite' feature to be enabled")
+    }
+}
@@ -6,12 +6,27 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+#[cfg(any(feature = "export-parquet", feature = "arrow"))]
+pub(crate) mod arrow_conv;
 pub(crate) mod callback;
+pub(crate) mod cursor;
 pub(crate) mod db;
+pub(crate) mod explain;
+pub(crate) mod export;
+#[cfg(feature = "export-parquet")]
+pub(crate) mod export_parquet;
 pub(crate) mod imperative;
+pub(crate) mod incremental;
+pub(crate) mod outline;
+pub(crate) mod prepared;
 pub(crate) mod relation;
+#[cfg(feature = "arrow")]
+pub(crate) mod script_arrow;
+pub(crate) mod snapshot;
 pub(crate) mod temp_store;
 pub(crate) mod transact;
+pub(crate) mod transaction;
+pub(crate) mod verify;
 pub(crate) mod hnsw;
 pub(crate) mod minhash_lsh;
 #[cfg(test)]
@@ -0,0 +1,113 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::functions::current_validity;
+use crate::data::program::InputProgram;
+use crate::data::value::DataValue;
+use crate::parse::{parse_script, CozoScript, SourceSpan};
+use crate::{Db, Storage};
+
+/// The structure of a script, as returned by [Db::parse_script](crate::Db::parse_script).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedScript {
+    /// What kind of script it is
+    pub kind: ScriptKind,
+    /// The queries of the script, in the order they appear in it. This includes the queries
+    /// in all branches of imperative scripts and those wrapped in `::explain`.
+    pub programs: Vec<ParsedProgram>,
+}
+
+/// The kinds of scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+    /// A single query
+    Query,
+    /// An imperative script in curly braces, made of queries and control flow
+    Imperative,
+    /// A system op starting with `::`
+    System,
+}
+
+/// A query of a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedProgram {
+    /// Where the query is in the script, from its first rule or option to its last one
+    pub span: SourceSpan,
+    /// The query as understood by the parser, one rule or option per line.
+    /// Parameters are replaced by their values.
+    pub text: String,
+    /// The rules defined by the query, each with the span of its first definition
+    pub rules: Vec<(String, SourceSpan)>,
+    /// The stored relations read by the query
+    pub reads: Vec<String>,
+    /// The stored relation written to by the query, with the option making the write,
+    /// e.g. `:put`
+    pub writes: Option<(String, String)>,
+}
+
+impl From<InputProgram> for ParsedProgram {
+    fn from(prog: InputProgram) -> Self {
+        ParsedProgram {
+            span: prog.span,
+            text: prog.to_string(),
+            rules: prog
+                .prog
+                .iter()
+                .map(|(name, rules)| (name.name.to_string(), rules.first_span()))
+                .collect_vec(),
+            reads: prog
+                .stored_relations()
+                .into_iter()
+                .map(|name| name.to_string())
+                .collect_vec(),
+            writes: prog
+                .out_opts
+                .store_relation
+                .as_ref()
+                .map(|(handle, op, _)| (handle.name.name.to_string(), op.command().to_string())),
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Parse a script without running it, for tools inspecting scripts such as formatters
+    /// and linters. Parse errors are returned as when running the script.
+    ///
+    /// Unlike [Db::plan](crate::Db::plan), nothing is compiled, so the stored relations
+    /// used by the script need not exist.
+    pub fn parse_script(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<ParsedScript> {
+        let script = parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+        )?;
+        let kind = match &script {
+            CozoScript::Single(_) => ScriptKind::Query,
+            CozoScript::Imperative(_) => ScriptKind::Imperative,
+            CozoScript::Sys(_) => ScriptKind::System,
+        };
+        Ok(ParsedScript {
+            kind,
+            programs: script
+                .into_programs()
+                .into_iter()
+                .map(ParsedProgram::from)
+                .collect_vec(),
+        })
+    }
+}
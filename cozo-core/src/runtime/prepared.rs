@@ -0,0 +1,225 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::expr::ParamSlot;
+use crate::data::functions::current_validity;
+use crate::data::program::{MagicSymbol, QueryOutOptions};
+use crate::parse::parse_prepared_query;
+use crate::query::compile::CompiledProgram;
+use crate::runtime::db::{QueryReport, RunningScript};
+use crate::runtime::relation::RelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::{DataValue, Db, NamedRows, Storage, Symbol};
+
+/// A query that has been parsed and compiled once, and can be executed
+/// many times with different parameters.
+/// Created by [Db::prepare] and run by [Db::execute].
+pub struct PreparedQuery {
+    source: String,
+    params: Vec<String>,
+    relations: BTreeMap<SmartString<LazyCompact>, RelationHandle>,
+    warnings: Vec<String>,
+    // the parameters are bound in the slots of a compiled copy of the query for the duration
+    // of an execution, so every execution running at the same time needs its own copy
+    idle: Mutex<Vec<CompiledQuery>>,
+}
+
+/// A compiled copy of a prepared query, with its own parameter slots
+struct CompiledQuery {
+    compiled: Arc<[CompiledProgram]>,
+    store_lifetimes: BTreeMap<MagicSymbol, usize>,
+    entry_head: Vec<Symbol>,
+    out_opts: QueryOutOptions,
+    params: BTreeMap<String, ParamSlot>,
+}
+
+impl PreparedQuery {
+    /// The script this query was prepared from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+    /// Names of the parameters that must be supplied on execution
+    pub fn params(&self) -> Vec<&str> {
+        self.params.iter().map(|k| k.as_str()).collect_vec()
+    }
+}
+
+impl Debug for PreparedQuery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreparedQuery")
+            .field("source", &self.source)
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Prepared queries cannot mutate stored relations")]
+#[diagnostic(code(eval::prepare_mutation))]
+#[diagnostic(help("Run queries with `:put`, `:rm` etc. through `run_script`"))]
+struct PrepareMutationError;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Required parameter {0} not supplied to prepared query")]
+#[diagnostic(code(eval::prepared_param_not_found))]
+struct PreparedParamNotFoundError(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Prepared query is stale: stored relation '{0}' has changed since it was prepared")]
+#[diagnostic(code(eval::prepared_query_stale))]
+#[diagnostic(help("Prepare the query again"))]
+struct PreparedQueryStaleError(String);
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Parse and compile a query once, so that it can be run repeatedly
+    /// by [Db::execute] with different parameters.
+    ///
+    /// Only single read-only queries can be prepared. Parameters are left
+    /// unbound until execution, so they cannot be used where a constant
+    /// is required, for example in query options. The exceptions are `:limit`
+    /// and `:offset`, which are evaluated on each execution, so that a single
+    /// prepared query can return every page of a result.
+    /// The prepared query becomes stale when any stored relation it reads
+    /// is changed by a schema operation (including index creation or removal),
+    /// and executing it will then fail.
+    pub fn prepare(&'s self, payload: &str) -> Result<PreparedQuery> {
+        let mut tx = self.transact()?;
+        let (query, stored_relations, warnings) = self.compile_prepared(&mut tx, payload)?;
+        let mut relations = BTreeMap::new();
+        for name in stored_relations {
+            let handle = tx.get_relation(&name, false)?;
+            relations.insert(name, handle);
+        }
+
+        Ok(PreparedQuery {
+            source: payload.to_string(),
+            params: query.params.keys().cloned().collect(),
+            relations,
+            warnings,
+            idle: Mutex::new(vec![query]),
+        })
+    }
+    /// Compile a copy of a prepared query, also returning the stored relations it reads
+    /// and its warnings
+    #[allow(clippy::type_complexity)]
+    fn compile_prepared(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        payload: &str,
+    ) -> Result<(
+        CompiledQuery,
+        BTreeSet<SmartString<LazyCompact>>,
+        Vec<String>,
+    )> {
+        let (input_program, params) = parse_prepared_query(
+            payload,
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+        )?;
+        if input_program.out_opts.store_relation.is_some() {
+            bail!(PrepareMutationError)
+        }
+
+        let stored_relations = input_program.stored_relations();
+        let warnings = input_program.unused_rule_warnings();
+        let entry_head = input_program.get_entry_out_head_or_default()?;
+        let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
+        let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
+        let program = stratified_program.magic_sets_rewrite(tx)?;
+        let compiled = tx.stratified_magic_compile(program)?.into();
+        let query = CompiledQuery {
+            compiled,
+            store_lifetimes,
+            entry_head,
+            out_opts,
+            params,
+        };
+        Ok((query, stored_relations, warnings))
+    }
+    /// Execute a query created by [Db::prepare]. The `params` argument is a map of parameters,
+    /// which must contain every parameter the query references.
+    ///
+    /// Executions of the same prepared query may run at the same time: an execution that
+    /// finds every compiled copy of the query in use compiles another one.
+    pub fn execute(
+        &'s self,
+        prepared: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        Ok(self.execute_with_report(prepared, params)?.0)
+    }
+    /// Execute a query created by [Db::prepare] like [Db::execute], also returning what the
+    /// query reports about its result besides the rows, such as warnings.
+    pub fn execute_with_report(
+        &'s self,
+        prepared: &PreparedQuery,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<(NamedRows, QueryReport)> {
+        let _admitted = self.admit_script()?;
+        for name in &prepared.params {
+            if !params.contains_key(name) {
+                bail!(PreparedParamNotFoundError(name.to_string()))
+            }
+        }
+
+        let mut tx = self.transact()?;
+        tx.script = Some(RunningScript::new(&prepared.source, &params));
+        let report = Arc::new(Mutex::new(QueryReport::default()));
+        tx.report = Some(report.clone());
+        for (name, handle) in &prepared.relations {
+            match tx.get_relation(name, false) {
+                Ok(current) if current == *handle => {}
+                _ => bail!(PreparedQueryStaleError(name.to_string())),
+            }
+        }
+
+        let idle = prepared.idle.lock().unwrap().pop();
+        let query = match idle {
+            Some(query) => query,
+            None => self.compile_prepared(&mut tx, &prepared.source)?.0,
+        };
+        for (name, slot) in &query.params {
+            slot.set(params.get(name).cloned());
+        }
+        let res = query.out_opts.resolve_params().and_then(|out_opts| {
+            self.run_compiled_query(
+                &mut tx,
+                &query.compiled,
+                query.store_lifetimes.clone(),
+                &query.entry_head,
+                &out_opts,
+                current_validity(),
+                &Default::default(),
+                &mut Default::default(),
+                true,
+            )
+        });
+        for slot in query.params.values() {
+            slot.set(None);
+        }
+        prepared.idle.lock().unwrap().push(query);
+
+        let (rows, cleanups) = res?;
+        for (lower, upper) in cleanups {
+            tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+        }
+        tx.commit_tx()?;
+        let mut report = std::mem::take(&mut *report.lock().unwrap());
+        report.warnings = prepared.warnings.clone();
+        Ok((rows, report))
+    }
+}
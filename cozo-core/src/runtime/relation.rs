@@ -19,15 +19,19 @@ use serde::Serialize;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::expr::{Bytecode, Expr};
+use crate::data::functions::{OP_ADD, OP_NOW};
 use crate::data::memcmp::MemCmpEncoder;
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN};
-use crate::data::value::{DataValue, ValidityTs};
+use crate::data::value::{DataValue, ValidityTs, LARGEST_UTF_CHAR};
 use crate::fts::FtsIndexManifest;
 use crate::parse::expr::build_expr;
-use crate::parse::sys::{FtsIndexConfig, HnswIndexConfig, MinHashLshConfig};
-use crate::parse::{CozoScriptParser, Rule, SourceSpan};
+use crate::parse::sys::{
+    FtsIndexConfig, GuardedTrigger, HnswIndexConfig, MinHashLshConfig, RelationSetOp,
+};
+use crate::parse::{parse_expressions, CozoScriptParser, Rule, SourceSpan};
 use crate::query::compile::IndexPositionUse;
 use crate::runtime::hnsw::HnswIndexManifest;
 use crate::runtime::minhash_lsh::{HashPermutations, LshParams, MinHashLshIndexManifest, Weights};
@@ -71,6 +75,59 @@ impl RelationId {
     }
 }
 
+/// Version of the layout in which tuples of newly created relations are encoded.
+///
+/// Version 0 marks relations created before the version was recorded; their layout
+/// is the same as version 1. When the encoding of some type changes, bump this and
+/// teach [RelationHandle::decode_kv] to read the previous layouts, so that old data
+/// can still be read, and rewritten with `::migrate_encoding`.
+pub(crate) const LATEST_ENCODING_VERSION: u8 = 1;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' is encoded with version {1}, which is newer than the supported version {LATEST_ENCODING_VERSION}")]
+#[diagnostic(code(tx::unsupported_encoding_version))]
+#[diagnostic(help("The relation was written by a newer version of the database"))]
+pub(crate) struct UnsupportedEncodingVersion(pub(crate) String, pub(crate) u8);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation '{0}' has no time to live")]
+#[diagnostic(code(tx::ttl_not_set))]
+#[diagnostic(help("Declare one when creating the relation with the `:ttl` option"))]
+pub(crate) struct TtlNotSet(pub(crate) String);
+
+/// The hidden non-key column holding the expiry of the rows of relations created with `:ttl`,
+/// in seconds since the epoch as returned by `now()`. Users cannot declare or put it, and it
+/// is left out of `::columns` and of positional access to the relation.
+pub(crate) const TTL_COLUMN: &str = "_expires_at";
+
+/// The expiry column of a relation whose rows expire `seconds` after they are put.
+/// Its default is the only way it gets a value, see [TTL_COLUMN].
+pub(crate) fn ttl_column(seconds: f64) -> ColumnDef {
+    ColumnDef {
+        name: SmartString::from(TTL_COLUMN),
+        typing: NullableColType {
+            coltype: ColType::Float,
+            nullable: false,
+        },
+        default_gen: Some(Expr::Apply {
+            op: &OP_ADD,
+            args: [
+                Expr::Apply {
+                    op: &OP_NOW,
+                    args: [].into(),
+                    span: Default::default(),
+                },
+                Expr::Const {
+                    val: DataValue::from(seconds),
+                    span: Default::default(),
+                },
+            ]
+            .into(),
+            span: Default::default(),
+        }),
+    }
+}
+
 #[derive(Clone, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
 pub(crate) struct RelationHandle {
     pub(crate) name: SmartString<LazyCompact>,
@@ -90,6 +147,16 @@ pub(crate) struct RelationHandle {
         (RelationHandle, RelationHandle, MinHashLshIndexManifest),
     >,
     pub(crate) description: SmartString<LazyCompact>,
+    #[serde(default)]
+    pub(crate) encoding_version: u8,
+    /// Guards of the put triggers as written in `::set_triggers`, by position.
+    /// Triggers past the end have no guard.
+    #[serde(default)]
+    pub(crate) put_trigger_guards: Vec<Option<String>>,
+    /// Guards of the rm triggers as written in `::set_triggers`, by position.
+    /// Triggers past the end have no guard.
+    #[serde(default)]
+    pub(crate) rm_trigger_guards: Vec<Option<String>>,
 }
 
 impl RelationHandle {
@@ -99,6 +166,47 @@ impl RelationHandle {
             || self.fts_indices.contains_key(index_name)
             || self.lsh_indices.contains_key(index_name)
     }
+    /// The handles of all relations holding index data for this relation
+    fn index_handles_mut(&mut self) -> impl Iterator<Item = &mut RelationHandle> {
+        self.indices
+            .values_mut()
+            .map(|(h, _)| h)
+            .chain(self.hnsw_indices.values_mut().map(|(h, _)| h))
+            .chain(self.fts_indices.values_mut().map(|(h, _)| h))
+            .chain(
+                self.lsh_indices
+                    .values_mut()
+                    .flat_map(|(inv, idx, _)| [inv, idx]),
+            )
+    }
+    /// Give the relation and its indices new names starting with `prefix`, and new ids,
+    /// for copying them into another database. Triggers are dropped,
+    /// since their scripts refer to the original names.
+    #[cfg(feature = "storage-sqlite")]
+    fn rename_for_restore(&mut self, prefix: &str, ids: &BTreeMap<RelationId, RelationId>) {
+        self.name = SmartString::from(format!("{prefix}{}", self.name));
+        self.id = ids[&self.id];
+        self.put_triggers.clear();
+        self.rm_triggers.clear();
+        self.replace_triggers.clear();
+        self.put_trigger_guards.clear();
+        self.rm_trigger_guards.clear();
+        for (_, manifest) in self.hnsw_indices.values_mut() {
+            manifest.base_relation =
+                SmartString::from(format!("{prefix}{}", manifest.base_relation));
+        }
+        for (_, manifest) in self.fts_indices.values_mut() {
+            manifest.base_relation =
+                SmartString::from(format!("{prefix}{}", manifest.base_relation));
+        }
+        for (_, _, manifest) in self.lsh_indices.values_mut() {
+            manifest.base_relation =
+                SmartString::from(format!("{prefix}{}", manifest.base_relation));
+        }
+        for idx_handle in self.index_handles_mut() {
+            idx_handle.rename_for_restore(prefix, ids);
+        }
+    }
     pub(crate) fn has_no_index(&self) -> bool {
         self.indices.is_empty()
             && self.hnsw_indices.is_empty()
@@ -119,10 +227,16 @@ impl RelationHandle {
     Ord,
     PartialOrd,
 )]
-pub(crate) enum AccessLevel {
+/// Access level of a stored relation, as set by `::access_level`.
+/// Each level allows everything the levels before it allow.
+pub enum AccessLevel {
+    /// The relation cannot be read or written, and does not show up in `::relations`
     Hidden,
+    /// The relation can be read but not written
     ReadOnly,
+    /// The relation can be read and written, but not removed or replaced
     Protected,
+    /// No restrictions
     #[default]
     Normal,
 }
@@ -138,6 +252,39 @@ impl Display for AccessLevel {
     }
 }
 
+/// The schema of a stored relation, returned by [Db::describe_relation](crate::Db::describe_relation).
+/// It holds the same information as `::columns`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelationSchema {
+    /// The key columns, in order
+    pub keys: Vec<ColumnSchema>,
+    /// The non-key columns, in order
+    pub non_keys: Vec<ColumnSchema>,
+    /// The access level of the relation
+    pub access_level: AccessLevel,
+}
+
+/// A column of a [RelationSchema]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnSchema {
+    /// Name of the column
+    pub name: String,
+    /// Type of the column as written in a schema, e.g. `Int?` or `[String; 3]`
+    pub typing: String,
+    /// Whether the column has a default value
+    pub has_default: bool,
+}
+
+impl From<&ColumnDef> for ColumnSchema {
+    fn from(col: &ColumnDef) -> Self {
+        Self {
+            name: col.name.to_string(),
+            typing: col.typing.to_string(),
+            has_default: col.default_gen.is_some(),
+        }
+    }
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Arity mismatch for stored relation {name}: expect {expect_arity}, got {actual_arity}")]
 #[diagnostic(code(eval::stored_rel_arity_mismatch))]
@@ -163,6 +310,58 @@ impl RelationHandle {
         }
         ret
     }
+    /// Names of the columns in stored order: keys first, then non-keys.
+    /// Whole-relation reads return columns in this order.
+    pub(crate) fn column_names(&self) -> Vec<String> {
+        self.metadata
+            .keys
+            .iter()
+            .chain(self.metadata.non_keys.iter())
+            .map(|col| col.name.to_string())
+            .collect_vec()
+    }
+    /// Compile the guard of a trigger, an expression over the columns of the relation.
+    /// Only the source of guards is stored, so they are compiled again whenever
+    /// their triggers run.
+    pub(crate) fn compile_trigger_guard(&self, guard: &str) -> Result<Vec<Bytecode>> {
+        let mut expr = parse_expressions(guard, &Default::default())?;
+        expr.partial_eval()?;
+        expr.fill_binding_indices(&self.raw_binding_map())?;
+        expr.compile()
+    }
+    /// Check that the guards still compile after the columns of the relation changed,
+    /// as when a relation with triggers is replaced
+    pub(crate) fn check_trigger_guards(&self) -> Result<()> {
+        for guard in self
+            .put_trigger_guards
+            .iter()
+            .chain(self.rm_trigger_guards.iter())
+            .flatten()
+        {
+            self.compile_trigger_guard(guard)?;
+        }
+        Ok(())
+    }
+    /// The arguments of a positional application of the relation, with one for the hidden
+    /// [TTL_COLUMN] made by `gen_symb` if it was left out
+    pub(crate) fn positional_args(
+        &self,
+        args: &[Symbol],
+        gen_symb: impl FnOnce() -> Symbol,
+    ) -> Vec<Symbol> {
+        let mut args = args.to_vec();
+        if self.has_ttl() && args.len() + 1 == self.arity() {
+            args.push(gen_symb());
+        }
+        args
+    }
+    /// Whether the relation was created with `:ttl`, and so has the hidden [TTL_COLUMN]
+    pub(crate) fn has_ttl(&self) -> bool {
+        self.metadata
+            .non_keys
+            .last()
+            .is_some_and(|col| col.name == TTL_COLUMN)
+    }
     pub(crate) fn has_triggers(&self) -> bool {
         !self.put_triggers.is_empty() || !self.rm_triggers.is_empty()
     }
@@ -174,19 +373,7 @@ impl RelationHandle {
     }
     pub(crate) fn as_named_rows(&self, tx: &SessionTx<'_>) -> Result<NamedRows> {
         let rows: Vec<_> = self.scan_all(tx).try_collect()?;
-        let mut headers = self
-            .metadata
-            .keys
-            .iter()
-            .map(|col| col.name.to_string())
-            .collect_vec();
-        headers.extend(
-            self.metadata
-                .non_keys
-                .iter()
-                .map(|col| col.name.to_string()),
-        );
-        Ok(NamedRows::new(headers, rows))
+        Ok(NamedRows::new(self.column_names(), rows))
     }
     #[allow(dead_code)]
     pub(crate) fn amend_key_prefix(&self, data: &mut [u8]) {
@@ -326,6 +513,16 @@ pub(crate) struct InputRelationHandle {
     pub(crate) span: SourceSpan,
 }
 
+impl InputRelationHandle {
+    /// Move all non-key columns into the keys, which is how every op other than the
+    /// creating ones binds its columns against an existing relation.
+    pub(crate) fn with_flattened_keys(mut self) -> Self {
+        self.key_bindings.append(&mut self.dep_bindings);
+        self.metadata.keys.append(&mut self.metadata.non_keys);
+        self
+    }
+}
+
 impl Debug for RelationHandle {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Relation<{}>", self.name)
@@ -354,6 +551,19 @@ impl RelationHandle {
             RelationDeserError
         })?)
     }
+    /// Decode a stored key-value pair into the key and the value part of the tuple,
+    /// according to the encoding version of the relation.
+    pub(crate) fn decode_kv(&self, key: &[u8], val: &[u8]) -> Result<(Tuple, Tuple)> {
+        match self.encoding_version {
+            0 | 1 => {
+                let key_part = decode_tuple_from_key(key, self.metadata.keys.len());
+                let mut val_part = vec![];
+                extend_tuple_from_v(&mut val_part, val);
+                Ok((key_part, val_part))
+            }
+            v => bail!(UnsupportedEncodingVersion(self.name.to_string(), v)),
+        }
+    }
     pub(crate) fn scan_all<'a>(
         &self,
         tx: &'a SessionTx<'_>,
@@ -553,8 +763,8 @@ impl<'a> SessionTx<'a> {
     pub(crate) fn set_relation_triggers(
         &mut self,
         name: &Symbol,
-        puts: &[String],
-        rms: &[String],
+        puts: &[GuardedTrigger],
+        rms: &[GuardedTrigger],
         replaces: &[String],
     ) -> Result<()> {
         if name.name.starts_with('_') {
@@ -568,8 +778,13 @@ impl<'a> SessionTx<'a> {
                 original.access_level
             ))
         }
-        original.put_triggers = puts.to_vec();
-        original.rm_triggers = rms.to_vec();
+        let guards =
+            |triggers: &[GuardedTrigger]| triggers.iter().map(|(_, guard)| guard.clone()).collect();
+        original.put_trigger_guards = guards(puts);
+        original.rm_trigger_guards = guards(rms);
+        original.check_trigger_guards()?;
+        original.put_triggers = puts.iter().map(|(script, _)| script.clone()).collect();
+        original.rm_triggers = rms.iter().map(|(script, _)| script.clone()).collect();
         original.replace_triggers = replaces.to_vec();
 
         let name_key =
@@ -583,6 +798,15 @@ impl<'a> SessionTx<'a> {
 
         Ok(())
     }
+    /// Take the next id for a stored relation. The counter key is locked first, so that the
+    /// in-memory counter only moves while this transaction holds the lock, and
+    /// [Self::reclaim_relation_ids] sees every id handed out.
+    fn next_relation_id(&mut self) -> Result<RelationId> {
+        let counter_key = vec![DataValue::Null].encode_as_key(RelationId::SYSTEM);
+        self.store_tx.get(&counter_key, true)?;
+        let last_id = self.relation_store_id.fetch_add(1, Ordering::SeqCst);
+        Ok(RelationId::new(last_id + 1))
+    }
     pub(crate) fn create_relation(
         &mut self,
         input_meta: InputRelationHandle,
@@ -601,14 +825,14 @@ impl<'a> SessionTx<'a> {
         }
 
         let metadata = input_meta.metadata.clone();
-        let last_id = if is_temp {
-            self.temp_store_id.fetch_add(1, Ordering::Relaxed) as u64
+        let id = if is_temp {
+            RelationId::new(self.temp_store_id.fetch_add(1, Ordering::Relaxed) as u64 + 1)
         } else {
-            self.relation_store_id.fetch_add(1, Ordering::SeqCst)
+            self.next_relation_id()?
         };
         let meta = RelationHandle {
             name: input_meta.name.name,
-            id: RelationId::new(last_id + 1),
+            id,
             metadata,
             put_triggers: vec![],
             rm_triggers: vec![],
@@ -620,6 +844,9 @@ impl<'a> SessionTx<'a> {
             fts_indices: Default::default(),
             lsh_indices: Default::default(),
             description: Default::default(),
+            encoding_version: LATEST_ENCODING_VERSION,
+            put_trigger_guards: vec![],
+            rm_trigger_guards: vec![],
         };
 
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -660,6 +887,10 @@ impl<'a> SessionTx<'a> {
                 .ok_or_else(|| StoredRelationNotFoundError(name.to_string()))?
         };
         let metadata = RelationHandle::decode(&found)?;
+        ensure!(
+            metadata.encoding_version <= LATEST_ENCODING_VERSION,
+            UnsupportedEncodingVersion(name.to_string(), metadata.encoding_version)
+        );
         Ok(metadata)
     }
     pub(crate) fn describe_relation(&mut self, name: &str, description: &str) -> Result<()> {
@@ -722,6 +953,39 @@ impl<'a> SessionTx<'a> {
         to_clean.push((lower_bound, upper_bound));
         Ok(to_clean)
     }
+    /// Delete every row of the relation within the transaction, leaving its metadata untouched.
+    /// Returns the number of rows deleted.
+    pub(crate) fn clear_relation_rows(&mut self, handle: &RelationHandle) -> Result<usize> {
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let keys = if handle.is_temp {
+            self.temp_store_tx
+                .range_scan(&lower, &upper)
+                .map_ok(|(k, _)| k)
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            self.store_tx
+                .range_scan(&lower, &upper)
+                .map_ok(|(k, _)| k)
+                .collect::<Result<Vec<_>>>()?
+        };
+        for key in &keys {
+            if handle.is_temp {
+                self.temp_store_tx.del(key)?;
+            } else {
+                self.store_tx.del(key)?;
+            }
+        }
+        Ok(keys.len())
+    }
+    /// Give a newly created stored relation its initial access level.
+    /// Temp relations are always left at [AccessLevel::Normal].
+    pub(crate) fn init_access_level(&mut self, rel: &Symbol, level: AccessLevel) -> Result<()> {
+        if level != AccessLevel::Normal && !rel.is_temp_store_name() {
+            self.set_access_level(rel, level)?;
+        }
+        Ok(())
+    }
     pub(crate) fn set_access_level(&mut self, rel: &Symbol, level: AccessLevel) -> Result<()> {
         let mut meta = self.get_relation(rel, true)?;
         meta.access_level = level;
@@ -736,6 +1000,33 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
+    /// Change the time to live of a relation created with `:ttl`.
+    /// Only rows put from now on get the new expiry.
+    pub(crate) fn set_relation_ttl(&mut self, rel: &Symbol, seconds: f64) -> Result<()> {
+        let mut meta = self.get_relation(rel, true)?;
+        // expiry removes rows, so only relations that allow everything may have a time to live
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "setting time to live".to_string(),
+                meta.access_level
+            ))
+        }
+        if !meta.has_ttl() {
+            bail!(TtlNotSet(meta.name.to_string()))
+        }
+        *meta.metadata.non_keys.last_mut().unwrap() = ttl_column(seconds);
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+
     pub(crate) fn create_minhash_lsh_index(&mut self, config: &MinHashLshConfig) -> Result<()> {
         // Get relation handle
         let mut rel_handle = self.get_relation(&config.base_relation, true)?;
@@ -1409,9 +1700,6 @@ impl<'a> SessionTx<'a> {
     }
 
     pub(crate) fn rename_relation(&mut self, old: &Symbol, new: &Symbol) -> Result<()> {
-        if old.name.starts_with('_') || new.name.starts_with('_') {
-            bail!("Bad name given");
-        }
         let new_key = DataValue::Str(new.name.clone());
         let new_encoded = vec![new_key].encode_as_key(RelationId::SYSTEM);
 
@@ -1419,10 +1707,36 @@ impl<'a> SessionTx<'a> {
             bail!(RelNameConflictError(new.name.to_string()))
         };
 
+        let mut rel = self.relation_to_rename(old, new)?;
+        rel.name = new.name.clone();
+
         let old_key = DataValue::Str(old.name.clone());
         let old_encoded = vec![old_key].encode_as_key(RelationId::SYSTEM);
+        self.store_tx.del(&old_encoded)?;
+        self.put_relation_meta(&rel)?;
 
-        let mut rel = self.get_relation(old, true)?;
+        Ok(())
+    }
+    /// Rename relations whose new names are taken by each other, such as `a -> b, b -> a`.
+    /// Done one after another, each rename would collide with the relation renamed next.
+    pub(crate) fn rename_relation_cycle(&mut self, cycle: &[&(Symbol, Symbol)]) -> Result<()> {
+        let mut renamed = vec![];
+        for (old, new) in cycle {
+            let mut rel = self.relation_to_rename(old, new)?;
+            rel.name = new.name.clone();
+            renamed.push(rel);
+        }
+        // every old name is also a new name, so all entries are overwritten
+        for rel in renamed {
+            self.put_relation_meta(&rel)?;
+        }
+        Ok(())
+    }
+    fn relation_to_rename(&mut self, old: &Symbol, new: &Symbol) -> Result<RelationHandle> {
+        if old.name.starts_with('_') || new.name.starts_with('_') {
+            bail!("Bad name given");
+        }
+        let rel = self.get_relation(old, true)?;
         if rel.access_level < AccessLevel::Normal {
             bail!(InsufficientAccessLevel(
                 rel.name.to_string(),
@@ -1430,14 +1744,375 @@ impl<'a> SessionTx<'a> {
                 rel.access_level
             ));
         }
-        rel.name = new.name.clone();
+        Ok(rel)
+    }
+    /// Copy every stored relation of `src`, including index relations, into this transaction
+    /// under new ids, with `prefix` prepended to their names.
+    /// Fails if any of the new names is already taken. Returns the number of relations copied.
+    #[cfg(feature = "storage-sqlite")]
+    pub(crate) fn copy_relations_prefixed(
+        &mut self,
+        src: &SessionTx<'_>,
+        prefix: &str,
+    ) -> Result<usize> {
+        let src_rels = src.live_relation_ids()?;
+        let mut ids = BTreeMap::new();
+        for (id, name) in &src_rels {
+            let new_name = format!("{prefix}{name}");
+            let encoded =
+                vec![DataValue::from(new_name.as_str())].encode_as_key(RelationId::SYSTEM);
+            if self.store_tx.exists(&encoded, true)? {
+                bail!(RelNameConflictError(new_name))
+            }
+            ids.insert(*id, self.next_relation_id()?);
+        }
+        if let Some(last_id) = ids.values().max() {
+            let t_encoded = vec![DataValue::Null].encode_as_key(RelationId::SYSTEM);
+            self.store_tx.put(&t_encoded, &last_id.raw_encode())?;
+        }
+        for (id, name) in &src_rels {
+            let mut handle = src.get_relation(name, false)?;
+            handle.rename_for_restore(prefix, &ids);
+            self.put_relation_meta(&handle)?;
+            let lower = Tuple::default().encode_as_key(*id);
+            let upper = Tuple::default().encode_as_key(id.next());
+            for kv in src.store_tx.range_scan(&lower, &upper) {
+                let (mut k, mut v) = kv?;
+                handle.amend_key_prefix(&mut k);
+                if !v.is_empty() {
+                    handle.amend_key_prefix(&mut v);
+                }
+                self.store_tx.put(&k, &v)?;
+            }
+        }
+        Ok(src_rels.len())
+    }
+    /// For a relation holding a regular index, the positions of its columns rearranged
+    /// into the declaration order of the indexed relation.
+    /// `None` if the stored order is already the declaration order.
+    pub(crate) fn declared_column_order(
+        &self,
+        handle: &RelationHandle,
+    ) -> Result<Option<Vec<usize>>> {
+        let Some((base_name, idx_name)) = handle.name.split_once(':') else {
+            return Ok(None);
+        };
+        let base = self.get_relation(base_name, false)?;
+        Ok(base.indices.get(idx_name).map(|(_, extractor)| {
+            (0..extractor.len())
+                .sorted_by_key(|i| extractor[*i])
+                .collect_vec()
+        }))
+    }
+    /// Ids of all live stored relations (including index relations), ordered by id.
+    pub(crate) fn live_relation_ids(&self) -> Result<Vec<(RelationId, SmartString<LazyCompact>)>> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let mut ret = vec![];
+        for kv_res in self.store_tx.range_scan(&lower, &upper) {
+            let (_, v_slice) = kv_res?;
+            let meta = RelationHandle::decode(&v_slice)?;
+            ret.push((meta.id, meta.name));
+        }
+        ret.sort();
+        Ok(ret)
+    }
+    /// Lower the stored relation id counter to the largest id used by a live relation,
+    /// so that the ids of removed relations above it are handed out again.
+    /// Returns the old and the new high-water mark.
+    ///
+    /// Only the stored counter is lowered: the caller lowers the in-memory one
+    /// once this transaction is committed.
+    pub(crate) fn reclaim_relation_ids(&mut self) -> Result<(u64, u64)> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot reclaim relation ids: data still exists for removed relation id {0}")]
+        #[diagnostic(code(eval::reclaim_ids_data_exists))]
+        struct DataExistsForRemovedRelation(u64);
+
+        // locking the counter key serializes us with transactions creating relations
+        let counter_key = vec![DataValue::Null].encode_as_key(RelationId::SYSTEM);
+        self.store_tx.get(&counter_key, true)?;
+        let current = self.relation_store_id.load(Ordering::SeqCst);
+        let max_live = self
+            .live_relation_ids()?
+            .last()
+            .map(|(id, _)| id.0)
+            .unwrap_or(0);
+        if max_live >= current {
+            return Ok((current, current));
+        }
+        let lower = Tuple::default().encode_as_key(RelationId::new(max_live + 1));
+        let upper = Tuple::default().encode_as_key(RelationId::new(current + 1));
+        if let Some(kv) = self.store_tx.range_scan(&lower, &upper).next() {
+            let (k, _) = kv?;
+            bail!(DataExistsForRemovedRelation(RelationId::raw_decode(&k).0));
+        }
+        self.store_tx
+            .put(&counter_key, &RelationId::new(max_live).raw_encode())?;
+        Ok((current, max_live))
+    }
+    /// Delete the rows left behind under relation ids that no live relation uses,
+    /// e.g. by a removal whose cleanup was interrupted. Returns the key ranges that had rows
+    /// in them, with the number of rows and of bytes deleted.
+    pub(crate) fn delete_orphaned_rows(
+        &mut self,
+    ) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, usize, usize)> {
+        // locking the counter key serializes us with transactions creating relations
+        let counter_key = vec![DataValue::Null].encode_as_key(RelationId::SYSTEM);
+        self.store_tx.get(&counter_key, true)?;
+        let current = self.relation_store_id.load(Ordering::SeqCst);
+
+        let mut gaps = vec![];
+        let mut prev = 0;
+        for (id, _) in self.live_relation_ids()? {
+            if id.0 > prev + 1 {
+                gaps.push((prev + 1, id.0));
+            }
+            prev = prev.max(id.0);
+        }
+        if current > prev {
+            gaps.push((prev + 1, current + 1));
+        }
 
+        let mut ranges = vec![];
+        let mut n_rows = 0;
+        let mut n_bytes = 0;
+        for (start, end) in gaps {
+            let lower = Tuple::default().encode_as_key(RelationId::new(start));
+            let upper = Tuple::default().encode_as_key(RelationId::new(end));
+            let mut found = false;
+            for kv in self.store_tx.range_scan(&lower, &upper) {
+                let (k, v) = kv?;
+                found = true;
+                n_rows += 1;
+                n_bytes += k.len() + v.len();
+            }
+            if found {
+                self.store_tx.del_range_from_persisted(&lower, &upper)?;
+                ranges.push((lower, upper));
+            }
+        }
+        Ok((ranges, n_rows, n_bytes))
+    }
+    /// Rewrite the stored relation and its indices in the latest encoding version.
+    /// Returns the name, the old version and the number of rewritten rows for each of them.
+    pub(crate) fn migrate_relation_encoding(
+        &mut self,
+        name: &str,
+    ) -> Result<Vec<(SmartString<LazyCompact>, u8, usize)>> {
+        let mut handle = self.get_relation(name, true)?;
+        if handle.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "encoding migration".to_string(),
+                handle.access_level
+            ))
+        }
+        let mut ret = vec![];
+        let mut changed = false;
+        for idx_handle in handle.index_handles_mut() {
+            // index relations are also stored under their own names
+            let mut stored_idx = self.get_relation(&idx_handle.name, true)?;
+            let old_version = stored_idx.encoding_version;
+            if old_version != LATEST_ENCODING_VERSION {
+                let n_rows = self.reencode_relation(&mut stored_idx)?;
+                self.put_relation_meta(&stored_idx)?;
+                idx_handle.encoding_version = LATEST_ENCODING_VERSION;
+                changed = true;
+                ret.push((stored_idx.name, old_version, n_rows));
+            } else {
+                ret.push((stored_idx.name, old_version, 0));
+            }
+        }
+        let old_version = handle.encoding_version;
+        let mut n_rows = 0;
+        if old_version != LATEST_ENCODING_VERSION {
+            n_rows = self.reencode_relation(&mut handle)?;
+            changed = true;
+        }
+        if changed {
+            self.put_relation_meta(&handle)?;
+        }
+        ret.push((handle.name, old_version, n_rows));
+        Ok(ret)
+    }
+    pub(crate) fn put_relation_meta(&mut self, handle: &RelationHandle) -> Result<()> {
+        let name_key = vec![DataValue::Str(handle.name.clone())].encode_as_key(RelationId::SYSTEM);
         let mut meta_val = vec![];
-        rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
-        self.store_tx.del(&old_encoded)?;
-        self.store_tx.put(&new_encoded, &meta_val)?;
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)
+    }
+    fn reencode_relation(&mut self, handle: &mut RelationHandle) -> Result<usize> {
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        let mut rows = vec![];
+        for kv in self.store_tx.range_scan(&lower, &upper) {
+            let (k, v) = kv?;
+            let (key_part, val_part) = handle.decode_kv(&k, &v)?;
+            rows.push((k, key_part, v.is_empty(), val_part));
+        }
+        let n_rows = rows.len();
+        handle.encoding_version = LATEST_ENCODING_VERSION;
+        for (old_key, key_part, no_val, val_part) in rows {
+            let key = handle.encode_partial_key_for_store(&key_part);
+            let val = if no_val {
+                vec![]
+            } else {
+                handle.encode_val_only_for_store(&val_part, Default::default())?
+            };
+            if key != old_key {
+                self.store_tx.del(&old_key)?;
+            }
+            self.store_tx.put(&key, &val)?;
+        }
+        Ok(n_rows)
+    }
+    /// Create the relation `out` holding the result of a set operation between two relations
+    /// with the same columns. Both relations are walked in key order and merged.
+    /// Returns the number of rows in the new relation.
+    pub(crate) fn relation_set_op(
+        &mut self,
+        op: RelationSetOp,
+        left: &Symbol,
+        right: &Symbol,
+        out: &Symbol,
+    ) -> Result<usize> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Relations '{0}' and '{1}' do not have the same columns")]
+        #[diagnostic(code(eval::set_op_incompatible))]
+        #[diagnostic(help(
+            "Both relations must have the same key and value columns, with the same types"
+        ))]
+        struct IncompatibleRelations(String, String, #[label] SourceSpan);
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot take union of '{0}' and '{1}': rows with key {2:?} differ")]
+        #[diagnostic(code(eval::set_op_key_conflict))]
+        struct UnionKeyConflict(String, String, Vec<DataValue>);
+
+        if self.relation_exists(out)? {
+            bail!(RelNameConflictError(out.name.to_string()))
+        }
+        let left_handle = self.get_relation(left, false)?;
+        let right_handle = self.get_relation(right, false)?;
+        for handle in [&left_handle, &right_handle] {
+            if handle.access_level < AccessLevel::ReadOnly {
+                bail!(InsufficientAccessLevel(
+                    handle.name.to_string(),
+                    "reading".to_string(),
+                    handle.access_level
+                ));
+            }
+        }
+        let same_cols = |a: &[ColumnDef], b: &[ColumnDef]| {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(x, y)| x.name == y.name && x.typing == y.typing)
+        };
+        ensure!(
+            same_cols(&left_handle.metadata.keys, &right_handle.metadata.keys)
+                && same_cols(
+                    &left_handle.metadata.non_keys,
+                    &right_handle.metadata.non_keys
+                ),
+            IncompatibleRelations(left.name.to_string(), right.name.to_string(), right.span)
+        );
 
-        Ok(())
+        let key_len = left_handle.metadata.keys.len();
+        let mut collected = TempCollector::default();
+        let mut n_rows = 0;
+        {
+            let mut push = |tuple: Tuple| {
+                n_rows += 1;
+                collected.push(tuple);
+            };
+            let mut left_iter = left_handle.scan_all(self);
+            let mut right_iter = right_handle.scan_all(self);
+            let mut left_cur = left_iter.next().transpose()?;
+            let mut right_cur = right_iter.next().transpose()?;
+            loop {
+                match (left_cur.take(), right_cur.take()) {
+                    (None, None) => break,
+                    (Some(l), None) => {
+                        if op != RelationSetOp::Intersect {
+                            push(l);
+                        }
+                        left_cur = left_iter.next().transpose()?;
+                    }
+                    (None, Some(r)) => {
+                        if op == RelationSetOp::Union {
+                            push(r);
+                        }
+                        right_cur = right_iter.next().transpose()?;
+                    }
+                    (Some(l), Some(r)) => match l[..key_len].cmp(&r[..key_len]) {
+                        std::cmp::Ordering::Less => {
+                            if op != RelationSetOp::Intersect {
+                                push(l);
+                            }
+                            left_cur = left_iter.next().transpose()?;
+                            right_cur = Some(r);
+                        }
+                        std::cmp::Ordering::Greater => {
+                            if op == RelationSetOp::Union {
+                                push(r);
+                            }
+                            left_cur = Some(l);
+                            right_cur = right_iter.next().transpose()?;
+                        }
+                        std::cmp::Ordering::Equal => {
+                            let same = l == r;
+                            match op {
+                                RelationSetOp::Union if !same => {
+                                    bail!(UnionKeyConflict(
+                                        left.name.to_string(),
+                                        right.name.to_string(),
+                                        l[..key_len].to_vec()
+                                    ))
+                                }
+                                RelationSetOp::Union | RelationSetOp::Intersect if same => push(l),
+                                RelationSetOp::Except if !same => push(l),
+                                _ => {}
+                            }
+                            left_cur = left_iter.next().transpose()?;
+                            right_cur = right_iter.next().transpose()?;
+                        }
+                    },
+                }
+            }
+        }
+
+        let out_handle = self.create_relation(InputRelationHandle {
+            name: out.clone(),
+            metadata: left_handle.metadata.clone(),
+            key_bindings: left_handle
+                .metadata
+                .keys
+                .iter()
+                .map(|col| Symbol::new(col.name.clone(), Default::default()))
+                .collect_vec(),
+            dep_bindings: left_handle
+                .metadata
+                .non_keys
+                .iter()
+                .map(|col| Symbol::new(col.name.clone(), Default::default()))
+                .collect_vec(),
+            span: out.span,
+        })?;
+        for tuple in collected.into_iter() {
+            let key = out_handle.encode_key_for_store(&tuple, out.span)?;
+            let val = out_handle.encode_val_for_store(&tuple, out.span)?;
+            if out_handle.is_temp {
+                self.temp_store_tx.put(&key, &val)?;
+            } else {
+                self.store_tx.put(&key, &val)?;
+            }
+        }
+        Ok(n_rows)
     }
     pub(crate) fn rename_temp_relation(&mut self, old: Symbol, new: Symbol) -> Result<()> {
         let new_key = DataValue::Str(new.name.clone());
@@ -1462,6 +2137,69 @@ impl<'a> SessionTx<'a> {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot export stored relation '{0}' with access level {1}")]
+#[diagnostic(code(export::insufficient_access_level))]
+#[diagnostic(help("Bulk export requires the access level read_only or above"))]
+pub(crate) struct ExportAccessDenied(pub(crate) String, pub(crate) AccessLevel);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot import into stored relation '{0}' with access level {1}")]
+#[diagnostic(code(import::insufficient_access_level))]
+#[diagnostic(help("Bulk import requires the access level protected or above"))]
+pub(crate) struct ImportAccessDenied(pub(crate) String, pub(crate) AccessLevel);
+
+impl RelationHandle {
+    /// Bulk export APIs must check this, as they read the storage without going through queries.
+    pub(crate) fn ensure_exportable(&self) -> Result<()> {
+        ensure!(
+            self.access_level >= AccessLevel::ReadOnly,
+            ExportAccessDenied(self.name.to_string(), self.access_level)
+        );
+        Ok(())
+    }
+    /// Bulk import APIs must check this, as they write the storage without going through queries.
+    pub(crate) fn ensure_importable(&self) -> Result<()> {
+        ensure!(
+            self.access_level >= AccessLevel::Protected,
+            ImportAccessDenied(self.name.to_string(), self.access_level)
+        );
+        Ok(())
+    }
+    /// Rows of `source` can be copied byte for byte into this relation only if both have
+    /// the same columns, with the same types, in the same order.
+    #[cfg(feature = "storage-sqlite")]
+    pub(crate) fn ensure_same_layout(&self, source: &RelationHandle) -> Result<()> {
+        fn same_columns(a: &[ColumnDef], b: &[ColumnDef]) -> bool {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(a, b)| a.name == b.name && a.typing == b.typing)
+        }
+
+        ensure!(
+            same_columns(&self.metadata.keys, &source.metadata.keys)
+                && same_columns(&self.metadata.non_keys, &source.metadata.non_keys),
+            ImportLayoutMismatch(
+                self.name.to_string(),
+                source.metadata.to_string(),
+                self.metadata.to_string()
+            )
+        );
+        Ok(())
+    }
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot copy the rows of relation '{0}' with schema {1} into one with schema {2}")]
+#[diagnostic(code(import::schema_mismatch))]
+#[diagnostic(help(
+    "Rows are copied as they are stored, \
+so the columns must have the same names and types, in the same order"
+))]
+pub(crate) struct ImportLayoutMismatch(pub(crate) String, pub(crate) String, pub(crate) String);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Insufficient access level {2} for {1} on stored relation '{0}'")]
 #[diagnostic(code(tx::insufficient_access_level))]
@@ -0,0 +1,111 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use arrow_array::builder::make_builder;
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{Field, Schema, SchemaRef};
+use itertools::Itertools;
+use miette::{IntoDiagnostic, Result};
+
+use crate::data::relation::{ColType, VecElementType};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num, Vector};
+use crate::runtime::arrow_conv::{append_value, arrow_type, finish_batch};
+use crate::{Db, ScriptMutability, Storage};
+
+/// Number of rows in each record batch written by [Db::run_script_arrow]
+const ARROW_BATCH_ROWS: usize = 8192;
+
+/// The type of the column at `idx`, which is the type of its first non-null value.
+/// Integers mixed with floats make a `Float` column. Any other mixture of types,
+/// a column of nulls, or values without an Arrow counterpart make an `Any` column,
+/// which holds the JSON representation of the values as strings.
+fn infer_col_type(rows: &[Tuple], idx: usize) -> ColType {
+    let mut inferred = None;
+    for val in rows.iter().map(|row| &row[idx]) {
+        let typ = match val {
+            DataValue::Null => continue,
+            DataValue::Bool(_) => ColType::Bool,
+            DataValue::Num(Num::Int(_)) => ColType::Int,
+            DataValue::Num(Num::Float(_)) => ColType::Float,
+            DataValue::Str(_) => ColType::String,
+            DataValue::Bytes(_) => ColType::Bytes,
+            DataValue::Uuid(_) => ColType::Uuid,
+            DataValue::Vec(Vector::F32(_)) => ColType::Vec {
+                eltype: VecElementType::F32,
+                len: 0,
+            },
+            DataValue::Vec(Vector::F64(_)) => ColType::Vec {
+                eltype: VecElementType::F64,
+                len: 0,
+            },
+            _ => return ColType::Any,
+        };
+        inferred = Some(match inferred {
+            None => typ,
+            Some(prev) if prev == typ => prev,
+            Some(ColType::Int | ColType::Float) if matches!(typ, ColType::Int | ColType::Float) => {
+                ColType::Float
+            }
+            Some(_) => return ColType::Any,
+        });
+    }
+    inferred.unwrap_or(ColType::Any)
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Run the script like [Db::run_script], but return its result as an
+    /// [Arrow IPC stream](https://arrow.apache.org/docs/format/Columnar.html#ipc-streaming-format),
+    /// which Arrow-based clients such as Polars or PyArrow can read without parsing JSON.
+    ///
+    /// The columns are named by the headers of the result, and are all nullable.
+    /// As results carry no declared types, the type of each column is that of its first
+    /// non-null value, with the Arrow types chosen as in [Db::export_relations_parquet].
+    /// Columns mixing integers and floats become `Float64` columns, and columns mixing
+    /// other types become `Utf8` columns holding the JSON representation of the values.
+    /// For imperative scripts that return several results, only the first one is written.
+    pub fn run_script_arrow(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        mutability: ScriptMutability,
+    ) -> Result<Vec<u8>> {
+        let res = self.run_script(payload, params, mutability)?;
+        let col_types = (0..res.headers.len())
+            .map(|idx| infer_col_type(&res.rows, idx))
+            .collect_vec();
+        let schema: SchemaRef = Arc::new(Schema::new(
+            res.headers
+                .iter()
+                .zip(&col_types)
+                .map(|(name, typ)| Field::new(name, arrow_type(typ), true))
+                .collect_vec(),
+        ));
+
+        let mut writer = StreamWriter::try_new(vec![], &schema).into_diagnostic()?;
+        for chunk in res.rows.chunks(ARROW_BATCH_ROWS) {
+            let mut builders = schema
+                .fields()
+                .iter()
+                .map(|field| make_builder(field.data_type(), chunk.len()))
+                .collect_vec();
+            for row in chunk {
+                for ((builder, typ), val) in builders.iter_mut().zip(&col_types).zip(row) {
+                    append_value(builder.as_mut(), typ, val);
+                }
+            }
+            writer
+                .write(&finish_batch(&schema, &mut builders)?)
+                .into_diagnostic()?;
+        }
+        writer.into_inner().into_diagnostic()
+    }
+}
@@ -0,0 +1,125 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::TupleT;
+use crate::runtime::db::DbManifest;
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::CURRENT_STORAGE_VERSION;
+use crate::{DataValue, Db, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The snapshot is truncated or malformed")]
+#[diagnostic(code(snapshot::malformed))]
+#[diagnostic(help("Snapshots must be created by `export_all_to_bytes`"))]
+struct MalformedSnapshot;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The snapshot has storage version {0}, but the database uses version {1}")]
+#[diagnostic(code(snapshot::version_mismatch))]
+struct SnapshotVersionMismatch(u64, u64);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot import snapshot: data exists in the current database")]
+#[diagnostic(code(snapshot::not_empty))]
+#[diagnostic(help("Snapshots can only be imported into a new database"))]
+struct SnapshotTargetNotEmpty;
+
+fn write_frame(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u64).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Reads back the frames written by [write_frame], in order
+struct FrameReader<'a>(&'a [u8]);
+
+impl<'a> FrameReader<'a> {
+    /// The next frame, or `None` if all frames have been read
+    fn next_frame(&mut self) -> Result<Option<&'a [u8]>> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        if self.0.len() < 8 {
+            bail!(MalformedSnapshot)
+        }
+        let (len, rest) = self.0.split_at(8);
+        let len = u64::from_be_bytes(len.try_into().unwrap());
+        if len > rest.len() as u64 {
+            bail!(MalformedSnapshot)
+        }
+        let (frame, rest) = rest.split_at(len as usize);
+        self.0 = rest;
+        Ok(Some(frame))
+    }
+}
+
+fn current_manifest() -> DbManifest {
+    DbManifest {
+        storage_version: CURRENT_STORAGE_VERSION[0] as u64,
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Serialize the whole database into a snapshot held in memory, which can be restored
+    /// with [Db::import_all_from_bytes]. Unlike [Db::backup_db], no file is involved,
+    /// so this works on targets without a filesystem.
+    ///
+    /// The snapshot is taken in a single read transaction. It is a sequence of frames,
+    /// each a big-endian `u64` length followed by that many bytes: first the [DbManifest]
+    /// serialized with MessagePack, then the key and the value of every stored entry in turn.
+    pub fn export_all_to_bytes(&'s self) -> Result<Vec<u8>> {
+        let mut out = vec![];
+        let manifest = rmp_serde::to_vec_named(&current_manifest()).into_diagnostic()?;
+        write_frame(&mut out, &manifest);
+        let mut tx = self.transact()?;
+        for kv in tx.store_tx.range_scan(&[], &[0xFF]) {
+            let (key, val) = kv?;
+            write_frame(&mut out, &key);
+            write_frame(&mut out, &val);
+        }
+        tx.commit_tx()?;
+        Ok(out)
+    }
+    /// Restore a snapshot created by [Db::export_all_to_bytes]. As with [Db::restore_backup],
+    /// the database must not contain any data. The whole snapshot is checked before
+    /// anything is written, so a truncated snapshot leaves the database untouched.
+    pub fn import_all_from_bytes(&'s self, data: &[u8]) -> Result<()> {
+        let mut reader = FrameReader(data);
+        let manifest = reader.next_frame()?.ok_or(MalformedSnapshot)?;
+        let manifest: DbManifest =
+            rmp_serde::from_slice(manifest).map_err(|_| MalformedSnapshot)?;
+        let expected = current_manifest().storage_version;
+        if manifest.storage_version != expected {
+            bail!(SnapshotVersionMismatch(manifest.storage_version, expected))
+        }
+        let mut entries = vec![];
+        while let Some(key) = reader.next_frame()? {
+            let val = reader.next_frame()?.ok_or(MalformedSnapshot)?;
+            entries.push((key, val));
+        }
+
+        // the last relation id is read for update, so that no relation can be created
+        // between the check and the import
+        {
+            let mut tx = self.transact_write()?;
+            let last_id_key = vec![DataValue::Null].encode_as_key(RelationId::SYSTEM);
+            if let Some(last_id) = tx.store_tx.get(&last_id_key, true)? {
+                if RelationId::raw_decode(&last_id) != RelationId::SYSTEM {
+                    bail!(SnapshotTargetNotEmpty)
+                }
+            }
+            for (key, val) in entries {
+                tx.store_tx.put(key, val)?;
+            }
+            tx.commit_tx()?;
+        }
+        self.load_last_ids()
+    }
+}
@@ -243,6 +243,12 @@ impl TempStore {
             TempStore::MeetAggr(m) => m.inner.is_empty(),
         }
     }
+    fn len(&self) -> usize {
+        match self {
+            TempStore::Normal(n) => n.inner.len(),
+            TempStore::MeetAggr(m) => m.inner.len(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -292,6 +298,13 @@ impl EpochStore {
             !self.delta.is_empty()
         }
     }
+    pub(crate) fn delta_len(&self) -> usize {
+        if self.use_total_for_delta {
+            self.total.len()
+        } else {
+            self.delta.len()
+        }
+    }
     pub(crate) fn range_iter(
         &self,
         lower: &Tuple,
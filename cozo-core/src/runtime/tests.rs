@@ -8,6 +8,7 @@
  */
 
 use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use itertools::Itertools;
@@ -23,7 +24,12 @@ use crate::fts::{TokenizerCache, TokenizerConfig};
 use crate::parse::SourceSpan;
 use crate::runtime::callback::CallbackOp;
 use crate::runtime::db::Poison;
-use crate::{DbInstance, FixedRule, RegularTempStore, ScriptMutability};
+use crate::runtime::relation::LATEST_ENCODING_VERSION;
+use crate::{
+    AccessLevel, ChangeEvent, ColumnOrder, ColumnSchema, DbInstance, ExplainOp, ExportFormat,
+    FixedRule, JoinKind, NamedRows, RegularTempStore, RelationSchema, ScriptKind, ScriptMutability,
+    SimpleFixedRule, NDJSON_IMPORT_BATCH_ROWS,
+};
 
 #[test]
 fn test_limit_offset() {
@@ -70,6 +76,62 @@ fn test_meet_aggr_empty() {
     assert_eq!(res, vec![vec![DataValue::Null, DataValue::from(0)]]);
 }
 
+#[test]
+fn test_simple_aggr_scan() {
+    let db = DbInstance::default();
+    db.run_default(
+        "?[x, y, z] <- [[1, 1, 'a'], [1, 2, null], [2, 3, 'c']] :create foo {x, y => z}",
+    )
+    .unwrap();
+    for q in [
+        "?[count(x), sum(x), min(y), max(y)] := *foo[x, y, _]",
+        "?[count(z)] := *foo[x, y, z]",
+        "?[max(y), count(x)] := *foo{x, y}",
+        "?[sum(y)] := *foo{y}",
+    ] {
+        let fast = db.run_default(q).unwrap();
+        // options disable the fast path
+        let slow = db.run_default(&format!("{q} :limit 10")).unwrap();
+        assert_eq!(fast.headers, slow.headers);
+        assert_eq!(fast.rows, slow.rows);
+    }
+    let res = db
+        .run_default("?[count(x), max(y)] := *foo[x, y, _]")
+        .unwrap();
+    assert_eq!(res.headers, vec!["count(x)", "max(y)"]);
+    assert_eq!(res.rows, vec![vec![DataValue::from(3), DataValue::from(3)]]);
+
+    db.run_default(":create empty {x}").unwrap();
+    let res = db.run_default("?[count(x), min(x)] := *empty[x]").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(0), DataValue::Null]]);
+
+    assert!(db.run_default("?[sum(z)] := *foo[x, y, z]").is_err());
+    assert!(db.run_default("?[count(x)] := *nope[x]").is_err());
+
+    // the scan is registered as a running query and can be killed
+    let reported = Arc::new(Mutex::new(vec![]));
+    let reported_inner = reported.clone();
+    db.run_script_with_query_id(
+        "?[count(x)] := *foo[x, y, _]",
+        Default::default(),
+        ScriptMutability::Immutable,
+        move |id| reported_inner.lock().unwrap().push(id),
+    )
+    .unwrap();
+    assert_eq!(reported.lock().unwrap().len(), 1);
+    let cancel = Poison::default();
+    cancel.kill();
+    let err = db
+        .run_script_with_cancel(
+            "?[count(x)] := *foo[x, y, _]",
+            Default::default(),
+            ScriptMutability::Immutable,
+            cancel,
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+}
+
 #[test]
 fn test_layers() {
     let _ = env_logger::builder().is_test(true).try_init();
@@ -393,6 +455,143 @@ fn test_trigger() {
     assert!(frs.rows.is_empty());
 }
 
+#[test]
+fn guarded_triggers() {
+    let db = DbInstance::default();
+    db.run_default(":create orders {id => amount}").unwrap();
+    db.run_default(":create audit {id, op}").unwrap();
+    db.run_default(
+        r#"
+        ::set_triggers orders
+        on put if amount >= 100 {
+            ?[id, op] := _new[id, amount], op = 'big'
+            :put audit {id, op}
+        }
+        on put if amount >= 100 {
+            ?[id, op] := _old[id, amount], op = 'old'
+            :put audit {id, op}
+        }
+        on rm if amount >= 100 {
+            ?[id, op] := _old[id, amount], op = 'big_rm'
+            :put audit {id, op}
+        }
+        "#,
+    )
+    .unwrap();
+    db.run_default("?[id, amount] <- [[1, 10], [2, 20]] :put orders {id => amount}")
+        .unwrap();
+    let res = db.run_default("?[id, op] := *audit{id, op}").unwrap();
+    assert!(res.rows.is_empty());
+
+    // the body only sees the affected rows for which the guard holds
+    db.run_default("?[id, amount] <- [[3, 30], [4, 400], [5, 50]] :put orders {id => amount}")
+        .unwrap();
+    db.run_default("?[id] <- [[1], [4]] :rm orders {id}")
+        .unwrap();
+    db.run_default("?[id] <- [[2]] :rm orders {id}").unwrap();
+    db.run_default("?[id, amount] <- [[3, 300], [5, 6]] :put orders {id => amount}")
+        .unwrap();
+    let res = db.run_default("?[id, op] := *audit{id, op}").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[3, "big"], [3, "old"], [4, "big"], [4, "big_rm"]])
+    );
+
+    let res = db.run_default("::show_triggers orders").unwrap();
+    assert_eq!(res.rows[0][3], DataValue::from("amount >= 100"));
+
+    let err = db
+        .run_default("::set_triggers orders on put if nothing > 1 { ?[id, op] := _new[id, _], op = 'x' :put audit {id, op} }")
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::bad_binding");
+    let err = db
+        .run_default("::set_triggers orders on replace if amount > 1 { ?[id, op] <- [[0, 'r']] :put audit {id, op} }")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::replace_trigger_guard"
+    );
+}
+
+#[test]
+fn trigger_guard_source() {
+    let db = DbInstance::default();
+    db.run_default(":create t {k => v}").unwrap();
+    db.run_default(":create log {k}").unwrap();
+    let guard = r#"v == 'x"y' || (k % 3 == 0 && starts_with(v, "a'b"))"#;
+    db.run_default(&format!(
+        "::set_triggers t on put if {guard} {{ ?[k] := _new[k, _] :put log {{k}} }}"
+    ))
+    .unwrap();
+    let res = db.run_default("::show_triggers t").unwrap();
+    assert_eq!(res.rows[0][3], DataValue::from(guard));
+
+    db.run_default(r#"?[k, v] <- [[1, 'x"y']] :put t {k => v}"#)
+        .unwrap();
+    db.run_default(r#"?[k, v] <- [[2, "a'bc"], [4, 'x']] :put t {k => v}"#)
+        .unwrap();
+    db.run_default(r#"?[k, v] <- [[3, "a'b"]] :put t {k => v}"#)
+        .unwrap();
+    let res = db.run_default("?[k] := *log{k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [3]]));
+
+    // the rows replacing a relation are checked against its new columns
+    db.run_default(":create s {a => b}").unwrap();
+    db.run_default("::set_triggers s on put if b > 10 { ?[k] <- [['s']] :put log {k} }")
+        .unwrap();
+    db.run_default("?[b, c, a] <- [[20, 0, 0]] :replace s {b => c, a}")
+        .unwrap();
+    let res = db.run_default("?[k] := *log{k}, k = 's'").unwrap();
+    assert_eq!(res.rows.len(), 1);
+}
+
+#[test]
+fn list_all_triggers() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k}").unwrap();
+    db.run_default(":create b {k}").unwrap();
+    db.run_default(":create log {k}").unwrap();
+    db.run_default(
+        r#"
+        ::set_triggers b
+        on put { ?[k] := _new[k] :put log {k} }
+        on put { ?[k] := _new[k], k > 1 :put log {k} }
+        on rm { ?[k] := _old[k] :rm log {k} }
+        "#,
+    )
+    .unwrap();
+    db.run_default("::set_triggers a on replace { ?[k] <- [[0]] :put log {k} }")
+        .unwrap();
+
+    let res = db.run_default("::triggers").unwrap();
+    assert_eq!(
+        res.headers,
+        vec!["relation", "type", "idx", "trigger", "guard"]
+    );
+    let summary = res
+        .rows
+        .iter()
+        .map(|row| (row[0].clone(), row[1].clone(), row[2].clone()))
+        .collect_vec();
+    assert_eq!(
+        summary,
+        vec![
+            ("a".into(), "replace".into(), DataValue::from(0)),
+            ("b".into(), "put".into(), DataValue::from(0)),
+            ("b".into(), "put".into(), DataValue::from(1)),
+            ("b".into(), "rm".into(), DataValue::from(0)),
+        ]
+    );
+    let shown = db.run_default("::show_triggers b").unwrap();
+    assert_eq!(
+        shown.rows,
+        res.rows[1..]
+            .iter()
+            .map(|row| row[1..].to_vec())
+            .collect_vec()
+    );
+}
+
 #[test]
 fn test_callback() {
     let db = DbInstance::default();
@@ -667,6 +866,126 @@ fn test_multi_tx() {
     assert!(db.run_default("?[a] := *a[a]").is_err());
 }
 
+#[test]
+fn begin_transaction() {
+    let db = DbInstance::default();
+    db.run_default("?[k, n] <- [['c', 1]] :create counter {k => n}")
+        .unwrap();
+
+    let mut tx = db.begin_transaction(true).unwrap();
+    let res = tx
+        .run_script("?[n] := *counter{k: 'c', n}", Default::default())
+        .unwrap();
+    let n = res.rows[0][0].get_int().unwrap();
+    tx.run_script(
+        "?[k, n] <- [['c', $n]] :put counter {k => n}",
+        BTreeMap::from([("n".to_string(), DataValue::from(n + 1))]),
+    )
+    .unwrap();
+    // writes are visible inside the transaction before it is committed
+    let inside = tx
+        .run_script("?[n] := *counter{k: 'c', n}", Default::default())
+        .unwrap();
+    assert_eq!(inside.rows, vec![vec![DataValue::from(2)]]);
+    tx.commit().unwrap();
+    let res = db.run_default("?[n] := *counter{k: 'c', n}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+
+    let mut tx = db.begin_transaction(true).unwrap();
+    tx.run_script(
+        "?[k, n] <- [['c', 100]] :put counter {k => n}",
+        Default::default(),
+    )
+    .unwrap();
+    tx.rollback();
+    let res = db.run_default("?[n] := *counter{k: 'c', n}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+
+    let mut tx = db.begin_transaction(false).unwrap();
+    let err = tx
+        .run_script(
+            "?[k, n] <- [['c', 3]] :put counter {k => n}",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::read_only");
+    assert!(tx.run_script("::relations", Default::default()).is_err());
+
+    // a relation written to stays locked against exclusive operations until the commit
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(
+        "?[k, n] <- [['c', 1]] :create counter {k => n}",
+        Default::default(),
+        ScriptMutability::Mutable,
+    )
+    .unwrap();
+    let lock = db
+        .obtain_relation_locks(std::iter::once(&SmartString::from("counter")))
+        .pop()
+        .unwrap();
+    let mut tx = db.begin_transaction(true).unwrap();
+    tx.run_script(
+        "?[k, n] <- [['d', 1]] :put counter {k => n}",
+        Default::default(),
+    )
+    .unwrap();
+    assert!(lock.try_write().is_err());
+    tx.commit().unwrap();
+    assert!(lock.try_write().is_ok());
+}
+
+#[test]
+fn transaction_savepoints() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k => v}").unwrap();
+    let mut tx = db.begin_transaction(true).unwrap();
+    tx.run_script("?[k, v] <- [[1, 'a']] :put r {k => v}", Default::default())
+        .unwrap();
+    tx.savepoint("batch");
+    tx.run_script(
+        "?[k, v] <- [[1, 'changed'], [2, 'b']] :put r {k => v}",
+        Default::default(),
+    )
+    .unwrap();
+    tx.run_script("?[x] <- [[1]] :create _tmp {x}", Default::default())
+        .unwrap();
+    tx.savepoint("inner");
+    tx.run_script("?[k] <- [[1]] :rm r {k}", Default::default())
+        .unwrap();
+    tx.rollback_to("batch").unwrap();
+    let err = tx.rollback_to("inner").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::savepoint_not_found");
+    assert!(tx
+        .run_script("?[x] := *_tmp{x}", Default::default())
+        .is_err());
+
+    tx.run_script("?[k, v] <- [[3, 'c']] :put r {k => v}", Default::default())
+        .unwrap();
+    tx.rollback_to("batch").unwrap();
+    tx.run_script("?[k, v] <- [[4, 'd']] :put r {k => v}", Default::default())
+        .unwrap();
+
+    // releasing keeps the changes made since the savepoint
+    tx.savepoint("last");
+    tx.run_script("?[k, v] <- [[5, 'e']] :put r {k => v}", Default::default())
+        .unwrap();
+    tx.release_savepoint("batch").unwrap();
+    let err = tx.rollback_to("last").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::savepoint_not_found");
+    tx.run_script("?[k, v] <- [[6, 'f']] :put r {k => v}", Default::default())
+        .unwrap();
+    tx.savepoint("again");
+    tx.run_script("?[k] <- [[6]] :rm r {k}", Default::default())
+        .unwrap();
+    tx.rollback_to("again").unwrap();
+    tx.commit().unwrap();
+    let res = db.run_default("?[k, v] := *r{k, v}").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "a"], [4, "d"], [5, "e"], [6, "f"]])
+    );
+}
+
 #[test]
 fn test_vec_types() {
     let db = DbInstance::new("mem", "", "").unwrap();
@@ -1183,6 +1502,41 @@ fn deletion() {
     db.run_default(r"?[x] <- [[1]] :delete a {x}").unwrap();
 }
 
+#[test]
+fn rows_affected() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x => y}").unwrap();
+    db.run_default(":create log {x}").unwrap();
+    db.run_default("::set_triggers a on put { ?[x] := _new[x, _] :put log {x} }")
+        .unwrap();
+    let affected = |script: &str| db.run_default(script).unwrap().rows[0][1].clone();
+
+    assert_eq!(
+        affected("?[x, y] <- [[1, 1], [2, 2], [3, 3]] :put a {x => y}"),
+        DataValue::from(3)
+    );
+    assert_eq!(
+        affected("?[x, y] := x in [1, 4], y = 0 :put a {x => y}"),
+        DataValue::from(2)
+    );
+    assert_eq!(
+        affected("?[x] := *a{x}, x > 2 :rm a {x}"),
+        DataValue::from(2)
+    );
+    assert_eq!(
+        affected("?[x, y] <- [[1, 0]] :ensure a {x => y}"),
+        DataValue::from(0)
+    );
+    assert_eq!(
+        affected("?[x] := *a{x}, x > 10 :rm a {x}"),
+        DataValue::from(0)
+    );
+    assert_eq!(
+        affected("?[x, y] <- [[5, 5]] :create b {x => y}"),
+        DataValue::from(1)
+    );
+}
+
 #[test]
 fn returning() {
     let db = DbInstance::new("mem", "", "").unwrap();
@@ -1190,7 +1544,8 @@ fn returning() {
     let res = db
         .run_default(r"?[x, y] <- [[1, 2]] :insert a {x => y} ")
         .unwrap();
-    assert_eq!(res.into_json()["rows"], json!([["OK"]]));
+    assert_eq!(res.headers, vec!["status", "rows_affected"]);
+    assert_eq!(res.into_json()["rows"], json!([["OK", 1]]));
     // for row in res.into_json()["rows"].as_array().unwrap() {
     //     println!("{}", row);
     // }
@@ -1532,3 +1887,3406 @@ fn hnsw_index() {
     "#).unwrap();
     println!("{}", res.into_json()["rows"][0][4]);
 }
+
+#[test]
+fn export_relation_where() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[id, status] <- [[1, 'open'], [2, 'cancelled'], [3, 'shipped']]
+        :create orders {id => status}
+        "#,
+    )
+    .unwrap();
+    let mut out = vec![];
+    db.export_relation_where("orders", "status != 'cancelled'", &mut out, ExportFormat::Json)
+        .unwrap();
+    let res: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(res["headers"], json!(["id", "status"]));
+    assert_eq!(res["rows"], json!([[1, "open"], [3, "shipped"]]));
+
+    let mut out = vec![];
+    db.export_relation_where("orders", "id > 2", &mut out, ExportFormat::JsonLines)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"id\":3,\"status\":\"shipped\"}\n"
+    );
+
+    db.run_default("::access_level hidden orders").unwrap();
+    assert!(db
+        .export_relation_where("orders", "true", vec![], ExportFormat::Json)
+        .is_err());
+}
+
+#[test]
+fn export_relation_sorted() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[id, city, total] <- [[1, 'Oslo', 30], [2, 'Bergen', 10], [3, 'Oslo', 20]]
+        :create orders {id => city, total}
+        "#,
+    )
+    .unwrap();
+    let mut out = vec![];
+    db.export_relation_sorted("orders", &["city", "-total"], &mut out, ExportFormat::Json)
+        .unwrap();
+    let res: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(res["headers"], json!(["id", "city", "total"]));
+    assert_eq!(
+        res["rows"],
+        json!([[2, "Bergen", 10], [1, "Oslo", 30], [3, "Oslo", 20]])
+    );
+
+    assert!(db
+        .export_relation_sorted("orders", &["nope"], vec![], ExportFormat::Json)
+        .is_err());
+}
+
+#[test]
+fn export_relation_by_key() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[id, zeta, alpha] <- [[10, 'x', 1], [-1, 'y', 2], [3, 'z', 3]]
+        :create r {id => zeta, alpha}
+        "#,
+    )
+    .unwrap();
+    let mut out = vec![];
+    db.export_relation_by_key("r", &mut out, ExportFormat::JsonLines)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        concat!(
+            "{\"id\":-1,\"zeta\":\"y\",\"alpha\":2}\n",
+            "{\"id\":3,\"zeta\":\"z\",\"alpha\":3}\n",
+            "{\"id\":10,\"zeta\":\"x\",\"alpha\":1}\n",
+        )
+    );
+}
+
+#[test]
+fn reclaim_relation_ids() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x}").unwrap();
+    db.run_default(":create b {x}").unwrap();
+    db.run_default(":create c {x}").unwrap();
+    db.run_default("?[x] <- [[1]] :put c {x}").unwrap();
+    db.run_default("::remove b, c").unwrap();
+
+    let res = db.run_default("::relation_ids").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(3));
+    assert_eq!(res.rows[0][1], DataValue::from(1));
+
+    assert!(db
+        .run_script(
+            "::reclaim_ids",
+            Default::default(),
+            ScriptMutability::Immutable
+        )
+        .is_err());
+    // the in-memory counter is only lowered once the stored one is committed
+    assert!(db.run_default("{::reclaim_ids}").is_err());
+    let res = db.run_default("::reclaim_ids").unwrap();
+    assert_eq!(res.rows[0], vec![DataValue::from(3), DataValue::from(1)]);
+
+    db.run_default(":create d {x}").unwrap();
+    let res = db.run_default("::relation_ids").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(2));
+    assert!(db.run_default("?[x] := *d{x}").unwrap().rows.is_empty());
+}
+
+#[test]
+fn kill_finished_query() {
+    let db = DbInstance::default();
+    db.run_default("?[x] <- [[1]]").unwrap();
+    let res = db.run_default("::kill 0").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("ALREADY_FINISHED"));
+    let res = db.run_default("::kill 1000").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("NOT_FOUND"));
+}
+
+#[test]
+fn capabilities() {
+    let db = DbInstance::default();
+    db.register_fixed_rule(
+        "SumCols".to_string(),
+        SimpleFixedRule::new(1, |_, _| Ok(NamedRows::default())),
+    )
+    .unwrap();
+    let caps = db.capabilities();
+    assert_eq!(caps["storage"]["mem"], json!(true));
+    assert_eq!(caps["fixed_rules"]["custom"], json!(["SumCols"]));
+    assert!(caps["fixed_rules"]["builtin"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("Constant")));
+}
+
+#[test]
+fn commit_stats() {
+    let db = DbInstance::default();
+    let res = db.run_default("::commit_stats").unwrap();
+    assert_eq!(
+        res.headers,
+        vec!["count", "mean", "p50", "p90", "p99", "max"]
+    );
+    let before = res.rows[0][0].get_int().unwrap();
+    db.run_default(":create a {x}").unwrap();
+    db.run_default("?[x] <- [[1], [2]] :put a {x}").unwrap();
+    db.run_default("?[x] := *a[x]").unwrap();
+    let res = db.run_default("::commit_stats").unwrap();
+    let row = &res.rows[0];
+    assert!(row[0].get_int().unwrap() >= before + 2);
+    let p50 = row[2].get_float().unwrap();
+    let p99 = row[4].get_float().unwrap();
+    let max = row[5].get_float().unwrap();
+    assert!(p50 <= p99);
+    assert!(p99 <= max);
+}
+
+/// Memory storage that records the key ranges it is asked to compact
+#[derive(Clone, Default)]
+struct CompactionLog {
+    store: crate::MemStorage,
+    ranges: Arc<Mutex<Vec<(Vec<u8>, Vec<u8>)>>>,
+}
+
+impl<'s> crate::Storage<'s> for CompactionLog {
+    type Tx = crate::storage::mem::MemTx<'s>;
+
+    fn storage_kind(&self) -> &'static str {
+        "mem"
+    }
+
+    fn transact(&'s self, write: bool) -> miette::Result<Self::Tx> {
+        self.store.transact(write)
+    }
+
+    fn range_compact(
+        &'s self,
+        lower: &[u8],
+        upper: &[u8],
+        _options: crate::CompactOptions,
+    ) -> miette::Result<()> {
+        self.ranges
+            .lock()
+            .unwrap()
+            .push((lower.to_vec(), upper.to_vec()));
+        Ok(())
+    }
+
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = miette::Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> miette::Result<()> {
+        self.store.batch_put(data)
+    }
+}
+
+#[test]
+fn compact_single_relation() {
+    use crate::data::tuple::{Tuple, TupleT};
+    use crate::runtime::relation::RelationId;
+    use crate::CompactOptions;
+
+    let storage = CompactionLog::default();
+    let db = crate::Db::new(storage.clone()).unwrap();
+    db.initialize().unwrap();
+    let run = |script: &str| db.run_script(script, Default::default(), ScriptMutability::Mutable);
+    let id_of = |name: &str| db.transact().unwrap().get_relation(name, false).unwrap().id;
+    let range_of = |id: RelationId| {
+        (
+            Tuple::default().encode_as_key(id),
+            Tuple::default().encode_as_key(id.next()),
+        )
+    };
+    let last_compacted = || storage.ranges.lock().unwrap().pop().unwrap();
+
+    run("?[k, v] := k in int_range(100), v = k * 2 :create r {k => v}").unwrap();
+    run("::index create r:by_v {v}").unwrap();
+    run("?[k] := k in int_range(50) :rm r {k}").unwrap();
+    run(":create s {k}").unwrap();
+    let r = id_of("r");
+    let by_v = id_of("r:by_v");
+    let s_id = id_of("s");
+
+    run("::compact :r").unwrap();
+    assert_eq!(last_compacted(), range_of(r));
+    run("::compact :r:by_v").unwrap();
+    assert_eq!(last_compacted(), range_of(by_v));
+    run("::compact").unwrap();
+    let (lower, _) = last_compacted();
+    assert_eq!(lower, Tuple::default().encode_as_key(RelationId(0)));
+    run("::compact :r {bottommost: true, exclusive: false}").unwrap();
+    assert_eq!(last_compacted(), range_of(r));
+    run("::compact {bottommost: true}").unwrap();
+    last_compacted();
+    assert!(run("::compact :r {bottommost: 1}").is_err());
+    assert!(run("::compact :r {background: true}").is_err());
+    assert!(run("::compact r").is_err());
+    db.compact_relation_named("r", Default::default()).unwrap();
+    assert_eq!(last_compacted(), range_of(r));
+    db.compact_relation_named(
+        "r",
+        CompactOptions {
+            bottommost: true,
+            exclusive: false,
+        },
+    )
+    .unwrap();
+    assert_eq!(last_compacted(), range_of(r));
+    let res = run("?[count(k)] := *r:by_v{k}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(50)]]);
+
+    // removed relations are compacted by the name they had, or by id
+    run("::remove s").unwrap();
+    run("::compact :s").unwrap();
+    assert_eq!(last_compacted(), range_of(s_id));
+    db.compact_relation_named("s", Default::default()).unwrap();
+    assert_eq!(last_compacted(), range_of(s_id));
+    run(&format!("::compact :{}", s_id.0)).unwrap();
+    assert_eq!(last_compacted(), range_of(s_id));
+    db.compact_relation_id(r.0, Default::default()).unwrap();
+    assert_eq!(last_compacted(), range_of(r));
+    assert!(run("::compact :281474976710656").is_err());
+
+    let err = run("::compact :nothing").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "query::relation_not_found");
+    assert!(db
+        .compact_relation_named("nothing", Default::default())
+        .is_err());
+    assert!(storage.ranges.lock().unwrap().is_empty());
+}
+
+#[test]
+fn relation_ttl() {
+    let db = DbInstance::default();
+    db.run_default(":create plain {id => data}").unwrap();
+    let err = db.run_default("::expire plain").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::ttl_not_set");
+    let err = db.run_default("::ttl plain 50").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::ttl_not_set");
+    let err = db
+        .run_default("?[id, data] <- [[1, 'a']] :put plain {id => data} :ttl 50")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::ttl_without_creation"
+    );
+    let err = db
+        .run_default(":create sessions {id => data} :ttl -1")
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::bad_ttl");
+
+    db.run_default(":create sessions {id => data} :ttl 1000")
+        .unwrap();
+    db.run_default("?[id, data] <- [[1, 'a'], [2, 'b']] :put sessions {id, data}")
+        .unwrap();
+    db.run_default("::index create sessions:by_data {data}")
+        .unwrap();
+
+    // the expiry column is hidden, and cannot be put
+    let res = db.run_default("::columns sessions").unwrap();
+    assert_eq!(res.rows.len(), 2);
+    let res = db
+        .run_default("?[id, data] := *sessions[id, data]")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "a"], [2, "b"]]));
+    assert!(db
+        .run_default(
+            "?[id, data, _expires_at] <- [[3, 'c', 0]] :put sessions {id, data, _expires_at}"
+        )
+        .is_err());
+    db.run_default("?[id, data, _expires_at] <- [[3, 'c', 0]] :put sessions")
+        .unwrap();
+    let res = db
+        .run_default("?[id] := *sessions{id, _expires_at: exp}, exp > now() + 900")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2], [3]]));
+    let res = db.run_default("::expire sessions").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["sessions", 0]]));
+
+    // only rows put after `::ttl` get the new expiry
+    db.run_default("::ttl sessions 0.001").unwrap();
+    db.run_default("?[id, data] <- [[2, 'b2']] :put sessions {id, data}")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    let res = db.run_default("::expire sessions").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["sessions", 1]]));
+    let res = db.run_default("?[id] := *sessions{id}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [3]]));
+    let res = db
+        .run_default("?[data] := *sessions:by_data{data}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a"], ["c"]]));
+
+    db.run_default("?[id, data] <- [[4, 'd']] :put sessions {id, data}")
+        .unwrap();
+    let sweeper = db.spawn_ttl_sweeper(Duration::from_millis(10));
+    let mut tries = 0;
+    while db
+        .run_default("?[data] := *sessions{id: 4, data}")
+        .unwrap()
+        .rows
+        .len()
+        == 1
+    {
+        tries += 1;
+        assert!(tries < 500, "expired row was not swept");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    sweeper.stop();
+    assert_eq!(db.expire_relations().unwrap()["sessions"], 0);
+    assert!(!db.expire_relations().unwrap().contains_key("plain"));
+
+    for level in ["protected", "read_only"] {
+        db.run_default(&format!("::access_level {level} sessions"))
+            .unwrap();
+        let err = db.run_default("::ttl sessions 50").unwrap_err();
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "tx::insufficient_access_level"
+        );
+    }
+}
+
+#[test]
+fn relation_stats() {
+    let db = DbInstance::default();
+    db.run_default(":create a {k: String, t: Int => v}")
+        .unwrap();
+    let res = db.run_default("::stats a").unwrap();
+    assert_eq!(
+        res.headers,
+        vec!["rows", "key_bytes", "value_bytes", "min_key", "max_key"]
+    );
+    assert_eq!(res.into_json()["rows"], json!([[0, 0, 0, null, null]]));
+
+    db.run_default(
+        "?[k, t, v] <- [['b', 1, 'x'], ['a', 2, 'y'], ['c', 0, null]] :put a {k, t => v}",
+    )
+    .unwrap();
+    db.run_default("::index create a:by_t {t}").unwrap();
+    let res = db.run_default("::stats a").unwrap().into_json();
+    let row = &res["rows"][0];
+    assert_eq!(row[0], json!(3));
+    assert!(row[1].as_i64().unwrap() > 3 * 8);
+    assert!(row[2].as_i64().unwrap() > 3 * 8);
+    assert_eq!(row[3], json!("a"));
+    assert_eq!(row[4], json!("c"));
+    let res = db.run_default("::stats a:by_t").unwrap().into_json();
+    assert_eq!(res["rows"][0][0], json!(3));
+    assert_eq!(res["rows"][0][2], json!(0));
+    assert_eq!(res["rows"][0][3], json!(0));
+    assert_eq!(res["rows"][0][4], json!(2));
+
+    db.run_default("::access_level hidden a").unwrap();
+    assert!(db.run_default("::stats a").is_err());
+}
+
+#[test]
+fn distinct_values() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[id, status, region] <- [[1, 'open', 'eu'], [2, 'shipped', 'us'], [3, 'open', 'us'], [4, 'cancelled', 'eu']]
+        :create orders {id => status, region}
+        "#,
+    )
+    .unwrap();
+    let vals = db.distinct_values("orders", "status", 10).unwrap();
+    assert_eq!(
+        vals,
+        vec![
+            DataValue::from("open"),
+            DataValue::from("shipped"),
+            DataValue::from("cancelled")
+        ]
+    );
+    let vals = db.distinct_values("orders", "status", 2).unwrap();
+    assert_eq!(vals.len(), 2);
+    let vals = db.distinct_values("orders", "id", 3).unwrap();
+    assert_eq!(
+        vals,
+        vec![DataValue::from(1), DataValue::from(2), DataValue::from(3)]
+    );
+
+    db.run_default("::index create orders:by_region {region}")
+        .unwrap();
+    let vals = db.distinct_values("orders", "region", 10).unwrap();
+    assert_eq!(vals, vec![DataValue::from("eu"), DataValue::from("us")]);
+    assert!(db.distinct_values("orders", "nonexistent", 10).is_err());
+}
+
+#[test]
+fn max_fanout() {
+    let db = DbInstance::default();
+    let script = r#"
+        r[x] := x = 0
+        r[y] := r[x], y in [x * 2 + 1, x * 2 + 2], y < 1000
+        ?[count(x)] := r[x]
+    "#;
+    let res = db.run_default(script).unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(1000));
+    let res = db.run_default(&format!("{script} :max_fanout 3")).unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(1000));
+    let err = db
+        .run_default(&format!("{script} :max_fanout 1.5"))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::fanout_exceeded");
+    for bad in ["0", "-1", "'x'"] {
+        let err = db
+            .run_default(&format!("{script} :max_fanout {bad}"))
+            .unwrap_err();
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "parser::option_not_pos_num"
+        );
+    }
+}
+
+#[test]
+fn max_join_matches() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[k, v] <- [['a', 1], ['a', 2], ['a', 3], ['b', 4]]
+        :create right {k, v}
+        "#,
+    )
+    .unwrap();
+    let script = r#"
+        l[k] <- [['a'], ['b']]
+        ?[k, v] := l[k], *right{k, v}
+    "#;
+    let res = db.run_default(script).unwrap();
+    assert_eq!(res.rows.len(), 4);
+    let res = db
+        .run_default(&format!("{script} :max_join_matches 3"))
+        .unwrap();
+    assert_eq!(res.rows.len(), 4);
+    let err = db
+        .run_default(&format!("{script} :max_join_matches 2"))
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::join_matches_exceeded"
+    );
+    assert!(err.to_string().contains("\"a\""));
+}
+
+#[test]
+fn prepared_query() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[name, age] <- [['alice', 30], ['bob', 25], ['carol', 41]]
+        :create people {name => age}
+        "#,
+    )
+    .unwrap();
+    let prepared = db
+        .prepare("?[age] := *people{name: $name, age}, age >= $min_age")
+        .unwrap();
+    assert_eq!(prepared.params(), vec!["min_age", "name"]);
+
+    let params = |name: &str, min_age: i64| {
+        BTreeMap::from([
+            ("name".to_string(), DataValue::from(name)),
+            ("min_age".to_string(), DataValue::from(min_age)),
+        ])
+    };
+    let res = db.execute(&prepared, params("alice", 20)).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(30)]]);
+    let res = db.execute(&prepared, params("bob", 20)).unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(25)]]);
+    let res = db.execute(&prepared, params("carol", 50)).unwrap();
+    assert!(res.rows.is_empty());
+
+    let err = db
+        .execute(
+            &prepared,
+            BTreeMap::from([("name".to_string(), DataValue::from("alice"))]),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::prepared_param_not_found"
+    );
+
+    // data changes do not invalidate a prepared query
+    db.run_default("?[name, age] <- [['dave', 52]] :put people {name => age}")
+        .unwrap();
+    let res = db.execute(&prepared, params("dave", 50)).unwrap();
+    assert_eq!(res.rows.len(), 1);
+
+    // but schema changes do
+    db.run_default("::index create people:by_age {age}")
+        .unwrap();
+    let err = db.execute(&prepared, params("dave", 50)).unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::prepared_query_stale"
+    );
+    let prepared = db.prepare(prepared.source()).unwrap();
+    let res = db.execute(&prepared, params("dave", 50)).unwrap();
+    assert_eq!(res.rows.len(), 1);
+
+    // executions running at the same time each see their own parameters
+    std::thread::scope(|s| {
+        for (name, age) in [("alice", 30), ("bob", 25), ("carol", 41), ("dave", 52)] {
+            let (db, prepared) = (&db, &prepared);
+            s.spawn(move || {
+                for _ in 0..50 {
+                    let res = db.execute(prepared, params(name, 0)).unwrap();
+                    assert_eq!(res.rows, vec![vec![DataValue::from(age)]]);
+                }
+            });
+        }
+    });
+
+    assert!(db.prepare("?[x] <- [[1]] :put people {x}").is_err());
+    assert!(db.prepare("::relations").is_err());
+}
+
+#[test]
+fn prepared_query_paging() {
+    let db = DbInstance::default();
+    db.run_default("?[k] := k in int_range(10) :create r {k}")
+        .unwrap();
+    let prepared = db
+        .prepare("?[k] := *r{k} :order k :limit $size :offset $size * $page")
+        .unwrap();
+    assert_eq!(prepared.params(), vec!["page", "size"]);
+    let page = |size: i64, page: i64| {
+        let params = BTreeMap::from([
+            ("size".to_string(), DataValue::from(size)),
+            ("page".to_string(), DataValue::from(page)),
+        ]);
+        db.execute(&prepared, params)
+            .map(|res| res.rows.into_iter().map(|row| row[0].clone()).collect_vec())
+    };
+    assert_eq!(
+        page(4, 0).unwrap(),
+        (0..4).map(DataValue::from).collect_vec()
+    );
+    assert_eq!(
+        page(4, 2).unwrap(),
+        (8..10).map(DataValue::from).collect_vec()
+    );
+    assert_eq!(
+        page(3, 1).unwrap(),
+        (3..6).map(DataValue::from).collect_vec()
+    );
+    let err = page(-1, 0).unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::option_not_non_neg"
+    );
+
+    // constant options are still checked when preparing
+    assert!(db.prepare("?[k] := *r{k} :limit -1").is_err());
+    let err = db.prepare("?[k] := *r{k} :limit 1 + 'a'").unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::option_not_constant"
+    );
+    // and parameters still cannot be used in other options
+    assert!(db.prepare("?[k] := *r{k} :timeout $t").is_err());
+}
+
+#[test]
+fn migrate_encoding() {
+    let db = crate::new_cozo_mem().unwrap();
+    let run = |script: &str| {
+        db.run_script(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+    };
+    run("?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']] :create a {k => v}");
+    run("::index create a:by_v {v}");
+
+    // relations created by this version are already up to date
+    let res = run("::migrate_encoding a");
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![
+                DataValue::from("a:by_v"),
+                DataValue::from(1),
+                DataValue::from(1),
+                DataValue::from(0)
+            ],
+            vec![
+                DataValue::from("a"),
+                DataValue::from(1),
+                DataValue::from(1),
+                DataValue::from(0)
+            ],
+        ]
+    );
+
+    let set_version = |version: u8| {
+        let mut tx = db.transact_write().unwrap();
+        let mut idx = tx.get_relation("a:by_v", true).unwrap();
+        idx.encoding_version = version;
+        tx.put_relation_meta(&idx).unwrap();
+        let mut handle = tx.get_relation("a", true).unwrap();
+        handle.encoding_version = version;
+        handle
+            .indices
+            .values_mut()
+            .next()
+            .unwrap()
+            .0
+            .encoding_version = version;
+        tx.put_relation_meta(&handle).unwrap();
+        tx.commit_tx().unwrap();
+    };
+
+    // relations from before the version was recorded are rewritten
+    set_version(0);
+    let res = run("::migrate_encoding a");
+    assert_eq!(res.rows[0][1], DataValue::from(0));
+    assert_eq!(res.rows[0][3], DataValue::from(3));
+    assert_eq!(res.rows[1][1], DataValue::from(0));
+    assert_eq!(res.rows[1][3], DataValue::from(3));
+    let res = run("?[k] := *a{k, v: 'b'}");
+    assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+    let res = run("?[k, v] := *a:by_v{k, v}");
+    assert_eq!(res.rows.len(), 3);
+
+    // relations written by a newer version are refused
+    set_version(LATEST_ENCODING_VERSION + 1);
+    let err = db
+        .run_script(
+            "?[k] := *a{k}",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "tx::unsupported_encoding_version"
+    );
+}
+
+#[test]
+fn export_relations_column_order() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v, w] <- [[1, 'a', true]] :create r {k => v, w}")
+        .unwrap();
+    db.run_default("::index create r:by_w {w}").unwrap();
+    let res = db.export_relations(["r", "r:by_w"].into_iter()).unwrap();
+    assert_eq!(res["r"].headers, vec!["k", "v", "w"]);
+    assert_eq!(res["r:by_w"].headers, vec!["w", "k"]);
+
+    let res = db
+        .export_relations_ordered(["r", "r:by_w"].into_iter(), ColumnOrder::Declared)
+        .unwrap();
+    assert_eq!(res["r"].headers, vec!["k", "v", "w"]);
+    assert_eq!(res["r:by_w"].headers, vec!["k", "w"]);
+    assert_eq!(
+        res["r:by_w"].rows,
+        vec![vec![DataValue::from(1), DataValue::from(true)]]
+    );
+}
+
+#[test]
+fn export_relations_projected() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v, w] <- [[1, 'a', true], [2, 'b', false]] :create r {k => v, w}")
+        .unwrap();
+    let res = db
+        .export_relations_projected(&[("r", vec!["w", "k"])])
+        .unwrap();
+    assert_eq!(res["r"].headers, vec!["w", "k"]);
+    assert_eq!(
+        res["r"].rows,
+        vec![
+            vec![DataValue::from(true), DataValue::from(1)],
+            vec![DataValue::from(false), DataValue::from(2)]
+        ]
+    );
+    let res = db.export_relations_projected(&[("r", vec!["k"])]).unwrap();
+    assert_eq!(res["r"].clone().into_json()["rows"], json!([[1], [2]]));
+
+    let err = db
+        .export_relations_projected(&[("r", vec!["k", "x"])])
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "export::column_not_found");
+}
+
+#[test]
+fn export_relation_range() {
+    let db = DbInstance::default();
+    db.run_default(
+        "?[p, t, v] <- [['a', 1, 1.], ['a', 2, 2.], ['b', 1, 3.], ['b', 3, 4.], ['c', 1, 5.]] \
+        :create r {p: String, t: Int => v}",
+    )
+    .unwrap();
+    let range = |lower: Vec<DataValue>, upper: Vec<DataValue>| {
+        db.export_relation_range("r", lower, upper)
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        range(vec![DataValue::from("b")], vec![DataValue::from("b")]),
+        json!([["b", 1, 3.], ["b", 3, 4.]])
+    );
+    assert_eq!(
+        range(
+            vec![DataValue::from("a"), DataValue::from(2)],
+            vec![DataValue::from("b"), DataValue::from(2)]
+        ),
+        json!([["a", 2, 2.], ["b", 1, 3.]])
+    );
+    assert_eq!(
+        range(vec![DataValue::from("b")], vec![]),
+        json!([["b", 1, 3.], ["b", 3, 4.], ["c", 1, 5.]])
+    );
+    assert_eq!(range(vec![], vec![]).as_array().unwrap().len(), 5);
+
+    let err = db
+        .export_relation_range(
+            "r",
+            vec![DataValue::from("a"), DataValue::from(1), DataValue::from(1)],
+            vec![],
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "export::bad_range_bound");
+    let err = db
+        .export_relation_range("r", vec![DataValue::from(1)], vec![])
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "export::bad_range_bound");
+}
+
+#[test]
+fn export_relations_csv() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[k, v, w] <- [[1, 'plain', 1.5], [2, 'a, "quoted"\nline', null], [3, 'x', [1, 'b']]]
+        :create r {k => v, w}
+        "#,
+    )
+    .unwrap();
+    db.run_default("?[x] <- [[true]] :create s {x}").unwrap();
+    let mut out = vec![];
+    db.export_relations_csv(["r", "s"].into_iter(), &mut out)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "r\r\n\
+        k,v,w\r\n\
+        1,plain,1.5\r\n\
+        2,\"a, \"\"quoted\"\"\nline\",\r\n\
+        3,x,\"[1,\"\"b\"\"]\"\r\n\
+        \r\n\
+        s\r\n\
+        x\r\n\
+        true\r\n"
+    );
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn export_relations_snapshot() {
+    let path = std::env::temp_dir().join(format!("cozo-snapshot-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    for db in [
+        DbInstance::default(),
+        DbInstance::new("sqlite", &path, "").unwrap(),
+    ] {
+        db.run_default(":create a {k => v}").unwrap();
+        db.run_default(":create b {k => v}").unwrap();
+        let writer = {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    let rows = NamedRows::new(
+                        vec!["k".to_string(), "v".to_string()],
+                        (0..50)
+                            .map(|k| vec![DataValue::from(k), DataValue::from(i)])
+                            .collect_vec(),
+                    );
+                    let data =
+                        BTreeMap::from([("a".to_string(), rows.clone()), ("b".to_string(), rows)]);
+                    db.import_relations(data).unwrap();
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let exported = db.export_relations(["a", "b"].into_iter()).unwrap();
+            assert_eq!(exported["a"].rows, exported["b"].rows);
+        }
+        writer.join().unwrap();
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "export-parquet")]
+#[test]
+fn export_relations_parquet() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Int64Type;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let db = DbInstance::default();
+    db.run_default(
+        r#"
+        ?[k, v, l, a] <- [[1, 'x', [1, 2], {"j": 1}], [2, null, [], 'y']]
+        :create r {k: Int => v: String?, l: [Int], a: Any}
+        "#,
+    )
+    .unwrap();
+    db.run_default(
+        "?[k, v, l, a] := k in int_range(3, 10000), v = null, l = [], a = k :put r {k, v, l, a}",
+    )
+    .unwrap();
+    let path = std::env::temp_dir().join(format!("cozo-export-{}.parquet", std::process::id()));
+    db.export_relations_parquet("r", &path).unwrap();
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    let batches: Vec<_> = reader.map(|b| b.unwrap()).collect();
+    std::fs::remove_file(&path).unwrap();
+    let schema = batches[0].schema();
+    let fields: Vec<_> = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            (
+                f.name().as_str(),
+                f.data_type().to_string(),
+                f.is_nullable(),
+            )
+        })
+        .collect();
+    assert_eq!(fields[0], ("k", "Int64".to_string(), false));
+    assert_eq!(fields[1], ("v", "Utf8".to_string(), true));
+    assert!(fields[2]
+        .1
+        .starts_with("List(Field { name: \"item\", data_type: Int64"));
+    assert_eq!(fields[3], ("a", "Utf8".to_string(), false));
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 9999);
+
+    let first = &batches[0];
+    assert_eq!(first.column(0).as_primitive::<Int64Type>().value(1), 2);
+    assert_eq!(first.column(1).as_string::<i32>().value(0), "x");
+    assert!(first.column(1).is_null(1));
+    let l = first.column(2).as_list::<i32>().value(0);
+    assert_eq!(l.as_primitive::<Int64Type>().values(), &[1, 2]);
+    assert_eq!(first.column(3).as_string::<i32>().value(0), r#"{"j":1}"#);
+    assert_eq!(first.column(3).as_string::<i32>().value(1), r#""y""#);
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn run_script_arrow() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, Int64Type};
+    use arrow_ipc::reader::StreamReader;
+
+    let db = DbInstance::default();
+    let bytes = db
+        .run_script_arrow(
+            r#"?[i, f, s, m, n] <- [[1, 1, 'a', 1, null], [2, 2.5, null, 'b', null]]"#,
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    let reader = StreamReader::try_new(bytes.as_slice(), None).unwrap();
+    let batches: Vec<_> = reader.map(|b| b.unwrap()).collect();
+    assert_eq!(batches.len(), 1);
+    let batch = &batches[0];
+    let types: Vec<_> = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| (f.name().to_string(), f.data_type().to_string()))
+        .collect();
+    assert_eq!(
+        types,
+        [
+            ("i", "Int64"),
+            ("f", "Float64"),
+            ("s", "Utf8"),
+            ("m", "Utf8"),
+            ("n", "Utf8")
+        ]
+        .map(|(n, t)| (n.to_string(), t.to_string()))
+    );
+    assert_eq!(
+        batch.column(0).as_primitive::<Int64Type>().values(),
+        &[1, 2]
+    );
+    assert_eq!(
+        batch.column(1).as_primitive::<Float64Type>().values(),
+        &[1.0, 2.5]
+    );
+    assert_eq!(batch.column(2).as_string::<i32>().value(0), "a");
+    assert!(batch.column(2).is_null(1));
+    assert_eq!(batch.column(3).as_string::<i32>().value(0), "1");
+    assert_eq!(batch.column(3).as_string::<i32>().value(1), r#""b""#);
+    assert_eq!(batch.column(4).null_count(), 2);
+
+    let bytes = db
+        .run_script_arrow(
+            "?[x] := x in int_range(10000)",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    let reader = StreamReader::try_new(bytes.as_slice(), None).unwrap();
+    let n_rows: Vec<_> = reader.map(|b| b.unwrap().num_rows()).collect();
+    assert_eq!(n_rows, [8192, 1808]);
+}
+
+#[test]
+fn default_access_level() {
+    let db = DbInstance::default();
+    db.run_default("?[k] <- [[1]] :create old {k}").unwrap();
+    db.set_default_access_level(AccessLevel::ReadOnly);
+
+    db.run_default("?[k] <- [[1]] :create r {k}").unwrap();
+    db.run_default("?[k] <- [[1]] :replace s {k}").unwrap();
+    db.run_default("::union old, r -> u").unwrap();
+    for rel in ["r", "s", "u"] {
+        let res = db.run_default(&format!("?[k] := *{rel}{{k}}")).unwrap();
+        assert_eq!(res.rows, vec![vec![DataValue::from(1)]]);
+        let err = db
+            .run_default(&format!("?[k] <- [[2]] :put {rel} {{k}}"))
+            .unwrap_err();
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "tx::insufficient_access_level"
+        );
+    }
+
+    db.run_default("?[k] <- [[2]] :put old {k}").unwrap();
+    db.run_default("{?[k] <- [[1]] :create _tmp {k}} {?[k] <- [[2]] :put _tmp {k}}")
+        .unwrap();
+    db.run_default("::access_level normal r").unwrap();
+    db.run_default("?[k] <- [[2]] :put r {k}").unwrap();
+}
+
+#[test]
+fn rename_swap() {
+    let db = DbInstance::default();
+    for (name, val) in [("a", 1), ("b", 2), ("c", 3), ("d", 4)] {
+        db.run_default(&format!("?[x] <- [[{val}]] :create {name} {{x}}"))
+            .unwrap();
+    }
+    let contents = |name: &str| {
+        db.run_default(&format!("?[x] := *{name}[x]")).unwrap().rows[0][0]
+            .get_int()
+            .unwrap()
+    };
+
+    db.run_default("::rename a -> b, b -> a").unwrap();
+    assert_eq!((contents("a"), contents("b")), (2, 1));
+
+    db.run_default("::rename d -> e, a -> b, b -> c, c -> a")
+        .unwrap();
+    assert_eq!(
+        (contents("a"), contents("b"), contents("c"), contents("e")),
+        (3, 2, 1, 4)
+    );
+
+    db.run_default("::access_level protected c").unwrap();
+    let err = db.run_default("::rename b -> c, c -> b").unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "tx::insufficient_access_level"
+    );
+    assert_eq!((contents("b"), contents("c")), (2, 1));
+}
+
+#[test]
+fn describe_relation() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k: Int, l: [String; 2] => v: Float? default 0., w}")
+        .unwrap();
+    db.run_default("::access_level protected r").unwrap();
+    let col = |name: &str, typing: &str, has_default| ColumnSchema {
+        name: name.to_string(),
+        typing: typing.to_string(),
+        has_default,
+    };
+    assert_eq!(
+        db.describe_relation("r").unwrap(),
+        RelationSchema {
+            keys: vec![col("k", "Int", false), col("l", "[String;2]", false)],
+            non_keys: vec![col("v", "Float?", true), col("w", "Any?", false)],
+            access_level: AccessLevel::Protected,
+        }
+    );
+    let err = db.describe_relation("s").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "query::relation_not_found");
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn incremental_backup() {
+    let dir = std::env::temp_dir().join(format!("cozo-incremental-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = |name: &str| dir.join(name);
+
+    let db = DbInstance::default();
+    db.run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :create r {k => v}")
+        .unwrap();
+    db.run_default("?[k] <- [[1]] :create gone {k}").unwrap();
+    let err = db
+        .backup_db_incremental(file("none.db"), Some(0))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "backup::not_tracking");
+    let base = db.backup_db_incremental(file("base.db"), None).unwrap();
+
+    db.run_default("?[k, v] <- [[2, 'x'], [3, 'c']] :put r {k => v}")
+        .unwrap();
+    db.run_default("?[k] <- [[1]] :rm r {k}").unwrap();
+    db.run_default("::remove gone").unwrap();
+    let first = db
+        .backup_db_incremental(file("first.db"), Some(base))
+        .unwrap();
+    assert!(first > base);
+
+    db.run_default("?[k] <- [[1]] :create new {k}").unwrap();
+    let second = db
+        .backup_db_incremental(file("second.db"), Some(first))
+        .unwrap();
+    assert!(second > first);
+    let err = db
+        .backup_db_incremental(file("stale.db"), Some(base))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "backup::marker_pruned");
+
+    let restored = DbInstance::default();
+    restored
+        .restore_backup_incremental(file("base.db"))
+        .unwrap();
+    let err = restored
+        .restore_backup_incremental(file("second.db"))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "backup::out_of_order");
+    restored
+        .restore_backup_incremental(file("first.db"))
+        .unwrap();
+    restored
+        .restore_backup_incremental(file("second.db"))
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let res = restored.run_default("?[k, v] := *r{k, v}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, "x"], [3, "c"]]));
+    assert!(restored.run_default("?[k] := *gone{k}").is_err());
+    let res = restored.run_default("?[k] := *new{k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+    restored
+        .run_default("?[k] <- [[2]] :create newer {k}")
+        .unwrap();
+    let res = restored.run_default("?[k] := *new{k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn backup_progress() {
+    use crate::BACKUP_PROGRESS_INTERVAL;
+
+    let path = std::env::temp_dir().join(format!("cozo-progress-{}.db", std::process::id()));
+    let db = DbInstance::default();
+    db.run_default("?[k] := k in int_range(10000) :create r {k}")
+        .unwrap();
+    let mut reported = vec![];
+    db.backup_db_with_progress(&path, |n| reported.push(n))
+        .unwrap();
+    let total = *reported.last().unwrap();
+    assert!(total > 10000);
+    assert_eq!(
+        &reported[..2],
+        &[BACKUP_PROGRESS_INTERVAL, 2 * BACKUP_PROGRESS_INTERVAL]
+    );
+
+    let restored = DbInstance::default();
+    let mut reported = vec![];
+    restored
+        .restore_backup_with_progress(&path, |n| reported.push(n))
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(reported.last(), Some(&total));
+    let res = restored.run_default("?[count(k)] := *r{k}").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(10000));
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn restore_backup_prefixed() {
+    let path = std::env::temp_dir().join(format!("cozo-prefixed-{}.db", std::process::id()));
+    let src = DbInstance::default();
+    src.run_default("?[k, v] <- [[1, 'hello world'], [2, 'bye']] :create r {k => v}")
+        .unwrap();
+    src.run_default("::index create r:by_v {v}").unwrap();
+    src.run_default("::fts create r:fts {extractor: v, tokenizer: Simple}")
+        .unwrap();
+    src.backup_db(&path).unwrap();
+
+    let db = DbInstance::default();
+    db.run_default("?[k] <- [[10]] :create r {k}").unwrap();
+    let err = db.restore_backup_prefixed(&path, "_t").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::bad_restore_prefix");
+    db.restore_backup_prefixed(&path, "t1_").unwrap();
+    let err = db.restore_backup_prefixed(&path, "t1_").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::rel_name_conflict");
+    std::fs::remove_file(&path).unwrap();
+
+    let res = db.run_default("?[k, v] := *t1_r{k, v}").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "hello world"], [2, "bye"]])
+    );
+    let res = db.run_default("?[v, k] := *t1_r:by_v{v, k}").unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["bye", 2], ["hello world", 1]])
+    );
+    let res = db
+        .run_default("?[k] := ~t1_r:fts{k | query: 'world', k: 2}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+    let res = db.run_default("?[k] := *r{k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[10]]));
+
+    // indices are maintained on writes, and new relations get fresh ids
+    db.run_default("?[k, v] <- [[3, 'new world']] :put t1_r {k => v}")
+        .unwrap();
+    let res = db
+        .run_default("?[k] := ~t1_r:fts{k | query: 'world', k: 5}")
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+    db.run_default("?[k] <- [[1]] :create s {k}").unwrap();
+    let res = db.run_default("?[count(k)] := *t1_r{k}").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(3));
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn verify_backup() {
+    use crate::data::tuple::TupleT;
+
+    let path = std::env::temp_dir().join(format!("cozo-verify-{}.db", std::process::id()));
+    let db = DbInstance::default();
+    db.run_default("?[k, v] <- [[1, 'hello world'], [2, 'bye']] :create r {k: Int => v: String}")
+        .unwrap();
+    db.run_default("::index create r:by_v {v}").unwrap();
+    db.run_default("::fts create r:fts {extractor: v, tokenizer: Simple}")
+        .unwrap();
+    db.backup_db(&path).unwrap();
+
+    let stats = db.verify_backup(&path).unwrap();
+    assert!(stats.is_ok(), "{:?}", stats.corrupt);
+    assert_eq!(stats.rows["r"], 2);
+    assert_eq!(stats.rows["r:by_v"], 2);
+    assert!(stats.rows["r:fts"] > 0);
+
+    {
+        let backup = crate::new_cozo_sqlite(&path).unwrap();
+        let mut tx = backup.transact_write().unwrap();
+        let id = tx.get_relation("r", false).unwrap().id;
+        let mut val = id.raw_encode().to_vec();
+        val.extend(rmp_serde::to_vec(&vec![DataValue::from(1), DataValue::from(2)]).unwrap());
+        tx.store_tx
+            .put(&vec![DataValue::from(3)].encode_as_key(id), &val)
+            .unwrap();
+        let mut val = id.raw_encode().to_vec();
+        val.extend(rmp_serde::to_vec(&vec![DataValue::from(1)]).unwrap());
+        tx.store_tx
+            .put(&vec![DataValue::from(4)].encode_as_key(id), &val)
+            .unwrap();
+        tx.store_tx
+            .put(&vec![DataValue::from(5)].encode_as_key(id), &[0xc1; 12])
+            .unwrap();
+        // a string key cut short
+        let mut key = id.raw_encode().to_vec();
+        key.extend([0x06, b'a']);
+        tx.store_tx.put(&key, &[]).unwrap();
+        tx.commit_tx().unwrap();
+    }
+
+    let stats = db.verify_backup(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(stats.rows["r"], 6);
+    assert_eq!(stats.corrupt.len(), 4);
+    assert!(stats.corrupt.iter().all(|entry| entry.relation == "r"));
+    assert!(stats.corrupt[0].reason.contains("has 3 values"));
+    assert!(stats.corrupt[1].reason.contains("column v"));
+    assert_eq!(stats.corrupt[2].reason, "cannot be decoded");
+    assert_eq!(stats.corrupt[3].reason, "cannot be decoded");
+
+    assert!(db.verify_backup(&path).is_err());
+    assert!(!path.exists());
+}
+
+#[test]
+fn snapshot_to_bytes() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :create r {k: Int => v: String}")
+        .unwrap();
+    db.run_default("::index create r:by_v {v}").unwrap();
+    let snapshot = db.export_all_to_bytes().unwrap();
+
+    let restored = DbInstance::default();
+    let err = restored
+        .import_all_from_bytes(&snapshot[..snapshot.len() - 1])
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "snapshot::malformed");
+    restored.import_all_from_bytes(&snapshot).unwrap();
+    let res = restored.run_default("?[k] := *r:by_v{v: 'b', k}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+    // relations created after the import get fresh ids
+    restored.run_default("?[k] <- [[1]] :create s {k}").unwrap();
+    let res = restored.run_default("?[k, v] := *r{k, v}").unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    let err = restored.import_all_from_bytes(&snapshot).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "snapshot::not_empty");
+}
+
+#[test]
+fn validate_import() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k: Int => v: String}").unwrap();
+    db.run_default("::index create r:by_v {v}").unwrap();
+    let rows = |rows: Vec<Vec<DataValue>>| NamedRows::new(vec!["k".into(), "v".into()], rows);
+
+    let data = BTreeMap::from([
+        (
+            "r".to_string(),
+            rows(vec![
+                vec![DataValue::from(1), DataValue::from("a")],
+                vec![DataValue::from("x"), DataValue::from("b")],
+                vec![DataValue::from(3), DataValue::from(3)],
+                vec![DataValue::from(4)],
+            ]),
+        ),
+        ("-r".to_string(), rows(vec![vec![DataValue::from(1)]])),
+        ("r:by_v".to_string(), rows(vec![])),
+        ("s".to_string(), rows(vec![])),
+        (
+            "t".to_string(),
+            NamedRows::new(vec!["k".into(), "k".into()], vec![]),
+        ),
+    ]);
+    db.run_default(":create t {k}").unwrap();
+    let invalid = db.validate_import(&data).unwrap();
+    assert_eq!(invalid.keys().collect_vec(), vec!["r", "r:by_v", "s", "t"]);
+    assert_eq!(
+        invalid["r"].iter().map(|row| row.row).collect_vec(),
+        vec![Some(1), Some(2), Some(3)]
+    );
+    assert!(invalid["r"][2].error.contains("row too short"));
+    assert_eq!(invalid["s"][0].row, None);
+    assert!(invalid["t"][0].error.contains("more than once"));
+    // nothing was written
+    assert!(db.run_default("?[k] := *r{k}").unwrap().rows.is_empty());
+
+    let data = BTreeMap::from([(
+        "r".to_string(),
+        rows(vec![vec![DataValue::from(1), DataValue::from("a")]]),
+    )]);
+    assert!(db.validate_import(&data).unwrap().is_empty());
+    db.import_relations(data).unwrap();
+    let res = db.run_default("?[v, k] := *r:by_v{v, k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["a", 1]]));
+}
+
+#[test]
+fn import_relations_chunked() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k: Int => v: String}").unwrap();
+    db.run_default("::index create r:by_v {v}").unwrap();
+    let rows = |rows: Vec<Vec<DataValue>>| NamedRows::new(vec!["k".into(), "v".into()], rows);
+
+    let data = BTreeMap::from([(
+        "r".to_string(),
+        rows(
+            (0..10)
+                .map(|i| vec![DataValue::from(i), DataValue::from(format!("v{i}"))])
+                .collect_vec(),
+        ),
+    )]);
+    db.import_relations_chunked(data, 3).unwrap();
+    let res = db.run_default("?[count(k)] := *r:by_v{k}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(10)]]);
+
+    // the batches before the bad row are kept
+    let data = BTreeMap::from([(
+        "r".to_string(),
+        rows(
+            (10..20)
+                .map(|i| match i {
+                    15 => vec![DataValue::from(i), DataValue::from(i)],
+                    _ => vec![DataValue::from(i), DataValue::from(format!("v{i}"))],
+                })
+                .collect_vec(),
+        ),
+    )]);
+    let err = db.import_relations_chunked(data, 2).unwrap_err();
+    assert_eq!(err.to_string(), "4 rows were committed before the failure");
+    let res = db.run_default("?[count(k)] := *r{k}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(14)]]);
+
+    let err = db.import_relations_chunked(BTreeMap::new(), 0).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "import::bad_batch_size");
+}
+
+#[test]
+fn import_ndjson() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k: Int => v: String, w: Int default 0}")
+        .unwrap();
+    db.run_default("::index create r:by_v {v}").unwrap();
+
+    let data = (0..NDJSON_IMPORT_BATCH_ROWS + 5)
+        .map(|i| format!("{{\"k\": {i}, \"v\": \"v{i}\"}}\n"))
+        .join("");
+    let n = db.import_ndjson("r", data.as_bytes()).unwrap();
+    assert_eq!(n, NDJSON_IMPORT_BATCH_ROWS + 5);
+    let res = db.run_default("?[count(k), sum(w)] := *r{k, w}").unwrap();
+    assert_eq!(
+        res.rows[0],
+        vec![DataValue::from(n as i64), DataValue::from(0.)]
+    );
+    let res = db.run_default("?[k] := *r:by_v{v: 'v3', k}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3]]));
+
+    let data =
+        "{\"k\": 3, \"v\": \"new\", \"w\": 1}\n\nnot json\n{\"k\": \"x\", \"v\": \"a\"}\n[1]\n";
+    let err = db.import_ndjson("r", data.as_bytes()).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "import::bad_ndjson");
+    let help = err.help().unwrap().to_string();
+    assert!(help.contains("line 3:"));
+    assert!(help.contains("line 4:"));
+    assert!(help.contains("line 5:"));
+    assert!(!help.contains("line 1:"));
+    let res = db.run_default("?[v] := *r{k: 3, v}").unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["v3"]]));
+
+    db.import_ndjson("r", &b"{\"k\": 3, \"v\": \"new\", \"w\": 1}"[..])
+        .unwrap();
+    let res = db.run_default("?[k] := *r:by_v{v: 'v3', k}").unwrap();
+    assert!(res.rows.is_empty());
+    let res = db
+        .run_default("?[k, w] := *r:by_v{v: 'new', k}, *r{k, w}")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3, 1]]));
+
+    db.import_ndjson("-r", &b"{\"k\": 3}"[..]).unwrap();
+    let res = db.run_default("?[k] := *r:by_v{v: 'new', k}").unwrap();
+    assert!(res.rows.is_empty());
+    assert!(db
+        .run_default("?[v] := *r{k: 3, v}")
+        .unwrap()
+        .rows
+        .is_empty());
+}
+
+#[test]
+fn relation_set_ops() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']] :create a {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] <- [[2, 'b'], [3, 'x'], [4, 'd']] :create b {k => v}")
+        .unwrap();
+    db.run_default("?[k, v] <- [[4, 'd'], [5, 'e']] :create c {k => v}")
+        .unwrap();
+    let rows = |rel: &str| {
+        db.run_default(&format!("?[k, v] := *{rel}{{k, v}}"))
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+
+    let res = db.run_default("::intersect a, b -> a_and_b").unwrap();
+    assert_eq!(res.rows[0][1], DataValue::from(1));
+    assert_eq!(rows("a_and_b"), json!([[2, "b"]]));
+
+    db.run_default("::except a, b -> a_not_b").unwrap();
+    assert_eq!(rows("a_not_b"), json!([[1, "a"], [3, "c"]]));
+
+    db.run_default("::union a, c -> a_or_c").unwrap();
+    assert_eq!(
+        rows("a_or_c"),
+        json!([[1, "a"], [2, "b"], [3, "c"], [4, "d"], [5, "e"]])
+    );
+
+    let err = db.run_default("::union a, b -> a_or_b").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::set_op_key_conflict");
+    assert!(db.run_default("?[k] := *a_or_b{k}").is_err());
+
+    db.run_default("?[k] <- [[1]] :create d {k}").unwrap();
+    let err = db.run_default("::union a, d -> a_or_d").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::set_op_incompatible");
+    assert!(db.run_default("::union a, c -> b").is_err());
+}
+
+#[test]
+fn aggr_progress() {
+    let db = crate::new_cozo_mem().unwrap();
+    let reported = Arc::new(Mutex::new(vec![]));
+    let collector = reported.clone();
+    let res = db
+        .run_script_with_progress(
+            r#"
+            edge[a, b, w] <- [[1, 2, 1], [2, 3, 1], [1, 3, 5], [3, 4, 1]]
+            dist[n, min(d)] := n = 1, d = 0
+            dist[b, min(d)] := dist[a, d1], edge[a, b, w], d = d1 + w
+            ?[n, d] := dist[n, d]
+            "#,
+            Default::default(),
+            ScriptMutability::Immutable,
+            move |progress| collector.lock().unwrap().push(progress),
+        )
+        .unwrap();
+    assert_eq!(res.rows.last().unwrap()[1], DataValue::from(3));
+
+    let reported = reported.lock().unwrap();
+    assert!(reported.len() > 1);
+    assert!(reported.iter().all(|p| p.rule == "dist"));
+    assert!(reported
+        .windows(2)
+        .all(|w| w[0].stratum < w[1].stratum || w[0].iteration < w[1].iteration));
+    // the distance to node 3 first appears through the direct edge, then improves
+    let dists_to_3 = reported
+        .iter()
+        .flat_map(|p| p.rows.iter())
+        .filter(|row| row[0] == DataValue::from(3))
+        .map(|row| row[1].clone())
+        .collect_vec();
+    assert_eq!(dists_to_3, vec![DataValue::from(5), DataValue::from(2)]);
+}
+
+#[test]
+fn export_relation_iter() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] := k in int_range(3000), v = k * 2 :create r {k => v}")
+        .unwrap();
+    let mut n = 0;
+    for (i, row) in db.export_relation_iter("r").unwrap().enumerate() {
+        let row = row.unwrap();
+        assert_eq!(
+            row,
+            vec![DataValue::from(i as i64), DataValue::from(i as i64 * 2)]
+        );
+        n += 1;
+    }
+    assert_eq!(n, 3000);
+
+    db.run_default(":create empty {k}").unwrap();
+    assert_eq!(db.export_relation_iter("empty").unwrap().count(), 0);
+    assert!(db.export_relation_iter("nope").is_err());
+}
+
+#[test]
+fn typed_params() {
+    let db = DbInstance::default();
+    let params = BTreeMap::from([
+        ("f".to_string(), DataValue::from(1.0)),
+        ("i".to_string(), DataValue::from(i64::MAX)),
+    ]);
+    let res = db
+        .run_script(
+            "?[f, i, fi, ii] := f = $f, i = $i, fi = is_float(f), ii = is_int(i)",
+            params,
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![
+            DataValue::from(1.0),
+            DataValue::from(i64::MAX),
+            DataValue::from(true),
+            DataValue::from(true)
+        ]]
+    );
+
+    let res: serde_json::Value = serde_json::from_str(&db.run_script_str(
+        "?[a, b] := a = is_float($a), b = is_int($b)",
+        r#"{"a": 1.0, "b": 1}"#,
+        true,
+    ))
+    .unwrap();
+    assert_eq!(res["rows"], json!([[true, true]]));
+}
+
+#[test]
+fn unused_rule_warnings() {
+    let db = DbInstance::default();
+    let script = r#"
+        a[x] := x = 1
+        b[x] := a[x]
+        c[x] := x = 2
+        d[x] := c[x]
+        ?[x] := b[x]
+    "#;
+    let (res, report) = db
+        .run_script_with_report(script, Default::default(), ScriptMutability::Immutable)
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1)]]);
+    let c_pos = script.find("c[x]").unwrap();
+    let d_pos = script.find("d[x]").unwrap();
+    assert_eq!(report.warnings.len(), 2);
+    assert!(report.warnings[0].starts_with(&format!("Rule 'c' at {c_pos}..")));
+    assert!(report.warnings[1].starts_with(&format!("Rule 'd' at {d_pos}..")));
+    let res = db.run_script_fold_err(script, Default::default(), ScriptMutability::Immutable);
+    assert_eq!(res["warnings"], json!(report.warnings));
+
+    let res = db.run_script_fold_err(
+        "?[x] := x = 1",
+        Default::default(),
+        ScriptMutability::Immutable,
+    );
+    assert!(res.get("warnings").is_none());
+}
+
+#[test]
+fn run_script_read_only() {
+    let db = DbInstance::default();
+    db.run_default(":create a {x}").unwrap();
+    db.run_default("?[x] <- [[1]] :put a {x}").unwrap();
+
+    let res = db
+        .run_script_read_only("?[x] := *a[x]", Default::default())
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1)]]);
+    db.run_script_read_only("::relations", Default::default())
+        .unwrap();
+    let res = db
+        .run_script_read_only(
+            "{?[a] <- [[1]] :replace _t {a}} {?[a] := *_t{a}}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1)]]);
+
+    for script in [
+        "?[x] <- [[2]] :put a {x}",
+        "{?[x] := *a[x]} {?[x] <- [[2]] :put a {x}}",
+        "%if {?[x] := *a[x]} %then {?[x] <- [[2]] :put a {x}} %end",
+        "::remove a",
+        "::rename a -> b",
+        "::set_triggers a",
+        "::access_level protected a",
+        "{?[x] := *a[x]} {::remove a}",
+    ] {
+        let err = db
+            .run_script_read_only(script, Default::default())
+            .unwrap_err();
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "eval::read_only_violation",
+            "{script}"
+        );
+    }
+    let res = db.run_default("?[x] := *a[x]").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1)]]);
+}
+
+#[test]
+fn run_script_with_cancel() {
+    let db = DbInstance::default();
+    let script = r#"
+        r[n] := n = 0
+        r[m] := r[n], m = n + 1
+        ?[n] := r[n]
+    "#;
+
+    let cancel = Poison::default();
+    let killer = cancel.clone();
+    let handle = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        killer.kill();
+    });
+    let err = db
+        .run_script_with_cancel(
+            script,
+            Default::default(),
+            ScriptMutability::Immutable,
+            cancel,
+        )
+        .unwrap_err();
+    handle.join().unwrap();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+
+    let cancel = Poison::default();
+    let res = db
+        .run_script_with_cancel(
+            "?[x] := x = 1",
+            Default::default(),
+            ScriptMutability::Immutable,
+            cancel.clone(),
+        )
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1)]]);
+    cancel.kill();
+    assert!(db
+        .run_script_with_cancel(
+            "{?[x] := x = 1} {?[x] := x = 2}",
+            Default::default(),
+            ScriptMutability::Immutable,
+            cancel,
+        )
+        .is_err());
+}
+
+#[test]
+fn inspect_relation() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] := k in int_range(10), v = k * 2 :create r {k: Int => v: Int}")
+        .unwrap();
+    let res = db.inspect_relation("r", 3).unwrap();
+    assert_eq!(res["name"], json!("r"));
+    assert_eq!(
+        res["columns"]["rows"],
+        json!([["k", true, 0, "Int", false], ["v", false, 1, "Int", false]])
+    );
+    assert_eq!(res["row_count"], json!(10));
+    assert_eq!(res["sample"]["headers"], json!(["k", "v"]));
+    assert_eq!(res["sample"]["rows"], json!([[0, 0], [1, 2], [2, 4]]));
+
+    let res = db.inspect_relation("r", 100).unwrap();
+    assert_eq!(res["sample"]["rows"].as_array().unwrap().len(), 10);
+    assert!(db.inspect_relation("nope", 3).is_err());
+}
+
+#[test]
+fn import_duplicate_header() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k => v}").unwrap();
+    let err = db
+        .import_relations(BTreeMap::from([(
+            "r".to_string(),
+            NamedRows::new(
+                vec!["k".to_string(), "v".to_string(), "v".to_string()],
+                vec![vec![
+                    DataValue::from(1),
+                    DataValue::from(2),
+                    DataValue::from(3),
+                ]],
+            ),
+        )]))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "import::duplicate_header");
+    assert!(err.to_string().contains("'v'"));
+    assert!(db.run_default("?[k] := *r{k}").unwrap().rows.is_empty());
+}
+
+#[test]
+fn run_script_with_query_id() {
+    let db = DbInstance::default();
+    let script = r#"
+        r[n] := n = 0
+        r[m] := r[n], m = n + 1
+        ?[n] := r[n]
+    "#;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let killer_db = db.clone();
+    let killer = std::thread::spawn(move || {
+        let id: u64 = receiver.recv().unwrap();
+        let res = killer_db
+            .run_script(
+                "::kill $id",
+                BTreeMap::from([("id".to_string(), DataValue::from(id as i64))]),
+                ScriptMutability::Immutable,
+            )
+            .unwrap();
+        assert_eq!(res.rows, vec![vec![DataValue::from("KILLING")]]);
+    });
+    let err = db
+        .run_script_with_query_id(
+            script,
+            Default::default(),
+            ScriptMutability::Immutable,
+            move |id| sender.send(id).unwrap(),
+        )
+        .unwrap_err();
+    killer.join().unwrap();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+
+    let reported = Arc::new(Mutex::new(vec![]));
+    let reported_inner = reported.clone();
+    db.run_script_with_query_id(
+        "{?[x] := x = 1} {?[x] := x = 2}",
+        Default::default(),
+        ScriptMutability::Immutable,
+        move |id| reported_inner.lock().unwrap().push(id),
+    )
+    .unwrap();
+    assert_eq!(reported.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn list_and_kill_running() {
+    let db = DbInstance::default();
+    let script = r#"
+        r[n] := n = 0
+        r[m] := r[n], m = n + 1
+        ?[n] := r[n]
+    "#;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let runner_db = db.clone();
+    let runner = std::thread::spawn(move || {
+        runner_db.run_script_with_query_id(
+            script,
+            BTreeMap::from([("secret".to_string(), DataValue::from("hunter2"))]),
+            ScriptMutability::Immutable,
+            move |id| sender.send(id).unwrap(),
+        )
+    });
+    let id = receiver.recv().unwrap();
+    // a mutable script would wait for the running query to release the in-memory store
+    let run = |script: &str| {
+        db.run_script(script, Default::default(), ScriptMutability::Immutable)
+            .unwrap()
+    };
+
+    let running = run("::running");
+    assert_eq!(
+        running.headers,
+        ["id", "started_at", "elapsed", "source", "params"]
+    );
+    assert_eq!(running.rows.len(), 1);
+    let row = &running.rows[0];
+    assert_eq!(row[0], DataValue::from(id as i64));
+    assert!(row[1].get_float().unwrap() > 0.);
+    assert!(row[2].get_float().unwrap() >= 0.);
+    assert_eq!(row[3], DataValue::from(script));
+    assert_eq!(
+        row[4],
+        DataValue::List(vec![DataValue::from("$secret: String")])
+    );
+
+    let res = run("::kill_matching 'no such query'");
+    assert!(res.rows.is_empty());
+    for pattern in ["''", "'  '"] {
+        let err = db
+            .run_script(
+                &format!("::kill_matching {pattern}"),
+                Default::default(),
+                ScriptMutability::Immutable,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "parser::empty_kill_pattern"
+        );
+    }
+    let res = run("::kill_matching 'm = n + 1'");
+    assert_eq!(res.headers, ["id"]);
+    assert_eq!(res.rows, vec![vec![DataValue::from(id as i64)]]);
+    let err = runner.join().unwrap().unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+}
+
+#[test]
+fn eval_progress() {
+    let db = DbInstance::default();
+    let reports = Arc::new(Mutex::new(vec![]));
+    let reports_inner = reports.clone();
+    let res = db
+        .run_script_with_eval_progress(
+            r#"
+            r[n] := n = 0
+            r[m] := r[n], m = n + 1, m < 50
+            ?[count(n)] := r[n]
+            "#,
+            Default::default(),
+            ScriptMutability::Immutable,
+            move |p| {
+                reports_inner
+                    .lock()
+                    .unwrap()
+                    .push((p.stratum, p.iteration, p.tuples));
+                true
+            },
+        )
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(50)]]);
+    let reports = reports.lock().unwrap();
+    let recursive = reports.iter().filter(|(s, _, _)| *s == 0).collect_vec();
+    assert_eq!(recursive.len(), 51);
+    assert_eq!(recursive.last().unwrap().2, 50);
+    assert!(recursive.windows(2).all(|w| w[0].2 <= w[1].2));
+
+    let err = db
+        .run_script_with_eval_progress(
+            r#"
+            r[n] := n = 0
+            r[m] := r[n], m = n + 1
+            ?[n] := r[n]
+            "#,
+            Default::default(),
+            ScriptMutability::Immutable,
+            |p| p.iteration < 10,
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+}
+
+#[test]
+fn max_eval_threads() {
+    let db = DbInstance::default();
+    let script = r#"
+        a[x] := x in int_range(100)
+        b[x] := x in int_range(50, 150)
+        c[x] := a[x], x % 2 == 0
+        d[x] := b[x], x % 3 == 0
+        ?[count(x)] := c[x] or d[x]
+    "#;
+    let expected = db.run_default(script).unwrap().rows;
+    // more threads than can run at once get no more than that
+    for n in [1, 2, 8, 1_000_000] {
+        let res = db
+            .run_default(&format!("{script} :max_eval_threads {n}"))
+            .unwrap();
+        assert_eq!(res.rows, expected);
+    }
+    let err = db
+        .run_default(&format!("{script} :max_eval_threads 0"))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::option_not_pos");
+}
+
+#[test]
+fn explain_analyze() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] := k in int_range(100), v = k % 10 :create r {k => v}")
+        .unwrap();
+    let res = db
+        .run_default("::explain_analyze { ?[k] := *r{k, v}, v == 3 }")
+        .unwrap();
+    let idx = |name: &str| res.headers.iter().position(|h| h == name).unwrap();
+    let (op, rows, calls) = (idx("op"), idx("actual_rows"), idx("calls"));
+    let load = res
+        .rows
+        .iter()
+        .find(|row| row[op] == DataValue::from("load_stored"))
+        .unwrap();
+    // the filter is applied while scanning
+    assert_eq!(load[rows], DataValue::from(10));
+    assert_eq!(load[calls], DataValue::from(1));
+    assert!(load[idx("time_ms")].get_float().is_some());
+
+    let plain = db
+        .run_default("::explain { ?[k] := *r{k, v}, v == 3 }")
+        .unwrap();
+    assert!(!plain.headers.contains(&"actual_rows".to_string()));
+    assert_eq!(plain.rows.len(), res.rows.len());
+
+    let err = db
+        .run_default("::explain_analyze { ?[k, v] <- [[1, 2]] :put r {k => v} }")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::explain_analyze_mutation"
+    );
+
+    // the analyzed query is a running query like any other
+    let reported = Arc::new(Mutex::new(vec![]));
+    let reported_inner = reported.clone();
+    db.run_script_with_query_id(
+        "::explain_analyze { ?[k] := *r{k, v}, v == 3 }",
+        Default::default(),
+        ScriptMutability::Immutable,
+        move |id| reported_inner.lock().unwrap().push(id),
+    )
+    .unwrap();
+    assert_eq!(reported.lock().unwrap().len(), 1);
+    let cancel = Poison::default();
+    cancel.kill();
+    let err = db
+        .run_script_with_cancel(
+            "::explain_analyze { ?[k] := *r{k, v}, v == 3 }",
+            Default::default(),
+            ScriptMutability::Immutable,
+            cancel,
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+}
+
+#[test]
+fn explain_plan() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] := k in int_range(10), v = k % 3 :create r {k => v}")
+        .unwrap();
+    db.run_default("?[k, w] := k in int_range(5), w = k * 2 :create s {k => w}")
+        .unwrap();
+    let query = "?[k, v, w] := *r{k, v}, *s{k, w}, v > 0";
+    let plan = db.explain(query, Default::default()).unwrap();
+    assert!(!plan.analyzed);
+    assert_eq!(plan.steps.last().unwrap().op, ExplainOp::Out);
+    let loads = plan
+        .steps
+        .iter()
+        .filter(|step| step.op == ExplainOp::LoadStored)
+        .filter_map(|step| step.reference.as_deref())
+        .collect_vec();
+    assert_eq!(loads, vec![":r", ":s"]);
+    let join = plan
+        .steps
+        .iter()
+        .find(|step| matches!(step.op, ExplainOp::Join(_)))
+        .unwrap();
+    assert_eq!(join.op, ExplainOp::Join(JoinKind::StoredPrefix));
+    assert_eq!(join.joins_on.len(), 1);
+    assert_eq!(join.joins_on[0].0, "k");
+
+    let wrapped = db
+        .explain(&format!("::explain {{ {query} }}"), Default::default())
+        .unwrap();
+    assert_eq!(wrapped, plan);
+    let rows = db.run_default(&format!("::explain {{ {query} }}")).unwrap();
+    assert_eq!(rows.into_json(), plan.into_named_rows().into_json());
+
+    let err = db.explain("::relations", Default::default()).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::expect_singleton");
+}
+
+#[test]
+fn plan_script() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] := k in int_range(10), v = k % 3 :create r {k => v}")
+        .unwrap();
+    let query = "?[k] := *r{k, v}, v > 0";
+    let plans = db.plan(query, Default::default()).unwrap();
+    assert_eq!(plans, vec![db.explain(query, Default::default()).unwrap()]);
+
+    let script = "
+        { ?[k] := *r{k, v}, v > 0 }
+        %if { ?[x] <- [[1]] }
+        %then { ?[k, v] := *r{k, v} :put r {k => v} }
+        %end
+        { ::relations }
+    ";
+    let plans = db.plan(script, Default::default()).unwrap();
+    assert_eq!(plans.len(), 3);
+    assert_eq!(plans[0], db.explain(query, Default::default()).unwrap());
+    assert!(plans[2]
+        .steps
+        .iter()
+        .any(|step| step.reference.as_deref() == Some(":r")));
+    // nothing was run
+    let res = db.run_default("?[count(k)] := *r{k}").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(10));
+
+    let err = db
+        .plan(
+            "{ ?[x] <- [[1]] :create _t {x} } { ?[x] := *_t{x} }",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("query 1"));
+}
+
+#[test]
+fn explain_est_rows() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] := k in int_range(10), v = k % 3 :create r {k => v}")
+        .unwrap();
+    db.run_default("?[k, w] := k in int_range(4), w = k * 2 :create s {k => w}")
+        .unwrap();
+    let plan = db
+        .explain(
+            "a[k] := *r{k}
+             ?[k, w] := a[k], *s{k, w}",
+            Default::default(),
+        )
+        .unwrap();
+    let est = |op: ExplainOp, reference: &str| {
+        plan.steps
+            .iter()
+            .find(|step| {
+                step.op == op
+                    && step
+                        .reference
+                        .as_deref()
+                        .is_some_and(|r| r.starts_with(reference))
+            })
+            .unwrap()
+            .est_rows
+    };
+    assert_eq!(est(ExplainOp::LoadStored, ":r"), Some(10));
+    assert_eq!(est(ExplainOp::LoadStored, ":s"), Some(4));
+    // the rule is held in memory under its magic name
+    assert_eq!(est(ExplainOp::LoadMem, "a"), Some(10));
+    let out = plan
+        .steps
+        .iter()
+        .find(|step| step.op == ExplainOp::Out && step.rule == "?")
+        .unwrap();
+    assert_eq!(out.est_rows, Some(4));
+
+    let res = db
+        .run_default("::explain { ?[k] := *r{k, v}, v > 0 }")
+        .unwrap();
+    let col = res.headers.iter().position(|h| h == "est_rows").unwrap();
+    assert_eq!(res.rows.last().unwrap()[col], DataValue::from(5));
+
+    // relations are not counted again by every explain
+    db.run_default("?[k, v] := k in int_range(10, 20), v = 0 :put r {k => v}")
+        .unwrap();
+    let res = db
+        .run_default("::explain { ?[k] := *r{k, v}, v > 0 }")
+        .unwrap();
+    assert_eq!(res.rows.last().unwrap()[col], DataValue::from(5));
+}
+
+#[test]
+fn run_script_cursor() {
+    let db = DbInstance::default();
+    let mut cursor = db
+        .run_script_cursor(
+            "?[x] := x in int_range(25)",
+            Default::default(),
+            ScriptMutability::Immutable,
+        )
+        .unwrap();
+    assert_eq!(cursor.headers(), ["x"]);
+    assert_eq!(cursor.total(), 25);
+    let mut seen = vec![];
+    let err = cursor.next_page(0).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "cursor::empty_page");
+    assert_eq!(cursor.remaining(), 25);
+    while let Some(page) = cursor.next_page(10).unwrap() {
+        assert!(page.rows.len() <= 10);
+        assert_eq!(page.headers, vec!["x"]);
+        seen.extend(page.rows);
+    }
+    assert_eq!(seen.len(), 25);
+    assert_eq!(seen[24], vec![DataValue::from(24)]);
+    assert_eq!(cursor.remaining(), 0);
+    assert!(cursor.next_page(10).unwrap().is_none());
+}
+
+#[test]
+fn float_fmt_option() {
+    let db = DbInstance::default();
+    let query = "?[i, x, l] := i = 1, x = 0.1 + 0.2, l = [2.675, 'a']";
+    let res = db.run_default(query).unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, 0.30000000000000004, [2.675, "a"]]])
+    );
+
+    let res = db
+        .run_default(&format!("{query} :float_fmt 'string'"))
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "0.30000000000000004", ["2.675", "a"]]])
+    );
+
+    let res = db.run_default(&format!("{query} :float_fmt 2")).unwrap();
+    let json = res.into_json();
+    assert_eq!(json["rows"], json!([[1, 0.3, [2.67, "a"]]]));
+    assert_eq!(json["rows"][0][1].to_string(), "0.3");
+
+    for bad in ["'exact'", "-1", "16"] {
+        let err = db
+            .run_default(&format!("{query} :float_fmt {bad}"))
+            .unwrap_err();
+        assert_eq!(err.code().unwrap().to_string(), "parser::bad_float_fmt");
+    }
+
+    // single-relation aggregates are formatted too
+    db.run_default("?[x] <- [[0.1], [0.2]] :create f {x}")
+        .unwrap();
+    let res = db
+        .run_default("?[sum(x)] := *f{x} :float_fmt 'string'")
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["0.30000000000000004"]]));
+}
+
+#[test]
+fn mem_limit() {
+    let db = DbInstance::default();
+    let script = r#"
+        r[n] := n = 0
+        r[m] := r[n], m = n + 1, m < 10000
+        ?[count(n)] := r[n]
+    "#;
+    let res = db
+        .run_default(&format!("{script} :mem_limit 100000000"))
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(10000)]]);
+    let err = db
+        .run_default(&format!("{script} :mem_limit 10000"))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::mem_limit");
+
+    db.set_default_mem_limit(Some(10000));
+    let err = db.run_default(script).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::mem_limit");
+    // the query option takes precedence over the default
+    db.run_default(&format!("{script} :mem_limit 100000000"))
+        .unwrap();
+    db.set_default_mem_limit(None);
+    db.run_default(script).unwrap();
+}
+
+#[test]
+fn default_timeout() {
+    let db = DbInstance::default();
+    let endless = r#"
+        r[n] := n = 0
+        r[m] := r[n], m = n + 1
+        ?[n] := r[n]
+    "#;
+    db.set_default_timeout(0.1);
+    let err = db.run_default(endless).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+    db.run_default("?[x] <- [[1]]").unwrap();
+
+    // the query option takes precedence over the default
+    db.set_default_timeout(100.);
+    let err = db
+        .run_default(&format!("{endless} :timeout 0.1"))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+
+    db.set_default_timeout(0.);
+    let res = db
+        .run_default("?[x] := x in int_range(3) :timeout 100")
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+}
+
+#[test]
+fn create_or_replace() {
+    let db = DbInstance::default();
+    let script = "?[k, v] <- $rows :create_or_replace r {k => v}";
+    let run = |rows: serde_json::Value| {
+        db.run_script(
+            script,
+            BTreeMap::from([("rows".to_string(), DataValue::from(rows))]),
+            ScriptMutability::Mutable,
+        )
+    };
+    let contents = || db.run_default("?[k, v] := *r[k, v]").unwrap().into_json()["rows"].clone();
+
+    run(json!([[1, "a"], [2, "b"]])).unwrap();
+    assert_eq!(contents(), json!([[1, "a"], [2, "b"]]));
+
+    db.run_default(":create audit {k}").unwrap();
+    db.run_default("::set_triggers r on put { ?[k] := _new[k, _] :put audit {k} }")
+        .unwrap();
+    db.run_default("::access_level protected r").unwrap();
+
+    run(json!([[3, "c"]])).unwrap();
+    assert_eq!(contents(), json!([[3, "c"]]));
+    assert_eq!(
+        db.run_default("?[k] := *audit[k]").unwrap().into_json()["rows"],
+        json!([[3]])
+    );
+    let schema = db.describe_relation("r").unwrap();
+    assert_eq!(schema.access_level, AccessLevel::Protected);
+
+    let err = db
+        .run_default("?[k, v] <- [[1, 2]] :create_or_replace r {k: String => v}")
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::col_type_mismatch");
+    assert_eq!(contents(), json!([[3, "c"]]));
+
+    db.run_default("::access_level read_only r").unwrap();
+    assert!(run(json!([[4, "d"]])).is_err());
+
+    db.run_default("?[k, v] <- [['a', 'hello']] :create t {k => v: String}")
+        .unwrap();
+    db.run_default("::fts create t:fts {extractor: v, tokenizer: Simple}")
+        .unwrap();
+    let err = db
+        .run_default("?[k, v] <- [['b', 'world']] :create_or_replace t {k => v: String}")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::replace_rel_with_indices"
+    );
+}
+
+#[test]
+fn import_coercion_context() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k: Int => v: Int}").unwrap();
+    let data = NamedRows::new(
+        vec!["k".to_string(), "v".to_string()],
+        vec![
+            vec![DataValue::from(1), DataValue::from(2)],
+            vec![DataValue::from(2), DataValue::from("two")],
+        ],
+    );
+    let err = db
+        .import_relations(BTreeMap::from([("r".to_string(), data)]))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        r#"when importing row 1 of relation r: column v cannot hold "two""#
+    );
+    assert!(db.run_default("?[k] := *r[k, _]").unwrap().rows.is_empty());
+}
+
+#[test]
+fn import_with_schema() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"?[k, l, v] <- [[1, 'a', 1.5], [2, 'b', null]]
+        :create r {k: Int, l: String => v: Float?, w: Int default 0, u: Uuid default rand_uuid_v4()}"#,
+    )
+    .unwrap();
+    let (exported, schemas) = db.export_relations_with_schema(["r"].into_iter()).unwrap();
+    assert_eq!(
+        schemas["r"],
+        "{k: Int, l: String => v: Float?, w: Int default 0, u: Uuid default rand_uuid_v4()}"
+    );
+
+    let fresh = DbInstance::default();
+    fresh
+        .import_relations_with_schema(exported.clone(), &schemas)
+        .unwrap();
+    assert_eq!(
+        fresh.describe_relation("r").unwrap(),
+        db.describe_relation("r").unwrap()
+    );
+    assert_eq!(
+        fresh.export_relations(["r"].into_iter()).unwrap()["r"].rows,
+        exported["r"].rows
+    );
+    assert!(DbInstance::default()
+        .import_relations(exported.clone())
+        .is_err());
+
+    // the schema is only part of the JSON export when asked for
+    let plain: serde_json::Value =
+        serde_json::from_str(&db.export_relations_str(r#"{"relations": ["r"]}"#)).unwrap();
+    assert!(plain["data"]["r"].get("schema").is_none());
+    let with_schema: serde_json::Value = serde_json::from_str(
+        &db.export_relations_str(r#"{"relations": ["r"], "with_schema": true}"#),
+    )
+    .unwrap();
+    assert_eq!(with_schema["data"]["r"]["schema"], json!(schemas["r"]));
+    let fresh = DbInstance::default();
+    fresh
+        .import_relations_str_with_err(&with_schema["data"].to_string())
+        .unwrap();
+    assert_eq!(
+        fresh.export_relations(["r"].into_iter()).unwrap()["r"].rows,
+        exported["r"].rows
+    );
+}
+
+#[test]
+fn schema_defaults_roundtrip() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#":create r {k: Int => a: String default 'a"b\'c', b: Float default 1e300 * 10.0,
+        c: Float default 1.0, d default [1, 'x', null, -2], e: String default '\u0001\n\\',
+        f default {"a": [1.5]}, g: Float default to_float('NAN'), h default -0.0,
+        i: Float default 1e300 * 1e300}"#,
+    )
+    .unwrap();
+    let schema = db
+        .export_relations_with_schema(["r"].into_iter())
+        .unwrap()
+        .1["r"]
+        .clone();
+    db.run_default(&format!(":create s {schema}")).unwrap();
+    let copied = db
+        .export_relations_with_schema(["s"].into_iter())
+        .unwrap()
+        .1["s"]
+        .clone();
+    assert_eq!(copied, schema);
+
+    db.run_default("?[k] <- [[1]] :put r {k}").unwrap();
+    db.run_default("?[k] <- [[1]] :put s {k}").unwrap();
+    let r = db
+        .run_default("?[a, b, c, d, e, f, h, i] := *r{a, b, c, d, e, f, h, i}")
+        .unwrap();
+    let s = db
+        .run_default("?[a, b, c, d, e, f, h, i] := *s{a, b, c, d, e, f, h, i}")
+        .unwrap();
+    assert_eq!(r.rows, s.rows);
+    assert_eq!(r.rows[0][0], DataValue::from(r#"a"b'c"#));
+    assert_eq!(r.rows[0][4], DataValue::from("\u{1}\n\\"));
+    assert_eq!(r.rows[0][1], DataValue::from(1e301));
+    assert_eq!(r.rows[0][7], DataValue::from(f64::INFINITY));
+    let g = db.run_default("?[g] := *s{g}").unwrap();
+    assert!(g.rows[0][0].get_float().unwrap().is_nan());
+}
+
+#[test]
+fn export_import_schema() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#"?[k, v, w] <- [[1, 'a', 2], [2, 'b', 1]]
+        :create r {k: Int => v: String default "x", w: Int}"#,
+    )
+    .unwrap();
+    db.run_default("::index create r:by_w {w}").unwrap();
+    db.run_default(":create s {a: [Float; 2]}").unwrap();
+
+    let schema = db.export_schema(["r", "s"].into_iter()).unwrap();
+    assert_eq!(
+        schema,
+        json!({
+            "r": [
+                ":create r {k: Int => v: String default 'x', w: Int}",
+                "::index create r:by_w {w, k}"
+            ],
+            "s": [":create s {a: [Float;2]}"]
+        })
+    );
+    assert_eq!(
+        db.export_schema(["r:by_w"].into_iter())
+            .unwrap_err()
+            .code()
+            .unwrap()
+            .to_string(),
+        "export::schema_of_index"
+    );
+
+    let fresh = DbInstance::default();
+    fresh.import_schema(&schema).unwrap();
+    fresh
+        .import_relations(db.export_relations(["r"].into_iter()).unwrap())
+        .unwrap();
+    assert_eq!(
+        fresh.describe_relation("r").unwrap(),
+        db.describe_relation("r").unwrap()
+    );
+    assert_eq!(
+        fresh.run_default("?[k] := *r:by_w{w: 1, k}").unwrap().rows,
+        vec![vec![DataValue::from(2)]]
+    );
+    assert!(fresh.import_schema(&schema).is_err());
+}
+
+#[test]
+fn export_import_schema_defaults() {
+    let db = DbInstance::default();
+    db.run_default(
+        r#":create d {k: Int => q: String default 'it\'s "quoted"', big: Float default 1e300 * 1e5,
+        neg: Float default -1.5e-300, f: String default concat('a', to_string(1 + 2)),
+        u: Uuid default rand_uuid_v4(), l default [1, 'x', [2.5]]}"#,
+    )
+    .unwrap();
+    let schema = db.export_schema(["d"].into_iter()).unwrap();
+
+    let fresh = DbInstance::default();
+    fresh.import_schema(&schema).unwrap();
+    assert_eq!(fresh.export_schema(["d"].into_iter()).unwrap(), schema);
+    assert_eq!(
+        fresh.describe_relation("d").unwrap(),
+        db.describe_relation("d").unwrap()
+    );
+
+    let query = "?[q, big, neg, f, l] := *d{q, big, neg, f, l}";
+    db.run_default("?[k] <- [[1]] :put d {k}").unwrap();
+    fresh.run_default("?[k] <- [[1]] :put d {k}").unwrap();
+    let rows = fresh.run_default(query).unwrap().rows;
+    assert_eq!(rows, db.run_default(query).unwrap().rows);
+    assert_eq!(
+        rows[0][..4],
+        [
+            DataValue::from(r#"it's "quoted""#),
+            DataValue::from(1e300 * 1e5),
+            DataValue::from(-1.5e-300),
+            DataValue::from("a3"),
+        ]
+    );
+    assert!(fresh.run_default("?[u] := *d{u}").unwrap().rows[0][0]
+        .get_uuid()
+        .is_some());
+}
+
+#[test]
+fn bulk_access_levels() {
+    let db = DbInstance::default();
+    db.run_default("?[k] <- [[1]] :create r {k}").unwrap();
+    let data = || {
+        BTreeMap::from([(
+            "r".to_string(),
+            NamedRows::new(vec!["k".to_string()], vec![vec![DataValue::from(2)]]),
+        )])
+    };
+    let code = |err: miette::Report| err.code().unwrap().to_string();
+
+    db.run_default("::access_level protected r").unwrap();
+    db.import_relations(data()).unwrap();
+
+    db.run_default("::access_level read_only r").unwrap();
+    assert_eq!(
+        db.export_relations(["r"].into_iter()).unwrap()["r"]
+            .rows
+            .len(),
+        2
+    );
+    assert_eq!(
+        code(db.import_relations(data()).unwrap_err()),
+        "import::insufficient_access_level"
+    );
+    assert_eq!(
+        code(db.import_relations_chunked(data(), 1).unwrap_err()),
+        "import::insufficient_access_level"
+    );
+
+    db.run_default("::access_level hidden r").unwrap();
+    assert_eq!(
+        code(db.export_relations(["r"].into_iter()).unwrap_err()),
+        "export::insufficient_access_level"
+    );
+    assert_eq!(
+        code(db.export_schema(["r"].into_iter()).unwrap_err()),
+        "export::insufficient_access_level"
+    );
+    assert_eq!(
+        code(db.export_relation_range("r", vec![], vec![]).unwrap_err()),
+        "export::insufficient_access_level"
+    );
+}
+
+#[test]
+fn run_scripts() {
+    let db = DbInstance::default();
+    db.run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :create r {k => v}")
+        .unwrap();
+    let res = db.run_scripts(
+        &[
+            "?[count(k)] := *r[k, _]",
+            "?[x] := x = $y + 1",
+            "?[v] := *r[$y, v]",
+            "?[k, v] := *r[k, v]",
+        ],
+        BTreeMap::from([("y".to_string(), DataValue::from(1))]),
+        ScriptMutability::Immutable,
+    );
+    assert_eq!(res.len(), 4);
+    assert_eq!(res[0]["headers"], json!(["count(k)"]));
+    assert_eq!(res[0]["rows"], json!([[2]]));
+    assert_eq!(res[1]["rows"], json!([[2]]));
+    assert_eq!(res[2]["ok"], json!(true));
+    assert_eq!(res[2]["rows"], json!([["a"]]));
+    assert_eq!(res[3]["headers"], json!(["k", "v"]));
+    assert_eq!(res[3]["rows"], json!([[1, "a"], [2, "b"]]));
+
+    let res = db.run_scripts(
+        &["?[k] := *nope[k]", "?[k] := *r[k, _]"],
+        Default::default(),
+        ScriptMutability::Immutable,
+    );
+    assert_eq!(res[0]["ok"], json!(false));
+    assert_eq!(res[1]["rows"], json!([[1], [2]]));
+}
+
+#[test]
+fn script_is_write() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k => v}").unwrap();
+    let is_write = |script: &str| db.script_is_write(script, Default::default()).unwrap();
+
+    assert!(!is_write("?[k, v] := *r[k, v]"));
+    assert!(!is_write("::relations"));
+    assert!(!is_write("{?[k] := *r[k, _]} {?[x] <- [[1]]}"));
+    assert!(is_write("?[k, v] <- [[1, 2]] :put r {k => v}"));
+    assert!(!is_write("?[k] <- [[1]] :create _tmp {k}"));
+    assert!(is_write("::remove r"));
+    assert!(is_write(
+        "{?[k] := *r[k, _] :assert none} %if_not_empty { ?[k, v] <- [[1, 2]] :put r {k => v} } %end"
+    ));
+    assert!(db
+        .script_is_write("?[x] := x = ", Default::default())
+        .is_err());
+    assert!(db
+        .run_default("?[k, v] := *r[k, v]")
+        .unwrap()
+        .rows
+        .is_empty());
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn panic_in_write_leaves_no_partial_rows() {
+    let path = std::env::temp_dir().join(format!("cozo-panic-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    for db in [
+        DbInstance::default(),
+        DbInstance::new("sqlite", &path, "").unwrap(),
+    ] {
+        db.run_default(":create r {k}").unwrap();
+        db.register_fixed_rule(
+            "Explode".to_string(),
+            SimpleFixedRule::new(1, |_, _| panic!("exploded")),
+        )
+        .unwrap();
+
+        let res = std::panic::catch_unwind(|| {
+            db.run_default(
+                r#"
+                {?[k] <- [[1], [2]] :put r {k}}
+                {?[k] <~ Explode()}
+                "#,
+            )
+        });
+        assert!(res.is_err());
+
+        assert!(db.run_default("?[k] := *r[k]").unwrap().rows.is_empty());
+        db.run_default("?[k] <- [[3]] :put r {k}").unwrap();
+        assert_eq!(
+            db.run_default("?[k] := *r[k]").unwrap().rows,
+            vec![vec![DataValue::from(3)]]
+        );
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn import_skips_triggers() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k}").unwrap();
+    db.run_default(":create derived {k}").unwrap();
+    db.run_default("::set_triggers r on put { ?[k] := _new[k] :put derived {k} }")
+        .unwrap();
+    let data = NamedRows::new(vec!["k".to_string()], vec![vec![DataValue::from(1)]]);
+    db.import_relations(BTreeMap::from([("r".to_string(), data)]))
+        .unwrap();
+    assert_eq!(db.run_default("?[k] := *r[k]").unwrap().rows.len(), 1);
+    assert!(db
+        .run_default("?[k] := *derived[k]")
+        .unwrap()
+        .rows
+        .is_empty());
+}
+
+#[test]
+fn run_script_diag() {
+    let db = DbInstance::default();
+    let run =
+        |script: &str| db.run_script_diag(script, Default::default(), ScriptMutability::Mutable);
+    assert_eq!(
+        run("?[x] <- [[1]]").unwrap().rows,
+        vec![vec![DataValue::from(1)]]
+    );
+
+    let err = run("?[x] := *nope[x]").unwrap_err();
+    assert_eq!(err.code.as_deref(), Some("query::relation_not_found"));
+    assert_eq!(err.message, "Cannot find requested stored relation 'nope'");
+
+    let err = run("?[x] := y = 1").unwrap_err();
+    let span = err.span.unwrap();
+    assert_eq!(&"?[x] := y = 1"[span.0..span.0 + span.1], "x");
+    let serialized = serde_json::to_value(&err).unwrap();
+    assert_eq!(serialized["span"], json!({"start": span.0, "len": span.1}));
+    assert_eq!(
+        serde_json::from_value::<crate::CozoError>(serialized).unwrap(),
+        err
+    );
+    assert!(err.code.is_some());
+
+    let err = run("?[x] := *nope[x]").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "query::relation_not_found: Cannot find requested stored relation 'nope'"
+    );
+    let err: Box<dyn std::error::Error> = Box::new(err);
+    assert!(err.source().is_none());
+}
+
+#[test]
+fn error_json_span() {
+    let db = DbInstance::default();
+    let script = "?[x] <- [[1]] :assert none";
+    let res = db.run_script_fold_err(script, Default::default(), ScriptMutability::Immutable);
+    assert_eq!(res["ok"], json!(false));
+    let start = res["span"]["start"].as_u64().unwrap() as usize;
+    let len = res["span"]["len"].as_u64().unwrap() as usize;
+    assert_eq!(&script[start..start + len], ":assert none");
+
+    let res = db.run_script_fold_err(
+        "?[x] := *nope[x]",
+        Default::default(),
+        ScriptMutability::Immutable,
+    );
+    assert_eq!(res["ok"], json!(false));
+    assert!(res.get("span").is_none());
+}
+
+#[test]
+fn profile_stratum_times() {
+    let db = DbInstance::default();
+    let script = r#"
+        edge[a, b] <- [[1, 2], [2, 3], [3, 4]]
+        reach[a, b] := edge[a, b]
+        reach[a, c] := reach[a, b], edge[b, c]
+        ?[count(a)] := reach[a, _]
+    "#;
+    let res = db.run_script_fold_err(script, Default::default(), ScriptMutability::Immutable);
+    assert!(res.get("stratum_times").is_none());
+    assert!(res.get("compile_time").is_none());
+
+    let res = db.run_script_fold_err(
+        &format!("{script} :profile"),
+        Default::default(),
+        ScriptMutability::Immutable,
+    );
+    assert_eq!(res["rows"], json!([[6]]));
+    let times = res["stratum_times"].as_array().unwrap();
+    assert!(times.len() >= 2);
+    assert!(times.iter().all(|t| t.as_f64().unwrap() >= 0.));
+    assert!(res["compile_time"].as_f64().unwrap() >= 0.);
+
+    // a single-relation aggregate is still profiled
+    db.run_default("?[k] <- [[1], [2]] :create r {k}").unwrap();
+    let res = db.run_script_fold_err(
+        "?[count(k)] := *r{k} :profile",
+        Default::default(),
+        ScriptMutability::Immutable,
+    );
+    assert_eq!(res["rows"], json!([[2]]));
+    assert!(!res["stratum_times"].as_array().unwrap().is_empty());
+    assert!(res["compile_time"].as_f64().unwrap() >= 0.);
+}
+
+#[test]
+fn count_option() {
+    let db = DbInstance::default();
+    let count = |script: &str| {
+        let res = db
+            .run_script(script, Default::default(), ScriptMutability::Immutable)
+            .unwrap();
+        assert_eq!(res.headers, vec!["count"]);
+        res.rows[0][0].get_int().unwrap()
+    };
+    assert_eq!(count("?[x] := x in [1, 2, 3, 4, 5] :count"), 5);
+    assert_eq!(count("?[x] := x in [1, 2, 3, 4, 5] :limit 2 :count"), 2);
+    assert_eq!(count("?[x] := x in [1, 2, 3, 4, 5] :offset 4 :count"), 1);
+    assert_eq!(count("?[x] := x in [1, 2, 3, 4, 5] :offset 6 :count"), 0);
+    assert_eq!(
+        count("?[x] := x in [1, 2, 3, 4, 5] :order -x :offset 1 :limit 3 :count"),
+        3
+    );
+    assert_eq!(count("?[x] := x in [] :count"), 0);
+
+    let err = db
+        .run_script(
+            "?[x] <- [[1]] :create rel {x} :count",
+            Default::default(),
+            ScriptMutability::Mutable,
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains(":count"));
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn storage_info() {
+    let info = DbInstance::default().storage_info();
+    assert_eq!(info.engine, "mem");
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+
+    let path = std::env::temp_dir().join(format!("cozo-info-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db = DbInstance::new("sqlite", &path, "").unwrap();
+    let info = db.storage_info();
+    assert_eq!(info.engine, "sqlite");
+    assert!(info.version.starts_with("3."));
+    drop(db);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn subscribe_change_events() {
+    let db = DbInstance::default();
+    let (_id, callbacks) = db.register_callback("r", None);
+    let events = db.subscribe("r");
+    db.run_default(":create r {k: Int => v: Int}").unwrap();
+    db.run_default("?[k, v] <- [[1, 10], [2, 20]] :put r {k => v}")
+        .unwrap();
+    db.run_default("?[k] <- [[1]] :rm r {k}").unwrap();
+
+    // a transaction that fails to complete sends nothing
+    assert!(db
+        .run_default("{?[k, v] <- [[3, 30]] :put r {k => v}} {?[x] <- [[1]] :assert none}")
+        .is_err());
+
+    let mut data = BTreeMap::new();
+    data.insert(
+        "r".to_string(),
+        NamedRows::new(
+            vec!["k".to_string(), "v".to_string()],
+            vec![vec![DataValue::from(4), DataValue::from(40)]],
+        ),
+    );
+    db.import_relations(data).unwrap();
+
+    let received = events.try_iter().collect_vec();
+    assert_eq!(
+        received,
+        vec![
+            ChangeEvent {
+                op: CallbackOp::Put,
+                key: vec![DataValue::from(1)],
+                value: Some(vec![DataValue::from(10)]),
+            },
+            ChangeEvent {
+                op: CallbackOp::Put,
+                key: vec![DataValue::from(2)],
+                value: Some(vec![DataValue::from(20)]),
+            },
+            ChangeEvent {
+                op: CallbackOp::Rm,
+                key: vec![DataValue::from(1)],
+                value: None,
+            },
+            ChangeEvent {
+                op: CallbackOp::Put,
+                key: vec![DataValue::from(4)],
+                value: Some(vec![DataValue::from(40)]),
+            },
+        ]
+    );
+    // callbacks are not run for imports
+    assert_eq!(callbacks.try_iter().count(), 2);
+
+    drop(events);
+    db.run_default("?[k, v] <- [[5, 50]] :put r {k => v}")
+        .unwrap();
+    assert_eq!(callbacks.try_iter().count(), 1);
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn write_lock_timeout() {
+    let path = std::env::temp_dir().join(format!("cozo-write-timeout-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    for db in [
+        DbInstance::default(),
+        DbInstance::new("sqlite", &path, "").unwrap(),
+    ] {
+        db.run_default(":create r {k}").unwrap();
+        db.set_write_lock_timeout(0.05);
+
+        let tx = db.begin_transaction(true).unwrap();
+        let err = db.run_default("?[k] <- [[1]] :put r {k}").unwrap_err();
+        assert_eq!(err.code().unwrap().to_string(), "db::write_timeout");
+        drop(tx);
+
+        db.run_default("?[k] <- [[1]] :put r {k}").unwrap();
+        db.set_write_lock_timeout(0.);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn relaxed_durability() {
+    use crate::Durability;
+
+    let path = std::env::temp_dir().join(format!("cozo-durability-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let db = DbInstance::new("sqlite", &path, "").unwrap();
+        db.set_durability(Durability::Relaxed).unwrap();
+        db.run_default(":create r {k}").unwrap();
+        db.run_default("?[k] := k in int_range(100) :put r {k}")
+            .unwrap();
+        db.flush().unwrap();
+        db.set_durability(Durability::Full).unwrap();
+        db.run_default("?[k] <- [[100]] :put r {k}").unwrap();
+    }
+    let db = DbInstance::new("sqlite", &path, "").unwrap();
+    let res = db.run_default("?[count(k)] := *r{k}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(101)]]);
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+
+    let db = DbInstance::default();
+    db.set_durability(Durability::Relaxed).unwrap();
+    db.flush().unwrap();
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn import_from_backup_checks_layout() {
+    let path = std::env::temp_dir().join(format!("cozo-layout-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let src = DbInstance::default();
+    src.run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :create a {k: Int => v: String}")
+        .unwrap();
+    src.run_default("?[k, v] <- [[1, 'a']] :create b {k: Int => v: String}")
+        .unwrap();
+    src.backup_db(&path).unwrap();
+
+    let db = DbInstance::default();
+    db.run_default(":create a {k: Int => v: String}").unwrap();
+    db.run_default(":create b {k: String => v: String}")
+        .unwrap();
+    let err = db
+        .import_from_backup(&path, &["a".to_string(), "b".to_string()])
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "import::schema_mismatch");
+    // nothing was copied, not even the rows of the compatible relation
+    let res = db.run_default("?[count(k)] := *a{k}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(0)]]);
+
+    db.run_default("::remove b").unwrap();
+    db.run_default(":create b {k: Int => w: String}").unwrap();
+    assert!(db.import_from_backup(&path, &["b".to_string()]).is_err());
+
+    db.import_from_backup(&path, &["a".to_string()]).unwrap();
+    let res = db.run_default("?[count(k)] := *a{k}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn import_from_backup_renamed() {
+    let path = std::env::temp_dir().join(format!("cozo-renamed-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let src = DbInstance::default();
+    src.run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :create orders {k: Int => v: String}")
+        .unwrap();
+    src.backup_db(&path).unwrap();
+
+    let db = DbInstance::default();
+    db.run_default(":create orders {k: Int => v: String}")
+        .unwrap();
+    db.run_default(":create orders_archive {k: Int => v: String}")
+        .unwrap();
+    db.import_from_backup_renamed(
+        &path,
+        &[("orders".to_string(), "orders_archive".to_string())],
+    )
+    .unwrap();
+    let res = db.run_default("?[k, v] := *orders_archive{k, v}").unwrap();
+    assert_eq!(res.rows.len(), 2);
+    let res = db.run_default("?[k] := *orders{k}").unwrap();
+    assert!(res.rows.is_empty());
+
+    let payload = json!({
+        "path": path.to_str().unwrap(),
+        "relations": [["orders", "orders_archive"], "orders"]
+    });
+    let res = db.import_from_backup_str(&payload.to_string());
+    assert_eq!(res, json!({"ok": true}).to_string());
+    let res = db.run_default("?[k] := *orders{k}").unwrap();
+    assert_eq!(res.rows.len(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn vacuum_reclaims_space() {
+    use crate::data::tuple::TupleT;
+    use crate::runtime::relation::RelationId;
+
+    let path = std::env::temp_dir().join(format!("cozo-vacuum-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db = DbInstance::new("sqlite", &path, "").unwrap();
+    db.run_default(":create a {k: Int => v: String}").unwrap();
+    db.run_default(":create b {k: Int => v: String}").unwrap();
+    let fill = "?[k, v] := k in int_range(2000), v = concat('row number ', to_string(k))";
+    db.run_default(&format!("{fill} :put a {{k => v}}"))
+        .unwrap();
+    db.run_default(&format!("{fill} :put b {{k => v}}"))
+        .unwrap();
+
+    // an interrupted removal of `a` leaves its rows without the relation
+    if let DbInstance::Sqlite(db) = &db {
+        let mut tx = db.transact_write().unwrap();
+        tx.store_tx
+            .del(&vec![DataValue::from("a")].encode_as_key(RelationId::SYSTEM))
+            .unwrap();
+        tx.commit_tx().unwrap();
+    }
+    db.run_default("?[k] := k in int_range(10, 2000) :rm b {k}")
+        .unwrap();
+
+    let res = db.run_default("::vacuum").unwrap();
+    assert_eq!(
+        res.headers,
+        ["orphaned_rows", "orphaned_bytes", "reclaimed_bytes"]
+    );
+    assert_eq!(res.rows[0][0], DataValue::from(2000));
+    assert!(res.rows[0][1].get_int().unwrap() > 2000 * 10);
+    assert!(res.rows[0][2].get_int().unwrap() > 0);
+    let res = db.run_default("?[count(k)] := *b{k}").unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(10));
+
+    let res = db.run_default("::vacuum").unwrap();
+    assert_eq!(
+        res.rows[0],
+        vec![DataValue::from(0), DataValue::from(0), DataValue::from(0)]
+    );
+    assert!(db.run_default("{::vacuum}").is_err());
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn sleep_does_not_hold_write_transaction() {
+    let db = DbInstance::default();
+    db.run_default(":create r {a}").unwrap();
+
+    // within a script the write transaction is open, so the sleep is skipped
+    let start = std::time::Instant::now();
+    let (_, report) = db
+        .run_script_with_report(
+            "{?[a] <- [[1]] :put r {a} :sleep 10}",
+            Default::default(),
+            ScriptMutability::Mutable,
+        )
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(
+        report.warnings,
+        vec!["`:sleep 10` is ignored within a write transaction".to_string()]
+    );
+
+    // a lone query sleeps after committing, so others can write meanwhile
+    let sleeper = {
+        let db = db.clone();
+        std::thread::spawn(move || {
+            db.run_default("?[a] <- [[2]] :put r {a} :sleep 1").unwrap();
+        })
+    };
+    std::thread::sleep(Duration::from_millis(200));
+    let start = std::time::Instant::now();
+    db.run_default("?[a] <- [[3]] :put r {a}").unwrap();
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert!(!sleeper.is_finished());
+    sleeper.join().unwrap();
+    let res = db.run_default("?[a] := *r{a}").unwrap();
+    assert_eq!(res.rows.len(), 3);
+}
+
+#[test]
+#[allow(irrefutable_let_patterns)]
+fn scan_readahead() {
+    let db = DbInstance::default();
+    db.set_scan_readahead(4 << 20);
+    db.run_default("?[k, v] := k in int_range(100), v = k * 2 :create r {k => v}")
+        .unwrap();
+    let exported = db.export_relations(["r"].iter()).unwrap();
+    assert_eq!(exported["r"].rows.len(), 100);
+    if let DbInstance::Mem(db) = &db {
+        let rows: Vec<_> = db.export_relation_iter("r").unwrap().try_collect().unwrap();
+        assert_eq!(rows.len(), 100);
+        assert_eq!(rows[99], vec![DataValue::from(99), DataValue::from(198)]);
+    }
+}
+
+#[test]
+fn parse_script_outline() {
+    let db = DbInstance::default();
+    let script = "{?[a] <- [[$x]] :put r {a}} {?[a] := *r{a}, *s{a} :limit 1}";
+    let params = BTreeMap::from([("x".to_string(), DataValue::from(5))]);
+    let parsed = db.parse_script(script, params).unwrap();
+    assert_eq!(parsed.kind, ScriptKind::Imperative);
+    assert_eq!(parsed.programs.len(), 2);
+
+    let put = &parsed.programs[0];
+    assert_eq!(
+        &script[put.span.0..put.span.0 + put.span.1],
+        "?[a] <- [[$x]] :put r {a}"
+    );
+    assert_eq!(put.writes, Some(("r".to_string(), ":put".to_string())));
+    assert!(put.text.contains('5'));
+
+    let read = &parsed.programs[1];
+    assert_eq!(
+        &script[read.span.0..read.span.0 + read.span.1],
+        "?[a] := *r{a}, *s{a} :limit 1"
+    );
+    assert_eq!(read.reads, ["r", "s"]);
+    assert_eq!(read.rules.len(), 1);
+    assert_eq!(read.rules[0].0, "?");
+    assert_eq!(read.writes, None);
+
+    let parsed = db.parse_script("::relations", Default::default()).unwrap();
+    assert_eq!(parsed.kind, ScriptKind::System);
+    assert!(parsed.programs.is_empty());
+    assert!(db.parse_script("?[a] := *r{a", Default::default()).is_err());
+}
+
+#[test]
+fn assert_count() {
+    let db = DbInstance::default();
+    let res = db
+        .run_default("?[x] := x in int_range(5) :assert count = 5")
+        .unwrap();
+    assert_eq!(res.rows.len(), 5);
+
+    let err = db
+        .run_default("?[x] := x in int_range(5) :assert count = 3")
+        .unwrap_err();
+    assert!(err.to_string().contains("asserted to return 3 rows"));
+
+    // the count is taken before the limit is applied
+    let res = db
+        .run_default("?[x] := x in int_range(5) :limit 2 :assert count = 5")
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+    assert!(db
+        .run_default("?[x] := x in int_range(5) :limit 2 :assert count = 2")
+        .is_err());
+
+    db.run_default("?[x] <- [[1]] :assert 1").unwrap();
+    assert!(db
+        .run_default("?[x] <- [[1]] :assert count = 1 :assert some")
+        .is_err());
+}
+
+#[test]
+fn running_plan() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k: Int}").unwrap();
+    db.run_default("?[k] <- [[0]] :put r {k}").unwrap();
+    let script = r#"
+        r[n] := *r{k: n}
+        r[m] := r[n], m = n + 1
+        ?[n] := r[n]
+    "#;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let runner_db = db.clone();
+    let runner = std::thread::spawn(move || {
+        runner_db.run_script_with_query_id(
+            script,
+            Default::default(),
+            ScriptMutability::Immutable,
+            move |id| sender.send(id).unwrap(),
+        )
+    });
+    let id = receiver.recv().unwrap();
+    let run = |script: &str, id: u64| {
+        db.run_script(
+            script,
+            BTreeMap::from([("id".to_string(), DataValue::from(id as i64))]),
+            ScriptMutability::Immutable,
+        )
+    };
+
+    let plan = run("::running_plan $id", id).unwrap();
+    assert_eq!(plan.headers[0], "stratum");
+    let op_idx = plan.headers.iter().position(|h| h == "op").unwrap();
+    let ref_idx = plan.headers.iter().position(|h| h == "ref").unwrap();
+    let est_idx = plan.headers.iter().position(|h| h == "est_rows").unwrap();
+    assert!(plan
+        .rows
+        .iter()
+        .any(|row| row[op_idx] == DataValue::from("load_stored")
+            && row[ref_idx] == DataValue::from(":r")));
+    assert!(plan.rows.iter().all(|row| row[est_idx] == DataValue::Null));
+
+    let err = run("::running_plan $id", id + 1000).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::query_not_running");
+
+    run("::kill $id", id).unwrap();
+    let err = runner.join().unwrap().unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+}
+
+#[test]
+fn max_concurrent_queries() {
+    let db = DbInstance::default();
+    db.set_max_concurrent_queries(Some(1));
+    db.run_default("?[x] <- [[1]]").unwrap();
+    let prepared = db.prepare("?[x] <- [[1]]").unwrap();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let runner_db = db.clone();
+    let runner = std::thread::spawn(move || {
+        runner_db.run_script_with_query_id(
+            "r[n] := n = 0 r[m] := r[n], m = n + 1 ?[n] := r[n]",
+            Default::default(),
+            ScriptMutability::Immutable,
+            move |id| sender.send(id).unwrap(),
+        )
+    });
+    let id = receiver.recv().unwrap();
+    // a mutable script would wait for the running query to release the in-memory store
+    let run = |script: &str| db.run_script(script, Default::default(), ScriptMutability::Immutable);
+
+    let err = run("?[x] <- [[1]]").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "db::too_busy");
+    let err = run("{?[x] <- [[1]]}").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "db::too_busy");
+    let err = db.execute(&prepared, Default::default()).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "db::too_busy");
+    let err = db.begin_transaction(false).err().unwrap();
+    assert_eq!(err.code().unwrap().to_string(), "db::too_busy");
+    // system ops are always admitted
+    assert_eq!(run("::running").unwrap().rows.len(), 1);
+
+    db.set_max_concurrent_queries(None);
+    run("?[x] <- [[1]]").unwrap();
+    db.set_max_concurrent_queries(Some(1));
+
+    db.run_script(
+        "::kill $id",
+        BTreeMap::from([("id".to_string(), DataValue::from(id as i64))]),
+        ScriptMutability::Immutable,
+    )
+    .unwrap();
+    runner.join().unwrap().unwrap_err();
+    db.run_default("?[x] <- [[1]]").unwrap();
+
+    // a transaction holds its slot until it ends
+    let mut tx = db.begin_transaction(false).unwrap();
+    tx.run_script("?[x] <- [[1]]", Default::default()).unwrap();
+    let err = run("?[x] <- [[1]]").unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "db::too_busy");
+    tx.rollback();
+    db.execute(&prepared, Default::default()).unwrap();
+}
+
+#[test]
+fn raw_row_access() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k: Int => v: String, w: Float default 0.}")
+        .unwrap();
+    db.run_default("::index create r:by_v {v}").unwrap();
+
+    assert_eq!(
+        db.relation_get("r", vec![DataValue::from(1)]).unwrap(),
+        None
+    );
+    db.relation_put(
+        "r",
+        vec![DataValue::from(1), DataValue::from("a"), DataValue::from(1)],
+    )
+    .unwrap();
+    // the value of w is coerced to a float
+    assert_eq!(
+        db.relation_get("r", vec![DataValue::from(1)]).unwrap(),
+        Some(vec![
+            DataValue::from(1),
+            DataValue::from("a"),
+            DataValue::from(1.)
+        ])
+    );
+    db.relation_put(
+        "r",
+        vec![
+            DataValue::from(1),
+            DataValue::from("b"),
+            DataValue::from(2.),
+        ],
+    )
+    .unwrap();
+    let res = db.run_default("?[v, k] := *r:by_v{v, k}").unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from("b"), DataValue::from(1)]]
+    );
+
+    let err = db
+        .relation_put("r", vec![DataValue::from(2), DataValue::from("c")])
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::raw_row_arity");
+    assert!(db
+        .relation_put(
+            "r",
+            vec![
+                DataValue::from("x"),
+                DataValue::from("c"),
+                DataValue::from(1.)
+            ]
+        )
+        .is_err());
+    assert!(db
+        .relation_put("r:by_v", vec![DataValue::from("c"), DataValue::from(2)])
+        .is_err());
+
+    db.relation_del("r", vec![DataValue::from(1)]).unwrap();
+    db.relation_del("r", vec![DataValue::from(1)]).unwrap();
+    assert_eq!(
+        db.relation_get("r", vec![DataValue::from(1)]).unwrap(),
+        None
+    );
+    assert!(db
+        .run_default("?[v, k] := *r:by_v{v, k}")
+        .unwrap()
+        .rows
+        .is_empty());
+
+    db.run_default("::access_level hidden r").unwrap();
+    assert!(db.relation_get("r", vec![DataValue::from(1)]).is_err());
+}
+
+#[test]
+fn with_types() {
+    let db = DbInstance::default();
+    let run = |script: &str| {
+        db.run_script_with_report(script, Default::default(), ScriptMutability::Mutable)
+            .unwrap()
+    };
+    let (_, report) = run("?[a, b] <- [[1, 'x']]");
+    assert_eq!(report.column_types, None);
+    let res = db.run_script_fold_err(
+        "?[a, b] <- [[1, 'x']]",
+        Default::default(),
+        ScriptMutability::Immutable,
+    );
+    assert!(res.get("column_types").is_none());
+
+    let (_, report) = run(
+        "?[a, b, c, d, e] <- [[1, 'x', null, [1], 1], [2, null, null, [2, 3], 1.5]] \
+         :with_types",
+    );
+    assert_eq!(
+        report.column_types.unwrap(),
+        ["Int", "String?", "Any", "[Any]", "Any"]
+    );
+    let res = db.run_script_fold_err(
+        "?[v] := v = vec([1, 2, 3]) :with_types",
+        Default::default(),
+        ScriptMutability::Immutable,
+    );
+    assert_eq!(res["column_types"], json!(["<F32;3>"]));
+
+    db.run_default(":create r {k: Int => v: String?}").unwrap();
+    let (res, report) = run("?[k, v] <- [[1, 'a']] :put r {k => v} :returning :with_types");
+    assert_eq!(res.headers, ["_kind", "k", "v"]);
+    assert_eq!(report.column_types.unwrap(), ["String", "Int", "String?"]);
+    let (_, report) = run("?[count(k)] := *r{k} :with_types");
+    assert_eq!(report.column_types.unwrap(), ["Int"]);
+
+    let prepared = db.prepare("?[a] <- [[1]] :with_types").unwrap();
+    let (_, report) = db
+        .execute_with_report(&prepared, Default::default())
+        .unwrap();
+    assert_eq!(report.column_types.unwrap(), ["Int"]);
+}
+
+#[test]
+fn import_relations_resumable() {
+    let db = DbInstance::default();
+    db.run_default(":create r {k: Int => v: String}").unwrap();
+    let data = |bad: Option<i64>, tag: &str| {
+        let rows = (0..10)
+            .map(|i| match bad {
+                Some(b) if b == i => vec![DataValue::from(i), DataValue::from(i)],
+                _ => vec![DataValue::from(i), DataValue::from(format!("{tag}{i}"))],
+            })
+            .collect_vec();
+        BTreeMap::from([(
+            "r".to_string(),
+            NamedRows::new(vec!["k".into(), "v".into()], rows),
+        )])
+    };
+
+    let err = db
+        .import_relations_resumable(data(Some(5), "a"), 2, "ckpt")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "4 rows were committed before the failure");
+    let res = db
+        .run_default("?[relation, rows] := *ckpt{relation, rows}")
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from("r"), DataValue::from(4)]]
+    );
+
+    // the rows committed by the failed run are skipped
+    db.import_relations_resumable(data(None, "b"), 2, "ckpt")
+        .unwrap();
+    let res = db.run_default("?[k, v] := *r{k, v}, k in [3, 4]").unwrap();
+    assert_eq!(
+        res.rows,
+        vec![
+            vec![DataValue::from(3), DataValue::from("a3")],
+            vec![DataValue::from(4), DataValue::from("b4")]
+        ]
+    );
+    let res = db
+        .run_default("?[rows] := *ckpt{relation: 'r', rows}")
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(10)]]);
+
+    // a complete import is not run again
+    db.import_relations_resumable(data(None, "c"), 2, "ckpt")
+        .unwrap();
+    let res = db.run_default("?[v] := *r{k: 9, v}").unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from("b9")]]);
+
+    let err = db
+        .import_relations_resumable(data(None, "c"), 2, "r")
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "import::bad_checkpoint_relation"
+    );
+}
+
+#[cfg(feature = "storage-sqlite")]
+#[test]
+fn backup_with_key_transform() {
+    let path = std::env::temp_dir().join(format!("cozo-keys-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db = DbInstance::default();
+    db.run_default("?[k, v] <- [[1, 'a'], [2, 'b']] :create r {k: Int => v: String}")
+        .unwrap();
+    let mut total = 0;
+    db.backup_db_with_key_transform(&path, |k| [b"ns/".as_slice(), k].concat(), |n| total = n)
+        .unwrap();
+
+    let src = if let DbInstance::Mem(db) = &db {
+        let tx = db.transact().unwrap();
+        tx.store_tx
+            .range_scan(&[], &[0xFF])
+            .map(|kv| kv.unwrap())
+            .collect_vec()
+    } else {
+        unreachable!()
+    };
+    assert_eq!(total, src.len() as u64);
+
+    let backup = crate::new_cozo_sqlite(&path).unwrap();
+    let tx = backup.transact().unwrap();
+    let copied = tx
+        .store_tx
+        .range_scan(b"ns/", b"ns0")
+        .map(|kv| kv.unwrap())
+        .collect_vec();
+    drop(tx);
+    drop(backup);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(copied.len(), src.len());
+    for ((k, v), (src_k, src_v)) in copied.iter().zip(&src) {
+        assert_eq!(&k[3..], &src_k[..]);
+        assert_eq!(v, src_v);
+    }
+
+    // reversing the keys breaks their order, and mapping them all to one key collides them
+    for transform in [
+        &(|k: &[u8]| k.iter().rev().copied().collect()) as &dyn Fn(&[u8]) -> Vec<u8>,
+        &|_: &[u8]| b"same".to_vec(),
+    ] {
+        let err = db
+            .backup_db_with_key_transform(&path, transform, |_| {})
+            .unwrap_err();
+        assert!(err.to_string().contains("strictly ascending"), "{err}");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -6,8 +6,10 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::sync::atomic::{AtomicU32, AtomicU64};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+#[allow(unused_imports)]
+use std::time::{Duration, Instant};
 
 use miette::{bail, Result};
 use crate::data::program::ReturnMutation;
@@ -16,7 +18,12 @@ use crate::data::tuple::TupleT;
 use crate::data::value::DataValue;
 use crate::fts::TokenizerCache;
 use crate::{CallbackOp, NamedRows};
+use crate::query::ra::RaStatsCollector;
 use crate::runtime::callback::CallbackCollector;
+use crate::runtime::db::{
+    AggrProgressCallback, EvalProgressCallback, Poison, QueryReport, QueryStartCallback,
+    RunningScript,
+};
 use crate::runtime::relation::RelationId;
 use crate::storage::temp::TempTx;
 use crate::storage::StoreTx;
@@ -27,6 +34,101 @@ pub struct SessionTx<'a> {
     pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) temp_store_id: AtomicU32,
     pub(crate) tokenizers: Arc<TokenizerCache>,
+    pub(crate) commit_latencies: Option<Arc<CommitLatencies>>,
+    /// Set by the `:max_join_matches` option of the query being evaluated
+    pub(crate) max_join_matches: Option<usize>,
+    /// Memory budget in bytes for the tuples derived by the query being evaluated
+    pub(crate) mem_limit: Option<usize>,
+    /// Receives intermediate values of meet aggregations during evaluation
+    pub(crate) aggr_progress: Option<AggrProgressCallback>,
+    /// Receives the progress of evaluation, and can stop it
+    pub(crate) eval_progress: Option<EvalProgressCallback>,
+    /// Supplied by the caller to cancel the queries run in this transaction
+    pub(crate) cancel: Option<Poison>,
+    /// The script run in this transaction, shown by `::running`
+    pub(crate) script: Option<Arc<RunningScript>>,
+    /// Called with the id of the first query registered as running in this transaction
+    pub(crate) on_query_start: Option<QueryStartCallback>,
+    /// Collects per-node row counts and timings for `::explain_analyze`
+    pub(crate) ra_stats: Option<RaStatsCollector>,
+    /// Seconds taken by each stratum of the last query run with `:profile`
+    pub(crate) stratum_times: Option<Vec<f64>>,
+    /// Receives the report of each top-level query run in this transaction
+    pub(crate) report: Option<Arc<Mutex<QueryReport>>>,
+    /// Set by [SessionTx::commit_tx]. A transaction dropped before that is rolled back.
+    pub(crate) committed: bool,
+    /// Whether this is a write transaction, which holds up other writers while it is open
+    pub(crate) is_write: bool,
+}
+
+impl Drop for SessionTx<'_> {
+    fn drop(&mut self) {
+        // read transactions have nothing to roll back
+        if self.is_write && !self.committed {
+            // nothing can be reported from here: the storage engine discards
+            // the changes anyway once its transaction is gone
+            let _ = self.store_tx.rollback();
+        }
+    }
+}
+
+const N_LATENCY_BUCKETS: usize = 32;
+
+/// Histogram of the time spent committing write transactions.
+/// Bucket `i` counts commits taking less than `2^i` microseconds
+/// (and at least `2^(i-1)` microseconds), the last bucket catches everything longer.
+#[derive(Default)]
+pub(crate) struct CommitLatencies {
+    buckets: [AtomicU64; N_LATENCY_BUCKETS],
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl CommitLatencies {
+    pub(crate) fn record(&self, took: Duration) {
+        let micros = took.as_micros().min(u64::MAX as u128) as u64;
+        let idx = ((u64::BITS - micros.leading_zeros()) as usize).min(N_LATENCY_BUCKETS - 1);
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+    pub(crate) fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+    /// Mean commit time in seconds
+    pub(crate) fn mean(&self) -> f64 {
+        let count = self.count();
+        if count == 0 {
+            return 0.;
+        }
+        self.total_micros.load(Ordering::Relaxed) as f64 / count as f64 / 1e6
+    }
+    /// Longest commit time in seconds
+    pub(crate) fn max(&self) -> f64 {
+        self.max_micros.load(Ordering::Relaxed) as f64 / 1e6
+    }
+    /// Upper bound, in seconds, of the commit time below which the fraction `p` of commits fall.
+    pub(crate) fn percentile(&self, p: f64) -> f64 {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return 0.;
+        }
+        let target = ((total as f64 * p).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, n) in counts.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                let upper = (1u64 << i).min(self.max_micros.load(Ordering::Relaxed));
+                return upper as f64 / 1e6;
+            }
+        }
+        self.max()
+    }
 }
 
 pub const CURRENT_STORAGE_VERSION: [u8; 1] = [0x00];
@@ -38,23 +140,31 @@ fn storage_version_key() -> Vec<u8> {
 
 const STATUS_STR: &str = "status";
 const OK_STR: &str = "OK";
+const ROWS_AFFECTED_STR: &str = "rows_affected";
 
 impl<'a> SessionTx<'a> {
-    pub(crate) fn get_returning_rows(&self, callback_collector: &mut CallbackCollector, rel: &str, returning: &ReturnMutation) -> Result<NamedRows> {
+    pub(crate) fn get_returning_rows(
+        &self,
+        callback_collector: &mut CallbackCollector,
+        rel: &str,
+        returning: &ReturnMutation,
+        n_affected: usize,
+    ) -> Result<NamedRows> {
         let returned_rows = {
             match returning {
-                ReturnMutation::NotReturning => {
-                    NamedRows::new(
-                        vec![STATUS_STR.to_string()],
-                        vec![vec![DataValue::from(OK_STR)]],
-                    )
-                }
+                ReturnMutation::NotReturning => NamedRows::new(
+                    vec![STATUS_STR.to_string(), ROWS_AFFECTED_STR.to_string()],
+                    vec![vec![
+                        DataValue::from(OK_STR),
+                        DataValue::from(n_affected as i64),
+                    ]],
+                ),
                 ReturnMutation::Returning => {
                     let meta = self.get_relation(rel, false)?;
                     let target_len = meta.metadata.keys.len() + meta.metadata.non_keys.len();
                     let mut returned_rows = Vec::new();
                     if let Some(collected) = callback_collector.get(&meta.name) {
-                        for (kind, insertions, deletions) in collected {
+                        for (kind, insertions, deletions) in &collected.batches {
                             let (pos_key, neg_key) = match kind {
                                 CallbackOp::Put => { ("inserted", "replaced") }
                                 CallbackOp::Rm => { ("requested", "deleted") }
@@ -80,14 +190,8 @@ impl<'a> SessionTx<'a> {
                         }
                     }
                     let mut header = vec!["_kind".to_string()];
-                    header.extend(meta.metadata.keys
-                        .iter()
-                        .chain(meta.metadata.non_keys.iter())
-                        .map(|s| s.name.to_string()));
-                    NamedRows::new(
-                        header,
-                        returned_rows,
-                    )
+                    header.extend(meta.column_names());
+                    NamedRows::new(header, returned_rows)
                 }
             }
         };
@@ -130,7 +234,16 @@ impl<'a> SessionTx<'a> {
     }
 
     pub fn commit_tx(&mut self) -> Result<()> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(latencies) = &self.commit_latencies {
+            let start = Instant::now();
+            self.store_tx.commit()?;
+            latencies.record(start.elapsed());
+            self.committed = true;
+            return Ok(());
+        }
         self.store_tx.commit()?;
+        self.committed = true;
         Ok(())
     }
 }
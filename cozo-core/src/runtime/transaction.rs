@@ -0,0 +1,410 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use crossbeam::sync::{ShardedLock, ShardedLockReadGuard};
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::parse::parse_script;
+use crate::runtime::callback::CallbackCollector;
+use crate::runtime::db::{AdmittedScript, RunningScript};
+use crate::runtime::transact::SessionTx;
+use crate::storage::temp::TempTx;
+use crate::storage::{ScanHint, StoreTx};
+use crate::{DataValue, Db, NamedRows, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot write to stored relation '{0}' in a read-only transaction")]
+#[diagnostic(code(tx::read_only))]
+#[diagnostic(help("Begin the transaction with `write` set to true"))]
+struct ReadOnlyTransactionError(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("No savepoint named '{0}' in the transaction")]
+#[diagnostic(code(tx::savepoint_not_found))]
+struct SavepointNotFound(String);
+
+/// A transaction spanning several scripts, created by [Db::begin_transaction].
+///
+/// All scripts run against the same snapshot and their writes become visible to others
+/// only when [commit](Self::commit) is called. Dropping the transaction without committing
+/// rolls it back. Unlike [crate::MultiTransaction], no thread is involved: the transaction
+/// borrows the database and runs each script on the calling thread.
+///
+/// Parts of the transaction can be rolled back with [savepoint](Self::savepoint)
+/// and [rollback_to](Self::rollback_to).
+pub struct CozoTransaction<'s, S: Storage<'s>> {
+    db: &'s Db<S>,
+    tx: SessionTx<'s>,
+    is_write: bool,
+    cur_vld: ValidityTs,
+    cleanups: Vec<(Vec<u8>, Vec<u8>)>,
+    callback_targets: BTreeSet<SmartString<LazyCompact>>,
+    callback_collector: CallbackCollector,
+    write_locks: BTreeMap<SmartString<LazyCompact>, HeldRelationLock>,
+    undo_log: Arc<Mutex<UndoLog>>,
+    savepoints: Vec<Savepoint>,
+    _admitted: AdmittedScript,
+}
+
+/// A relation lock taken for writing, held until the transaction is over,
+/// so that the relation cannot be changed underneath it by index or other exclusive operations
+struct HeldRelationLock {
+    // declared first, as it must be dropped before the lock it borrows from
+    _guard: ShardedLockReadGuard<'static, ()>,
+    _lock: Arc<ShardedLock<()>>,
+}
+
+impl HeldRelationLock {
+    fn new(lock: Arc<ShardedLock<()>>) -> Self {
+        let guard = lock.read().unwrap();
+        // SAFETY: the guard borrows the lock inside the allocation of the `Arc`, which does not
+        // move and is kept alive by `_lock` until after the guard is dropped
+        let guard = unsafe {
+            mem::transmute::<ShardedLockReadGuard<'_, ()>, ShardedLockReadGuard<'static, ()>>(guard)
+        };
+        Self {
+            _guard: guard,
+            _lock: lock,
+        }
+    }
+}
+
+/// The state of a transaction when a savepoint was set, apart from stored data,
+/// which is restored from the undo log
+struct Savepoint {
+    name: String,
+    undo_len: usize,
+    cleanups_len: usize,
+    callbacks_len: BTreeMap<SmartString<LazyCompact>, usize>,
+    temp_store_tx: TempTx,
+    temp_store_id: u32,
+}
+
+/// The previous values of the keys written while a savepoint is set, oldest first
+#[derive(Default)]
+struct UndoLog {
+    recording: bool,
+    entries: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+/// Wraps the storage transaction of a [CozoTransaction] to fill its undo log
+struct UndoLogTx<'s> {
+    inner: Box<dyn StoreTx<'s> + 's>,
+    log: Arc<Mutex<UndoLog>>,
+}
+
+impl UndoLogTx<'_> {
+    fn record(&self, key: &[u8]) -> Result<()> {
+        // the log is not locked while reading, so that parallel writes are not serialized on it
+        if !self.log.lock().unwrap().recording {
+            return Ok(());
+        }
+        let old = self.inner.get(key, false)?;
+        self.log.lock().unwrap().entries.push((key.to_vec(), old));
+        Ok(())
+    }
+}
+
+impl<'s> StoreTx<'s> for UndoLogTx<'s> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+
+    fn multi_get(&self, keys: &[Vec<u8>], for_update: bool) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.multi_get(keys, for_update)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record(key)?;
+        self.inner.put(key, val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.record(key)?;
+        self.inner.par_put(key, val)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.record(key)?;
+        self.inner.del(key)
+    }
+
+    fn par_del(&self, key: &[u8]) -> Result<()> {
+        self.record(key)?;
+        self.inner.par_del(key)
+    }
+
+    // only called when committing, after which there is nothing left to roll back
+    fn del_range_from_persisted(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.del_range_from_persisted(lower, upper)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.inner.rollback()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_tuple(lower, upper)
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        self.inner.range_skip_scan_tuple(lower, upper, valid_at)
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan(lower, upper)
+    }
+
+    fn range_scan_with_hint<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        hint: ScanHint,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.range_scan_with_hint(lower, upper, hint)
+    }
+
+    fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
+    where
+        's: 'a,
+    {
+        self.inner.range_count(lower, upper)
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.inner.total_scan()
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Begin a transaction in which several scripts can be run as one atomic unit.
+    ///
+    /// Whether the transaction may write must be decided up front with `write`,
+    /// as storage engines cannot turn a read transaction into a write transaction.
+    /// Write transactions _may_ block other reads, but we guarantee that this does not happen
+    /// for the RocksDB backend.
+    pub fn begin_transaction(&'s self, write: bool) -> Result<CozoTransaction<'s, S>> {
+        let admitted = self.admit_script()?;
+        let mut tx = if write {
+            self.transact_write()?
+        } else {
+            self.transact()?
+        };
+        let undo_log: Arc<Mutex<UndoLog>> = Default::default();
+        // the empty temp store only holds the place of the storage transaction while it is wrapped
+        let inner = mem::replace(&mut tx.store_tx, Box::new(TempTx::default()));
+        tx.store_tx = Box::new(UndoLogTx {
+            inner,
+            log: undo_log.clone(),
+        });
+        Ok(CozoTransaction {
+            db: self,
+            tx,
+            is_write: write,
+            cur_vld: current_validity(),
+            cleanups: vec![],
+            callback_targets: if write {
+                self.current_callback_targets()
+            } else {
+                Default::default()
+            },
+            callback_collector: Default::default(),
+            write_locks: Default::default(),
+            undo_log,
+            savepoints: vec![],
+            _admitted: admitted,
+        })
+    }
+}
+
+impl<'s, S: Storage<'s>> CozoTransaction<'s, S> {
+    /// Whether the transaction was begun as a write transaction
+    pub fn is_write(&self) -> bool {
+        self.is_write
+    }
+    /// Run a script inside the transaction. Only single queries are allowed:
+    /// system ops and imperative scripts manage their own transactions.
+    ///
+    /// A script that fails may have written part of its results,
+    /// so the transaction should usually be rolled back afterwards.
+    pub fn run_script(
+        &mut self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let script = parse_script(
+            payload,
+            &params,
+            &self.db.fixed_rules.read().unwrap(),
+            self.cur_vld,
+        )?;
+        let p = script.get_single_program()?;
+        if let Some(name) = p.needs_write_lock() {
+            if !self.is_write {
+                bail!(ReadOnlyTransactionError(name.to_string()));
+            }
+            if !self.write_locks.contains_key(&name) {
+                let lock = self
+                    .db
+                    .obtain_relation_locks(std::iter::once(&name))
+                    .pop()
+                    .unwrap();
+                self.write_locks.insert(name, HeldRelationLock::new(lock));
+            }
+        }
+        self.tx.script = Some(RunningScript::new(payload, &params));
+        self.db.execute_single_program(
+            p,
+            &mut self.tx,
+            &mut self.cleanups,
+            self.cur_vld,
+            &self.callback_targets,
+            &mut self.callback_collector,
+        )
+    }
+    /// Set a savepoint that the transaction can later be rolled back to with
+    /// [rollback_to](Self::rollback_to). Savepoints can be nested, and a savepoint with
+    /// the name of an existing one hides it until it is rolled back past.
+    ///
+    /// While a savepoint is set, every write also reads the previous value of its key,
+    /// so writes are slower. Release savepoints that are no longer needed with
+    /// [release_savepoint](Self::release_savepoint) to stop this.
+    pub fn savepoint(&mut self, name: &str) {
+        let undo_len = {
+            let mut log = self.undo_log.lock().unwrap();
+            log.recording = true;
+            log.entries.len()
+        };
+        self.savepoints.push(Savepoint {
+            name: name.to_string(),
+            undo_len,
+            cleanups_len: self.cleanups.len(),
+            callbacks_len: self
+                .callback_collector
+                .iter()
+                .map(|(k, v)| (k.clone(), v.batches.len()))
+                .collect(),
+            temp_store_tx: self.tx.temp_store_tx.clone(),
+            temp_store_id: self.tx.temp_store_id.load(Ordering::Acquire),
+        });
+    }
+    /// Undo everything done in the transaction since the savepoint `name` was set,
+    /// including changes to temp relations. The savepoint stays set, so it can be
+    /// rolled back to again, while savepoints set after it are removed.
+    pub fn rollback_to(&mut self, name: &str) -> Result<()> {
+        let idx = match self.savepoints.iter().rposition(|sp| sp.name == name) {
+            Some(idx) => idx,
+            None => bail!(SavepointNotFound(name.to_string())),
+        };
+        self.savepoints.truncate(idx + 1);
+        let sp = &self.savepoints[idx];
+        let undo = {
+            let mut log = self.undo_log.lock().unwrap();
+            log.recording = false;
+            log.entries.split_off(sp.undo_len)
+        };
+        for (key, val) in undo.into_iter().rev() {
+            match val {
+                Some(val) => self.tx.store_tx.put(&key, &val)?,
+                None => self.tx.store_tx.del(&key)?,
+            }
+        }
+        self.undo_log.lock().unwrap().recording = true;
+        self.tx.temp_store_tx = sp.temp_store_tx.clone();
+        self.tx
+            .temp_store_id
+            .store(sp.temp_store_id, Ordering::Release);
+        self.cleanups.truncate(sp.cleanups_len);
+        self.callback_collector
+            .retain(|table, collected| match sp.callbacks_len.get(table) {
+                Some(len) => {
+                    collected.batches.truncate(*len);
+                    true
+                }
+                None => false,
+            });
+        Ok(())
+    }
+    /// Remove the savepoint `name` and the savepoints set after it, keeping everything done
+    /// since. Once no savepoint is left, writes no longer record the previous values of keys.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        let idx = match self.savepoints.iter().rposition(|sp| sp.name == name) {
+            Some(idx) => idx,
+            None => bail!(SavepointNotFound(name.to_string())),
+        };
+        self.savepoints.truncate(idx);
+        if self.savepoints.is_empty() {
+            let mut log = self.undo_log.lock().unwrap();
+            log.recording = false;
+            log.entries = vec![];
+        }
+        Ok(())
+    }
+    /// Commit the transaction, making all its writes visible at once
+    pub fn commit(mut self) -> Result<()> {
+        for (lower, upper) in mem::take(&mut self.cleanups) {
+            self.tx.store_tx.del_range_from_persisted(&lower, &upper)?;
+        }
+        self.tx.commit_tx()?;
+        #[cfg(not(target_arch = "wasm32"))]
+        if !self.callback_collector.is_empty() {
+            self.db.send_callbacks(self.callback_collector)
+        }
+        Ok(())
+    }
+    /// Roll back the transaction, discarding all its writes.
+    /// This is the same as dropping it.
+    pub fn rollback(self) {}
+}
@@ -0,0 +1,141 @@
+/*
+ * Copyright 2023, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[allow(unused_imports)]
+use miette::{bail, Result};
+
+#[cfg(feature = "storage-sqlite")]
+use crate::data::functions::current_validity;
+#[cfg(feature = "storage-sqlite")]
+use crate::data::tuple::{try_decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN};
+#[cfg(feature = "storage-sqlite")]
+use crate::data::value::DataValue;
+#[cfg(feature = "storage-sqlite")]
+use crate::runtime::relation::RelationHandle;
+#[cfg(feature = "storage-sqlite")]
+use crate::runtime::transact::SessionTx;
+use crate::{Db, Storage};
+
+/// The result of [Db::verify_backup].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackupStats {
+    /// Number of rows in each stored relation, including index relations
+    pub rows: BTreeMap<String, usize>,
+    /// Entries that failed to decode or do not match the metadata of their relation
+    pub corrupt: Vec<CorruptEntry>,
+}
+
+impl BackupStats {
+    /// Whether no corrupt entries were found
+    pub fn is_ok(&self) -> bool {
+        self.corrupt.is_empty()
+    }
+}
+
+/// A stored entry found to be corrupt by [Db::verify_backup].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptEntry {
+    /// The relation the entry belongs to
+    pub relation: String,
+    /// The raw storage key of the entry
+    pub key: Vec<u8>,
+    /// What is wrong with the entry
+    pub reason: String,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Check the integrity of an Sqlite backup created by [Db::backup_db].
+    ///
+    /// Every row of every stored relation in the backup is decoded, and its arity and the
+    /// types of its values are checked against the metadata of the relation.
+    /// Problems are collected in the returned [BackupStats] instead of stopping the check,
+    /// so an error is returned only if the backup cannot be read at all,
+    /// including when `in_file` does not exist.
+    #[allow(unused_variables)]
+    pub fn verify_backup(&'s self, in_file: impl AsRef<Path>) -> Result<BackupStats> {
+        #[cfg(feature = "storage-sqlite")]
+        {
+            if !in_file.as_ref().is_file() {
+                bail!("backup file {} does not exist", in_file.as_ref().display())
+            }
+            let source_db = crate::new_cozo_sqlite(in_file)?;
+            let mut tx = source_db.transact()?;
+            let mut stats = BackupStats::default();
+            for (_, name) in tx.live_relation_ids()? {
+                let handle = tx.get_relation(&name, false)?;
+                let rows = verify_relation(&tx, &handle, &mut stats.corrupt)?;
+                stats.rows.insert(name.to_string(), rows);
+            }
+            tx.commit_tx()?;
+            Ok(stats)
+        }
+        #[cfg(not(feature = "storage-sqlite"))]
+        bail!("backup requires the 'storage-sqlite' feature to be enabled")
+    }
+}
+
+/// Decode a stored row as [crate::decode_tuple_from_kv] does,
+/// but return `None` if the key or value is malformed
+#[cfg(feature = "storage-sqlite")]
+fn try_decode_tuple_from_kv(key: &[u8], val: &[u8]) -> Option<Tuple> {
+    let mut tuple = try_decode_tuple_from_key(key)?;
+    if !val.is_empty() {
+        let vals: Vec<DataValue> = rmp_serde::from_slice(val.get(ENCODED_KEY_MIN_LEN..)?).ok()?;
+        tuple.extend(vals);
+    }
+    Some(tuple)
+}
+
+#[cfg(feature = "storage-sqlite")]
+fn verify_relation(
+    tx: &SessionTx<'_>,
+    handle: &RelationHandle,
+    corrupt: &mut Vec<CorruptEntry>,
+) -> Result<usize> {
+    let lower = Tuple::default().encode_as_key(handle.id);
+    let upper = Tuple::default().encode_as_key(handle.id.next());
+    let cur_vld = current_validity();
+    let n_keys = handle.metadata.keys.len();
+    let mut rows = 0;
+    for kv in tx.store_tx.range_scan(&lower, &upper) {
+        let (key, val) = kv?;
+        rows += 1;
+        let reason = match try_decode_tuple_from_kv(&key, &val) {
+            None => Some("cannot be decoded".to_string()),
+            Some(tuple) if tuple.len() != handle.arity() => Some(format!(
+                "has {} values, but the relation has {} columns",
+                tuple.len(),
+                handle.arity()
+            )),
+            Some(tuple) => handle
+                .metadata
+                .keys
+                .iter()
+                .chain(handle.metadata.non_keys.iter())
+                .zip(tuple)
+                .enumerate()
+                .find_map(|(i, (col, val))| {
+                    col.typing.coerce(val, cur_vld).err().map(|err| {
+                        let kind = if i < n_keys { "key" } else { "value" };
+                        format!("{kind} column {}: {err}", col.name)
+                    })
+                }),
+        };
+        if let Some(reason) = reason {
+            corrupt.push(CorruptEntry {
+                relation: handle.name.to_string(),
+                key,
+                reason,
+            });
+        }
+    }
+    Ok(rows)
+}
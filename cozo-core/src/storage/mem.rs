@@ -14,7 +14,8 @@ use std::default::Default;
 use std::iter::Fuse;
 use std::mem;
 use std::ops::Bound;
-use std::sync::Arc;
+use std::sync::{Arc, PoisonError};
+use std::time::Duration;
 
 use itertools::Itertools;
 use miette::{bail, Result};
@@ -22,7 +23,7 @@ use miette::{bail, Result};
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{write_lock_within, CompactOptions, Storage, StoreTx};
 use crate::utils::swap_option_result;
 
 /// Create a database backed by memory.
@@ -49,16 +50,28 @@ impl<'s> Storage<'s> for MemStorage {
     }
 
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        // A writer only touches the store when committing, so a panic in the middle of a
+        // write transaction leaves the store intact and the poisoned lock can be reused.
         Ok(if write {
-            let wtr = self.store.write().unwrap();
+            let wtr = write_lock_within(&self.store, None).unwrap();
             MemTx::Writer(wtr, Default::default())
         } else {
-            let rdr = self.store.read().unwrap();
+            let rdr = self.store.read().unwrap_or_else(PoisonError::into_inner);
             MemTx::Reader(rdr)
         })
     }
 
-    fn range_compact(&'s self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+    fn transact_write_within(&'s self, timeout: Duration) -> Result<Option<Self::Tx>> {
+        Ok(write_lock_within(&self.store, Some(timeout))
+            .map(|wtr| MemTx::Writer(wtr, Default::default())))
+    }
+
+    fn range_compact(
+        &'s self,
+        _lower: &[u8],
+        _upper: &[u8],
+        _options: CompactOptions,
+    ) -> Result<()> {
         Ok(())
     }
 
@@ -176,6 +189,13 @@ impl<'s> StoreTx<'s> for MemTx<'s> {
         }
     }
 
+    fn rollback(&mut self) -> Result<()> {
+        if let MemTx::Writer(_, cached) = self {
+            cached.clear();
+        }
+        Ok(())
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
@@ -6,6 +6,11 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::sync::TryLockError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::sync::{ShardedLock, ShardedLockWriteGuard};
 use itertools::Itertools;
 use miette::Result;
 
@@ -25,6 +30,31 @@ pub(crate) mod temp;
 pub(crate) mod tikv;
 // pub(crate) mod re;
 
+/// Take the write lock of an engine that allows only one writer at a time, waiting for
+/// at most `timeout` if given. A panic in a writer leaves the store intact for all the
+/// engines using this, so a poisoned lock is taken as well.
+pub(crate) fn write_lock_within<T>(
+    lock: &ShardedLock<T>,
+    timeout: Option<Duration>,
+) -> Option<ShardedLockWriteGuard<'_, T>> {
+    let Some(timeout) = timeout else {
+        return Some(lock.write().unwrap_or_else(|err| err.into_inner()));
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        match lock.try_write() {
+            Ok(guard) => return Some(guard),
+            Err(TryLockError::Poisoned(err)) => return Some(err.into_inner()),
+            Err(TryLockError::WouldBlock) => {
+                if Instant::now() >= deadline {
+                    return None;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
 /// Swappable storage trait for Cozo's storage engine
 pub trait Storage<'s>: Send + Sync + Clone {
     /// The associated transaction type used by this engine
@@ -33,12 +63,49 @@ pub trait Storage<'s>: Send + Sync + Clone {
     /// Returns a string that identifies the storage kind
     fn storage_kind(&self) -> &'static str;
 
+    /// Returns the version of the storage engine. Engines implemented within this crate
+    /// report the version of the crate.
+    fn storage_version(&self) -> String {
+        env!("CARGO_PKG_VERSION").to_string()
+    }
+
     /// Create a transaction object. Write ops will only be called when `write == true`.
+    ///
+    /// All reads of the transaction should be made from a consistent snapshot of the database,
+    /// so that writes committed by others after the transaction is created are not visible to it,
+    /// in part or in full. Engines that cannot guarantee this should document it.
     fn transact(&'s self, write: bool) -> Result<Self::Tx>;
 
+    /// Create a write transaction like [Storage::transact], but return `None` if it cannot
+    /// be started within `timeout`, e.g. because the engine allows only one writer at a time.
+    /// Engines that never make writers wait need not implement this.
+    fn transact_write_within(&'s self, _timeout: Duration) -> Result<Option<Self::Tx>> {
+        self.transact(true).map(Some)
+    }
+
+    /// Set how durable the commits of write transactions are.
+    /// Engines that cannot trade durability for throughput ignore this.
+    fn set_durability(&self, _durability: Durability) -> Result<()> {
+        Ok(())
+    }
+
+    /// Make all committed writes durable, including those committed with
+    /// [Durability::Relaxed]. Can be a no-op if the engine has nothing to sync.
+    fn flush(&'s self) -> Result<()> {
+        Ok(())
+    }
+
     /// Compact the key range. Can be a no-op if the storage engine does not
-    /// have the concept of compaction.
-    fn range_compact(&'s self, lower: &[u8], upper: &[u8]) -> Result<()>;
+    /// have the concept of compaction. Engines are free to ignore any of the `options`.
+    fn range_compact(&'s self, lower: &[u8], upper: &[u8], options: CompactOptions) -> Result<()>;
+
+    /// Give the space held by deleted data back to the file system, if the engine can do so
+    /// cheaply compared to compacting everything, e.g. because much of the store is free.
+    /// Returns the number of bytes given back. Can be a no-op for engines that reclaim space
+    /// on their own or only by compaction.
+    fn reclaim_space(&'s self) -> Result<u64> {
+        Ok(0)
+    }
 
     /// Put multiple key-value pairs into the database.
     /// No duplicate data will be sent, and the order data come in is strictly ascending.
@@ -49,6 +116,55 @@ pub trait Storage<'s>: Send + Sync + Clone {
     ) -> Result<()>;
 }
 
+/// How [Storage::range_compact] compacts, for engines that support the choice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactOptions {
+    /// Also rewrite the bottommost level, i.e. a full compaction of the range.
+    /// Reclaims the most space, but is by far the most expensive.
+    pub bottommost: bool,
+    /// Block automatic compactions while this one runs, so that it finishes sooner.
+    /// Without it the compaction shares the engine with background compactions.
+    pub exclusive: bool,
+}
+
+impl Default for CompactOptions {
+    fn default() -> Self {
+        Self {
+            bottommost: false,
+            exclusive: true,
+        }
+    }
+}
+
+/// How durable the commits of write transactions are, set by [crate::Db::set_durability]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// A committed transaction survives a crash of the process or of the machine.
+    #[default]
+    Full,
+    /// Commits do not wait for the data to reach the disk, which is much faster for many
+    /// small write transactions. **Not safe on crash**: a crash of the machine loses the
+    /// transactions committed since the last [crate::Db::flush], and with SQLite it can
+    /// even corrupt the database file.
+    Relaxed,
+}
+
+/// How to read ahead during a long scan, see [StoreTx::range_scan_with_hint]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ScanHint {
+    /// Bytes to fetch from storage ahead of the iterator. 0 leaves it to the engine.
+    pub readahead_bytes: usize,
+}
+
+/// The storage engine used by a database, as returned by [crate::Db::storage_info].
+#[derive(Debug, Clone, PartialEq, Eq, serde_derive::Serialize)]
+pub struct StorageInfo {
+    /// The kind of the engine, e.g. `"mem"` or `"sqlite"`, see [Storage::storage_kind]
+    pub engine: &'static str,
+    /// The version of the engine, see [Storage::storage_version]
+    pub version: String,
+}
+
 /// Trait for the associated transaction type of a storage engine.
 /// A transaction needs to guarantee MVCC semantics for all operations.
 pub trait StoreTx<'s>: Sync {
@@ -101,6 +217,15 @@ pub trait StoreTx<'s>: Sync {
     /// and discard all changes introduced by this transaction.
     fn commit(&mut self) -> Result<()>;
 
+    /// Discard all changes introduced by this transaction. Called when a write transaction is
+    /// dropped without having been committed, for example while unwinding from a panic,
+    /// so it must neither block nor panic.
+    /// The default implementation does nothing, which is correct for engines that
+    /// discard uncommitted changes when their transaction is dropped.
+    fn rollback(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     /// Scan on a range. `lower` is inclusive whereas `upper` is exclusive.
     /// The default implementation calls [`range_scan_owned`](Self::range_scan) and converts the results.
     ///
@@ -151,6 +276,21 @@ pub trait StoreTx<'s>: Sync {
     where
         's: 'a;
 
+    /// Scan on a range like [`range_scan`](Self::range_scan), for scans that read most of
+    /// the range, such as exports and backups. Engines that can fetch ahead of the iterator
+    /// may use the `hint` for it. The default implementation ignores the hint.
+    fn range_scan_with_hint<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        _hint: ScanHint,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        self.range_scan(lower, upper)
+    }
+
     /// Return the number of rows in the range.
     fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
     where
@@ -18,7 +18,7 @@ use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::db::{BadDbInit, DbManifest};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{CompactOptions, ScanHint, Storage, StoreTx};
 use crate::utils::swap_option_result;
 use crate::Db;
 
@@ -29,6 +29,7 @@ const CURRENT_STORAGE_VERSION: u64 = 3;
 /// This is currently the fastest persistent storage and it can
 /// sustain huge concurrency.
 /// Supports concurrent readers and writers.
+/// Each transaction reads from a snapshot taken when it is created.
 pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
     let builder = DbBuilder::default().path(path.as_ref());
     fs::create_dir_all(path.as_ref()).map_err(|err| {
@@ -134,8 +135,10 @@ impl Storage<'_> for RocksDbStorage {
         Ok(RocksDbTx { db_tx })
     }
 
-    fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
-        self.db.range_compact(lower, upper).into_diagnostic()
+    fn range_compact(&self, lower: &[u8], upper: &[u8], options: CompactOptions) -> Result<()> {
+        self.db
+            .range_compact(lower, upper, options.bottommost, options.exclusive)
+            .into_diagnostic()
     }
 
     fn batch_put<'a>(
@@ -208,6 +211,10 @@ impl<'s> StoreTx<'s> for RocksDbTx {
         Ok(self.db_tx.commit()?)
     }
 
+    fn rollback(&mut self) -> Result<()> {
+        Ok(self.db_tx.rollback()?)
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
@@ -257,6 +264,28 @@ impl<'s> StoreTx<'s> for RocksDbTx {
         })
     }
 
+    fn range_scan_with_hint<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        hint: ScanHint,
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        let mut builder = self.db_tx.iterator().upper_bound(upper);
+        if hint.readahead_bytes > 0 {
+            builder = builder.readahead_size(hint.readahead_bytes);
+        }
+        let mut inner = builder.start();
+        inner.seek(lower);
+        Box::new(RocksDbIteratorRaw {
+            inner,
+            started: false,
+            upper_bound: upper.to_vec(),
+        })
+    }
+
     fn range_count<'a>(&'a self, lower: &[u8], upper: &[u8]) -> Result<usize>
     where
         's: 'a,
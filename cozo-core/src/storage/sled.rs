@@ -18,12 +18,15 @@ use sled::{Batch, Config, Db, IVec, Iter, Mode};
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::decode_tuple_from_kv;
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{CompactOptions, Storage, StoreTx};
 use crate::utils::{swap_option_result, TempCollector};
 
 /// Creates a Sled database object. Experimental.
 /// You should use [`new_cozo_rocksdb`](crate::new_cozo_rocksdb) or
 /// [`new_cozo_sqlite`](crate::new_cozo_sqlite) instead.
+///
+/// Sled has no snapshots: a transaction sees writes committed after it was created,
+/// so reading several relations, e.g. when exporting, is not guaranteed to be consistent.
 pub fn new_cozo_sled(path: impl AsRef<Path>) -> Result<crate::Db<SledStorage>> {
     let db = sled::open(path).into_diagnostic()?;
     let ret = crate::Db::new(SledStorage { db })?;
@@ -48,6 +51,11 @@ impl Storage<'_> for SledStorage {
         "sled"
     }
 
+    fn flush(&self) -> Result<()> {
+        self.db.flush().into_diagnostic()?;
+        Ok(())
+    }
+
     fn transact(&self, _write: bool) -> Result<Self::Tx> {
         Ok(SledTx {
             db: self.db.clone(),
@@ -55,7 +63,7 @@ impl Storage<'_> for SledStorage {
         })
     }
 
-    fn range_compact(&self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+    fn range_compact(&self, _lower: &[u8], _upper: &[u8], _options: CompactOptions) -> Result<()> {
         Ok(())
     }
 
@@ -182,6 +190,11 @@ impl<'s> StoreTx<'s> for SledTx {
         Ok(())
     }
 
+    fn rollback(&mut self) -> Result<()> {
+        self.changes = None;
+        Ok(())
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
@@ -6,8 +6,11 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 
 use ::sqlite::Connection;
 use crossbeam::sync::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
@@ -18,7 +21,7 @@ use sqlite::{ConnectionWithFullMutex, State, Statement};
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{write_lock_within, CompactOptions, Durability, Storage, StoreTx};
 use crate::utils::swap_option_result;
 
 /// The Sqlite storage engine
@@ -27,6 +30,7 @@ pub struct SqliteStorage {
     lock: Arc<ShardedLock<()>>,
     name: PathBuf,
     pool: Arc<Mutex<Vec<ConnectionWithFullMutex>>>,
+    relaxed: Arc<AtomicBool>,
 }
 
 /// Create a sqlite backed database.
@@ -53,28 +57,37 @@ pub fn new_cozo_sqlite(path: impl AsRef<Path>) -> Result<crate::Db<SqliteStorage
         lock: Default::default(),
         name: PathBuf::from(path.as_ref()),
         pool: Default::default(),
+        relaxed: Default::default(),
     })?;
 
     ret.initialize()?;
     Ok(ret)
 }
 
-impl<'s> Storage<'s> for SqliteStorage {
-    type Tx = SqliteTx<'s>;
-
-    fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+impl SqliteStorage {
+    /// Start a transaction once the lock for it is taken. A transaction dropped while
+    /// panicking is rolled back, so a poisoned lock is safe to reuse.
+    fn start_tx<'s>(
+        &'s self,
+        lock: Either<ShardedLockReadGuard<'s, ()>, ShardedLockWriteGuard<'s, ()>>,
+    ) -> Result<SqliteTx<'s>> {
         let conn = {
             match self.pool.lock().unwrap().pop() {
                 None => Connection::open_with_full_mutex(&self.name).into_diagnostic()?,
                 Some(conn) => conn,
             }
         };
-        let lock = if write {
-            Right(self.lock.write().unwrap())
-        } else {
-            Left(self.lock.read().unwrap())
-        };
-        if write {
+        if lock.is_right() {
+            // set for every transaction, as pooled connections may predate the setting
+            let synchronous = if self.relaxed.load(Ordering::Acquire) {
+                "off"
+            } else {
+                "full"
+            };
+            let mut stmt = conn
+                .prepare(format!("pragma synchronous = {synchronous};"))
+                .into_diagnostic()?;
+            while stmt.next().into_diagnostic()? != State::Done {}
             let mut stmt = conn.prepare("begin;").into_diagnostic()?;
             while stmt.next().into_diagnostic()? != State::Done {}
         }
@@ -91,6 +104,26 @@ impl<'s> Storage<'s> for SqliteStorage {
             committed: false,
         })
     }
+}
+
+impl<'s> Storage<'s> for SqliteStorage {
+    type Tx = SqliteTx<'s>;
+
+    fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        let lock = if write {
+            Right(write_lock_within(&self.lock, None).unwrap())
+        } else {
+            Left(self.lock.read().unwrap_or_else(PoisonError::into_inner))
+        };
+        self.start_tx(lock)
+    }
+
+    fn transact_write_within(&'s self, timeout: Duration) -> Result<Option<Self::Tx>> {
+        match write_lock_within(&self.lock, Some(timeout)) {
+            None => Ok(None),
+            Some(lock) => self.start_tx(Right(lock)).map(Some),
+        }
+    }
 
     fn batch_put<'a>(
         &'a self,
@@ -105,15 +138,74 @@ impl<'s> Storage<'s> for SqliteStorage {
         Ok(())
     }
 
-    fn range_compact(&'_ self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+    fn range_compact(
+        &'_ self,
+        _lower: &[u8],
+        _upper: &[u8],
+        _options: CompactOptions,
+    ) -> Result<()> {
         let mut pool = self.pool.lock().unwrap();
         while pool.pop().is_some() {}
         Ok(())
     }
 
+    fn reclaim_space(&'s self) -> Result<u64> {
+        // vacuuming rebuilds the whole file, so it is only worth it if much of it is free
+        let _lock = write_lock_within(&self.lock, None);
+        let conn = match self.pool.lock().unwrap().pop() {
+            None => Connection::open_with_full_mutex(&self.name).into_diagnostic()?,
+            Some(conn) => conn,
+        };
+        let page_size = pragma_value(&conn, "page_size")?;
+        let page_count = pragma_value(&conn, "page_count")?;
+        let free_count = pragma_value(&conn, "freelist_count")?;
+        let mut reclaimed = 0;
+        if free_count * VACUUM_FREE_RATIO >= page_count && free_count > 0 {
+            let mut stmt = conn.prepare("vacuum;").into_diagnostic()?;
+            while stmt.next().into_diagnostic()? != State::Done {}
+            drop(stmt);
+            let new_count = pragma_value(&conn, "page_count")?;
+            reclaimed = page_count.saturating_sub(new_count) * page_size;
+        }
+        self.pool.lock().unwrap().push(conn);
+        Ok(reclaimed)
+    }
+
     fn storage_kind(&self) -> &'static str {
         "sqlite"
     }
+
+    fn set_durability(&self, durability: Durability) -> Result<()> {
+        self.relaxed
+            .store(durability == Durability::Relaxed, Ordering::Release);
+        Ok(())
+    }
+
+    fn flush(&'s self) -> Result<()> {
+        // no transaction may be writing while the file is synced
+        let _lock = write_lock_within(&self.lock, None);
+        File::open(&self.name)
+            .and_then(|f| f.sync_all())
+            .into_diagnostic()
+    }
+
+    fn storage_version(&self) -> String {
+        // the bundled SQLite library, whose version number is encoded as e.g. 3039004
+        let v = sqlite::version();
+        format!("{}.{}.{}", v / 1_000_000, v / 1000 % 1000, v % 1000)
+    }
+}
+
+/// [Storage::reclaim_space] vacuums once at least one page in this many is free
+const VACUUM_FREE_RATIO: u64 = 4;
+
+fn pragma_value(conn: &ConnectionWithFullMutex, name: &str) -> Result<u64> {
+    let mut stmt = conn.prepare(format!("pragma {name};")).into_diagnostic()?;
+    if stmt.next().into_diagnostic()? != State::Row {
+        bail!("no value for sqlite pragma {}", name)
+    }
+    let v = stmt.read::<i64, _>(0).into_diagnostic()?;
+    Ok(v as u64)
 }
 
 pub struct SqliteTx<'a> {
@@ -154,6 +246,11 @@ impl Drop for SqliteTx<'_> {
                 let _ = self.conn.as_ref().unwrap().execute(query);
             }
         }
+        // A cached statement that stopped at a row keeps a read transaction open in Sqlite.
+        // Finalize them now, as the fields (including the lock) are dropped in declaration order.
+        for stmt in &self.stmts {
+            *stmt.lock().unwrap() = None;
+        }
         let mut pool = self.storage.pool.lock().unwrap();
         let conn = self.conn.take().unwrap();
         pool.push(conn)
@@ -269,6 +366,20 @@ impl<'s> StoreTx<'s> for SqliteTx<'s> {
         Ok(())
     }
 
+    fn rollback(&mut self) -> Result<()> {
+        if let Right(ShardedLockWriteGuard { .. }) = self.lock {
+            if !self.committed {
+                let query = r#"rollback;"#;
+                let conn = self.conn.as_ref().ok_or_else(|| miette!("No connection"))?;
+                let mut statement = conn.prepare(query).into_diagnostic()?;
+                while statement.next().into_diagnostic()? != State::Done {}
+                // the transaction is finished, so it must not be rolled back again on drop
+                self.committed = true;
+            }
+        }
+        Ok(())
+    }
+
     fn range_scan_tuple<'a>(
         &'a self,
         lower: &[u8],
@@ -15,7 +15,7 @@ use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::decode_tuple_from_kv;
 use crate::storage::mem::SkipIterator;
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{CompactOptions, Storage, StoreTx};
 
 #[derive(Default, Clone)]
 pub(crate) struct TempStorage;
@@ -33,7 +33,12 @@ impl<'s> Storage<'s> for TempStorage {
         })
     }
 
-    fn range_compact(&'s self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+    fn range_compact(
+        &'s self,
+        _lower: &[u8],
+        _upper: &[u8],
+        _options: CompactOptions,
+    ) -> Result<()> {
         panic!("range compact called on temp store")
     }
 
@@ -45,6 +50,7 @@ impl<'s> Storage<'s> for TempStorage {
     }
 }
 
+#[derive(Clone, Default)]
 pub(crate) struct TempTx {
     store: BTreeMap<Vec<u8>, Vec<u8>>,
 }
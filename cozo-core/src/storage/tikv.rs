@@ -13,13 +13,13 @@ use std::sync::{Arc, Mutex};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use miette::{miette, IntoDiagnostic, Result};
-use tikv_client::{RawClient, Transaction, TransactionClient};
+use tikv_client::{CheckLevel, RawClient, Transaction, TransactionClient, TransactionOptions};
 use tokio::runtime::Runtime;
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::runtime::relation::decode_tuple_from_kv;
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{CompactOptions, Storage, StoreTx};
 use crate::utils::{swap_option_result, TempCollector};
 use crate::Db;
 
@@ -62,19 +62,23 @@ impl Storage<'_> for TiKvStorage {
     }
 
     fn transact(&self, _write: bool) -> Result<Self::Tx> {
-        let tx = if self.optimistic {
-            RT.block_on(self.client.begin_optimistic())
-                .into_diagnostic()?
+        let options = if self.optimistic {
+            TransactionOptions::new_optimistic()
         } else {
-            RT.block_on(self.client.begin_pessimistic())
-                .into_diagnostic()?
+            TransactionOptions::new_pessimistic()
         };
+        // a transaction dropped without committing is left for TiKV to clean up,
+        // rolling it back would mean waiting on the cluster while dropping
+        let options = options.drop_check(CheckLevel::Warn);
+        let tx = RT
+            .block_on(self.client.begin_with_options(options))
+            .into_diagnostic()?;
         Ok(TiKvTx {
             tx: Arc::new(Mutex::new(tx)),
         })
     }
 
-    fn range_compact(&self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+    fn range_compact(&self, _lower: &[u8], _upper: &[u8], _options: CompactOptions) -> Result<()> {
         Ok(())
     }
 
@@ -101,8 +101,12 @@ struct RocksDbBridge {
         write_status(s, status);
     }
 
-    void compact_range(RustBytes start, RustBytes end, RocksDbStatus &status) const {
+    void compact_range(RustBytes start, RustBytes end, bool bottommost, bool exclusive,
+                       RocksDbStatus &status) const {
         CompactRangeOptions options;
+        options.bottommost_level_compaction = bottommost ? BottommostLevelCompaction::kForce
+                                                         : BottommostLevelCompaction::kIfHaveCompactionFilter;
+        options.exclusive_manual_compaction = exclusive;
         auto cf = db->DefaultColumnFamily();
         auto start_s = convert_slice(start);
         auto end_s = convert_slice(end);
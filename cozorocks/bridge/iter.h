@@ -64,6 +64,10 @@ struct IterBridge {
         r_opts->pin_data = val;
     }
 
+    inline void readahead_size(size_t val) {
+        r_opts->readahead_size = val;
+    }
+
     inline void clear_bounds() {
         r_opts->iterate_lower_bound = nullptr;
         r_opts->iterate_upper_bound = nullptr;
@@ -16,4 +16,5 @@ void TxBridge::start() {
         tx.reset(txn);
     }
     assert(tx);
+    r_opts->snapshot = tx->GetSnapshot();
 }
\ No newline at end of file
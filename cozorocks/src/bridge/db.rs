@@ -168,9 +168,16 @@ impl RocksDb {
         }
     }
     #[inline]
-    pub fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<(), RocksDbStatus> {
+    pub fn range_compact(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+        bottommost: bool,
+        exclusive: bool,
+    ) -> Result<(), RocksDbStatus> {
         let mut status = RocksDbStatus::default();
-        self.inner.compact_range(lower, upper, &mut status);
+        self.inner
+            .compact_range(lower, upper, bottommost, exclusive, &mut status);
         if status.is_ok() {
             Ok(())
         } else {
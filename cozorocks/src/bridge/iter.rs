@@ -73,6 +73,11 @@ impl IterBuilder {
         self.inner.pin_mut().pin_data(val);
         self
     }
+    #[inline]
+    pub fn readahead_size(mut self, val: usize) -> Self {
+        self.inner.pin_mut().readahead_size(val);
+        self
+    }
 }
 
 impl DbIter {
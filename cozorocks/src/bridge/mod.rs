@@ -131,6 +131,8 @@ pub(crate) mod ffi {
             self: &RocksDbBridge,
             lower: &[u8],
             upper: &[u8],
+            bottommost: bool,
+            exclusive: bool,
             status: &mut RocksDbStatus,
         );
         fn get_sst_writer(
@@ -187,6 +189,7 @@ pub(crate) mod ffi {
         fn auto_prefix_mode(self: Pin<&mut IterBridge>, val: bool);
         fn prefix_same_as_start(self: Pin<&mut IterBridge>, val: bool);
         fn pin_data(self: Pin<&mut IterBridge>, val: bool);
+        fn readahead_size(self: Pin<&mut IterBridge>, val: usize);
 
         fn to_start(self: Pin<&mut IterBridge>);
         fn to_end(self: Pin<&mut IterBridge>);